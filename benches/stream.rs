@@ -0,0 +1,290 @@
+//! Run with `--features profiling` and compare against a run without it to measure the
+//! overhead of timing each frame's encryption and decryption.
+//!
+//! The `backends` group compares cryptography backends. It measures ring alongside pure
+//! Rust unless run with `--no-default-features --features crypto-rust`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio::{
+    io::{duplex, AsyncReadExt, DuplexStream},
+    runtime::Runtime,
+};
+use tokio_noise::{
+    handshakes::{NNpsk0, SnowHandshake},
+    NoiseBuilder, NoiseStream,
+};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+/// The capacity of each direction of the in-memory transport.
+const DUPLEX_CAPACITY: usize = 256 * 1024;
+
+/// Connect a pair of noise streams over an in-memory duplex transport.
+async fn connect_pair() -> (NoiseStream<DuplexStream>, NoiseStream<DuplexStream>) {
+    connect_pair_with(NoiseBuilder::new()).await
+}
+
+/// Connect a pair of noise streams, configuring both with the given builder.
+async fn connect_pair_with(
+    builder: NoiseBuilder,
+) -> (NoiseStream<DuplexStream>, NoiseStream<DuplexStream>) {
+    let (client, server) = duplex(DUPLEX_CAPACITY);
+    let (client, server) = tokio::join!(
+        builder.handshake_initiator(client, NNpsk0::try_new(&PSK).unwrap()),
+        builder.handshake_responder(server, NNpsk0::try_new(&PSK).unwrap()),
+    );
+    (client.unwrap(), server.unwrap())
+}
+
+/// Latency of sending a small message and receiving the echoed reply.
+fn round_trip(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let (mut client, mut server) = rt.block_on(connect_pair());
+
+    rt.spawn(async move {
+        let mut buf = [0u8; 64];
+        loop {
+            let n = match server.recv(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            server.send(&buf[..n]).await.unwrap();
+        }
+    });
+
+    c.bench_function("round_trip", |b| {
+        let mut buf = [0u8; 64];
+        b.iter(|| {
+            rt.block_on(async {
+                client.send(b"ping").await.unwrap();
+                let n = client.recv(&mut buf).await.unwrap();
+                assert_eq!(&buf[..n], b"ping");
+            })
+        })
+    });
+}
+
+/// Throughput of many tiny messages, each sent and flushed on its own, as in
+/// request/response protocols.
+fn small_messages(c: &mut Criterion) {
+    const MESSAGE_SIZE: usize = 16;
+    const N_MESSAGES: usize = 1000;
+
+    let rt = Runtime::new().unwrap();
+    let (mut client, mut server) = rt.block_on(connect_pair());
+    let message = [0xAB; MESSAGE_SIZE];
+    let mut recv_buf = vec![0u8; MESSAGE_SIZE * N_MESSAGES];
+
+    let mut group = c.benchmark_group("small_messages");
+    group.throughput(Throughput::Elements(N_MESSAGES as u64));
+    group.bench_function(BenchmarkId::from_parameter(MESSAGE_SIZE), |b| {
+        b.iter(|| {
+            rt.block_on(async {
+                let send = async {
+                    for _ in 0..N_MESSAGES {
+                        client.send(&message).await.unwrap();
+                    }
+                };
+                let (_, received) = tokio::join!(send, server.read_exact(&mut recv_buf));
+                received.unwrap();
+            })
+        })
+    });
+    group.finish();
+}
+
+/// Throughput of one large transfer, at several write sizes.
+fn bulk_transfer(c: &mut Criterion) {
+    const TRANSFER_SIZE: usize = 4 * 1024 * 1024;
+
+    let rt = Runtime::new().unwrap();
+    let (mut client, mut server) = rt.block_on(connect_pair());
+    let data = vec![0xAB; TRANSFER_SIZE];
+    let mut recv_buf = vec![0u8; TRANSFER_SIZE];
+
+    let mut group = c.benchmark_group("bulk_transfer");
+    group.throughput(Throughput::Bytes(TRANSFER_SIZE as u64));
+    for write_size in [1024, 2030, 16 * 1024, 256 * 1024, 1024 * 1024] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(write_size),
+            &write_size,
+            |b, &write_size| {
+                b.iter(|| {
+                    rt.block_on(async {
+                        let send = async {
+                            for chunk in data.chunks(write_size) {
+                                client.send(chunk).await.unwrap();
+                            }
+                        };
+                        let (_, received) = tokio::join!(send, server.read_exact(&mut recv_buf));
+                        received.unwrap();
+                    })
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+/// Throughput of a large transfer consumed by many small reads, with and without
+/// read-ahead.
+fn small_reads(c: &mut Criterion) {
+    const TRANSFER_SIZE: usize = 1024 * 1024;
+    const WRITE_SIZE: usize = 16 * 1024;
+    const READ_SIZE: usize = 64;
+
+    let rt = Runtime::new().unwrap();
+    let data = vec![0xAB; TRANSFER_SIZE];
+
+    let mut group = c.benchmark_group("small_reads");
+    group.throughput(Throughput::Bytes(TRANSFER_SIZE as u64));
+    for read_ahead in [0, 64 * 1024] {
+        let builder = NoiseBuilder::new().read_ahead(read_ahead);
+        let (mut client, mut server) = rt.block_on(connect_pair_with(builder));
+        group.bench_with_input(
+            BenchmarkId::new("read_ahead", read_ahead),
+            &read_ahead,
+            |b, _| {
+                b.iter(|| {
+                    rt.block_on(async {
+                        let send = async {
+                            for chunk in data.chunks(WRITE_SIZE) {
+                                client.send(chunk).await.unwrap();
+                            }
+                        };
+                        let receive = async {
+                            let mut buf = [0u8; READ_SIZE];
+                            let mut received = 0;
+                            while received < TRANSFER_SIZE {
+                                received += server.recv(&mut buf).await.unwrap();
+                            }
+                        };
+                        tokio::join!(send, receive);
+                    })
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+/// Throughput of one large transfer with each cryptography backend, for each cipher.
+fn backends(c: &mut Criterion) {
+    const TRANSFER_SIZE: usize = 4 * 1024 * 1024;
+    const WRITE_SIZE: usize = 256 * 1024;
+
+    type NewResolver = fn() -> snow::resolvers::BoxedCryptoResolver;
+    let resolvers: &[(&str, NewResolver)] = &[
+        ("rust", || Box::new(snow::resolvers::DefaultResolver)),
+        #[cfg(feature = "crypto-ring")]
+        ("ring", || {
+            Box::new(snow::resolvers::FallbackResolver::new(
+                Box::new(snow::resolvers::RingResolver),
+                Box::new(snow::resolvers::DefaultResolver),
+            ))
+        }),
+    ];
+
+    let rt = Runtime::new().unwrap();
+    let data = vec![0xAB; TRANSFER_SIZE];
+    let mut recv_buf = vec![0u8; TRANSFER_SIZE];
+
+    let mut group = c.benchmark_group("backends");
+    group.throughput(Throughput::Bytes(TRANSFER_SIZE as u64));
+    for cipher in ["ChaChaPoly", "AESGCM"] {
+        let name = format!("Noise_NN_25519_{}_SHA256", cipher);
+        for &(backend, new_resolver) in resolvers {
+            let handshake = || {
+                SnowHandshake::new(&name)
+                    .unwrap()
+                    .with_builder(move |params| {
+                        snow::Builder::with_resolver(params, new_resolver())
+                    })
+            };
+            let (client, server) = duplex(DUPLEX_CAPACITY);
+            let (client, server) = rt.block_on(async {
+                tokio::join!(
+                    NoiseStream::handshake_initiator(client, handshake()),
+                    NoiseStream::handshake_responder(server, handshake()),
+                )
+            });
+            let (mut client, mut server) = (client.unwrap(), server.unwrap());
+            group.bench_function(BenchmarkId::new(backend, cipher), |b| {
+                b.iter(|| {
+                    rt.block_on(async {
+                        let send = async {
+                            for chunk in data.chunks(WRITE_SIZE) {
+                                client.send(chunk).await.unwrap();
+                            }
+                        };
+                        let (_, received) = tokio::join!(send, server.read_exact(&mut recv_buf));
+                        received.unwrap();
+                    })
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Many connections transferring data concurrently.
+fn fan_out(c: &mut Criterion) {
+    const PER_CONNECTION: usize = 256 * 1024;
+
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("fan_out");
+    for n_connections in [16, 128] {
+        let pairs = rt.block_on(async {
+            let mut pairs = Vec::new();
+            for _ in 0..n_connections {
+                pairs.push(connect_pair().await);
+            }
+            pairs
+        });
+        let mut pairs: Vec<_> = pairs.into_iter().map(Some).collect();
+
+        group.throughput(Throughput::Bytes((PER_CONNECTION * n_connections) as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(n_connections),
+            &n_connections,
+            |b, _| {
+                b.iter(|| {
+                    rt.block_on(async {
+                        let tasks: Vec<_> = pairs
+                            .iter_mut()
+                            .map(|pair| {
+                                let (mut client, mut server) = pair.take().unwrap();
+                                tokio::spawn(async move {
+                                    let data = vec![0xAB; PER_CONNECTION];
+                                    let mut recv_buf = vec![0u8; PER_CONNECTION];
+                                    let (sent, received) = tokio::join!(
+                                        client.send(&data),
+                                        server.read_exact(&mut recv_buf)
+                                    );
+                                    sent.unwrap();
+                                    received.unwrap();
+                                    (client, server)
+                                })
+                            })
+                            .collect();
+                        for (pair, task) in pairs.iter_mut().zip(tasks) {
+                            *pair = Some(task.await.unwrap());
+                        }
+                    })
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    round_trip,
+    small_messages,
+    bulk_transfer,
+    small_reads,
+    backends,
+    fan_out
+);
+criterion_main!(benches);
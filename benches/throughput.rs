@@ -0,0 +1,58 @@
+//! Bulk transfer over loopback TCP, including the cost of the socket calls which the
+//! in-memory benchmarks in `stream.rs` leave out.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio::{
+    io::AsyncReadExt,
+    net::{TcpListener, TcpStream},
+    runtime::Runtime,
+};
+use tokio_noise::NoiseTcpStream;
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+/// Connect a pair of noise streams over the loopback interface.
+async fn connect_pair() -> (NoiseTcpStream, NoiseTcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let srv = tokio::spawn(async move {
+        let (tcp_stream, _) = listener.accept().await.unwrap();
+        NoiseTcpStream::handshake_responder_psk0(tcp_stream, &PSK)
+            .await
+            .unwrap()
+    });
+
+    let tcp_stream = TcpStream::connect(addr).await.unwrap();
+    let client = NoiseTcpStream::handshake_initiator_psk0(tcp_stream, &PSK)
+        .await
+        .unwrap();
+    (client, srv.await.unwrap())
+}
+
+fn tcp_bulk_transfer(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let (mut client, mut server) = rt.block_on(connect_pair());
+
+    let mut group = c.benchmark_group("tcp_bulk_transfer");
+    for size in [64 * 1024, 1024 * 1024, 8 * 1024 * 1024] {
+        let data = vec![0xAB; size];
+        let mut recv_buf = vec![0u8; size];
+
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| {
+            b.iter(|| {
+                rt.block_on(async {
+                    let (sent, received) =
+                        tokio::join!(client.send(&data), server.read_exact(&mut recv_buf));
+                    sent.unwrap();
+                    received.unwrap();
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, tcp_bulk_transfer);
+criterion_main!(benches);
@@ -0,0 +1,256 @@
+//! This module provides [`AllowlistFile`], which admits peers whose static keys are
+//! listed in a file that can be edited while the process runs.
+
+use log::{error, info, warn};
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use crate::{
+    errors::{AllowlistError, NoiseError},
+    fingerprint::{Fingerprint, FINGERPRINT_PREFIX},
+    hex,
+};
+
+/// A set of allowed static public keys, read from a file in the style of OpenSSH's
+/// `authorized_keys`.
+///
+/// Each line holds a static public key in hex, or its [`Fingerprint`], optionally
+/// followed by whitespace and a comment. Blank lines, and lines starting with `#`, are
+/// ignored.
+///
+/// ```text
+/// # Billing service
+/// 3b6a27bcceb6a42d62a3a8d02a6f0d73653215771de243a63ac048a18b59da29 billing-1
+/// SHA256:Zmh6rfhivXdsj8GLjp+OIAiXFIVu4jOzkCpZHQ1fKSU billing-2
+/// ```
+///
+/// The file is checked for changes each time a peer is verified, and re-read if its
+/// modification time or length has changed, so edits take effect without a restart.
+/// A file which fails to parse after an edit is logged, and the keys last read stay in
+/// force. Call [`reload`][Self::reload] to re-read it immediately and see any error.
+///
+/// Only handshakes consult the allowlist, so removing a key turns away new connections
+/// from that peer but leaves its established streams open.
+///
+/// Use it with [`SnowHandshake::remote_allowlist`][crate::handshakes::SnowHandshake::remote_allowlist],
+/// or call [`verify`][Self::verify] from a custom handshake's
+/// [`verify_remote_static`][crate::handshakes::Handshake::verify_remote_static]. Clones
+/// share the same keys, so a reload through one is seen by all.
+#[derive(Clone, Debug)]
+pub struct AllowlistFile {
+    path: Arc<PathBuf>,
+    loaded: Arc<Mutex<Loaded>>,
+}
+
+/// The keys last read from the file, and the state of the file when they were read.
+#[derive(Debug)]
+struct Loaded {
+    stamp: FileStamp,
+    fingerprints: HashSet<Fingerprint>,
+}
+
+/// What is compared to decide whether the file has changed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct FileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> io::Result<FileStamp> {
+        let metadata = fs::metadata(path)?;
+        Ok(FileStamp {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+        })
+    }
+}
+
+impl AllowlistFile {
+    /// Read the allowlist at the given path, failing if it can't be read or any line
+    /// is malformed.
+    pub fn open(path: impl AsRef<Path>) -> Result<AllowlistFile, AllowlistError> {
+        let path = path.as_ref().to_path_buf();
+        let loaded = load(&path)?;
+        Ok(AllowlistFile {
+            path: Arc::new(path),
+            loaded: Arc::new(Mutex::new(loaded)),
+        })
+    }
+
+    /// Returns the path of the file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Re-read the file now, whether or not it has changed. On failure, the keys last
+    /// read stay in force.
+    pub fn reload(&self) -> Result<(), AllowlistError> {
+        let loaded = load(&self.path)?;
+        *self.loaded.lock().unwrap() = loaded;
+        Ok(())
+    }
+
+    /// Returns how many distinct keys are allowed, as of the last read.
+    pub fn len(&self) -> usize {
+        self.loaded.lock().unwrap().fingerprints.len()
+    }
+
+    /// Returns whether no keys are allowed, as of the last read.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns whether the given static public key is allowed, first re-reading the
+    /// file if it has changed.
+    pub fn allows(&self, public_key: &[u8]) -> bool {
+        self.allows_fingerprint(&Fingerprint::of(public_key))
+    }
+
+    /// Returns whether the key with the given fingerprint is allowed, first re-reading
+    /// the file if it has changed.
+    pub fn allows_fingerprint(&self, fingerprint: &Fingerprint) -> bool {
+        self.reload_if_changed();
+        self.loaded
+            .lock()
+            .unwrap()
+            .fingerprints
+            .contains(fingerprint)
+    }
+
+    /// Checks a peer's static public key, failing with [`NoiseError::PeerNotAllowed`]
+    /// if it isn't allowed. Suits
+    /// [`Handshake::verify_remote_static`][crate::handshakes::Handshake::verify_remote_static].
+    pub fn verify(&self, remote_static_key: &[u8]) -> Result<(), NoiseError> {
+        let fingerprint = Fingerprint::of(remote_static_key);
+        if self.allows_fingerprint(&fingerprint) {
+            Ok(())
+        } else {
+            Err(NoiseError::PeerNotAllowed(fingerprint))
+        }
+    }
+
+    fn reload_if_changed(&self) {
+        let stamp = match FileStamp::of(&self.path) {
+            Ok(stamp) => stamp,
+            Err(e) => {
+                warn!(
+                    "cannot check allowlist {} for changes: {}",
+                    self.path.display(),
+                    e
+                );
+                return;
+            }
+        };
+        if self.loaded.lock().unwrap().stamp == stamp {
+            return;
+        }
+        match load(&self.path) {
+            Ok(loaded) => {
+                info!(
+                    "reloaded allowlist {} with {} keys",
+                    self.path.display(),
+                    loaded.fingerprints.len()
+                );
+                *self.loaded.lock().unwrap() = loaded;
+            }
+            Err(e) => {
+                let mut loaded = self.loaded.lock().unwrap();
+                error!(
+                    "failed to reload allowlist {}: {}; keeping the previous {} keys",
+                    self.path.display(),
+                    e,
+                    loaded.fingerprints.len()
+                );
+                // Don't retry until the file changes again.
+                loaded.stamp = stamp;
+            }
+        }
+    }
+}
+
+fn load(path: &Path) -> Result<Loaded, AllowlistError> {
+    // Stamp the file before reading it, so that an edit made during the read is picked
+    // up by the next check.
+    let stamp = FileStamp::of(path).map_err(AllowlistError::Io)?;
+    let contents = fs::read_to_string(path).map_err(AllowlistError::Io)?;
+    Ok(Loaded {
+        stamp,
+        fingerprints: parse(&contents)?,
+    })
+}
+
+fn parse(contents: &str) -> Result<HashSet<Fingerprint>, AllowlistError> {
+    let mut fingerprints = HashSet::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let entry = line.split_whitespace().next().expect("line is not blank");
+        let parse_error = |reason: String| AllowlistError::Parse {
+            line: index + 1,
+            reason,
+        };
+        let fingerprint = if entry.starts_with(FINGERPRINT_PREFIX) {
+            entry
+                .parse::<Fingerprint>()
+                .map_err(|e| parse_error(e.to_string()))?
+        } else {
+            let key = hex::decode(entry)
+                .filter(|key| !key.is_empty())
+                .ok_or_else(|| {
+                    parse_error(format!(
+                        "{:?} is neither a hex key nor a fingerprint",
+                        entry
+                    ))
+                })?;
+            Fingerprint::of(&key)
+        };
+        fingerprints.insert(fingerprint);
+    }
+    Ok(fingerprints)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_keys_fingerprints_and_comments() {
+        let key = [7u8; 32];
+        let contents = format!(
+            "# comment\n\n  {} first key\n{}\t# second\n{}\n",
+            hex::encode(&key),
+            Fingerprint::of(b"other"),
+            hex::encode(&key).to_uppercase(),
+        );
+        let fingerprints = parse(&contents).unwrap();
+        assert_eq!(fingerprints.len(), 2);
+        assert!(fingerprints.contains(&Fingerprint::of(&key)));
+        assert!(fingerprints.contains(&Fingerprint::of(b"other")));
+    }
+
+    #[test]
+    fn reports_the_first_bad_line() {
+        let cases = [
+            ("abcd\nxyz comment\n", 2),
+            ("# ok\n\nabc\n", 3),
+            ("SHA256:not-base64!\n", 1),
+            ("SHA256:AAAA\n", 1),
+        ];
+        for (contents, line) in cases {
+            match parse(contents) {
+                Err(AllowlistError::Parse { line: got, .. }) => {
+                    assert_eq!(got, line, "{:?}", contents)
+                }
+                other => panic!("{:?} parsed as {:?}", contents, other),
+            }
+        }
+    }
+}
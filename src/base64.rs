@@ -0,0 +1,64 @@
+//! Standard base64, as used for PSKs and [`Fingerprint`][crate::Fingerprint]s.
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encode bytes as standard base64 without padding.
+pub(crate) fn encode_unpadded(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+        }
+    }
+    encoded
+}
+
+/// Decode standard base64, with or without padding. Returns `None` if the string is
+/// malformed, including if the last character carries bits beyond the last byte.
+pub(crate) fn decode(s: &str) -> Option<Vec<u8>> {
+    let data = s.trim_end_matches('=');
+    let padding = s.len() - data.len();
+    if padding > 2 || (padding > 0 && !s.len().is_multiple_of(4)) {
+        return None;
+    }
+
+    let mut decoded = Vec::with_capacity(data.len() * 3 / 4);
+    let (mut acc, mut bits) = (0u32, 0);
+    for c in data.bytes() {
+        let value = ALPHABET.iter().position(|&a| a == c)? as u32;
+        acc = acc << 6 | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    // A trailing character must not be left over, nor carry bits beyond the last byte.
+    if bits >= 6 || acc != 0 {
+        return None;
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_every_length() {
+        let data: Vec<u8> = (0..=255).collect();
+        for len in 0..40 {
+            let encoded = encode_unpadded(&data[..len]);
+            assert!(!encoded.ends_with('='));
+            assert_eq!(decode(&encoded).unwrap(), &data[..len]);
+        }
+        assert_eq!(encode_unpadded(b"foobar"), "Zm9vYmFy");
+        assert_eq!(encode_unpadded(b"fooba"), "Zm9vYmE");
+        assert_eq!(decode("Zm9vYmE=").unwrap(), b"fooba");
+    }
+}
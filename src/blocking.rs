@@ -0,0 +1,81 @@
+use std::io;
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    runtime::Handle,
+};
+
+use crate::stream::NoiseStream;
+
+/// A blocking wrapper around a [`NoiseStream`], implementing [`std::io::Read`] and
+/// [`std::io::Write`] for libraries which only accept synchronous IO. Requires the
+/// `blocking` feature.
+///
+/// Each read, write and flush blocks the calling thread on the given runtime handle
+/// until the operation completes. This has some pitfalls:
+///
+/// - It must not be used from within an async context, such as a task spawned on a
+///   tokio runtime, where blocking would stall the runtime. Tokio panics if it detects
+///   this. Call it from a plain thread, or from [`tokio::task::spawn_blocking`].
+/// - With a current-thread runtime, the handle can't drive the runtime's IO and timer
+///   drivers, so a TCP transport will hang unless another thread is running
+///   [`Runtime::block_on`][tokio::runtime::Runtime::block_on] on that runtime. A
+///   multi-threaded runtime doesn't have this problem.
+/// - Unlike [`NoiseStream::send`], a write doesn't flush, so call
+///   [`flush`][io::Write::flush] before waiting on a response.
+///
+/// ```no_run
+/// # fn example(noise_stream: tokio_noise::NoiseTcpStream, handle: tokio::runtime::Handle) -> std::io::Result<()> {
+/// use std::io::{BufRead, BufReader, Write};
+/// use tokio_noise::SyncNoiseStream;
+///
+/// let mut stream = BufReader::new(SyncNoiseStream::new(noise_stream, handle));
+/// stream.get_mut().write_all(b"GET / HTTP/1.0\r\n\r\n")?;
+/// stream.get_mut().flush()?;
+/// let mut status_line = String::new();
+/// stream.read_line(&mut status_line)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct SyncNoiseStream<S: AsyncRead + AsyncWrite + Unpin> {
+    stream: NoiseStream<S>,
+    handle: Handle,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> SyncNoiseStream<S> {
+    /// Wraps the stream, running its operations on the runtime with the given handle.
+    pub fn new(stream: NoiseStream<S>, handle: Handle) -> SyncNoiseStream<S> {
+        SyncNoiseStream { stream, handle }
+    }
+
+    /// Returns a reference to the wrapped stream.
+    pub fn get_ref(&self) -> &NoiseStream<S> {
+        &self.stream
+    }
+
+    /// Returns a mutable reference to the wrapped stream.
+    pub fn get_mut(&mut self) -> &mut NoiseStream<S> {
+        &mut self.stream
+    }
+
+    /// Unwraps the stream, for use from async code again.
+    pub fn into_inner(self) -> NoiseStream<S> {
+        self.stream
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> io::Read for SyncNoiseStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.handle.block_on(self.stream.read(buf))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> io::Write for SyncNoiseStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.handle.block_on(self.stream.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.handle.block_on(self.stream.flush())
+    }
+}
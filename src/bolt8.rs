@@ -0,0 +1,648 @@
+//! This module encapsulates [`Bolt8Stream`], which speaks the Lightning Network's
+//! transport protocol. Requires the `bolt8` feature.
+
+use std::fmt;
+
+use ::secp256k1::{ecdh::SharedSecret, PublicKey, Secp256k1, SecretKey};
+use snow::{
+    params::{CipherChoice, HashChoice},
+    types::{Cipher, Hash},
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::{
+    errors::{HandshakeError, NoiseError},
+    handshakes::backend_resolver,
+    transport::Transport,
+};
+
+/// The protocol name which BOLT 8 mixes into the handshake hash.
+pub const BOLT8_PROTOCOL_NAME: &str = "Noise_XK_secp256k1_ChaChaPoly_SHA256";
+
+/// The prologue which BOLT 8 mixes into the handshake hash.
+pub const BOLT8_PROLOGUE: &[u8] = b"lightning";
+
+/// The longest message a [`Bolt8Stream`] can send or receive, as its length is encrypted
+/// in two bytes.
+pub const BOLT8_MAX_MESSAGE_LEN: usize = u16::MAX as usize;
+
+/// The number of encryptions after which each direction's key is rotated. Every message
+/// takes two, one for its length and one for its body.
+pub const BOLT8_KEY_ROTATION_INTERVAL: u64 = 1000;
+
+/// The handshake version which prefixes each act.
+const HANDSHAKE_VERSION: u8 = 0;
+
+const ACT_ONE_LEN: usize = 50;
+const ACT_TWO_LEN: usize = 50;
+const ACT_THREE_LEN: usize = 66;
+
+const TAG_LEN: usize = 16;
+
+/// The size of the encrypted length which precedes each message's body.
+const LENGTH_HEADER_LEN: usize = 2 + TAG_LEN;
+
+const PUBLIC_KEY_LEN: usize = 33;
+
+type Key = [u8; 32];
+
+fn sha256() -> Box<dyn Hash> {
+    backend_resolver()
+        .resolve_hash(&HashChoice::SHA256)
+        .expect("the resolver provides SHA-256")
+}
+
+fn chacha_poly(key: &Key) -> Box<dyn Cipher> {
+    let mut cipher = backend_resolver()
+        .resolve_cipher(&CipherChoice::ChaChaPoly)
+        .expect("the resolver provides ChaChaPoly");
+    cipher.set(key);
+    cipher
+}
+
+/// HKDF with the given salt, splitting the output into two keys.
+fn hkdf(hash: &mut dyn Hash, salt: &Key, input_key_material: &[u8]) -> (Key, Key) {
+    let (mut first, mut second) = ([0u8; 32], [0u8; 32]);
+    hash.hkdf(
+        salt,
+        input_key_material,
+        2,
+        &mut first,
+        &mut second,
+        &mut [],
+    );
+    (first, second)
+}
+
+/// BOLT 8's ECDH: the SHA-256 hash of the compressed shared point.
+fn ecdh(public: &PublicKey, secret: &SecretKey) -> Key {
+    SharedSecret::new(public, secret).secret_bytes()
+}
+
+fn handshake_error(description: &str) -> NoiseError {
+    NoiseError::Handshake(HandshakeError {
+        description: description.to_string(),
+        handshake_pattern: "XK".to_string(),
+    })
+}
+
+fn parse_public_key(bytes: &[u8]) -> Result<PublicKey, NoiseError> {
+    PublicKey::from_slice(bytes).map_err(|_| handshake_error("peer sent an invalid public key"))
+}
+
+fn generate_ephemeral() -> SecretKey {
+    let mut rng = backend_resolver()
+        .resolve_rng()
+        .expect("the resolver provides a generator");
+    // Almost every 32-byte string is a valid secret key.
+    let mut bytes = [0u8; 32];
+    loop {
+        rng.fill_bytes(&mut bytes);
+        if let Ok(key) = SecretKey::from_slice(&bytes) {
+            return key;
+        }
+    }
+}
+
+/// The state of a BOLT 8 handshake, from either side, as the acts are exchanged.
+struct HandshakeState {
+    hash: Box<dyn Hash>,
+    ck: Key,
+    h: Key,
+    local: SecretKey,
+    ephemeral: SecretKey,
+    remote: Option<PublicKey>,
+    remote_ephemeral: Option<PublicKey>,
+    /// The temporary key from the latest act, which after act two also encrypts the
+    /// initiator's static key in act three.
+    temp_k: Key,
+}
+
+impl HandshakeState {
+    /// Start a handshake in which `responder` is the responder's static key.
+    fn new(
+        local: SecretKey,
+        ephemeral: SecretKey,
+        responder: &PublicKey,
+        remote: Option<PublicKey>,
+    ) -> HandshakeState {
+        let mut hash = sha256();
+        let mut h = [0u8; 32];
+        hash.reset();
+        hash.input(BOLT8_PROTOCOL_NAME.as_bytes());
+        hash.result(&mut h);
+        let mut state = HandshakeState {
+            hash,
+            ck: h,
+            h,
+            local,
+            ephemeral,
+            remote,
+            remote_ephemeral: None,
+            temp_k: [0; 32],
+        };
+        state.mix_hash(BOLT8_PROLOGUE);
+        state.mix_hash(&responder.serialize());
+        state
+    }
+
+    fn initiator(local: SecretKey, ephemeral: SecretKey, remote: PublicKey) -> HandshakeState {
+        HandshakeState::new(local, ephemeral, &remote, Some(remote))
+    }
+
+    fn responder(local: SecretKey, ephemeral: SecretKey) -> HandshakeState {
+        let public = PublicKey::from_secret_key(&Secp256k1::signing_only(), &local);
+        HandshakeState::new(local, ephemeral, &public, None)
+    }
+
+    fn mix_hash(&mut self, data: &[u8]) {
+        self.hash.reset();
+        self.hash.input(&self.h);
+        self.hash.input(data);
+        self.hash.result(&mut self.h);
+    }
+
+    /// Mix a DH output into the chaining key, returning a temporary key.
+    fn mix_key(&mut self, dh: &Key) -> Key {
+        let (ck, temp_k) = hkdf(&mut *self.hash, &self.ck, dh);
+        self.ck = ck;
+        temp_k
+    }
+
+    /// Encrypt `plaintext` with the handshake hash as associated data, then mix the
+    /// ciphertext into the hash.
+    fn encrypt_and_hash(&mut self, key: &Key, nonce: u64, plaintext: &[u8], out: &mut [u8]) {
+        let n = chacha_poly(key).encrypt(nonce, &self.h, plaintext, out);
+        self.mix_hash(&out[..n]);
+    }
+
+    fn decrypt_and_hash(
+        &mut self,
+        key: &Key,
+        nonce: u64,
+        ciphertext: &[u8],
+        out: &mut [u8],
+    ) -> Result<(), NoiseError> {
+        chacha_poly(key).decrypt(nonce, &self.h, ciphertext, out)?;
+        self.mix_hash(ciphertext);
+        Ok(())
+    }
+
+    fn ephemeral_public(&self) -> [u8; PUBLIC_KEY_LEN] {
+        PublicKey::from_secret_key(&Secp256k1::signing_only(), &self.ephemeral).serialize()
+    }
+
+    /// Write an act which carries our ephemeral key, and mix in the DH of that key with
+    /// the peer's `remote` key.
+    fn write_ephemeral_act(&mut self, remote: &PublicKey) -> [u8; ACT_ONE_LEN] {
+        let mut act = [0u8; ACT_ONE_LEN];
+        act[0] = HANDSHAKE_VERSION;
+        let ephemeral = self.ephemeral_public();
+        act[1..34].copy_from_slice(&ephemeral);
+        self.mix_hash(&ephemeral);
+        let temp_k = self.mix_key(&ecdh(remote, &self.ephemeral));
+        self.encrypt_and_hash(&temp_k, 0, &[], &mut act[34..]);
+        self.temp_k = temp_k;
+        act
+    }
+
+    /// Read an act which carries the peer's ephemeral key, and mix in the DH of that key
+    /// with our `local` key.
+    fn read_ephemeral_act(
+        &mut self,
+        act: &[u8; ACT_ONE_LEN],
+        local: SecretKey,
+    ) -> Result<(), NoiseError> {
+        if act[0] != HANDSHAKE_VERSION {
+            return Err(handshake_error("unsupported handshake version"));
+        }
+        let remote_ephemeral = parse_public_key(&act[1..34])?;
+        self.mix_hash(&act[1..34]);
+        let temp_k = self.mix_key(&ecdh(&remote_ephemeral, &local));
+        self.decrypt_and_hash(&temp_k, 0, &act[34..], &mut [0u8; TAG_LEN])?;
+        self.temp_k = temp_k;
+        self.remote_ephemeral = Some(remote_ephemeral);
+        Ok(())
+    }
+
+    /// Act one, from the initiator: its ephemeral key, DH'd with the responder's static.
+    fn write_act_one(&mut self) -> [u8; ACT_ONE_LEN] {
+        let remote = self
+            .remote
+            .expect("the initiator knows the responder's key");
+        self.write_ephemeral_act(&remote)
+    }
+
+    fn read_act_one(&mut self, act: &[u8; ACT_ONE_LEN]) -> Result<(), NoiseError> {
+        self.read_ephemeral_act(act, self.local)
+    }
+
+    /// Act two, from the responder: its ephemeral key, DH'd with the initiator's.
+    fn write_act_two(&mut self) -> [u8; ACT_TWO_LEN] {
+        let remote_ephemeral = self.remote_ephemeral.expect("act one was read");
+        self.write_ephemeral_act(&remote_ephemeral)
+    }
+
+    fn read_act_two(&mut self, act: &[u8; ACT_TWO_LEN]) -> Result<(), NoiseError> {
+        self.read_ephemeral_act(act, self.ephemeral)
+    }
+
+    /// Act three, from the initiator: its static key, encrypted, and the DH of that key
+    /// with the responder's ephemeral.
+    fn write_act_three(mut self) -> ([u8; ACT_THREE_LEN], TransportKeys) {
+        let mut act = [0u8; ACT_THREE_LEN];
+        act[0] = HANDSHAKE_VERSION;
+        let local = PublicKey::from_secret_key(&Secp256k1::signing_only(), &self.local);
+        let temp_k2 = self.temp_k;
+        self.encrypt_and_hash(&temp_k2, 1, &local.serialize(), &mut act[1..50]);
+        let remote_ephemeral = self.remote_ephemeral.expect("act two was read");
+        let temp_k3 = self.mix_key(&ecdh(&remote_ephemeral, &self.local));
+        self.encrypt_and_hash(&temp_k3, 0, &[], &mut act[50..]);
+        let remote = self
+            .remote
+            .expect("the initiator knows the responder's key");
+        let keys = self.split(true, remote);
+        (act, keys)
+    }
+
+    fn read_act_three(mut self, act: &[u8; ACT_THREE_LEN]) -> Result<TransportKeys, NoiseError> {
+        if act[0] != HANDSHAKE_VERSION {
+            return Err(handshake_error("unsupported handshake version"));
+        }
+        let mut remote = [0u8; PUBLIC_KEY_LEN + TAG_LEN];
+        let temp_k2 = self.temp_k;
+        self.decrypt_and_hash(&temp_k2, 1, &act[1..50], &mut remote)?;
+        let remote = parse_public_key(&remote[..PUBLIC_KEY_LEN])?;
+        let temp_k3 = self.mix_key(&ecdh(&remote, &self.ephemeral));
+        self.decrypt_and_hash(&temp_k3, 0, &act[50..], &mut [0u8; TAG_LEN])?;
+        Ok(self.split(false, remote))
+    }
+
+    fn split(mut self, initiator: bool, remote: PublicKey) -> TransportKeys {
+        let (initiator_key, responder_key) = hkdf(&mut *self.hash, &self.ck, &[]);
+        let (sending, receiving) = match initiator {
+            true => (initiator_key, responder_key),
+            false => (responder_key, initiator_key),
+        };
+        TransportKeys {
+            sending: MessageCipher::new(self.ck, sending),
+            receiving: MessageCipher::new(self.ck, receiving),
+            remote,
+        }
+    }
+}
+
+/// The ciphers for each direction, and the peer's static key, once a handshake is done.
+struct TransportKeys {
+    sending: MessageCipher,
+    receiving: MessageCipher,
+    remote: PublicKey,
+}
+
+/// One direction of a BOLT 8 transport, which rotates its key every
+/// [`BOLT8_KEY_ROTATION_INTERVAL`] encryptions.
+struct MessageCipher {
+    hash: Box<dyn Hash>,
+    cipher: Box<dyn Cipher>,
+    ck: Key,
+    key: Key,
+    nonce: u64,
+}
+
+impl MessageCipher {
+    fn new(ck: Key, key: Key) -> MessageCipher {
+        MessageCipher {
+            hash: sha256(),
+            cipher: chacha_poly(&key),
+            ck,
+            key,
+            nonce: 0,
+        }
+    }
+
+    /// Advance the nonce after an encryption or decryption, rotating the key if it is due.
+    fn advance(&mut self) {
+        self.nonce += 1;
+        if self.nonce == BOLT8_KEY_ROTATION_INTERVAL {
+            let (ck, key) = hkdf(&mut *self.hash, &self.ck, &self.key);
+            self.ck = ck;
+            self.key = key;
+            self.cipher.set(&key);
+            self.nonce = 0;
+        }
+    }
+
+    /// Append the encryption of `plaintext` to `out`.
+    fn encrypt(&mut self, plaintext: &[u8], out: &mut Vec<u8>) {
+        let start = out.len();
+        out.resize(start + plaintext.len() + TAG_LEN, 0);
+        self.cipher
+            .encrypt(self.nonce, &[], plaintext, &mut out[start..]);
+        self.advance();
+    }
+
+    /// Decrypt `ciphertext` into `out`, returning the plaintext length.
+    fn decrypt(&mut self, ciphertext: &[u8], out: &mut [u8]) -> Result<usize, NoiseError> {
+        let n = self.cipher.decrypt(self.nonce, &[], ciphertext, out)?;
+        self.advance();
+        Ok(n)
+    }
+
+    /// Encrypt a whole message, with its length, for the wire.
+    fn encrypt_message(&mut self, message: &[u8], out: &mut Vec<u8>) -> Result<(), NoiseError> {
+        if message.len() > BOLT8_MAX_MESSAGE_LEN {
+            return Err(NoiseError::MessageTooLarge {
+                len: message.len(),
+                max: BOLT8_MAX_MESSAGE_LEN,
+            });
+        }
+        self.encrypt(&(message.len() as u16).to_be_bytes(), out);
+        self.encrypt(message, out);
+        Ok(())
+    }
+}
+
+/// A connection using the Lightning Network's transport protocol, as specified by
+/// [BOLT 8](https://github.com/lightning/bolts/blob/master/08-transport.md). Available
+/// with the `bolt8` feature.
+///
+/// BOLT 8 fixes its own handshake framing and message format, so this is separate from
+/// [`NoiseStream`][crate::NoiseStream], and interoperates with Lightning nodes rather
+/// than with other peers using this crate. Its `Noise_XK` handshake runs over secp256k1,
+/// with each side identified by its node key. The initiator must know the responder's
+/// node key in advance, and the responder learns the initiator's from the handshake.
+///
+/// Messages are sent whole, with an encrypted two-byte length, so each may be up to
+/// [`BOLT8_MAX_MESSAGE_LEN`] bytes. Each direction's key is rotated every
+/// [`BOLT8_KEY_ROTATION_INTERVAL`] encryptions, as the specification requires.
+///
+/// [`recv_message`][Self::recv_message] is not cancellation safe: if its future is
+/// dropped partway through a message, the stream is no longer usable.
+///
+/// ```no_run
+/// # async fn example(
+/// #     tcp_stream: tokio::net::TcpStream,
+/// #     node_key: tokio_noise::secp256k1::SecretKey,
+/// #     peer_id: tokio_noise::secp256k1::PublicKey,
+/// # ) -> Result<(), tokio_noise::NoiseError> {
+/// use tokio_noise::Bolt8Stream;
+///
+/// let mut stream = Bolt8Stream::handshake_initiator(tcp_stream, &node_key, &peer_id).await?;
+/// let init = stream.recv_message().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Bolt8Stream<S> {
+    transport: S,
+    sending: MessageCipher,
+    receiving: MessageCipher,
+    remote_node_id: PublicKey,
+    write_buf: Vec<u8>,
+}
+
+impl<S: Transport> Bolt8Stream<S> {
+    /// Conduct the BOLT 8 handshake as the initiator, with our node key `local`, to the
+    /// node whose key is `remote`.
+    pub async fn handshake_initiator(
+        mut socket: S,
+        local: &SecretKey,
+        remote: &PublicKey,
+    ) -> Result<Bolt8Stream<S>, NoiseError> {
+        let mut state = HandshakeState::initiator(*local, generate_ephemeral(), *remote);
+        socket.write_all(&state.write_act_one()).await?;
+        let mut act_two = [0u8; ACT_TWO_LEN];
+        socket.read_exact(&mut act_two).await?;
+        state.read_act_two(&act_two)?;
+        let (act_three, keys) = state.write_act_three();
+        socket.write_all(&act_three).await?;
+        socket.flush().await?;
+        Ok(Bolt8Stream::new(socket, keys))
+    }
+
+    /// Conduct the BOLT 8 handshake as the responder, with our node key `local`. The
+    /// initiator's node key is then available from
+    /// [`remote_node_id`][Self::remote_node_id].
+    pub async fn handshake_responder(
+        mut socket: S,
+        local: &SecretKey,
+    ) -> Result<Bolt8Stream<S>, NoiseError> {
+        let mut state = HandshakeState::responder(*local, generate_ephemeral());
+        let mut act_one = [0u8; ACT_ONE_LEN];
+        socket.read_exact(&mut act_one).await?;
+        state.read_act_one(&act_one)?;
+        socket.write_all(&state.write_act_two()).await?;
+        socket.flush().await?;
+        let mut act_three = [0u8; ACT_THREE_LEN];
+        socket.read_exact(&mut act_three).await?;
+        let keys = state.read_act_three(&act_three)?;
+        Ok(Bolt8Stream::new(socket, keys))
+    }
+
+    fn new(transport: S, keys: TransportKeys) -> Bolt8Stream<S> {
+        Bolt8Stream {
+            transport,
+            sending: keys.sending,
+            receiving: keys.receiving,
+            remote_node_id: keys.remote,
+            write_buf: Vec::new(),
+        }
+    }
+
+    /// Returns the node key of the peer.
+    pub fn remote_node_id(&self) -> PublicKey {
+        self.remote_node_id
+    }
+
+    /// Encrypt and send a message, and flush the transport. Fails with
+    /// [`NoiseError::MessageTooLarge`] if the message is longer than
+    /// [`BOLT8_MAX_MESSAGE_LEN`].
+    pub async fn send_message(&mut self, message: &[u8]) -> Result<(), NoiseError> {
+        self.write_buf.clear();
+        self.sending.encrypt_message(message, &mut self.write_buf)?;
+        self.transport.write_all(&self.write_buf).await?;
+        self.transport.flush().await?;
+        Ok(())
+    }
+
+    /// Receive and decrypt the next message.
+    pub async fn recv_message(&mut self) -> Result<Vec<u8>, NoiseError> {
+        let mut header = [0u8; LENGTH_HEADER_LEN];
+        self.transport.read_exact(&mut header).await?;
+        let mut len = [0u8; LENGTH_HEADER_LEN];
+        self.receiving.decrypt(&header, &mut len)?;
+        let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+
+        let mut body = vec![0u8; len + TAG_LEN];
+        self.transport.read_exact(&mut body).await?;
+        let mut message = vec![0u8; len + TAG_LEN];
+        let n = self.receiving.decrypt(&body, &mut message)?;
+        message.truncate(n);
+        Ok(message)
+    }
+
+    /// Returns a reference to the underlying transport.
+    pub fn get_ref(&self) -> &S {
+        &self.transport
+    }
+
+    /// Returns a mutable reference to the underlying transport. Reading or writing it
+    /// directly will corrupt the stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.transport
+    }
+}
+
+impl<S> fmt::Debug for Bolt8Stream<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The keys are left out, so they don't end up in logs.
+        f.debug_struct("Bolt8Stream")
+            .field("remote_node_id", &self.remote_node_id)
+            .field("sending_nonce", &self.sending.nonce)
+            .field("receiving_nonce", &self.receiving.nonce)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn secret(byte: u8) -> SecretKey {
+        SecretKey::from_slice(&[byte; 32]).unwrap()
+    }
+
+    fn public(byte: u8) -> PublicKey {
+        PublicKey::from_secret_key(&Secp256k1::signing_only(), &secret(byte))
+    }
+
+    // The test vectors from BOLT 8, Appendix A.
+    const ACT_ONE: &str = "00036360e856310ce5d294e8be33fc807077dc56ac80d95d9cd4ddbd21325eff73f70df6086551151f58b8afe6c195782c6a";
+    const ACT_TWO: &str = "0002466d7fcae563e5cb09a0d1870bb580344804617879a14949cf22285f1bae3f276e2470b93aac583c9ef6eafca3f730ae";
+    const ACT_THREE: &str = "00b9e3a702e93e3a9948c2ed6e5fd7590a6e1c3a0344cfc9d5b57357049aa22355361aa02e55a8fc28fef5bd6d71ad0c38228dc68b1c466263b47fdf31e560e139ba";
+    const INITIATOR_SK: &str = "969ab31b4d288cedf6218839b27a3e2140827047f2c0f01bf5c04435d43511a9";
+    const INITIATOR_RK: &str = "bb9020b8965f4df047e07f955f3c4b88418984aadc5cdb35096b9ea8fa5c3442";
+    const CK: &str = "919219dbb2920afa8db80f9a51787a840bcf111ed8d588caf9ab4be716e42b01";
+
+    fn act<const N: usize>(hex: &str) -> [u8; N] {
+        from_hex(hex).try_into().unwrap()
+    }
+
+    #[test]
+    fn initiator_matches_test_vectors() {
+        let mut state = HandshakeState::initiator(secret(0x11), secret(0x12), public(0x21));
+        assert_eq!(state.write_act_one(), act(ACT_ONE));
+        state.read_act_two(&act(ACT_TWO)).unwrap();
+        let (act_three, keys) = state.write_act_three();
+        assert_eq!(act_three, act(ACT_THREE));
+        assert_eq!(keys.sending.key[..], from_hex(INITIATOR_SK));
+        assert_eq!(keys.receiving.key[..], from_hex(INITIATOR_RK));
+        assert_eq!(keys.sending.ck[..], from_hex(CK));
+        assert_eq!(keys.remote, public(0x21));
+    }
+
+    #[test]
+    fn responder_matches_test_vectors() {
+        let mut state = HandshakeState::responder(secret(0x21), secret(0x22));
+        state.read_act_one(&act(ACT_ONE)).unwrap();
+        assert_eq!(state.write_act_two(), act(ACT_TWO));
+        let keys = state.read_act_three(&act(ACT_THREE)).unwrap();
+        assert_eq!(keys.sending.key[..], from_hex(INITIATOR_RK));
+        assert_eq!(keys.receiving.key[..], from_hex(INITIATOR_SK));
+        assert_eq!(keys.remote, public(0x11));
+    }
+
+    #[test]
+    fn responder_rejects_bad_acts() {
+        let mut bad_version = act::<ACT_ONE_LEN>(ACT_ONE);
+        bad_version[0] = 1;
+        let mut bad_key = act::<ACT_ONE_LEN>(ACT_ONE);
+        bad_key[1] = 4;
+        let mut bad_tag = act::<ACT_ONE_LEN>(ACT_ONE);
+        bad_tag[ACT_ONE_LEN - 1] ^= 1;
+        for act_one in [bad_version, bad_key, bad_tag] {
+            let mut state = HandshakeState::responder(secret(0x21), secret(0x22));
+            assert!(state.read_act_one(&act_one).is_err());
+        }
+
+        let mut state = HandshakeState::responder(secret(0x21), secret(0x22));
+        state.read_act_one(&act(ACT_ONE)).unwrap();
+        state.write_act_two();
+        let mut bad_tag = act::<ACT_THREE_LEN>(ACT_THREE);
+        bad_tag[ACT_THREE_LEN - 1] ^= 1;
+        assert!(matches!(
+            state.read_act_three(&bad_tag),
+            Err(NoiseError::Snow(snow::Error::Decrypt))
+        ));
+    }
+
+    #[test]
+    fn messages_match_test_vectors() {
+        let ck: Key = act(CK);
+        let mut sending = MessageCipher::new(ck, act(INITIATOR_SK));
+        let mut receiving = MessageCipher::new(ck, act(INITIATOR_SK));
+        let expected = [
+            (
+                0,
+                "cf2b30ddf0cf3f80e7c35a6e6730b59fe802473180f396d88a8fb0db8cbcf25d2f214cf9ea1d95",
+            ),
+            (
+                1,
+                "72887022101f0b6753e0c7de21657d35a4cb2a1f5cde2650528bbc8f837d0f0d7ad833b1a256a1",
+            ),
+            (
+                500,
+                "178cb9d7387190fa34db9c2d50027d21793c9bc2d40b1e14dcf30ebeeeb220f48364f7a4c68bf8",
+            ),
+            (
+                501,
+                "1b186c57d44eb6de4c057c49940d79bb838a145cb528d6e8fd26dbe50a60ca2c104b56b60e45bd",
+            ),
+            (
+                1000,
+                "4a2f3cc3b5e78ddb83dcb426d9863d9d9a723b0337c89dd0b005d89f8d3c05c52b76b29b740f09",
+            ),
+            (
+                1001,
+                "2ecd8c8a5629d0d02ab457a0fdd0f7b90a192cd46be5ecb6ca570bfc5e268338b1a16cf4ef2d36",
+            ),
+        ];
+        let mut expected = expected.iter().peekable();
+        for i in 0..=1001 {
+            let mut wire = Vec::new();
+            sending.encrypt_message(b"hello", &mut wire).unwrap();
+            if let Some((_, hex)) = expected.next_if(|(n, _)| *n == i) {
+                assert_eq!(wire, from_hex(hex), "message {}", i);
+            }
+
+            let mut len = [0u8; LENGTH_HEADER_LEN];
+            receiving
+                .decrypt(&wire[..LENGTH_HEADER_LEN], &mut len)
+                .unwrap();
+            assert_eq!(len[..2], [0, 5]);
+            let mut message = [0u8; 5 + TAG_LEN];
+            let n = receiving
+                .decrypt(&wire[LENGTH_HEADER_LEN..], &mut message)
+                .unwrap();
+            assert_eq!(&message[..n], b"hello");
+        }
+        assert!(expected.next().is_none());
+    }
+
+    #[test]
+    fn oversized_messages_are_rejected() {
+        let mut cipher = MessageCipher::new([0; 32], [0; 32]);
+        let message = vec![0u8; BOLT8_MAX_MESSAGE_LEN + 1];
+        assert!(matches!(
+            cipher.encrypt_message(&message, &mut Vec::new()),
+            Err(NoiseError::MessageTooLarge { .. })
+        ));
+        assert_eq!(cipher.nonce, 0);
+    }
+}
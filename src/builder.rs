@@ -0,0 +1,478 @@
+use log::warn;
+use std::{sync::Arc, time::Duration};
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+use crate::{
+    config::InterMessageHook,
+    errors::NoiseError,
+    events::{ConnectionEvents, EventsHook},
+    handshakes::Handshake,
+    log_context::LogContext,
+    stream::{Handshaked, NoiseStream, MAX_FRAME_SIZE, MIN_FRAME_SIZE},
+    tcp::NoiseTcpStream,
+    transport::Transport,
+};
+
+/// The default for [`NoiseBuilder::max_decrypt_failures`].
+pub const DEFAULT_MAX_DECRYPT_FAILURES: u32 = 1;
+
+/// The default for [`NoiseBuilder::write_high_watermark`].
+pub const DEFAULT_WRITE_HIGH_WATERMARK: usize = 64 * 1024;
+
+/// The default for [`NoiseBuilder::max_frames_per_poll`].
+pub const DEFAULT_MAX_FRAMES_PER_POLL: usize = 16;
+
+/// The default for [`NoiseBuilder::nodelay`].
+pub const DEFAULT_NODELAY: bool = true;
+
+/// Determines the size of the ciphertext frames a [`NoiseStream`] sends.
+///
+/// Each side chooses its own frame size, and declares it to the peer at the start of
+/// the stream. Every frame of data carries 19 bytes of overhead: a 16-byte
+/// authentication tag and a 3-byte header. The Noise nonce is implicit, and takes no
+/// space on the wire.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FrameSizing {
+    /// Send frames of [`MAX_FRAME_SIZE`] bytes, which has the lowest overhead.
+    #[default]
+    Max,
+    /// Send frames which fit within the given maximum segment size (MSS), so that each
+    /// frame can be carried by a single TCP segment, and the loss of one IP packet
+    /// delays at most the frames it carried.
+    ///
+    /// The resulting frame size is clamped between [`MIN_FRAME_SIZE`] and
+    /// [`MAX_FRAME_SIZE`].
+    FitMss(usize),
+    /// Like [`FitMss`][FrameSizing::FitMss], using the MSS reported by the transport
+    /// when the handshake completes. See [`Transport::max_segment_size`]. Falls back
+    /// to [`Max`][FrameSizing::Max] if the transport can't report its MSS.
+    Auto,
+}
+
+impl FrameSizing {
+    /// Resolve the size of each frame, given the transport's MSS if known.
+    pub(crate) fn frame_size(self, mss: Option<usize>) -> usize {
+        let fit = |mss: usize| mss.clamp(MIN_FRAME_SIZE, MAX_FRAME_SIZE);
+        match self {
+            FrameSizing::Max => MAX_FRAME_SIZE,
+            FrameSizing::FitMss(mss) => fit(mss),
+            FrameSizing::Auto => mss.map_or(MAX_FRAME_SIZE, fit),
+        }
+    }
+}
+
+/// Configures the behavior of a [`NoiseStream`], and conducts handshakes to create one.
+///
+/// ```no_run
+/// # async fn example(tcp_stream: tokio::net::TcpStream) -> Result<(), tokio_noise::NoiseError> {
+/// use tokio_noise::{handshakes::NNpsk0, NoiseBuilder};
+///
+/// let noise_stream = NoiseBuilder::new()
+///     .max_decrypt_failures(3)
+///     .handshake_initiator(tcp_stream, NNpsk0::try_new(&[0xFF; 32])?)
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct NoiseBuilder {
+    pub(crate) max_decrypt_failures: u32,
+    pub(crate) write_high_watermark: usize,
+    pub(crate) write_low_watermark: Option<usize>,
+    pub(crate) max_frames_per_poll: usize,
+    pub(crate) read_ahead: usize,
+    pub(crate) key_confirmation: bool,
+    pub(crate) frame_sizing: FrameSizing,
+    pub(crate) nodelay: bool,
+    pub(crate) sequence_numbers: bool,
+    pub(crate) strict_nonce: bool,
+    pub(crate) allow_unexpected_close: bool,
+    pub(crate) first_message_timeout: Option<Duration>,
+    pub(crate) log_context: LogContext,
+    pub(crate) events: Option<EventsHook>,
+}
+
+impl Default for NoiseBuilder {
+    fn default() -> Self {
+        NoiseBuilder {
+            max_decrypt_failures: DEFAULT_MAX_DECRYPT_FAILURES,
+            write_high_watermark: DEFAULT_WRITE_HIGH_WATERMARK,
+            write_low_watermark: None,
+            max_frames_per_poll: DEFAULT_MAX_FRAMES_PER_POLL,
+            read_ahead: 0,
+            key_confirmation: false,
+            frame_sizing: FrameSizing::default(),
+            nodelay: DEFAULT_NODELAY,
+            sequence_numbers: false,
+            strict_nonce: false,
+            allow_unexpected_close: false,
+            first_message_timeout: None,
+            log_context: LogContext::new(),
+            events: None,
+        }
+    }
+}
+
+impl NoiseBuilder {
+    /// Construct a builder with the default configuration.
+    pub fn new() -> NoiseBuilder {
+        NoiseBuilder::default()
+    }
+
+    /// Sets the number of consecutive received frames which may fail to decrypt before
+    /// the stream is poisoned. Once poisoned, all further reads and writes fail with
+    /// [`NoiseError::TooManyDecryptFailures`].
+    ///
+    /// TCP guarantees the integrity of the byte stream, so a frame which fails to decrypt
+    /// indicates tampering or a broken peer. The default is therefore
+    /// [`DEFAULT_MAX_DECRYPT_FAILURES`], which poisons the stream on the first failure.
+    /// Values below one are treated as one.
+    pub fn max_decrypt_failures(mut self, max_decrypt_failures: u32) -> NoiseBuilder {
+        self.max_decrypt_failures = max_decrypt_failures.max(1);
+        self
+    }
+
+    /// Sets the number of bytes of encrypted-but-unsent ciphertext the stream may buffer
+    /// when the socket can't keep up with writes. Once this many bytes are buffered,
+    /// `poll_write` returns `Pending` until the socket drains some of them, so a writer
+    /// on a stalled connection is slowed down rather than consuming unbounded memory.
+    /// It also bounds a single `poll_write`, which encrypts as many frames of the
+    /// caller's buffer as fit below it rather than just one.
+    ///
+    /// The buffer may exceed this limit by up to one frame. Flushing and shutting down
+    /// the stream always drain the whole buffer. A watermark of zero disables buffering
+    /// beyond the frame currently being written. Defaults to
+    /// [`DEFAULT_WRITE_HIGH_WATERMARK`].
+    pub fn write_high_watermark(mut self, write_high_watermark: usize) -> NoiseBuilder {
+        self.write_high_watermark = write_high_watermark;
+        self
+    }
+
+    /// Sets the number of buffered ciphertext bytes below which writes resume, once they
+    /// have hit the [high watermark][Self::write_high_watermark]. Until the socket drains
+    /// the buffer below this mark, `poll_write` keeps returning `Pending`, and the
+    /// writer is woken when it does. This spares a writer from waking for every few
+    /// bytes the socket accepts while it stays congested.
+    ///
+    /// A low watermark above the high watermark is treated as equal to it. Defaults to
+    /// the high watermark, so writes resume as soon as the buffer dips below it.
+    pub fn write_low_watermark(mut self, write_low_watermark: usize) -> NoiseBuilder {
+        self.write_low_watermark = Some(write_low_watermark);
+        self
+    }
+
+    /// The effective low watermark, which is never above the high watermark.
+    pub(crate) fn resolved_write_low_watermark(&self) -> usize {
+        self.write_low_watermark
+            .unwrap_or(usize::MAX)
+            .min(self.write_high_watermark)
+    }
+
+    /// Sets the maximum number of frames a single read may decrypt before yielding back to
+    /// the runtime, even if the output buffer has room and more data is available on the
+    /// socket. This prevents one busy stream from starving other tasks on the same thread.
+    ///
+    /// This bounds a single read, but a caller which reads in a loop only yields when the
+    /// transport runs dry or exhausts tokio's cooperative budget. With the `coop`
+    /// feature, every frame decrypted and every write also spends a unit of that budget,
+    /// so such a loop yields regularly however fast the peer sends.
+    ///
+    /// Values below one are treated as one. Defaults to [`DEFAULT_MAX_FRAMES_PER_POLL`].
+    pub fn max_frames_per_poll(mut self, max_frames_per_poll: usize) -> NoiseBuilder {
+        self.max_frames_per_poll = max_frames_per_poll.max(1);
+        self
+    }
+
+    /// Sets how many bytes of decrypted data a read may buffer ahead of the caller. Once
+    /// a read has filled the caller's buffer, it goes on to decrypt the frames which have
+    /// already arrived from the socket, until this many bytes are buffered, so that later
+    /// small reads are served from memory.
+    ///
+    /// This helps callers which make many small reads, such as line-based parsers, keep
+    /// up with large incoming frames. Frames decrypted ahead count towards
+    /// [`max_frames_per_poll`][Self::max_frames_per_poll], and the buffer may exceed the
+    /// limit by up to one frame. Defaults to zero, which disables read-ahead.
+    pub fn read_ahead(mut self, limit: usize) -> NoiseBuilder {
+        self.read_ahead = limit;
+        self
+    }
+
+    /// Sets whether the handshake ends with an explicit key confirmation, so that both
+    /// sides learn of mismatched keys, such as a wrong PSK, before the handshake returns.
+    ///
+    /// Without confirmation, the side which sends the final handshake message can't tell
+    /// whether the peer accepted it, and only finds out on its first read. With it, the
+    /// peer which received the final message replies straight away with its preamble
+    /// packet, and the handshake waits for that reply. This costs an extra half round
+    /// trip on one side. The preamble is sent with the first write anyway, so a peer
+    /// without this option enabled interoperates with one which has it, but the waiting
+    /// side then only finishes its handshake once that peer writes something. Don't
+    /// enable this only on the waiting side of a protocol where that side speaks first.
+    ///
+    /// One-way handshakes such as `N` are never confirmed, since the responder can't
+    /// reply. Defaults to false.
+    pub fn key_confirmation(mut self, key_confirmation: bool) -> NoiseBuilder {
+        self.key_confirmation = key_confirmation;
+        self
+    }
+
+    /// Sets whether each data frame carries an application sequence number, which the
+    /// receiver checks is one more than the last. A frame out of order fails the read
+    /// with [`NoiseError::SequenceGap`], and poisons the stream.
+    ///
+    /// The transport already rejects reordered frames within a session. Sequence numbers
+    /// are for continuity across sessions, such as when an application reconnects with a
+    /// fresh handshake, whose nonces start again from zero, and resumes numbering where
+    /// the last session left off with
+    /// [`NoiseStream::resume_sequences`][crate::NoiseStream::resume_sequences].
+    ///
+    /// Each frame's payload shrinks by [`SEQUENCE_NUMBER_SIZE`][crate::SEQUENCE_NUMBER_SIZE]
+    /// bytes. Both peers must enable this option. Each declares it in its preamble, and
+    /// a stream whose peer disagrees is poisoned on the first read. Defaults to false.
+    pub fn sequence_numbers(mut self, sequence_numbers: bool) -> NoiseBuilder {
+        self.sequence_numbers = sequence_numbers;
+        self
+    }
+
+    /// Sets whether every frame received must be encrypted with exactly the next
+    /// receiving nonce. By default, a frame is also accepted under any of the next
+    /// [`NONCE_JUMP_LIMIT`][crate::NONCE_JUMP_LIMIT] nonces, so that a peer can
+    /// [skip nonces][crate::NoiseStream::skip_sending_nonce] it may have used. That also
+    /// means up to that many frames removed from the stream go unnoticed.
+    ///
+    /// With strict nonces, a frame under a later nonce fails the read with
+    /// [`NoiseError::NonceMismatch`], and poisons the stream. Only enable this if the
+    /// peer never skips nonces. Defaults to false.
+    pub fn strict_nonce(mut self, strict_nonce: bool) -> NoiseBuilder {
+        self.strict_nonce = strict_nonce;
+        self
+    }
+
+    /// Sets whether a transport which closes without the peer sending a close notify
+    /// reads as a plain EOF. By default such a read fails with
+    /// [`NoiseError::UnexpectedClose`], because the peer may have crashed or the
+    /// connection dropped partway through what it meant to send, and an attacker can
+    /// truncate the stream by injecting a FIN. Enable this for protocols which detect
+    /// truncation themselves, or don't care about it. Defaults to false.
+    pub fn allow_unexpected_close(mut self, allow_unexpected_close: bool) -> NoiseBuilder {
+        self.allow_unexpected_close = allow_unexpected_close;
+        self
+    }
+
+    /// Sets how long a responder waits for the initiator's first handshake message before
+    /// failing with [`NoiseError::HandshakeTimeout`]. This turns away clients which
+    /// connect and then go silent, without limiting the rest of the handshake, which
+    /// may include slow work such as key lookups. Has no effect on initiators. By
+    /// default, there is no limit.
+    pub fn first_message_timeout(mut self, timeout: Duration) -> NoiseBuilder {
+        self.first_message_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the size of the ciphertext frames the stream sends. Smaller frames cost more
+    /// overhead per byte, but on lossy links a frame which fits within one TCP segment
+    /// avoids having a single lost packet stall two frames.
+    ///
+    /// Defaults to [`FrameSizing::Max`].
+    pub fn frame_sizing(mut self, frame_sizing: FrameSizing) -> NoiseBuilder {
+        self.frame_sizing = frame_sizing;
+        self
+    }
+
+    /// Sets whether Nagle's algorithm stays disabled on the transport (`TCP_NODELAY`
+    /// for TCP sockets) once the handshake completes.
+    ///
+    /// Nagle's algorithm is always disabled for the duration of the handshake, whose
+    /// small messages would otherwise each wait on a delayed ACK. Afterwards, if this is
+    /// false, the transport's previous setting is restored. Defaults to
+    /// [`DEFAULT_NODELAY`].
+    pub fn nodelay(mut self, nodelay: bool) -> NoiseBuilder {
+        self.nodelay = nodelay;
+        self
+    }
+
+    /// Sets key-value pairs which tag the log messages of each handshake conducted with
+    /// this builder and the stream it creates, such as a connection id. A stream's
+    /// pairs can be changed later with [`NoiseStream::set_log_context`]. See
+    /// [`LogContext`]. By default, there are none.
+    pub fn log_context(mut self, context: LogContext) -> NoiseBuilder {
+        self.log_context = context;
+        self
+    }
+
+    /// Sets a handler which is notified when each stream created by this builder fails
+    /// or closes, for telemetry without polling [`stats`][NoiseStream::stats]. The
+    /// handler is shared by all such streams. See [`ConnectionEvents`].
+    ///
+    /// ```
+    /// use tokio_noise::{CloseReason, ConnectionEvents, NoiseBuilder, NoiseError};
+    ///
+    /// struct Telemetry;
+    ///
+    /// impl ConnectionEvents for Telemetry {
+    ///     fn on_close(&self, stream: &str, reason: &CloseReason) {
+    ///         log::info!("{} closed: {:?}", stream, reason);
+    ///     }
+    ///
+    ///     fn on_error(&self, stream: &str, error: &NoiseError) {
+    ///         log::warn!("{} failed: {}", stream, error);
+    ///     }
+    /// }
+    ///
+    /// let builder = NoiseBuilder::new().events(Telemetry);
+    /// ```
+    pub fn events(mut self, events: impl ConnectionEvents + 'static) -> NoiseBuilder {
+        self.events = Some(EventsHook(Arc::new(events)));
+        self
+    }
+
+    /// Conduct a Noise handshake over the given transport as the initiator,
+    /// using a custom [`Handshake`] protocol.
+    pub async fn handshake_initiator<S: Transport>(
+        &self,
+        mut socket: S,
+        handshake: impl Handshake,
+    ) -> Result<NoiseStream<S>, NoiseError> {
+        let handshaked = self.run_initiator(&mut socket, handshake, None).await?;
+        self.establish("initiator".to_string(), socket, handshaked)
+            .await
+    }
+
+    /// Connect to the given address over TCP, and conduct a Noise handshake as the
+    /// initiator using a custom [`Handshake`] protocol.
+    ///
+    /// If the address resolves to several socket addresses, each is tried in turn until
+    /// one connects. If none does, this fails with [`NoiseError::Connect`], holding the
+    /// error from the last one. Errors from the handshake itself are returned as they
+    /// are from [`handshake_initiator`][Self::handshake_initiator].
+    pub async fn connect_with(
+        &self,
+        addr: impl ToSocketAddrs,
+        handshake: impl Handshake,
+    ) -> Result<NoiseTcpStream, NoiseError> {
+        let socket = TcpStream::connect(addr)
+            .await
+            .map_err(NoiseError::Connect)?;
+        self.handshake_initiator(socket, handshake).await
+    }
+
+    /// Conduct a Noise handshake over the given transport as the responder,
+    /// using a custom [`Handshake`] protocol.
+    pub async fn handshake_responder<S: Transport>(
+        &self,
+        mut socket: S,
+        handshake: impl Handshake,
+    ) -> Result<NoiseStream<S>, NoiseError> {
+        let handshaked = self.run_responder(&mut socket, handshake, None).await?;
+        self.establish("responder".to_string(), socket, handshaked)
+            .await
+    }
+
+    /// Resume a stream exported by
+    /// [`NoiseStream::export_session`][crate::NoiseStream::export_session] over the same
+    /// transport, with this configuration. Available with the `session-export` feature.
+    ///
+    /// The stream keeps the frame size it was exported with, since the peer has already
+    /// been told it, whatever the configured [`FrameSizing`].
+    #[cfg(feature = "session-export")]
+    pub fn import_session<S: Transport>(
+        &self,
+        socket: S,
+        snapshot: crate::SessionSnapshot,
+    ) -> Result<NoiseStream<S>, NoiseError> {
+        let peer_addr = socket.peer_addr();
+        let mut stream = NoiseStream::from_snapshot(socket, snapshot, self.clone())?;
+        stream.peer_addr = peer_addr;
+        Ok(stream)
+    }
+
+    /// Drives the initiator's side of a handshake over a borrowed socket, with Nagle's
+    /// algorithm disabled for its duration. The hook, if any, runs between messages.
+    pub(crate) async fn run_initiator<S: Transport>(
+        &self,
+        socket: &mut S,
+        handshake: impl Handshake,
+        hook: Option<&InterMessageHook>,
+    ) -> Result<Handshaked, NoiseError> {
+        let previous = begin_nodelay(socket);
+        let result = NoiseStream::run_initiator(socket, handshake, hook, &self.log_context).await;
+        self.end_nodelay(socket, previous);
+        result.map_err(|e| e.with_context("initiator", socket.peer_addr()))
+    }
+
+    /// Drives the responder's side of a handshake over a borrowed socket, with Nagle's
+    /// algorithm disabled for its duration. The hook, if any, runs between messages.
+    pub(crate) async fn run_responder<S: Transport>(
+        &self,
+        socket: &mut S,
+        handshake: impl Handshake,
+        hook: Option<&InterMessageHook>,
+    ) -> Result<Handshaked, NoiseError> {
+        let previous = begin_nodelay(socket);
+        let result = NoiseStream::run_responder(
+            socket,
+            handshake,
+            hook,
+            self.first_message_timeout,
+            &self.log_context,
+        )
+        .await;
+        self.end_nodelay(socket, previous);
+        result.map_err(|e| e.with_context("responder", socket.peer_addr()))
+    }
+
+    /// Restore the transport's previous `nodelay` setting after a handshake, unless
+    /// this builder keeps it enabled.
+    fn end_nodelay<S: Transport>(&self, socket: &S, previous: Option<bool>) {
+        if previous == Some(false) && !self.nodelay {
+            if let Err(e) = socket.set_nodelay(false) {
+                warn!("failed to restore nodelay after handshake: {}", e);
+            }
+        }
+    }
+
+    /// Assemble a stream from the outputs of a completed handshake, and confirm its keys
+    /// if enabled. Any cleartext which arrived with the final handshake message is
+    /// served to the first read.
+    pub(crate) async fn establish<S: Transport>(
+        &self,
+        name: String,
+        socket: S,
+        handshaked: Handshaked,
+    ) -> Result<NoiseStream<S>, NoiseError> {
+        let mss = match self.frame_sizing {
+            FrameSizing::Auto => socket.max_segment_size(),
+            _ => None,
+        };
+        let peer_addr = socket.peer_addr();
+        let mut stream = NoiseStream::from_parts(
+            name,
+            socket,
+            handshaked.state.into(),
+            Some(handshaked.info),
+            handshaked.read_overflow_buf,
+            self.clone(),
+            mss,
+        );
+        stream.peer_addr = peer_addr;
+        if self.key_confirmation {
+            stream
+                .confirm_keys(handshaked.sent_last_message)
+                .await
+                .map_err(|e| e.with_context(stream.name(), peer_addr))?;
+        }
+        Ok(stream)
+    }
+}
+
+/// Disable Nagle's algorithm on the transport for a handshake, returning the previous
+/// setting, or `None` if the transport doesn't use it.
+fn begin_nodelay<S: Transport>(socket: &S) -> Option<bool> {
+    let previous = socket.nodelay()?;
+    if !previous {
+        if let Err(e) = socket.set_nodelay(true) {
+            warn!("failed to set nodelay for handshake: {}", e);
+        }
+    }
+    Some(previous)
+}
@@ -0,0 +1,378 @@
+use log::debug;
+use snow::params::NoiseParams;
+use std::{
+    fmt,
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+#[cfg(unix)]
+use tokio::net::unix::UCred;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    time::Instant,
+};
+
+use crate::{
+    builder::NoiseBuilder, errors::NoiseError, fingerprint::Fingerprint, handshakes::Handshake,
+    stream::NoiseStream, transport::Transport,
+};
+
+/// A boxed future, as returned by the hook passed to
+/// [`HandshakeConfig::with_inter_message_hook`].
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+type HookFn = dyn FnMut() -> BoxFuture<'static, ()> + Send;
+
+/// A hook awaited between the messages of a handshake. Clones share the same hook.
+#[derive(Clone)]
+pub(crate) struct InterMessageHook(Arc<Mutex<Box<HookFn>>>);
+
+impl InterMessageHook {
+    /// Await the hook, if there is one.
+    pub(crate) async fn run(hook: Option<&InterMessageHook>) {
+        if let Some(InterMessageHook(hook)) = hook {
+            // The lock is released before awaiting, so concurrent handshakes sharing
+            // the hook don't wait on each other.
+            let future = (hook.lock().unwrap())();
+            future.await;
+        }
+    }
+}
+
+impl fmt::Debug for InterMessageHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("InterMessageHook")
+    }
+}
+
+/// The details of a successful handshake, as reported to
+/// [`HandshakeConfig::on_handshake`].
+#[derive(Clone, Debug)]
+pub struct HandshakeOutcome {
+    /// The peer's static public key, if the handshake pattern authenticated one.
+    pub remote_static_key: Option<Vec<u8>>,
+    /// The fingerprint of the peer's static public key.
+    pub peer_fingerprint: Option<Fingerprint>,
+    /// The Noise protocol name of the handshake.
+    pub protocol_name: String,
+    /// The negotiated protocol parameters.
+    pub params: NoiseParams,
+    /// The handshake hash, which is unique to the session.
+    pub handshake_hash: Vec<u8>,
+    /// When the handshake began.
+    pub started_at: SystemTime,
+    /// How long the handshake took, including any delay imposed by a listener's tarpit.
+    pub duration: Duration,
+}
+
+/// What is known about a peer before its handshake begins, as passed to
+/// [`HandshakeConfig::admit`].
+#[derive(Clone, Debug)]
+pub struct PeerInfo {
+    /// The address of the peer, if the transport has one. For a connection accepted by
+    /// a listener expecting a [PROXY protocol][crate::NoiseTcpListener::set_proxy_protocol]
+    /// header, this is the original client's address, if the header carries it.
+    pub addr: Option<SocketAddr>,
+    /// The credentials of the peer's process, if the transport is a Unix socket. Only
+    /// available on Unix.
+    #[cfg(unix)]
+    pub cred: Option<UCred>,
+}
+
+impl PeerInfo {
+    pub(crate) fn of<S: Transport>(socket: &S) -> PeerInfo {
+        PeerInfo {
+            addr: socket.peer_addr(),
+            #[cfg(unix)]
+            cred: socket.peer_cred(),
+        }
+    }
+}
+
+type AdmitFn = dyn Fn(&PeerInfo) -> bool + Send + Sync;
+
+/// A hook which decides whether to conduct a handshake with a peer. Clones share the
+/// same hook.
+#[derive(Clone)]
+struct AdmitHook(Arc<AdmitFn>);
+
+impl fmt::Debug for AdmitHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("AdmitHook")
+    }
+}
+
+type AuditFn = dyn Fn(Result<&HandshakeOutcome, &NoiseError>, Option<SocketAddr>) + Send + Sync;
+
+/// A hook called once with the result of each handshake. Clones share the same hook.
+#[derive(Clone)]
+pub(crate) struct AuditHook(Arc<AuditFn>);
+
+impl AuditHook {
+    /// Run a handshake, reporting its result to the hook, if there is one.
+    pub(crate) async fn audit<S: AsyncRead + AsyncWrite + Unpin>(
+        hook: Option<&AuditHook>,
+        peer_addr: Option<SocketAddr>,
+        handshake: impl Future<Output = Result<NoiseStream<S>, NoiseError>>,
+    ) -> Result<NoiseStream<S>, NoiseError> {
+        let Some(AuditHook(hook)) = hook else {
+            return handshake.await;
+        };
+        let (started_at, started) = (SystemTime::now(), Instant::now());
+        let result = handshake.await;
+        match &result {
+            Ok(stream) => {
+                let params = stream
+                    .params()
+                    .expect("handshaked streams have parameters")
+                    .clone();
+                let outcome = HandshakeOutcome {
+                    remote_static_key: stream.remote_static_key().map(<[u8]>::to_vec),
+                    peer_fingerprint: stream.peer_fingerprint(),
+                    protocol_name: params.name.clone(),
+                    params,
+                    handshake_hash: stream.handshake_hash().unwrap_or_default().to_vec(),
+                    started_at,
+                    duration: started.elapsed(),
+                };
+                hook(Ok(&outcome), peer_addr);
+            }
+            Err(e) => hook(Err(e), peer_addr),
+        }
+        result
+    }
+}
+
+impl fmt::Debug for AuditHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("AuditHook")
+    }
+}
+
+/// A reusable pairing of a [`Handshake`] protocol with the [`NoiseBuilder`] options used
+/// for the resulting streams.
+///
+/// A `HandshakeConfig` is configured once, typically at startup, and each connection
+/// conducts its handshake using a fresh clone of the prototype handshake. Because the
+/// built-in handshakes own their key material, a config has no lifetime parameters and
+/// can be stored in a server struct or shared between tasks.
+///
+/// ```no_run
+/// # async fn example(listener: tokio::net::TcpListener) -> Result<(), tokio_noise::NoiseError> {
+/// use tokio_noise::{handshakes::NNpsk0, HandshakeConfig, NoiseBuilder};
+///
+/// let config = HandshakeConfig::new(NNpsk0::try_new(&[0xFF; 32])?)
+///     .with_builder(NoiseBuilder::new().max_decrypt_failures(3));
+///
+/// loop {
+///     let (tcp_stream, _) = listener.accept().await?;
+///     let config = config.clone();
+///     tokio::spawn(async move {
+///         let noise_stream = config.respond(tcp_stream).await?;
+///         // ...
+///         # drop(noise_stream);
+///         Ok::<_, tokio_noise::NoiseError>(())
+///     });
+/// }
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct HandshakeConfig<H> {
+    handshake: H,
+    builder: NoiseBuilder,
+    hook: Option<InterMessageHook>,
+    audit: Option<AuditHook>,
+    admit: Option<AdmitHook>,
+}
+
+impl<H> HandshakeConfig<H> {
+    /// Construct a config which clones the given handshake for each connection, using
+    /// the default [`NoiseBuilder`] options.
+    pub fn new(handshake: H) -> HandshakeConfig<H> {
+        HandshakeConfig {
+            handshake,
+            builder: NoiseBuilder::default(),
+            hook: None,
+            audit: None,
+            admit: None,
+        }
+    }
+
+    /// Sets the options used for streams created with this config.
+    pub fn with_builder(mut self, builder: NoiseBuilder) -> HandshakeConfig<H> {
+        self.builder = builder;
+        self
+    }
+
+    /// Sets a hook which is awaited between each message of the handshake: after each
+    /// message is sent or received, and before the next. This can be used to pace
+    /// handshake messages on links which penalize back-to-back sends, or to probe the
+    /// path MTU.
+    ///
+    /// The hook only affects the timing of the handshake, not its messages. It is shared
+    /// by all clones of this config, and so by concurrent handshakes.
+    ///
+    /// ```no_run
+    /// # async fn example(tcp_stream: tokio::net::TcpStream) -> Result<(), tokio_noise::NoiseError> {
+    /// use std::time::Duration;
+    /// use tokio_noise::{handshakes::NNpsk0, HandshakeConfig};
+    ///
+    /// let config = HandshakeConfig::new(NNpsk0::try_new(&[0xFF; 32])?).with_inter_message_hook(|| {
+    ///     Box::pin(tokio::time::sleep(Duration::from_millis(20)))
+    /// });
+    /// let noise_stream = config.initiate(tcp_stream).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_inter_message_hook(
+        mut self,
+        hook: impl FnMut() -> BoxFuture<'static, ()> + Send + 'static,
+    ) -> HandshakeConfig<H> {
+        self.hook = Some(InterMessageHook(Arc::new(Mutex::new(Box::new(hook)))));
+        self
+    }
+
+    /// Sets a hook which is called exactly once for each handshake conducted with this
+    /// config, with the [`HandshakeOutcome`] if it succeeded or the error if it failed,
+    /// and the peer's address if the transport has one. This suits audit logging of
+    /// every authentication attempt.
+    ///
+    /// The hook is called from the task conducting the handshake, such as the
+    /// connection's own task when accepting with
+    /// [`IncomingConnection::handshake_with`][crate::IncomingConnection::handshake_with],
+    /// so a slow hook delays only that connection. It is shared by all clones of this
+    /// config.
+    ///
+    /// ```no_run
+    /// # async fn example(tcp_stream: tokio::net::TcpStream) -> Result<(), tokio_noise::NoiseError> {
+    /// use tokio_noise::{handshakes::NNpsk0, HandshakeConfig};
+    ///
+    /// let config = HandshakeConfig::new(NNpsk0::try_new(&[0xFF; 32])?).on_handshake(|result, addr| {
+    ///     match result {
+    ///         Ok(outcome) => log::info!("{:?} connected using {}", addr, outcome.protocol_name),
+    ///         Err(e) => log::warn!("{:?} failed to connect: {}", addr, e),
+    ///     }
+    /// });
+    /// let noise_stream = config.respond(tcp_stream).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn on_handshake(
+        mut self,
+        hook: impl Fn(Result<&HandshakeOutcome, &NoiseError>, Option<SocketAddr>)
+            + Send
+            + Sync
+            + 'static,
+    ) -> HandshakeConfig<H> {
+        self.audit = Some(AuditHook(Arc::new(hook)));
+        self
+    }
+
+    /// Sets a hook which decides, before any handshake message is exchanged, whether to
+    /// conduct the handshake with a peer at all. If it returns false, the handshake
+    /// fails with [`NoiseError::PeerRejected`] without doing any cryptography.
+    ///
+    /// On Unix sockets, the [`PeerInfo`] carries the peer process's credentials, so
+    /// that for example only processes of a given user may attempt a handshake. The hook
+    /// is shared by all clones of this config.
+    ///
+    /// ```no_run
+    /// # #[cfg(unix)]
+    /// # async fn example(unix_stream: tokio::net::UnixStream) -> Result<(), tokio_noise::NoiseError> {
+    /// use tokio_noise::{handshakes::NNpsk0, HandshakeConfig};
+    ///
+    /// let config = HandshakeConfig::new(NNpsk0::try_new(&[0xFF; 32])?)
+    ///     .admit(|peer| peer.cred.is_some_and(|cred| cred.uid() == 0));
+    /// let noise_stream = config.respond(unix_stream).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn admit(
+        mut self,
+        hook: impl Fn(&PeerInfo) -> bool + Send + Sync + 'static,
+    ) -> HandshakeConfig<H> {
+        self.admit = Some(AdmitHook(Arc::new(hook)));
+        self
+    }
+
+    /// Returns the prototype handshake which is cloned for each connection.
+    pub fn handshake(&self) -> &H {
+        &self.handshake
+    }
+
+    /// Returns the options used for streams created with this config.
+    pub fn builder(&self) -> &NoiseBuilder {
+        &self.builder
+    }
+
+    pub(crate) fn inter_message_hook(&self) -> Option<&InterMessageHook> {
+        self.hook.as_ref()
+    }
+
+    pub(crate) fn audit_hook(&self) -> Option<&AuditHook> {
+        self.audit.as_ref()
+    }
+
+    /// Ask the admission hook, if any, whether to conduct a handshake over the socket.
+    pub(crate) fn admit_peer<S: Transport>(&self, socket: &S) -> Result<(), NoiseError> {
+        self.admit_peer_info(|| PeerInfo::of(socket))
+    }
+
+    /// Run the admission hook, if there is one, on the peer described by `peer`.
+    pub(crate) fn admit_peer_info(
+        &self,
+        peer: impl FnOnce() -> PeerInfo,
+    ) -> Result<(), NoiseError> {
+        let Some(AdmitHook(hook)) = &self.admit else {
+            return Ok(());
+        };
+        let peer = peer();
+        if !hook(&peer) {
+            debug!("rejected peer {:?} before the handshake", peer);
+            return Err(NoiseError::PeerRejected);
+        }
+        Ok(())
+    }
+}
+
+impl<H: Handshake + Clone> HandshakeConfig<H> {
+    /// Conduct a Noise handshake over the given transport as the initiator, using a
+    /// clone of the configured handshake.
+    pub async fn initiate<S: Transport>(
+        &self,
+        mut socket: S,
+    ) -> Result<NoiseStream<S>, NoiseError> {
+        let peer_addr = socket.peer_addr();
+        AuditHook::audit(self.audit.as_ref(), peer_addr, async {
+            self.admit_peer(&socket)?;
+            let handshaked = self
+                .builder
+                .run_initiator(&mut socket, self.handshake.clone(), self.hook.as_ref())
+                .await?;
+            self.builder
+                .establish("initiator".to_string(), socket, handshaked)
+                .await
+        })
+        .await
+    }
+
+    /// Conduct a Noise handshake over the given transport as the responder, using a
+    /// clone of the configured handshake.
+    pub async fn respond<S: Transport>(&self, mut socket: S) -> Result<NoiseStream<S>, NoiseError> {
+        let peer_addr = socket.peer_addr();
+        AuditHook::audit(self.audit.as_ref(), peer_addr, async {
+            self.admit_peer(&socket)?;
+            let handshaked = self
+                .builder
+                .run_responder(&mut socket, self.handshake.clone(), self.hook.as_ref())
+                .await?;
+            self.builder
+                .establish("responder".to_string(), socket, handshaked)
+                .await
+        })
+        .await
+    }
+}
@@ -0,0 +1,434 @@
+use log::{debug, warn};
+use std::{fmt, future::Future, io, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    net::{TcpSocket, TcpStream},
+    time::Instant,
+};
+
+use crate::{
+    config::HandshakeConfig,
+    errors::NoiseError,
+    handshakes::Handshake,
+    resolver::{sealed::ConnectTarget, ConnectAddr, Resolver, SystemResolver},
+    stream::{with_deadline, NoiseStream},
+    tcp::NoiseTcpStream,
+    transport::Transport,
+};
+
+/// Accepts connections as the responder, with a [`HandshakeConfig`] set up once and
+/// shared by every connection.
+///
+/// The config is held behind an [`Arc`], so an acceptor is cheap to clone into each
+/// connection's task, and the futures returned by [`accept`][Self::accept] borrow
+/// nothing from it, so they can be spawned directly.
+///
+/// ```no_run
+/// # async fn example(listener: tokio::net::TcpListener) -> Result<(), tokio_noise::NoiseError> {
+/// use std::time::Duration;
+/// use tokio_noise::{handshakes::NNpsk0, HandshakeConfig, NoiseAcceptor};
+///
+/// let acceptor = NoiseAcceptor::new(HandshakeConfig::new(NNpsk0::try_new(&[0xFF; 32])?))
+///     .timeout(Duration::from_secs(10));
+/// loop {
+///     let (tcp_stream, _) = listener.accept().await?;
+///     let accept = acceptor.accept(tcp_stream);
+///     tokio::spawn(async move {
+///         let noise_stream = accept.await?;
+///         // ...
+///         # drop(noise_stream);
+///         Ok::<_, tokio_noise::NoiseError>(())
+///     });
+/// }
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct NoiseAcceptor<H> {
+    config: Arc<HandshakeConfig<H>>,
+    timeout: Option<Duration>,
+}
+
+/// Dials connections as the initiator, with a [`HandshakeConfig`] set up once and
+/// shared by every connection. Like a [`NoiseAcceptor`], it is cheap to clone.
+///
+/// Host names are looked up with a [`Resolver`], by default the [`SystemResolver`].
+///
+/// ```no_run
+/// # async fn example() -> Result<(), tokio_noise::NoiseError> {
+/// use tokio_noise::{handshakes::NNpsk0, HandshakeConfig, NoiseConnector};
+///
+/// let connector = NoiseConnector::new(HandshakeConfig::new(NNpsk0::try_new(&[0xFF; 32])?));
+/// let mut noise_stream = connector.connect("127.0.0.1:8080").await?;
+/// noise_stream.send(b"hello").await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct NoiseConnector<H> {
+    config: Arc<HandshakeConfig<H>>,
+    timeout: Option<Duration>,
+    handshake_retries: u32,
+    retry_backoff: RetryBackoff,
+    socket_options: SocketOptions,
+    resolver: Arc<dyn Resolver>,
+}
+
+type ConfigureSocketFn = dyn Fn(&TcpSocket) -> io::Result<()> + Send + Sync;
+
+/// How a [`NoiseConnector`] sets up each TCP socket before connecting it.
+#[derive(Clone, Default)]
+struct SocketOptions {
+    local_addr: Option<SocketAddr>,
+    bind_device: Option<String>,
+    configure: Option<Arc<ConfigureSocketFn>>,
+}
+
+impl fmt::Debug for SocketOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SocketOptions")
+            .field("local_addr", &self.local_addr)
+            .field("bind_device", &self.bind_device)
+            .field("configure", &self.configure.is_some())
+            .finish()
+    }
+}
+
+impl SocketOptions {
+    fn is_default(&self) -> bool {
+        self.local_addr.is_none() && self.bind_device.is_none() && self.configure.is_none()
+    }
+
+    /// Connect to the first of `addrs` which accepts, with a fresh socket for each.
+    async fn connect(&self, addrs: &[SocketAddr]) -> io::Result<TcpStream> {
+        if self.is_default() {
+            return TcpStream::connect(addrs).await;
+        }
+        let mut last_error = None;
+        for &addr in addrs {
+            match self.connect_one(addr).await {
+                Ok(stream) => return Ok(stream),
+                Err(e) => {
+                    debug!("failed to connect to {}: {}", addr, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not resolve to any address",
+            )
+        }))
+    }
+
+    async fn connect_one(&self, addr: SocketAddr) -> io::Result<TcpStream> {
+        let socket = match addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        if let Some(device) = &self.bind_device {
+            bind_device(&socket, device)?;
+        }
+        if let Some(local_addr) = self.local_addr {
+            if local_addr.is_ipv4() != addr.is_ipv4() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "local address {} can't reach {}, of another address family",
+                        local_addr, addr
+                    ),
+                ));
+            }
+            socket.bind(local_addr)?;
+        }
+        if let Some(configure) = &self.configure {
+            configure(&socket)?;
+        }
+        socket.connect(addr).await
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+fn bind_device(socket: &TcpSocket, device: &str) -> io::Result<()> {
+    socket.bind_device(Some(device.as_bytes()))
+}
+
+#[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+fn bind_device(_socket: &TcpSocket, _device: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "binding to a device is not supported on this platform",
+    ))
+}
+
+/// How long a [`NoiseConnector`] waits before each retry of a failed connection.
+///
+/// The delay starts at `initial`, and is doubled for each further retry up to `max`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryBackoff {
+    initial: Duration,
+    max: Duration,
+}
+
+impl RetryBackoff {
+    /// Wait the same delay before every retry.
+    pub fn fixed(delay: Duration) -> RetryBackoff {
+        RetryBackoff {
+            initial: delay,
+            max: delay,
+        }
+    }
+
+    /// Wait `initial` before the first retry, doubling the delay for each retry after
+    /// it up to `max`.
+    pub fn exponential(initial: Duration, max: Duration) -> RetryBackoff {
+        RetryBackoff { initial, max }
+    }
+
+    /// Returns the delay before the given retry, counting from zero.
+    pub fn delay(&self, retry: u32) -> Duration {
+        self.initial
+            .checked_mul(1 << retry.min(31))
+            .map_or(self.max, |delay| delay.min(self.max))
+    }
+}
+
+impl Default for RetryBackoff {
+    /// Backs off exponentially from 100ms to 5s.
+    fn default() -> Self {
+        RetryBackoff::exponential(Duration::from_millis(100), Duration::from_secs(5))
+    }
+}
+
+impl<H> Clone for NoiseAcceptor<H> {
+    fn clone(&self) -> Self {
+        NoiseAcceptor {
+            config: self.config.clone(),
+            timeout: self.timeout,
+        }
+    }
+}
+
+impl<H> Clone for NoiseConnector<H> {
+    fn clone(&self) -> Self {
+        NoiseConnector {
+            config: self.config.clone(),
+            timeout: self.timeout,
+            handshake_retries: self.handshake_retries,
+            retry_backoff: self.retry_backoff,
+            socket_options: self.socket_options.clone(),
+            resolver: self.resolver.clone(),
+        }
+    }
+}
+
+/// Run `future` within `timeout`, if there is one.
+async fn within<T>(
+    timeout: Option<Duration>,
+    future: impl Future<Output = Result<T, NoiseError>>,
+) -> Result<T, NoiseError> {
+    match timeout {
+        Some(timeout) => with_deadline(Instant::now() + timeout, future).await,
+        None => future.await,
+    }
+}
+
+impl<H: Handshake + Clone> NoiseAcceptor<H> {
+    /// Construct an acceptor which responds to each connection with the given config.
+    pub fn new(config: HandshakeConfig<H>) -> NoiseAcceptor<H> {
+        NoiseAcceptor {
+            config: Arc::new(config),
+            timeout: None,
+        }
+    }
+
+    /// Sets how long each handshake may take before failing with
+    /// [`NoiseError::DeadlineExceeded`]. By default, there is no limit.
+    pub fn timeout(mut self, timeout: Duration) -> NoiseAcceptor<H> {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Returns the shared config.
+    pub fn config(&self) -> &HandshakeConfig<H> {
+        &self.config
+    }
+
+    /// Conduct the handshake over an accepted connection as the responder, with a clone
+    /// of the configured handshake. See [`HandshakeConfig::respond`].
+    pub fn accept<S: Transport>(
+        &self,
+        socket: S,
+    ) -> impl Future<Output = Result<NoiseStream<S>, NoiseError>> {
+        let (config, timeout) = (self.config.clone(), self.timeout);
+        async move { within(timeout, config.respond(socket)).await }
+    }
+}
+
+impl<H: Handshake + Clone> NoiseConnector<H> {
+    /// Construct a connector which initiates each connection with the given config.
+    pub fn new(config: HandshakeConfig<H>) -> NoiseConnector<H> {
+        NoiseConnector {
+            config: Arc::new(config),
+            timeout: None,
+            handshake_retries: 0,
+            retry_backoff: RetryBackoff::default(),
+            socket_options: SocketOptions::default(),
+            resolver: Arc::new(SystemResolver),
+        }
+    }
+
+    /// Sets how long connecting and conducting each handshake may take, together,
+    /// before failing with [`NoiseError::DeadlineExceeded`]. By default, there is no
+    /// limit. With [retries][Self::handshake_retries], the limit applies to each
+    /// attempt.
+    pub fn timeout(mut self, timeout: Duration) -> NoiseConnector<H> {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how many times [`connect`][Self::connect] retries after a
+    /// [transient][NoiseError::is_transient] failure to connect or handshake, such as a
+    /// connection refused or reset while the server restarts. Each retry dials a fresh
+    /// TCP connection and conducts a fresh handshake, after a delay set by
+    /// [`retry_backoff`][Self::retry_backoff]. Authentication failures, such as a wrong
+    /// PSK or an unexpected static key, are never retried. Defaults to zero.
+    ///
+    /// In patterns where the initiator speaks first and the responder never proves its
+    /// keys to a rejected initiator, such as `NNpsk0`, a responder which rejects the
+    /// initiator simply closes the connection. The initiator can't tell this from a
+    /// dropped connection, so such attempts are retried.
+    pub fn handshake_retries(mut self, retries: u32) -> NoiseConnector<H> {
+        self.handshake_retries = retries;
+        self
+    }
+
+    /// Sets the delays between retries. Defaults to [`RetryBackoff::default`].
+    pub fn retry_backoff(mut self, backoff: RetryBackoff) -> NoiseConnector<H> {
+        self.retry_backoff = backoff;
+        self
+    }
+
+    /// Sets the local address to bind each connection to before connecting, such as to
+    /// pick the source address on a multi-homed host. A port of zero lets the system
+    /// choose one. Resolved addresses of the other address family can't be reached from
+    /// it, and are skipped. By default, the system chooses the source address.
+    pub fn local_addr(mut self, local_addr: SocketAddr) -> NoiseConnector<H> {
+        self.socket_options.local_addr = Some(local_addr);
+        self
+    }
+
+    /// Sets the network interface, such as `eth1`, to bind each connection to before
+    /// connecting, with `SO_BINDTODEVICE`. This usually requires the `CAP_NET_RAW`
+    /// capability. Only supported on Android, Fuchsia and Linux; elsewhere every
+    /// connection fails.
+    pub fn bind_device(mut self, device: impl Into<String>) -> NoiseConnector<H> {
+        self.socket_options.bind_device = Some(device.into());
+        self
+    }
+
+    /// Sets a function to call on each socket before it connects, after any
+    /// [`local_addr`][Self::local_addr] or [`bind_device`][Self::bind_device] binding,
+    /// for socket options which must be set before connecting, such as buffer sizes or
+    /// the type of service. An error from the function fails that connection attempt
+    /// as [`NoiseError::Connect`].
+    ///
+    /// The function is shared by every connection the connector makes, including
+    /// retries.
+    ///
+    /// ```no_run
+    /// # async fn example() -> Result<(), tokio_noise::NoiseError> {
+    /// use tokio_noise::{handshakes::NNpsk0, HandshakeConfig, NoiseConnector};
+    ///
+    /// let connector = NoiseConnector::new(HandshakeConfig::new(NNpsk0::try_new(&[0xFF; 32])?))
+    ///     .local_addr("10.0.0.2:0".parse().unwrap())
+    ///     .configure_socket(|socket| socket.set_recv_buffer_size(1 << 20));
+    /// let noise_stream = connector.connect("10.0.0.1:8080").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn configure_socket(
+        mut self,
+        configure: impl Fn(&TcpSocket) -> io::Result<()> + Send + Sync + 'static,
+    ) -> NoiseConnector<H> {
+        self.socket_options.configure = Some(Arc::new(configure));
+        self
+    }
+
+    /// Sets the resolver with which [`connect`][Self::connect] looks up host names.
+    /// Socket addresses and IP address literals are connected to directly. Defaults to
+    /// the [`SystemResolver`].
+    pub fn resolver(mut self, resolver: impl Resolver + 'static) -> NoiseConnector<H> {
+        self.resolver = Arc::new(resolver);
+        self
+    }
+
+    /// Returns the shared config.
+    pub fn config(&self) -> &HandshakeConfig<H> {
+        &self.config
+    }
+
+    /// Connect to the given address over TCP, and conduct the handshake as the
+    /// initiator, with a clone of the configured handshake.
+    ///
+    /// A host name is looked up with the [resolver][Self::resolver], and its addresses
+    /// are tried in the order it returns them. Failing to resolve the name, or to
+    /// connect to any address, is reported as [`NoiseError::Connect`]. The address is
+    /// resolved once, and not again for [retries][Self::handshake_retries].
+    pub fn connect<A: ConnectAddr>(
+        &self,
+        addr: A,
+    ) -> impl Future<Output = Result<NoiseTcpStream, NoiseError>> {
+        let this = self.clone();
+        let target = addr.to_target();
+        async move {
+            let addrs: Vec<SocketAddr> = within(this.timeout, async {
+                match target.map_err(NoiseError::Connect)? {
+                    ConnectTarget::Addr(addr) => Ok(vec![addr]),
+                    ConnectTarget::Host(host, port) => this
+                        .resolver
+                        .resolve(&host, port)
+                        .await
+                        .map_err(NoiseError::Connect),
+                }
+            })
+            .await?;
+            let mut retry = 0;
+            loop {
+                let result = within(this.timeout, async {
+                    let socket = this
+                        .socket_options
+                        .connect(&addrs)
+                        .await
+                        .map_err(NoiseError::Connect)?;
+                    this.config.initiate(socket).await
+                })
+                .await;
+                match result {
+                    Err(e) if retry < this.handshake_retries && e.is_transient() => {
+                        let delay = this.retry_backoff.delay(retry);
+                        retry += 1;
+                        warn!(
+                            "connection attempt {} of {} failed: {}; retrying in {:?}",
+                            retry,
+                            this.handshake_retries + 1,
+                            e,
+                            delay
+                        );
+                        tokio::time::sleep(delay).await;
+                    }
+                    result => return result,
+                }
+            }
+        }
+    }
+
+    /// Conduct the handshake as the initiator over an existing transport, with a clone
+    /// of the configured handshake. See [`HandshakeConfig::initiate`].
+    pub fn connect_over<S: Transport>(
+        &self,
+        socket: S,
+    ) -> impl Future<Output = Result<NoiseStream<S>, NoiseError>> {
+        let (config, timeout) = (self.config.clone(), self.timeout);
+        async move { within(timeout, config.initiate(socket)).await }
+    }
+}
@@ -0,0 +1,213 @@
+use std::io;
+
+use crate::{
+    errors::{HandshakeError, NoiseError},
+    handshakes::{build_state, Handshake},
+    log_context::LogContext,
+    stream::NoiseStream,
+    transport::Transport,
+};
+
+/// The size of the nonce prefix which [`NoiseDatagramCodec::seal`] adds to each datagram,
+/// in the default [`NoncePrefixFormat`].
+pub const DATAGRAM_NONCE_SIZE: usize = 8;
+
+/// The number of bytes [`NoiseDatagramCodec::encrypt`] adds to each plaintext: a 16-byte
+/// authentication tag.
+pub const DATAGRAM_TAG_SIZE: usize = 16;
+
+/// How [`NoiseDatagramCodec::seal`] and [`open`][NoiseDatagramCodec::open] encode the
+/// nonce at the start of each datagram, for interoperating with peers implemented
+/// elsewhere. Both sides must use the same format: a datagram sealed in one format opens
+/// under another with the wrong nonce, and fails to decrypt.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum NoncePrefixFormat {
+    /// An 8-byte big-endian prefix.
+    #[default]
+    BigEndian64,
+    /// An 8-byte little-endian prefix.
+    LittleEndian64,
+    /// No prefix. The nonce must be conveyed some other way, so datagrams can't be
+    /// [opened][NoiseDatagramCodec::open], only [decrypted][NoiseDatagramCodec::decrypt]
+    /// with the nonce known to the caller.
+    None,
+}
+
+impl NoncePrefixFormat {
+    /// Returns the size of the prefix in bytes.
+    pub fn len(&self) -> usize {
+        match self {
+            NoncePrefixFormat::BigEndian64 | NoncePrefixFormat::LittleEndian64 => 8,
+            NoncePrefixFormat::None => 0,
+        }
+    }
+
+    /// Returns whether the format has no prefix.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Encrypts and decrypts independent datagrams with a key established by a Noise
+/// handshake, for callers which manage their own unreliable transport.
+///
+/// Unlike a [`NoiseStream`], the codec keeps no nonce counters. The caller chooses the
+/// nonce of each datagram it encrypts, and must never reuse one. Datagrams can then be
+/// decrypted in any order. Since any datagram may be decrypted more than once, detecting
+/// replayed and duplicate datagrams is also the caller's job, for example with a sliding
+/// window over the nonces received.
+///
+/// [`seal`][Self::seal] and [`open`][Self::open] carry the nonce in the datagram as an
+/// 8-byte big-endian prefix, unless [another format][Self::nonce_prefix_format] is set.
+/// Stream frames carry no nonce, because theirs is implied by their order.
+///
+/// ```no_run
+/// # async fn example(mut tcp_stream: tokio::net::TcpStream) -> Result<(), tokio_noise::NoiseError> {
+/// use tokio_noise::{handshakes::NNpsk0, NoiseDatagramCodec};
+///
+/// // Run the handshake over a reliable channel, then send datagrams by other means.
+/// let codec =
+///     NoiseDatagramCodec::handshake_initiator(&mut tcp_stream, NNpsk0::try_new(&[0xFF; 32])?).await?;
+/// let mut datagram = [0u8; 64];
+/// let n = codec.seal(0, b"hello", &mut datagram)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct NoiseDatagramCodec {
+    noise: snow::StatelessTransportState,
+    nonce_prefix: NoncePrefixFormat,
+}
+
+impl NoiseDatagramCodec {
+    /// Conduct a Noise handshake over the given reliable transport as the initiator, and
+    /// return a codec for datagrams. Pass `&mut socket` to keep using the transport
+    /// afterwards.
+    pub async fn handshake_initiator<S: Transport>(
+        mut socket: S,
+        handshake: impl Handshake,
+    ) -> Result<NoiseDatagramCodec, NoiseError> {
+        let initiator = build_state(&handshake, true)?;
+        NoiseDatagramCodec::run(&mut socket, handshake, initiator).await
+    }
+
+    /// Conduct a Noise handshake over the given reliable transport as the responder, and
+    /// return a codec for datagrams. Pass `&mut socket` to keep using the transport
+    /// afterwards.
+    pub async fn handshake_responder<S: Transport>(
+        mut socket: S,
+        handshake: impl Handshake,
+    ) -> Result<NoiseDatagramCodec, NoiseError> {
+        let responder = build_state(&handshake, false)?;
+        NoiseDatagramCodec::run(&mut socket, handshake, responder).await
+    }
+
+    async fn run<S: Transport>(
+        socket: &mut S,
+        handshake: impl Handshake,
+        state: snow::HandshakeState,
+    ) -> Result<NoiseDatagramCodec, NoiseError> {
+        let name = handshake.name();
+        let handshaked =
+            NoiseStream::run_handshake(socket, handshake, state, None, None, &LogContext::new())
+                .await?;
+        if !handshaked.read_overflow_buf.is_empty() {
+            return Err(NoiseError::Handshake(HandshakeError {
+                description: "received cleartext with the final handshake message, which a \
+                              datagram codec can't deliver"
+                    .to_string(),
+                handshake_pattern: name,
+            }));
+        }
+        Ok(NoiseDatagramCodec {
+            noise: handshaked.state.into_stateless_transport_mode()?,
+            nonce_prefix: NoncePrefixFormat::default(),
+        })
+    }
+
+    /// Sets how [`seal`][Self::seal] and [`open`][Self::open] encode each datagram's
+    /// nonce. Defaults to [`NoncePrefixFormat::BigEndian64`]. The peer must use the same
+    /// format.
+    pub fn nonce_prefix_format(mut self, format: NoncePrefixFormat) -> NoiseDatagramCodec {
+        self.nonce_prefix = format;
+        self
+    }
+
+    /// Returns how the codec encodes each datagram's nonce.
+    pub fn nonce_prefix(&self) -> NoncePrefixFormat {
+        self.nonce_prefix
+    }
+
+    /// Encrypt `plaintext` with the given nonce into `out`, returning the number of bytes
+    /// written. The ciphertext is [`DATAGRAM_TAG_SIZE`] bytes longer than the plaintext.
+    pub fn encrypt(
+        &self,
+        nonce: u64,
+        plaintext: &[u8],
+        out: &mut [u8],
+    ) -> Result<usize, NoiseError> {
+        Ok(self.noise.write_message(nonce, plaintext, out)?)
+    }
+
+    /// Decrypt `ciphertext` with the given nonce into `out`, returning the number of bytes
+    /// written. Fails if the ciphertext was not encrypted by the peer with that nonce, or
+    /// was tampered with.
+    pub fn decrypt(
+        &self,
+        nonce: u64,
+        ciphertext: &[u8],
+        out: &mut [u8],
+    ) -> Result<usize, NoiseError> {
+        Ok(self.noise.read_message(nonce, ciphertext, out)?)
+    }
+
+    /// Encrypt `plaintext` with the given nonce into a datagram in `out`, prefixed with
+    /// the nonce in the codec's [format][Self::nonce_prefix_format]. Returns the size of
+    /// the datagram, which is the size of the prefix, [`DATAGRAM_NONCE_SIZE`] by default,
+    /// plus [`DATAGRAM_TAG_SIZE`] bytes longer than the plaintext.
+    pub fn seal(&self, nonce: u64, plaintext: &[u8], out: &mut [u8]) -> Result<usize, NoiseError> {
+        let prefix_len = self.nonce_prefix.len();
+        if out.len() < prefix_len {
+            return Err(NoiseError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}-byte output buffer can't hold a datagram", out.len()),
+            )));
+        }
+        let (prefix, ciphertext) = out.split_at_mut(prefix_len);
+        match self.nonce_prefix {
+            NoncePrefixFormat::BigEndian64 => prefix.copy_from_slice(&nonce.to_be_bytes()),
+            NoncePrefixFormat::LittleEndian64 => prefix.copy_from_slice(&nonce.to_le_bytes()),
+            NoncePrefixFormat::None => {}
+        }
+        Ok(prefix_len + self.encrypt(nonce, plaintext, ciphertext)?)
+    }
+
+    /// Decrypt a datagram created by the peer's [`seal`][Self::seal] into `out`, returning
+    /// the datagram's nonce and the number of bytes written.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] if the codec's
+    /// [format][Self::nonce_prefix_format] is [`NoncePrefixFormat::None`], since the
+    /// datagram doesn't say which nonce to decrypt it with.
+    pub fn open(&self, datagram: &[u8], out: &mut [u8]) -> Result<(u64, usize), NoiseError> {
+        let prefix_len = self.nonce_prefix.len();
+        if self.nonce_prefix == NoncePrefixFormat::None {
+            return Err(NoiseError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "datagrams without a nonce prefix can't be opened; decrypt them instead",
+            )));
+        }
+        if datagram.len() < prefix_len + DATAGRAM_TAG_SIZE {
+            return Err(NoiseError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}-byte datagram is too short to decrypt", datagram.len()),
+            )));
+        }
+        let (prefix, ciphertext) = datagram.split_at(prefix_len);
+        let prefix: [u8; 8] = prefix.try_into().expect("8-byte prefix");
+        let nonce = match self.nonce_prefix {
+            NoncePrefixFormat::LittleEndian64 => u64::from_le_bytes(prefix),
+            _ => u64::from_be_bytes(prefix),
+        };
+        Ok((nonce, self.decrypt(nonce, ciphertext, out)?))
+    }
+}
@@ -1,4 +1,6 @@
-use std::{error::Error, fmt, io};
+use std::{error::Error, fmt, io, net::SocketAddr};
+
+use crate::fingerprint::Fingerprint;
 
 /// An error derived from either `std::io::Error` or `snow::Error`.
 #[derive(Debug)]
@@ -9,14 +11,383 @@ pub enum NoiseError {
     Snow(snow::Error),
     /// An error occurred within a [`Handshake`][crate::handshakes::Handshake] implementation.
     Handshake(HandshakeError),
+    /// The handshake pattern was not finished after exchanging the number of messages
+    /// given by [`Handshake::message_count`][crate::handshakes::Handshake::message_count].
+    HandshakeIncomplete {
+        /// The name of the handshake pattern.
+        pattern: String,
+        /// The number of handshake messages sent and received.
+        messages_exchanged: usize,
+    },
+    /// The transport closed partway through a handshake message from the peer.
+    HandshakeTruncated {
+        /// Which message of the handshake was being received, counting from one.
+        message: usize,
+        /// How many bytes of the message arrived before the transport closed. If the
+        /// length of the message is unknown, this counts bytes of its length prefix.
+        received: usize,
+        /// The length of the message, or `None` if the transport closed before the
+        /// length arrived.
+        expected: Option<usize>,
+    },
+    /// The stream received too many consecutive frames which failed to decrypt, and has
+    /// been shut down. All further reads and writes on the stream fail with this error.
+    ///
+    /// See [`NoiseBuilder::max_decrypt_failures`][crate::NoiseBuilder::max_decrypt_failures].
+    TooManyDecryptFailures,
+    /// A pre-shared key was rejected as unsuitable for use.
+    InvalidPsk(PskError),
+    /// The remote peer frames its transport messages differently to us, so no data
+    /// can be safely exchanged with it.
+    ///
+    /// See [`FRAMING_VERSION`][crate::FRAMING_VERSION].
+    FramingVersionMismatch {
+        /// Our framing version.
+        local: u8,
+        /// The framing version declared by the peer, or `None` if the peer did not
+        /// declare one, as is the case for peers which predate framing versions.
+        remote: Option<u8>,
+    },
+    /// The remote peer declared a frame size which we can't receive.
+    ///
+    /// See [`FrameSizing`][crate::FrameSizing].
+    UnsupportedFrameSize {
+        /// The frame size declared by the peer.
+        size: usize,
+    },
+    /// An authenticated frame from the peer declared more plaintext than a frame can
+    /// hold. The stream has been poisoned.
+    FrameLengthExceeded {
+        /// The plaintext length declared by the frame.
+        len: usize,
+        /// The most plaintext a frame can hold.
+        max: usize,
+    },
+    /// The remote peer closed the stream with an application error code and reason.
+    ///
+    /// See [`NoiseStream::close_with_error`][crate::NoiseStream::close_with_error].
+    ClosedByPeer {
+        /// The error code sent by the peer.
+        code: u32,
+        /// The reason sent by the peer.
+        reason: String,
+    },
+    /// An operation did not complete before its deadline.
+    ///
+    /// See [`NoiseStream::send_deadline`][crate::NoiseStream::send_deadline] and
+    /// [`NoiseStream::recv_deadline`][crate::NoiseStream::recv_deadline].
+    DeadlineExceeded,
+    /// The initiator did not send its first handshake message before the responder's
+    /// timeout.
+    ///
+    /// See [`NoiseBuilder::first_message_timeout`][crate::NoiseBuilder::first_message_timeout].
+    HandshakeTimeout,
+    /// The peer authenticated, but is not one we accept.
+    ///
+    /// See [`Router`][crate::Router], available with the `router` feature.
+    UnknownPeer,
+    /// The peer was turned away before its handshake began.
+    ///
+    /// See [`HandshakeConfig::admit`][crate::HandshakeConfig::admit].
+    PeerRejected,
+    /// The peer's static public key is not the one which the handshake expected, so the
+    /// handshake was aborted.
+    ///
+    /// See [`SnowHandshake::expect_remote_static`][crate::handshakes::SnowHandshake::expect_remote_static].
+    PeerKeyMismatch {
+        /// The fingerprint of the expected key.
+        expected: Fingerprint,
+        /// The fingerprint of the peer's key.
+        got: Fingerprint,
+    },
+    /// The peer's static public key is not in the allowlist, so the handshake was
+    /// aborted. Contains the fingerprint of the peer's key.
+    ///
+    /// See [`AllowlistFile`][crate::AllowlistFile].
+    PeerNotAllowed(Fingerprint),
+    /// The stream was established by a one-way handshake pattern such as `N`, in which
+    /// only the initiator sends, and the caller tried to use the missing direction.
+    ///
+    /// See [`NoiseStream::handshake_initiator_n`][crate::NoiseStream::handshake_initiator_n].
+    OneWay {
+        /// Set if the responder tried to send, rather than the initiator to receive.
+        send: bool,
+    },
+    /// The sending nonce can't be skipped to the requested value, because it would move
+    /// backwards or further ahead than the peer can follow.
+    ///
+    /// See [`NoiseStream::skip_sending_nonce`][crate::NoiseStream::skip_sending_nonce].
+    InvalidNonceSkip {
+        /// The current sending nonce.
+        from: u64,
+        /// The requested sending nonce.
+        to: u64,
+    },
+    /// A typed message could not be encoded, or a received one could not be decoded.
+    ///
+    /// See [`TypedNoiseStream`][crate::TypedNoiseStream], available with the `typed`
+    /// feature.
+    MessageFormat(Box<dyn Error + Send + Sync>),
+    /// A typed message, sent or received, was longer than the stream allows.
+    ///
+    /// See [`TypedNoiseStream::max_message_len`][crate::TypedNoiseStream::max_message_len],
+    /// available with the `typed` feature.
+    MessageTooLarge {
+        /// The length of the encoded message.
+        len: usize,
+        /// The longest message the stream allows.
+        max: usize,
+    },
+    /// A data frame's application sequence number was not the one expected, so frames
+    /// were lost, duplicated, or reordered at the application level. The stream has been
+    /// poisoned.
+    ///
+    /// See [`NoiseBuilder::sequence_numbers`][crate::NoiseBuilder::sequence_numbers].
+    SequenceGap {
+        /// The sequence number expected.
+        expected: u32,
+        /// The sequence number received.
+        got: u32,
+    },
+    /// The peer's preamble disagrees with ours about whether data frames carry
+    /// application sequence numbers, so no data can be safely exchanged with it. The
+    /// stream has been poisoned.
+    ///
+    /// See [`NoiseBuilder::sequence_numbers`][crate::NoiseBuilder::sequence_numbers].
+    SequenceNumbersMismatch {
+        /// Set if the peer sends sequence numbers, and so we don't expect them.
+        remote: bool,
+    },
+    /// A frame was encrypted with a later nonce than expected, so the frames in between
+    /// were lost or removed. The stream has been poisoned.
+    ///
+    /// See [`NoiseBuilder::strict_nonce`][crate::NoiseBuilder::strict_nonce].
+    NonceMismatch {
+        /// The receiving nonce expected.
+        expected: u64,
+        /// The nonce the frame was encrypted with.
+        got: u64,
+    },
+    /// The transport closed without the peer sending a close notify, so data it sent
+    /// may have been cut short.
+    ///
+    /// See [`NoiseBuilder::allow_unexpected_close`][crate::NoiseBuilder::allow_unexpected_close].
+    UnexpectedClose,
+    /// The initiator's first handshake message was rejected as a possible replay.
+    ///
+    /// See [`AntiReplay`][crate::handshakes::AntiReplay].
+    Replay(ReplayError),
+    /// A stream could not be exported to, or imported from, a
+    /// [`SessionSnapshot`][crate::SessionSnapshot].
+    ///
+    /// See [`NoiseStream::export_session`][crate::NoiseStream::export_session], available
+    /// with the `session-export` feature.
+    Session(SessionError),
+    /// A connection expected to start with a PROXY protocol header did not, or the header
+    /// was malformed, so no handshake was attempted.
+    ///
+    /// See [`NoiseTcpListener::set_proxy_protocol`][crate::NoiseTcpListener::set_proxy_protocol].
+    ProxyHeader(ProxyHeaderError),
+    /// A TCP connection to the peer could not be established, so no handshake was
+    /// attempted. Holds the error from the last address tried.
+    ///
+    /// See [`NoiseStream::connect_with`][crate::NoiseStream::connect_with].
+    Connect(io::Error),
+    /// An error on a stream whose transport has a peer address, such as a TCP socket,
+    /// together with the name of the stream and the address of its peer. Errors from a
+    /// handshake over such a transport, and from reading or writing the stream, are
+    /// returned in this form.
+    ///
+    /// See [`NoiseError::context`] and [`NoiseError::without_context`].
+    WithContext {
+        /// Which stream the error occurred on.
+        context: ErrorContext,
+        /// The error itself.
+        error: Box<NoiseError>,
+    },
+}
+
+/// Identifies the stream on which a [`NoiseError`] occurred, among the many a process may
+/// have open. See [`NoiseError::context`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ErrorContext {
+    /// The name of the stream. See [`NoiseStream::name`][crate::NoiseStream::name].
+    pub stream_name: String,
+    /// The address of the stream's peer.
+    pub peer_addr: SocketAddr,
+}
+
+/// A broad classification of a [`NoiseError`], for callers which need to react to the
+/// cause of an error without matching on every variant. See [`NoiseError::kind`].
+///
+/// More kinds may be added in future releases.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum NoiseErrorKind {
+    /// Received data failed to decrypt, because it was corrupted, tampered with, or
+    /// encrypted with a different key.
+    Decrypt,
+    /// A nonce counter reached its limit, so no more messages can be sent or received
+    /// with the current keys.
+    NonceExhausted,
+    /// The handshake has not finished, so the operation is not yet possible.
+    HandshakeIncomplete,
+    /// An argument or configuration was invalid, such as a malformed PSK or a message too
+    /// large to encrypt.
+    InvalidInput,
+    /// The peer broke the protocol, for example with an incompatible framing.
+    Protocol,
+    /// The peer closed the stream with an error.
+    ClosedByPeer,
+    /// An operation did not complete before its deadline.
+    TimedOut,
+    /// An IO error occurred on the underlying transport.
+    Io,
+    /// Any other error.
+    Other,
+}
+
+impl NoiseError {
+    /// Classify this error by its cause. Errors from [`snow`] are classified by their
+    /// variant, so for example a nonce exhausted while encrypting is reported as
+    /// [`NoiseErrorKind::NonceExhausted`].
+    pub fn kind(&self) -> NoiseErrorKind {
+        match self {
+            NoiseError::Io(_) => NoiseErrorKind::Io,
+            NoiseError::Snow(e) => snow_error_kind(e),
+            NoiseError::Handshake(_) => NoiseErrorKind::Other,
+            NoiseError::HandshakeIncomplete { .. } => NoiseErrorKind::HandshakeIncomplete,
+            NoiseError::HandshakeTruncated { .. } => NoiseErrorKind::Io,
+            NoiseError::TooManyDecryptFailures => NoiseErrorKind::Decrypt,
+            NoiseError::InvalidPsk(_) => NoiseErrorKind::InvalidInput,
+            NoiseError::FramingVersionMismatch { .. }
+            | NoiseError::UnsupportedFrameSize { .. }
+            | NoiseError::FrameLengthExceeded { .. } => NoiseErrorKind::Protocol,
+            NoiseError::ClosedByPeer { .. } => NoiseErrorKind::ClosedByPeer,
+            NoiseError::DeadlineExceeded | NoiseError::HandshakeTimeout => NoiseErrorKind::TimedOut,
+            NoiseError::UnknownPeer
+            | NoiseError::PeerRejected
+            | NoiseError::PeerKeyMismatch { .. }
+            | NoiseError::PeerNotAllowed(_)
+            | NoiseError::OneWay { .. } => NoiseErrorKind::Other,
+            NoiseError::InvalidNonceSkip { .. } => NoiseErrorKind::InvalidInput,
+            NoiseError::MessageFormat(_) => NoiseErrorKind::Other,
+            NoiseError::MessageTooLarge { .. } => NoiseErrorKind::Protocol,
+            NoiseError::SequenceGap { .. } => NoiseErrorKind::Protocol,
+            NoiseError::SequenceNumbersMismatch { .. } => NoiseErrorKind::Protocol,
+            NoiseError::NonceMismatch { .. } => NoiseErrorKind::Protocol,
+            NoiseError::Replay(_) => NoiseErrorKind::Protocol,
+            NoiseError::Session(_) => NoiseErrorKind::InvalidInput,
+            NoiseError::ProxyHeader(ProxyHeaderError::Timeout) => NoiseErrorKind::TimedOut,
+            NoiseError::ProxyHeader(_) => NoiseErrorKind::Protocol,
+            NoiseError::UnexpectedClose | NoiseError::Connect(_) => NoiseErrorKind::Io,
+            NoiseError::WithContext { error, .. } => error.kind(),
+        }
+    }
+
+    /// Returns whether this error may not recur on a fresh connection: an IO error, such
+    /// as a connection refused or closed partway through a handshake, or a timeout.
+    /// Authentication and protocol failures are not transient.
+    pub fn is_transient(&self) -> bool {
+        matches!(self.kind(), NoiseErrorKind::Io | NoiseErrorKind::TimedOut)
+    }
+
+    /// Returns the name and peer address of the stream on which this error occurred, if
+    /// the stream's transport has a peer address.
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            NoiseError::WithContext { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// Returns the address of the peer of the stream on which this error occurred, if
+    /// known. Shorthand for the `peer_addr` of [`context`][NoiseError::context].
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.context().map(|context| context.peer_addr)
+    }
+
+    /// Strips the context from this error, if any, for matching on its cause.
+    pub fn without_context(self) -> NoiseError {
+        match self {
+            NoiseError::WithContext { error, .. } => *error,
+            e => e,
+        }
+    }
+
+    /// Attach the stream's name and peer address to this error, unless the peer address
+    /// is unknown or the error already has context.
+    pub(crate) fn with_context(self, stream_name: &str, peer_addr: Option<SocketAddr>) -> Self {
+        match (self, peer_addr) {
+            (e @ NoiseError::WithContext { .. }, _) | (e, None) => e,
+            (e, Some(peer_addr)) => NoiseError::WithContext {
+                context: ErrorContext {
+                    stream_name: stream_name.to_string(),
+                    peer_addr,
+                },
+                error: Box::new(e),
+            },
+        }
+    }
+
+    /// The kind of IO error which this error is reported as.
+    fn io_error_kind(&self) -> io::ErrorKind {
+        match self {
+            NoiseError::Io(e) | NoiseError::Connect(e) => e.kind(),
+            NoiseError::ClosedByPeer { .. } => io::ErrorKind::ConnectionAborted,
+            NoiseError::DeadlineExceeded
+            | NoiseError::HandshakeTimeout
+            | NoiseError::ProxyHeader(ProxyHeaderError::Timeout) => io::ErrorKind::TimedOut,
+            NoiseError::HandshakeTruncated { .. } | NoiseError::UnexpectedClose => {
+                io::ErrorKind::UnexpectedEof
+            }
+            NoiseError::OneWay { .. } => io::ErrorKind::Unsupported,
+            NoiseError::WithContext { error, .. } => error.io_error_kind(),
+            _ => io::ErrorKind::InvalidData,
+        }
+    }
+}
+
+fn snow_error_kind(e: &snow::Error) -> NoiseErrorKind {
+    use snow::error::{PatternProblem, StateProblem};
+    match e {
+        snow::Error::Decrypt => NoiseErrorKind::Decrypt,
+        snow::Error::State(StateProblem::Exhausted) => NoiseErrorKind::NonceExhausted,
+        snow::Error::State(StateProblem::HandshakeNotFinished) => {
+            NoiseErrorKind::HandshakeIncomplete
+        }
+        snow::Error::Input | snow::Error::Pattern(PatternProblem::InvalidPsk) => {
+            NoiseErrorKind::InvalidInput
+        }
+        _ => NoiseErrorKind::Other,
+    }
 }
 
 impl From<io::Error> for NoiseError {
+    /// Converts an IO error into a `NoiseError`. If the IO error is just a wrapper around
+    /// a `NoiseError` (as returned by [`NoiseTcpStream`][crate::NoiseTcpStream]'s
+    /// `AsyncRead` and `AsyncWrite` implementations), the inner error is returned as-is.
     fn from(e: io::Error) -> Self {
+        if e.get_ref().is_some_and(|inner| inner.is::<NoiseError>()) {
+            let inner = e.into_inner().expect("checked for inner error");
+            return *inner.downcast::<NoiseError>().expect("checked error type");
+        }
         NoiseError::Io(e)
     }
 }
 
+impl From<NoiseError> for io::Error {
+    /// Converts a `NoiseError` into an IO error, so that it can be returned from the
+    /// `AsyncRead` and `AsyncWrite` traits. The original error can be recovered
+    /// by converting back with `NoiseError::from`.
+    fn from(e: NoiseError) -> Self {
+        match e {
+            NoiseError::Io(e) => e,
+            e => io::Error::new(e.io_error_kind(), e),
+        }
+    }
+}
+
 impl From<snow::Error> for NoiseError {
     fn from(e: snow::Error) -> Self {
         NoiseError::Snow(e)
@@ -35,11 +406,431 @@ impl fmt::Display for NoiseError {
             NoiseError::Io(e) => write!(f, "Noise IO error: {}", e),
             NoiseError::Snow(e) => write!(f, "Noise snow error: {}", e),
             NoiseError::Handshake(e) => write!(f, "Noise snow error: {}", e),
+            NoiseError::HandshakeIncomplete {
+                pattern,
+                messages_exchanged,
+            } => write!(
+                f,
+                "Noise handshake {} is incomplete after {} messages",
+                pattern, messages_exchanged
+            ),
+            NoiseError::HandshakeTruncated {
+                message,
+                received,
+                expected: Some(expected),
+            } => write!(
+                f,
+                "Noise transport closed after receiving {} of {} bytes of handshake message {}",
+                received, expected, message
+            ),
+            NoiseError::HandshakeTruncated {
+                message,
+                received,
+                expected: None,
+            } => write!(
+                f,
+                "Noise transport closed after receiving {} bytes, before the length of handshake message {}",
+                received, message
+            ),
+            NoiseError::TooManyDecryptFailures => {
+                write!(f, "Noise stream closed after too many decryption failures")
+            }
+            NoiseError::InvalidPsk(e) => write!(f, "Noise PSK error: {}", e),
+            NoiseError::FramingVersionMismatch {
+                local,
+                remote: Some(remote),
+            } => write!(
+                f,
+                "Noise peer uses framing version {}, but we use version {}",
+                remote, local
+            ),
+            NoiseError::FramingVersionMismatch {
+                local,
+                remote: None,
+            } => write!(
+                f,
+                "Noise peer did not declare a framing version, but we use version {}",
+                local
+            ),
+            NoiseError::UnsupportedFrameSize { size } => write!(
+                f,
+                "Noise peer uses {}-byte frames, but frames must be between {} and {} bytes",
+                size,
+                crate::MIN_FRAME_SIZE,
+                crate::MAX_FRAME_SIZE
+            ),
+            NoiseError::FrameLengthExceeded { len, max } => write!(
+                f,
+                "Noise frame specifies plaintext length={}; exceeds maximum of {}",
+                len, max
+            ),
+            NoiseError::ClosedByPeer { code, reason } => write!(
+                f,
+                "Noise peer closed the stream with error code {}: {}",
+                code, reason
+            ),
+            NoiseError::DeadlineExceeded => {
+                write!(f, "Noise operation did not complete before its deadline")
+            }
+            NoiseError::HandshakeTimeout => write!(
+                f,
+                "Noise initiator sent no handshake message before the timeout"
+            ),
+            NoiseError::UnknownPeer => write!(f, "Noise peer matched no known identity"),
+            NoiseError::PeerRejected => write!(f, "Noise peer was rejected before the handshake"),
+            NoiseError::PeerKeyMismatch { expected, got } => write!(
+                f,
+                "Noise peer's static key {} does not match the expected key {}",
+                got, expected
+            ),
+            NoiseError::PeerNotAllowed(got) => {
+                write!(f, "Noise peer's static key {} is not in the allowlist", got)
+            }
+            NoiseError::OneWay { send: true } => write!(
+                f,
+                "Noise stream is one-way, so the responder cannot send"
+            ),
+            NoiseError::OneWay { send: false } => write!(
+                f,
+                "Noise stream is one-way, so the initiator cannot receive"
+            ),
+            NoiseError::InvalidNonceSkip { from, to } => write!(
+                f,
+                "cannot skip Noise sending nonce from {} to {}; it may only advance by up to {}",
+                from,
+                to,
+                crate::NONCE_JUMP_LIMIT
+            ),
+            NoiseError::MessageFormat(e) => write!(f, "Noise typed message error: {}", e),
+            NoiseError::MessageTooLarge { len, max } => write!(
+                f,
+                "Noise typed message of {} bytes is longer than the limit of {} bytes",
+                len, max
+            ),
+            NoiseError::SequenceGap { expected, got } => write!(
+                f,
+                "Noise frame has sequence number {}, but {} was expected",
+                got, expected
+            ),
+            NoiseError::SequenceNumbersMismatch { remote: true } => write!(
+                f,
+                "Noise peer sends sequence numbers, but we don't expect them"
+            ),
+            NoiseError::SequenceNumbersMismatch { remote: false } => write!(
+                f,
+                "Noise peer doesn't send sequence numbers, but we expect them"
+            ),
+            NoiseError::NonceMismatch { expected, got } => write!(
+                f,
+                "Noise frame was encrypted with nonce {}, but {} was expected",
+                got, expected
+            ),
+            NoiseError::UnexpectedClose => write!(
+                f,
+                "Noise peer's transport closed without a close notify; data may be truncated"
+            ),
+            NoiseError::Replay(e) => write!(f, "Noise handshake rejected: {}", e),
+            NoiseError::Session(e) => write!(f, "Noise session handoff error: {}", e),
+            NoiseError::ProxyHeader(e) => write!(f, "Noise connection's PROXY header: {}", e),
+            NoiseError::Connect(e) => write!(f, "Noise could not connect to peer: {}", e),
+            NoiseError::WithContext { context, error } => write!(
+                f,
+                "{} (stream {}, peer {})",
+                error, context.stream_name, context.peer_addr
+            ),
         }
     }
 }
 impl Error for NoiseError {}
 
+impl From<ReplayError> for NoiseError {
+    fn from(e: ReplayError) -> Self {
+        NoiseError::Replay(e)
+    }
+}
+
+impl From<SessionError> for NoiseError {
+    fn from(e: SessionError) -> Self {
+        NoiseError::Session(e)
+    }
+}
+
+impl From<ProxyHeaderError> for NoiseError {
+    fn from(e: ProxyHeaderError) -> Self {
+        NoiseError::ProxyHeader(e)
+    }
+}
+
+impl From<PskError> for NoiseError {
+    fn from(e: PskError) -> Self {
+        NoiseError::InvalidPsk(e)
+    }
+}
+
+/// Describes why a pre-shared key (PSK) was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PskError {
+    /// The PSK was not the length required by the Noise protocol. Contains the
+    /// length of the rejected PSK.
+    InvalidLength(usize),
+    /// The PSK was all zeros, which is almost always a placeholder left in a config
+    /// rather than a real key.
+    AllZero,
+    /// The PSK string was not valid hexadecimal.
+    InvalidHex,
+    /// The PSK string was not valid base64.
+    InvalidBase64,
+}
+
+impl fmt::Display for PskError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PskError::InvalidLength(len) => write!(
+                f,
+                "PSK length {} is invalid, must be exactly {} bytes",
+                len,
+                crate::handshakes::PSK_LEN
+            ),
+            PskError::AllZero => write!(f, "PSK is all zeros, which is not a secret key"),
+            PskError::InvalidHex => write!(f, "PSK is not a valid hex string"),
+            PskError::InvalidBase64 => write!(f, "PSK is not a valid base64 string"),
+        }
+    }
+}
+impl Error for PskError {}
+
+/// Describes why a string could not be parsed as a [`Fingerprint`][crate::Fingerprint].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FingerprintError {
+    /// The string did not start with
+    /// [`FINGERPRINT_PREFIX`][crate::FINGERPRINT_PREFIX].
+    MissingPrefix,
+    /// The hash was not valid base64.
+    InvalidBase64,
+    /// The hash was not the length of a SHA-256 hash. Contains the length of the decoded
+    /// hash.
+    InvalidLength(usize),
+}
+
+impl fmt::Display for FingerprintError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FingerprintError::MissingPrefix => write!(
+                f,
+                "fingerprint does not start with {:?}",
+                crate::FINGERPRINT_PREFIX
+            ),
+            FingerprintError::InvalidBase64 => write!(f, "fingerprint is not valid base64"),
+            FingerprintError::InvalidLength(len) => write!(
+                f,
+                "fingerprint hash length {} is invalid, must be exactly {} bytes",
+                len,
+                crate::FINGERPRINT_LEN
+            ),
+        }
+    }
+}
+impl Error for FingerprintError {}
+
+/// Describes why an [`AllowlistFile`][crate::AllowlistFile] could not be read.
+#[derive(Debug)]
+pub enum AllowlistError {
+    /// The file could not be read.
+    Io(io::Error),
+    /// A line is neither a hex key nor a fingerprint.
+    Parse {
+        /// The number of the line, counting from one.
+        line: usize,
+        /// What is wrong with the line.
+        reason: String,
+    },
+}
+
+impl fmt::Display for AllowlistError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AllowlistError::Io(e) => write!(f, "cannot read allowlist: {}", e),
+            AllowlistError::Parse { line, reason } => {
+                write!(f, "allowlist line {} is invalid: {}", line, reason)
+            }
+        }
+    }
+}
+
+impl Error for AllowlistError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AllowlistError::Io(e) => Some(e),
+            AllowlistError::Parse { .. } => None,
+        }
+    }
+}
+
+/// An item which failed in [`NoiseTcpListener::incoming`][crate::NoiseTcpListener::incoming].
+/// Available with the `incoming` feature.
+#[cfg(feature = "incoming")]
+#[derive(Debug)]
+pub enum AcceptError {
+    /// A connection was accepted, but its handshake failed. The stream carries on.
+    Handshake {
+        /// The address of the peer.
+        peer_addr: SocketAddr,
+        /// Why the handshake failed.
+        error: NoiseError,
+    },
+    /// The listener itself failed. This is the last error the stream yields; it ends
+    /// once any handshakes already under way have finished.
+    Listener(io::Error),
+}
+
+#[cfg(feature = "incoming")]
+impl fmt::Display for AcceptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AcceptError::Handshake { peer_addr, error } => {
+                write!(f, "handshake from {} failed: {}", peer_addr, error)
+            }
+            AcceptError::Listener(e) => write!(f, "listener failed: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "incoming")]
+impl Error for AcceptError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AcceptError::Handshake { error, .. } => Some(error),
+            AcceptError::Listener(e) => Some(e),
+        }
+    }
+}
+
+/// Describes why a [`TicketKeyRing`][crate::TicketKeyRing] could not open a ticket or
+/// import keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TicketError {
+    /// The ticket was sealed under a key which has aged out of the ring, or was never in
+    /// it. Contains the key id. This is expected of old tickets, whose holders should
+    /// fall back to a full handshake.
+    UnknownKey(u32),
+    /// The ticket was truncated, or tampered with.
+    Invalid,
+    /// Key material passed to [`TicketKeyRing::import`][crate::TicketKeyRing::import]
+    /// was not in the form produced by
+    /// [`TicketKeyRing::export`][crate::TicketKeyRing::export].
+    MalformedKeys,
+}
+
+impl fmt::Display for TicketError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TicketError::UnknownKey(id) => write!(f, "ticket key {:#010x} is not known", id),
+            TicketError::Invalid => write!(f, "ticket is truncated or has been tampered with"),
+            TicketError::MalformedKeys => write!(f, "exported ticket keys are malformed"),
+        }
+    }
+}
+impl Error for TicketError {}
+
+/// Describes why an [`AntiReplay`][crate::handshakes::AntiReplay] responder rejected the
+/// initiator's first handshake message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayError {
+    /// The message's timestamp is further in the past than the skew window allows.
+    /// Contains its age.
+    Stale(std::time::Duration),
+    /// The message's timestamp is further in the future than the skew window allows.
+    /// Contains how far ahead it is.
+    FromFuture(std::time::Duration),
+    /// The message's token has been seen before within the window.
+    Replayed,
+    /// Too many tokens within the window are remembered to accept another without
+    /// risking a replay. See [`ReplayGuard::capacity`][crate::handshakes::ReplayGuard::capacity].
+    CacheFull,
+    /// The message's payload is too short to hold a timestamp and token, so the
+    /// initiator is probably not using anti-replay.
+    MissingPayload,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Stale(age) => write!(f, "first message is stale by {:?}", age),
+            ReplayError::FromFuture(ahead) => {
+                write!(f, "first message is {:?} in the future", ahead)
+            }
+            ReplayError::Replayed => write!(f, "first message was replayed"),
+            ReplayError::CacheFull => write!(f, "replay cache is full"),
+            ReplayError::MissingPayload => {
+                write!(f, "first message has no anti-replay timestamp and token")
+            }
+        }
+    }
+}
+impl Error for ReplayError {}
+
+/// Describes why a stream could not be exported to, or imported from, a
+/// [`SessionSnapshot`][crate::SessionSnapshot].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionError {
+    /// The stream's transport keys are unknown, because it was not established by a
+    /// handshake in this process, such as one created with
+    /// [`NoiseStream::new`][crate::NoiseStream::new] from a bare transport state.
+    KeysUnavailable,
+    /// The stream has been exported, and can no longer be used.
+    Exported,
+    /// The snapshot is truncated, inconsistent, or was not produced by a compatible
+    /// version of this crate.
+    Malformed,
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SessionError::KeysUnavailable => write!(f, "stream's transport keys are unknown"),
+            SessionError::Exported => write!(f, "stream has been exported"),
+            SessionError::Malformed => write!(f, "session snapshot is malformed"),
+        }
+    }
+}
+impl Error for SessionError {}
+
+/// Describes why a connection's PROXY protocol header was rejected.
+///
+/// See [`ProxyHeader`][crate::ProxyHeader].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyHeaderError {
+    /// The connection did not start with a PROXY protocol header, so it probably did not
+    /// come through the proxy.
+    Missing,
+    /// A version 2 header declared a version of the protocol other than 2. Contains the
+    /// declared version.
+    UnsupportedVersion(u8),
+    /// The header was malformed, such as with an unparseable address, a command or
+    /// address family which doesn't exist, or TLVs which overrun the header.
+    Malformed,
+    /// The bytes given to [`ProxyHeader::parse`][crate::ProxyHeader::parse] ended
+    /// partway through the header.
+    Incomplete,
+    /// The whole header did not arrive before the timeout.
+    Timeout,
+}
+
+impl fmt::Display for ProxyHeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProxyHeaderError::Missing => write!(f, "header is missing"),
+            ProxyHeaderError::UnsupportedVersion(version) => {
+                write!(f, "header version {} is not supported", version)
+            }
+            ProxyHeaderError::Malformed => write!(f, "header is malformed"),
+            ProxyHeaderError::Incomplete => write!(f, "header is incomplete"),
+            ProxyHeaderError::Timeout => write!(f, "header did not arrive before the timeout"),
+        }
+    }
+}
+impl Error for ProxyHeaderError {}
+
 /// An error returned from custom handshake extension methods.
 #[derive(Debug)]
 pub struct HandshakeError {
@@ -59,3 +850,35 @@ impl fmt::Display for HandshakeError {
     }
 }
 impl Error for HandshakeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use snow::error::StateProblem;
+
+    #[test]
+    fn snow_errors_are_classified() {
+        for (e, kind) in [
+            (snow::Error::Decrypt, NoiseErrorKind::Decrypt),
+            (
+                snow::Error::State(StateProblem::Exhausted),
+                NoiseErrorKind::NonceExhausted,
+            ),
+            (
+                snow::Error::State(StateProblem::HandshakeNotFinished),
+                NoiseErrorKind::HandshakeIncomplete,
+            ),
+            (snow::Error::Input, NoiseErrorKind::InvalidInput),
+            (snow::Error::Dh, NoiseErrorKind::Other),
+        ] {
+            assert_eq!(NoiseError::from(e).kind(), kind);
+        }
+    }
+
+    #[test]
+    fn kind_survives_io_error_round_trip() {
+        let e: io::Error = NoiseError::from(snow::Error::State(StateProblem::Exhausted)).into();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(NoiseError::from(e).kind(), NoiseErrorKind::NonceExhausted);
+    }
+}
@@ -0,0 +1,62 @@
+use std::{fmt, sync::Arc};
+
+use crate::errors::NoiseError;
+
+/// Why a [`NoiseStream`][crate::NoiseStream] closed, as reported to
+/// [`ConnectionEvents::on_close`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CloseReason {
+    /// We shut the stream down, sending a close notify.
+    Shutdown,
+    /// We closed the stream with
+    /// [`close_with_error`][crate::NoiseStream::close_with_error].
+    LocalError {
+        /// The error code sent to the peer.
+        code: u32,
+        /// The reason sent to the peer.
+        reason: String,
+    },
+    /// The peer shut its side of the stream down cleanly.
+    PeerShutdown,
+    /// The peer closed the stream with an application error code and reason.
+    PeerError {
+        /// The error code sent by the peer.
+        code: u32,
+        /// The reason sent by the peer.
+        reason: String,
+    },
+    /// The transport reached EOF without the peer sending a close notify.
+    TransportClosed,
+}
+
+/// Receives notifications of events in the lifetime of a
+/// [`NoiseStream`][crate::NoiseStream], for telemetry. Every method does nothing by
+/// default. Set with [`NoiseBuilder::events`][crate::NoiseBuilder::events].
+///
+/// Each method receives the [name][crate::NoiseStream::name] of the stream, since one
+/// handler is shared by every stream created from the same builder.
+///
+/// The methods are called synchronously, from within the stream's reads and writes,
+/// so they must not block. Heavy work, or anything async, should be handed off through
+/// a channel.
+pub trait ConnectionEvents: Send + Sync {
+    /// Called once, when the stream is first closed by either side. A stream which is
+    /// dropped without closing reports nothing.
+    fn on_close(&self, _stream: &str, _reason: &CloseReason) {}
+
+    /// Called when the stream encounters an error: a frame which fails to decrypt, a
+    /// peer which breaks the protocol, or an IO error on the transport. Errors which
+    /// poison the stream are reported once, when they first occur.
+    fn on_error(&self, _stream: &str, _error: &NoiseError) {}
+}
+
+/// The handler set with [`NoiseBuilder::events`][crate::NoiseBuilder::events]. Clones
+/// share the same handler.
+#[derive(Clone)]
+pub(crate) struct EventsHook(pub(crate) Arc<dyn ConnectionEvents>);
+
+impl fmt::Debug for EventsHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("EventsHook")
+    }
+}
@@ -0,0 +1,201 @@
+use snow::{
+    params::HashChoice,
+    resolvers::{CryptoResolver, DefaultResolver},
+};
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+    str::FromStr,
+};
+
+use crate::{base64, errors::FingerprintError};
+
+/// The size of a [`Fingerprint`] in bytes.
+pub const FINGERPRINT_LEN: usize = 32;
+
+/// The prefix of a [`Fingerprint`]'s string form, naming its hash function.
+pub const FINGERPRINT_PREFIX: &str = "SHA256:";
+
+/// A short, stable identifier for a static public key, for use in logs, dashboards and
+/// allowlists where raw keys are unwieldy.
+///
+/// A fingerprint is the SHA-256 hash of the public key, as sent in the handshake. It is
+/// displayed in the style of OpenSSH, as `SHA256:` followed by the hash in unpadded
+/// base64, and parsed back from the same form. Comparisons take constant time.
+///
+/// ```
+/// use tokio_noise::Fingerprint;
+///
+/// let fingerprint = Fingerprint::of(&[0u8; 32]);
+/// assert_eq!(
+///     fingerprint.to_string(),
+///     "SHA256:Zmh6rfhivXdsj8GLjp+OIAiXFIVu4jOzkCpZHQ1fKSU"
+/// );
+/// assert_eq!(fingerprint.to_string().parse::<Fingerprint>().unwrap(), fingerprint);
+/// ```
+#[derive(Clone, Copy, Eq)]
+pub struct Fingerprint([u8; FINGERPRINT_LEN]);
+
+impl Fingerprint {
+    /// Compute the fingerprint of a public key.
+    pub fn of(public_key: &[u8]) -> Fingerprint {
+        let mut hash = DefaultResolver
+            .resolve_hash(&HashChoice::SHA256)
+            .expect("the default resolver provides SHA-256");
+        let mut fingerprint = [0u8; FINGERPRINT_LEN];
+        hash.input(public_key);
+        hash.result(&mut fingerprint);
+        Fingerprint(fingerprint)
+    }
+
+    /// Construct a fingerprint from its raw hash.
+    pub fn from_bytes(bytes: [u8; FINGERPRINT_LEN]) -> Fingerprint {
+        Fingerprint(bytes)
+    }
+
+    /// Returns the raw hash.
+    pub fn as_bytes(&self) -> &[u8; FINGERPRINT_LEN] {
+        &self.0
+    }
+
+    /// Returns whether this is the fingerprint of the given public key.
+    pub fn matches(&self, public_key: &[u8]) -> bool {
+        *self == Fingerprint::of(public_key)
+    }
+}
+
+impl PartialEq for Fingerprint {
+    /// Compares in constant time, so that checking a peer against an allowlist doesn't
+    /// reveal how close its key came to an allowed one.
+    fn eq(&self, other: &Fingerprint) -> bool {
+        self.0
+            .iter()
+            .zip(other.0.iter())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+    }
+}
+
+impl Hash for Fingerprint {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}{}",
+            FINGERPRINT_PREFIX,
+            base64::encode_unpadded(&self.0)
+        )
+    }
+}
+
+impl fmt::Debug for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Fingerprint({})", self)
+    }
+}
+
+impl FromStr for Fingerprint {
+    type Err = FingerprintError;
+
+    /// Parses the form produced by [`Display`][fmt::Display]. Padding is accepted, and
+    /// surrounding whitespace is ignored.
+    fn from_str(s: &str) -> Result<Fingerprint, FingerprintError> {
+        let encoded = s
+            .trim()
+            .strip_prefix(FINGERPRINT_PREFIX)
+            .ok_or(FingerprintError::MissingPrefix)?;
+        let decoded = base64::decode(encoded).ok_or(FingerprintError::InvalidBase64)?;
+        let bytes = decoded
+            .try_into()
+            .map_err(|decoded: Vec<u8>| FingerprintError::InvalidLength(decoded.len()))?;
+        Ok(Fingerprint(bytes))
+    }
+}
+
+/// Serializes as the string form produced by [`Display`][fmt::Display].
+#[cfg(feature = "serde")]
+impl serde::Serialize for Fingerprint {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Deserializes from the string form accepted by [`FromStr`].
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Fingerprint {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Fingerprint, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Changing any of these would break every stored allowlist.
+    #[test]
+    fn format_is_stable() {
+        let cases: [(&[u8], &str); 3] = [
+            (
+                &[0u8; 32],
+                "SHA256:Zmh6rfhivXdsj8GLjp+OIAiXFIVu4jOzkCpZHQ1fKSU",
+            ),
+            (
+                &core::array::from_fn::<u8, 32, _>(|i| i as u8),
+                "SHA256:Yw3NKWbEM2aRElRIu7JbT/QSpJxzLbLIq8G4WBvXEN0",
+            ),
+            (
+                &[
+                    0x03, 0x4f, 0x35, 0x5b, 0xdc, 0xb7, 0xcc, 0x0a, 0xf7, 0x28, 0xef, 0x3c, 0xce,
+                    0xb9, 0x61, 0x5d, 0x90, 0x68, 0x4b, 0xb5, 0xb2, 0xca, 0x5f, 0x85, 0x9a, 0xb0,
+                    0xf0, 0xb7, 0x04, 0x07, 0x58, 0x71, 0xaa,
+                ],
+                "SHA256:W2uSs3t2WWOrYdUqMXGlTaM3eMExGBCPkY54zSqOPBU",
+            ),
+        ];
+        for (key, expected) in cases {
+            assert_eq!(Fingerprint::of(key).to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn round_trips_through_strings() {
+        let fingerprint = Fingerprint::of(b"some key");
+        let s = fingerprint.to_string();
+        assert_eq!(s.parse::<Fingerprint>().unwrap(), fingerprint);
+        assert_eq!(
+            format!(" {}= \n", s).parse::<Fingerprint>().unwrap(),
+            fingerprint
+        );
+        assert!(fingerprint.matches(b"some key"));
+        assert!(!fingerprint.matches(b"other key"));
+        assert_ne!(fingerprint, Fingerprint::of(b"other key"));
+    }
+
+    #[test]
+    fn malformed_strings_are_rejected() {
+        let encoded = base64::encode_unpadded(&[7u8; 32]);
+        assert_eq!(
+            encoded.parse::<Fingerprint>(),
+            Err(FingerprintError::MissingPrefix)
+        );
+        assert_eq!(
+            format!("MD5:{}", encoded).parse::<Fingerprint>(),
+            Err(FingerprintError::MissingPrefix)
+        );
+        assert_eq!(
+            "SHA256:not base64!".parse::<Fingerprint>(),
+            Err(FingerprintError::InvalidBase64)
+        );
+        assert_eq!(
+            "SHA256:AAAA".parse::<Fingerprint>(),
+            Err(FingerprintError::InvalidLength(3))
+        );
+    }
+}
@@ -0,0 +1,327 @@
+//! This module provides [`AntiReplay`], which stops a recorded first handshake message
+//! from being replayed to a responder.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use snow::{params::NoiseParams, HandshakeState};
+
+use super::{backend_resolver, Handshake, NNpsk0};
+use crate::errors::{NoiseError, ReplayError};
+
+/// The size of the random token which identifies each first handshake message.
+pub const REPLAY_TOKEN_LEN: usize = 16;
+
+/// The number of bytes which [`AntiReplay`] adds to the initiator's first handshake
+/// payload: an 8-byte big-endian timestamp in milliseconds since the Unix epoch, and a
+/// random token.
+pub const REPLAY_PAYLOAD_LEN: usize = 8 + REPLAY_TOKEN_LEN;
+
+/// The default for [`ReplayGuard::max_skew`].
+pub const DEFAULT_MAX_SKEW: Duration = Duration::from_secs(30);
+
+/// The default for [`ReplayGuard::capacity`].
+pub const DEFAULT_REPLAY_CACHE_CAPACITY: usize = 64 * 1024;
+
+type ClockFn = dyn Fn() -> SystemTime + Send + Sync;
+
+#[derive(Clone)]
+struct Clock(Arc<ClockFn>);
+
+impl fmt::Debug for Clock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Clock")
+    }
+}
+
+/// Decides which first handshake messages an [`AntiReplay`] responder accepts.
+///
+/// A message is accepted if its timestamp is within [`max_skew`][Self::max_skew] of our
+/// clock, and its token hasn't been seen before. Each token is remembered until its
+/// timestamp falls out of the window, after which a replay is rejected as stale.
+///
+/// Clones share one cache of tokens, so a guard can be cloned into each connection's
+/// handshake, or shared between listeners.
+#[derive(Clone, Debug)]
+pub struct ReplayGuard {
+    max_skew: Duration,
+    capacity: usize,
+    clock: Option<Clock>,
+    /// The tokens seen, each with the time in milliseconds since the Unix epoch after
+    /// which its message is stale anyway.
+    seen: Arc<Mutex<HashMap<[u8; REPLAY_TOKEN_LEN], u64>>>,
+}
+
+impl Default for ReplayGuard {
+    fn default() -> ReplayGuard {
+        ReplayGuard::new()
+    }
+}
+
+impl ReplayGuard {
+    /// A guard with the default skew window and cache capacity.
+    pub fn new() -> ReplayGuard {
+        ReplayGuard {
+            max_skew: DEFAULT_MAX_SKEW,
+            capacity: DEFAULT_REPLAY_CACHE_CAPACITY,
+            clock: None,
+            seen: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Set how far the initiator's clock may be behind or ahead of ours. Replays are
+    /// rejected for twice this long after the original message, so it should be no
+    /// larger than the clock skew expected between peers. Defaults to
+    /// [`DEFAULT_MAX_SKEW`].
+    pub fn max_skew(mut self, max_skew: Duration) -> ReplayGuard {
+        self.max_skew = max_skew;
+        self
+    }
+
+    /// Set how many tokens may be remembered at once. Once the cache is full of tokens
+    /// which are still within the window, further handshakes fail with
+    /// [`ReplayError::CacheFull`] rather than risk accepting a replay. Defaults to
+    /// [`DEFAULT_REPLAY_CACHE_CAPACITY`].
+    pub fn capacity(mut self, capacity: usize) -> ReplayGuard {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Read the time from the given function rather than [`SystemTime::now`], for
+    /// example to test clock skew. The initiator stamps its messages with this clock too.
+    pub fn clock(mut self, clock: impl Fn() -> SystemTime + Send + Sync + 'static) -> ReplayGuard {
+        self.clock = Some(Clock(Arc::new(clock)));
+        self
+    }
+
+    /// Returns how many tokens are remembered.
+    pub fn cached_tokens(&self) -> usize {
+        self.seen.lock().unwrap().len()
+    }
+
+    /// The current time in milliseconds since the Unix epoch.
+    fn now_millis(&self) -> u64 {
+        let now = match &self.clock {
+            Some(Clock(clock)) => clock(),
+            None => SystemTime::now(),
+        };
+        now.duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64)
+    }
+
+    /// Build the payload for a new first message.
+    fn stamp(&self) -> [u8; REPLAY_PAYLOAD_LEN] {
+        let mut payload = [0u8; REPLAY_PAYLOAD_LEN];
+        payload[..8].copy_from_slice(&self.now_millis().to_be_bytes());
+        backend_resolver()
+            .resolve_rng()
+            .expect("the resolver provides a generator")
+            .fill_bytes(&mut payload[8..]);
+        payload
+    }
+
+    /// Accept a first message's payload, or explain why it must be rejected.
+    fn check(&self, payload: &[u8]) -> Result<(), ReplayError> {
+        if payload.len() < REPLAY_PAYLOAD_LEN {
+            return Err(ReplayError::MissingPayload);
+        }
+        let timestamp = u64::from_be_bytes(payload[..8].try_into().expect("8-byte slice"));
+        let token: [u8; REPLAY_TOKEN_LEN] = payload[8..REPLAY_PAYLOAD_LEN]
+            .try_into()
+            .expect("token-sized slice");
+
+        let now = self.now_millis();
+        let max_skew = self.max_skew.as_millis() as u64;
+        if now > timestamp && now - timestamp > max_skew {
+            return Err(ReplayError::Stale(Duration::from_millis(now - timestamp)));
+        }
+        if timestamp > now && timestamp - now > max_skew {
+            return Err(ReplayError::FromFuture(Duration::from_millis(
+                timestamp - now,
+            )));
+        }
+
+        let mut seen = self.seen.lock().unwrap();
+        if seen.contains_key(&token) {
+            return Err(ReplayError::Replayed);
+        }
+        if seen.len() >= self.capacity {
+            seen.retain(|_, expiry| *expiry >= now);
+            if seen.len() >= self.capacity {
+                return Err(ReplayError::CacheFull);
+            }
+        }
+        seen.insert(token, timestamp.saturating_add(max_skew));
+        Ok(())
+    }
+}
+
+/// Wraps a handshake so that the initiator's first message carries a timestamp and a
+/// random token, which the responder checks with a [`ReplayGuard`]. An attacker who
+/// records a first message can then neither replay it later, nor replay it again
+/// within the window.
+///
+/// The payload is only protected if the first message is encrypted, as it is in
+/// [`NNpsk0`] and in patterns where the initiator knows the responder's static key in
+/// advance. In other patterns an attacker could forge a fresh payload.
+///
+/// The inner handshake's [`initiator_first_message`][Handshake::initiator_first_message]
+/// is not called, since the first message carries the anti-replay payload instead. Its
+/// [`responder_first_message`][Handshake::responder_first_message] receives the payload
+/// with the timestamp and token stripped. Every other method is passed through.
+///
+/// ```
+/// use std::time::Duration;
+/// use tokio_noise::handshakes::{NNpsk0, ReplayGuard};
+///
+/// let guard = ReplayGuard::new().max_skew(Duration::from_secs(10));
+/// let handshake = NNpsk0::try_new(&[0xFF; 32]).unwrap().anti_replay(guard);
+/// ```
+#[derive(Clone, Debug)]
+pub struct AntiReplay<H> {
+    inner: H,
+    guard: ReplayGuard,
+}
+
+impl<H: Handshake> AntiReplay<H> {
+    /// Wrap a handshake, checking first messages with the given guard.
+    pub fn new(inner: H, guard: ReplayGuard) -> AntiReplay<H> {
+        AntiReplay { inner, guard }
+    }
+
+    /// Returns the guard which checks first messages.
+    pub fn guard(&self) -> &ReplayGuard {
+        &self.guard
+    }
+
+    /// Unwrap the inner handshake.
+    pub fn into_inner(self) -> H {
+        self.inner
+    }
+}
+
+impl NNpsk0 {
+    /// Protect the handshake from replayed first messages. See [`AntiReplay`].
+    pub fn anti_replay(self, guard: ReplayGuard) -> AntiReplay<NNpsk0> {
+        AntiReplay::new(self, guard)
+    }
+}
+
+impl<H: Handshake> Handshake for AntiReplay<H> {
+    fn name(&self) -> String {
+        self.inner.name()
+    }
+
+    fn params(&self) -> Result<NoiseParams, NoiseError> {
+        self.inner.params()
+    }
+
+    fn new_builder(&self) -> snow::Builder<'_> {
+        self.inner.new_builder()
+    }
+
+    fn local_static_public_key(&self) -> Option<Vec<u8>> {
+        self.inner.local_static_public_key()
+    }
+
+    fn local_key_count(&self) -> usize {
+        self.inner.local_key_count()
+    }
+
+    fn select_local_key(&mut self, index: usize) {
+        self.inner.select_local_key(index)
+    }
+
+    fn verify_remote_static(&self, remote_static_key: &[u8]) -> Result<(), NoiseError> {
+        self.inner.verify_remote_static(remote_static_key)
+    }
+
+    fn message_count(&self) -> usize {
+        self.inner.message_count()
+    }
+
+    fn initiator_first_message(
+        &mut self,
+        initiator: &mut HandshakeState,
+        send_buf: &mut [u8],
+    ) -> Result<usize, NoiseError> {
+        Ok(initiator.write_message(&self.guard.stamp(), send_buf)?)
+    }
+
+    fn responder_first_message(
+        &mut self,
+        responder: &mut HandshakeState,
+        recv_buf: &[u8],
+        send_buf: &mut [u8],
+    ) -> Result<usize, NoiseError> {
+        self.guard.check(recv_buf)?;
+        self.inner
+            .responder_first_message(responder, &recv_buf[REPLAY_PAYLOAD_LEN..], send_buf)
+    }
+
+    fn initiator_second_message(
+        &mut self,
+        initiator: &mut HandshakeState,
+        recv_buf: &[u8],
+        send_buf: &mut [u8],
+    ) -> Result<usize, NoiseError> {
+        self.inner
+            .initiator_second_message(initiator, recv_buf, send_buf)
+    }
+
+    fn responder_second_message(
+        &mut self,
+        responder: &mut HandshakeState,
+        recv_buf: &[u8],
+        send_buf: &mut [u8],
+    ) -> Result<usize, NoiseError> {
+        self.inner
+            .responder_second_message(responder, recv_buf, send_buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload_at(timestamp: u64, token: u8) -> [u8; REPLAY_PAYLOAD_LEN] {
+        let mut payload = [token; REPLAY_PAYLOAD_LEN];
+        payload[..8].copy_from_slice(&timestamp.to_be_bytes());
+        payload
+    }
+
+    #[test]
+    fn expired_tokens_make_room_in_a_full_cache() {
+        let now = Arc::new(Mutex::new(UNIX_EPOCH + Duration::from_secs(1_000)));
+        let clock = now.clone();
+        let guard = ReplayGuard::new()
+            .max_skew(Duration::from_secs(10))
+            .capacity(2)
+            .clock(move || *clock.lock().unwrap());
+        let millis = 1_000_000;
+
+        guard.check(&payload_at(millis, 1)).unwrap();
+        guard.check(&payload_at(millis, 2)).unwrap();
+        assert_eq!(
+            guard.check(&payload_at(millis, 3)),
+            Err(ReplayError::CacheFull)
+        );
+
+        // Once the first two are stale, their tokens are forgotten.
+        *now.lock().unwrap() += Duration::from_secs(20);
+        guard.check(&payload_at(millis + 20_000, 3)).unwrap();
+        assert_eq!(guard.cached_tokens(), 1);
+    }
+
+    #[test]
+    fn short_payload_is_rejected() {
+        assert_eq!(
+            ReplayGuard::new().check(&[0; REPLAY_PAYLOAD_LEN - 1]),
+            Err(ReplayError::MissingPayload)
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! This module provides [`StaticKeyOps`], for handshakes whose static private key is
+//! held outside the process, such as in an HSM or a KMS.
+
+use std::{
+    future::Future,
+    pin::pin,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+};
+
+use log::error;
+use snow::{
+    params::{CipherChoice, DHChoice, HashChoice},
+    resolvers::CryptoResolver,
+    types::{Cipher, Dh, Hash, Random},
+};
+
+use crate::{config::BoxFuture, errors::NoiseError};
+
+/// The length of an X25519 key, or of the result of a DH with one.
+pub const X25519_KEY_LEN: usize = 32;
+
+/// The operations a handshake needs from a static X25519 private key which it cannot
+/// hold itself: the matching public key, and Diffie-Hellman with a peer's public key.
+///
+/// Implement this for a signer backed by an HSM or a KMS, and pass it to
+/// [`SnowHandshake::local_key_ops`][super::SnowHandshake::local_key_ops] in place of
+/// the private key bytes.
+///
+/// # Blocking
+///
+/// [`snow`] computes each DH synchronously, partway through writing or reading a
+/// handshake message, so the future returned by [`dh`][Self::dh] is driven to
+/// completion by blocking the thread which drives the handshake. The future must make
+/// progress without that thread: it may complete immediately, or wait on a thread or
+/// runtime of its own, such as an HSM client's connection pool. A future which awaits
+/// IO on the same current-thread runtime as the handshake never completes.
+///
+/// Handshake patterns call `dh` at most twice per handshake, so on a multi-threaded
+/// runtime a brief block is usually acceptable.
+pub trait StaticKeyOps: Send + Sync {
+    /// Returns the X25519 public key matching the private key.
+    fn public_key(&self) -> [u8; X25519_KEY_LEN];
+
+    /// Computes the X25519 Diffie-Hellman shared secret between the private key and
+    /// `remote_public`. A failure aborts the handshake.
+    fn dh<'a>(
+        &'a self,
+        remote_public: &'a [u8; X25519_KEY_LEN],
+    ) -> BoxFuture<'a, Result<[u8; X25519_KEY_LEN], NoiseError>>;
+}
+
+/// Resolves the given primitives, except that a DH object given a private key
+/// delegates to a [`StaticKeyOps`] instead.
+pub(crate) struct KeyOpsResolver {
+    ops: Arc<dyn StaticKeyOps>,
+    primitives: Box<dyn CryptoResolver + Send>,
+}
+
+impl KeyOpsResolver {
+    pub(crate) fn new(
+        ops: Arc<dyn StaticKeyOps>,
+        primitives: Box<dyn CryptoResolver + Send>,
+    ) -> KeyOpsResolver {
+        KeyOpsResolver { ops, primitives }
+    }
+}
+
+impl CryptoResolver for KeyOpsResolver {
+    fn resolve_rng(&self) -> Option<Box<dyn Random>> {
+        self.primitives.resolve_rng()
+    }
+
+    fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<dyn Dh>> {
+        if *choice != DHChoice::Curve25519 {
+            return None;
+        }
+        Some(Box::new(KeyOpsDh {
+            ops: self.ops.clone(),
+            public_key: self.ops.public_key(),
+            external: false,
+            software: self.primitives.resolve_dh(choice)?,
+        }))
+    }
+
+    fn resolve_hash(&self, choice: &HashChoice) -> Option<Box<dyn Hash>> {
+        self.primitives.resolve_hash(choice)
+    }
+
+    fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<dyn Cipher>> {
+        self.primitives.resolve_cipher(choice)
+    }
+}
+
+/// An X25519 DH object. Snow sets the static key with [`Dh::set`], which switches it
+/// over to the [`StaticKeyOps`], while ephemeral keys are generated and used in
+/// software as usual.
+struct KeyOpsDh {
+    ops: Arc<dyn StaticKeyOps>,
+    public_key: [u8; X25519_KEY_LEN],
+    external: bool,
+    software: Box<dyn Dh>,
+}
+
+impl Dh for KeyOpsDh {
+    fn name(&self) -> &'static str {
+        self.software.name()
+    }
+
+    fn pub_len(&self) -> usize {
+        self.software.pub_len()
+    }
+
+    fn priv_len(&self) -> usize {
+        self.software.priv_len()
+    }
+
+    /// The private key passed is a placeholder, and is ignored.
+    fn set(&mut self, _privkey: &[u8]) {
+        self.external = true;
+    }
+
+    fn generate(&mut self, rng: &mut dyn Random) {
+        self.external = false;
+        self.software.generate(rng);
+    }
+
+    fn pubkey(&self) -> &[u8] {
+        match self.external {
+            true => &self.public_key,
+            false => self.software.pubkey(),
+        }
+    }
+
+    fn privkey(&self) -> &[u8] {
+        match self.external {
+            true => &[],
+            false => self.software.privkey(),
+        }
+    }
+
+    fn dh(&self, pubkey: &[u8], out: &mut [u8]) -> Result<(), snow::Error> {
+        if !self.external {
+            return self.software.dh(pubkey, out);
+        }
+        // Snow passes keys in buffers sized for its largest DH function.
+        let remote_public = pubkey
+            .get(..X25519_KEY_LEN)
+            .and_then(|key| key.try_into().ok())
+            .ok_or(snow::Error::Dh)?;
+        let shared = block_on(self.ops.dh(remote_public)).map_err(|e| {
+            error!("static key DH failed: {}", e);
+            snow::Error::Dh
+        })?;
+        out[..X25519_KEY_LEN].copy_from_slice(&shared);
+        Ok(())
+    }
+}
+
+/// Wakes a thread parked in [`block_on`].
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Drive a future to completion on the current thread, parking it while the future is
+/// pending.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
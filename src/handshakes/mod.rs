@@ -1,17 +1,40 @@
 //! This module encapsulates an interface for customizing handshake protocols.
 
+use log::info;
+#[cfg(feature = "crypto-ring")]
+use snow::resolvers::{FallbackResolver, RingResolver};
 use snow::{
-    params::{CipherChoice, DHChoice, HashChoice},
+    params::{CipherChoice, DHChoice, HandshakePattern, HashChoice, NoiseParams},
+    resolvers::{BoxedCryptoResolver, DefaultResolver},
     HandshakeState,
 };
 
 use crate::errors::{HandshakeError, NoiseError};
 
+pub mod anti_replay;
+pub mod key_ops;
 pub mod nn_psk0;
 pub mod nn_psk2;
+pub mod rng;
+#[cfg(feature = "secp256k1")]
+pub mod secp256k1;
+pub mod snow_handshake;
 
+pub use anti_replay::{AntiReplay, ReplayGuard};
+pub use key_ops::StaticKeyOps;
 pub use nn_psk0::NNpsk0;
 pub use nn_psk2::NNpsk2;
+pub use rng::SharedRng;
+#[cfg(feature = "secp256k1")]
+pub use secp256k1::Secp256k1Handshake;
+pub use snow_handshake::SnowHandshake;
+
+/// The length of a pre-shared key (PSK), as required by the Noise protocol.
+pub const PSK_LEN: usize = 32;
+
+/// The largest number of messages a handshake may use. Each message is created by
+/// one of the message methods on [`Handshake`].
+pub const MAX_HANDSHAKE_MESSAGES: usize = 4;
 
 /// A default choice for the diffie-hellman key-exchange group.
 pub const DEFAULT_DH_CHOICE: DHChoice = DHChoice::Curve25519;
@@ -22,6 +45,29 @@ pub const DEFAULT_CIPHER_CHOICE: CipherChoice = CipherChoice::ChaChaPoly;
 /// A default choice for a secure hash function.
 pub const DEFAULT_HASH_CHOICE: HashChoice = HashChoice::SHA512;
 
+/// The cryptography backend which handshakes draw their primitives from, as chosen by
+/// cargo features: `"ring"` with the `crypto-ring` feature, which is on by default, and
+/// `"rust"` with `default-features = false, features = ["crypto-rust"]`.
+///
+/// Backends differ only in speed. They implement the same primitives, so protocol names
+/// and the wire format are the same, and peers using different backends interoperate.
+pub const CRYPTO_BACKEND: &str = if cfg!(feature = "crypto-ring") {
+    "ring"
+} else {
+    "rust"
+};
+
+/// Resolves primitives from the [`CRYPTO_BACKEND`], as [`snow::Builder::new`] does.
+pub(crate) fn backend_resolver() -> BoxedCryptoResolver {
+    #[cfg(feature = "crypto-ring")]
+    return Box::new(FallbackResolver::new(
+        Box::new(RingResolver),
+        Box::new(DefaultResolver),
+    ));
+    #[cfg(not(feature = "crypto-ring"))]
+    Box::new(DefaultResolver)
+}
+
 /// A set of cryptographic primitives which make up the functional dependencies
 /// of a Noise handshake protocol instantiation.
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -55,21 +101,46 @@ impl CryptoChoices {
                 DHChoice::Curve25519 => "25519",
                 DHChoice::Ed448 => "448",
             },
-            match self.cipher {
-                CipherChoice::AESGCM => "AESGCM",
-                CipherChoice::ChaChaPoly => "ChaChaPoly",
-                // CipherChoice::XChaChaPoly => "XChaChaPoly",
-            },
-            match self.hash {
-                HashChoice::SHA256 => "SHA256",
-                HashChoice::SHA512 => "SHA512",
-                HashChoice::Blake2s => "BLAKE2s",
-                HashChoice::Blake2b => "BLAKE2b",
-            },
+            cipher_name(self.cipher),
+            hash_name(self.hash),
         )
     }
 }
 
+/// The name of a cipher in a Noise protocol name.
+pub(crate) fn cipher_name(cipher: CipherChoice) -> &'static str {
+    match cipher {
+        CipherChoice::AESGCM => "AESGCM",
+        CipherChoice::ChaChaPoly => "ChaChaPoly",
+        // CipherChoice::XChaChaPoly => "XChaChaPoly",
+    }
+}
+
+/// The name of a hash function in a Noise protocol name.
+pub(crate) fn hash_name(hash: HashChoice) -> &'static str {
+    match hash {
+        HashChoice::SHA256 => "SHA256",
+        HashChoice::SHA512 => "SHA512",
+        HashChoice::Blake2s => "BLAKE2s",
+        HashChoice::Blake2b => "BLAKE2b",
+    }
+}
+
+/// Returns the number of messages exchanged by a Noise handshake pattern, not
+/// counting pre-messages.
+///
+/// See: <https://noiseprotocol.org/noise.html#handshake-patterns>
+pub fn pattern_message_count(pattern: HandshakePattern) -> usize {
+    use HandshakePattern::*;
+    match pattern {
+        N | X | K => 1,
+        NN | NK | NX | KN | KK | KX | IN | IK | IX | NK1 | KK1 | IK1 => 2,
+        XN | XK | XX | NX1 | XK1 | XX1 | K1N | K1K | K1K1 | K1X | KX1 | K1X1 | I1N | I1K | I1K1
+        | I1X | IX1 | I1X1 => 3,
+        X1N | X1K | X1K1 | X1X | X1X1 => 4,
+    }
+}
+
 /// A type which implements `Handshake` is a particular instantiation of the Noise protocol
 /// with a pre-arranged authentication and encryption procedure.
 ///
@@ -79,14 +150,85 @@ impl CryptoChoices {
 /// By overriding certain methods on `Handshake`, a caller can extend the handshake protocol
 /// by attaching additional authenticated data along with each Noise protocol message.
 pub trait Handshake {
-    /// Returns a string pattern representing the handshake pattern and cryptographic primitives
-    /// in use. This can be constructed with [`CryptoChoices::stringify_with_pattern`].
+    /// Returns the Noise protocol name, representing the handshake pattern and cryptographic
+    /// primitives in use. This can be constructed with [`CryptoChoices::stringify_with_pattern`].
+    ///
+    /// The name must be a valid Noise protocol name, as checked by [`Handshake::params`],
+    /// and is reported by [`NoiseStream::protocol_name`][crate::NoiseStream::protocol_name]
+    /// once the handshake completes.
     fn name(&self) -> String;
 
+    /// Parses [`Handshake::name`] into its protocol parameters, failing with a
+    /// [`HandshakeError`] which describes the problem if it isn't a valid Noise protocol
+    /// name.
+    ///
+    /// Each handshake checks this before sending or receiving any messages. Call it
+    /// when constructing a handshake to catch a malformed name even sooner.
+    fn params(&self) -> Result<NoiseParams, NoiseError> {
+        let name = self.name();
+        name.parse::<NoiseParams>().map_err(|e| {
+            self.error(format_args!(
+                "invalid Noise protocol name {:?}: {}",
+                name, e
+            ))
+            .into()
+        })
+    }
+
     /// Construct a handshake state [`Builder`][snow::Builder]. This can be useful for setting a custom
     /// [`CryptoResolver`][snow::resolvers::CryptoResolver], or to set pre-shared symmetric keys or
     /// known static public keys.
-    fn new_builder(&self) -> snow::Builder;
+    fn new_builder(&self) -> snow::Builder<'_>;
+
+    /// Returns our static public key, for handshake patterns in which we have one. It is
+    /// reported by
+    /// [`NoiseStream::local_static_public_key`][crate::NoiseStream::local_static_public_key]
+    /// once the handshake completes.
+    ///
+    /// By default this returns `None`, as is correct for the built-in `NN` handshakes.
+    fn local_static_public_key(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Returns how many static keys the responder holds. While a key is being rotated,
+    /// initiators may encrypt their first message to either the old or the new key, in
+    /// patterns such as `NK`, `XK` and `IK` where the responder's key is known in
+    /// advance. If that message fails to decrypt under the first key, the responder
+    /// tries each other key in turn with [`select_local_key`][Self::select_local_key].
+    ///
+    /// By default there is one key. See [`SnowHandshake::fallback_local_private_key`].
+    fn local_key_count(&self) -> usize {
+        1
+    }
+
+    /// Switches to the static key at `index`, below
+    /// [`local_key_count`][Self::local_key_count], so that
+    /// [`new_builder`][Self::new_builder] and
+    /// [`local_static_public_key`][Self::local_static_public_key] use it. Index 0 is the
+    /// primary key, which is used unless the initiator's first message needs another.
+    fn select_local_key(&mut self, _index: usize) {}
+
+    /// Checks the peer's static public key, failing the handshake if it isn't the key
+    /// we expect. The handshake calls this as soon as the key is known, after the
+    /// message which revealed it and before sending anything more, so a rejected peer
+    /// never receives our next message.
+    ///
+    /// By default any key is accepted. See [`SnowHandshake::expect_remote_static`].
+    fn verify_remote_static(&self, _remote_static_key: &[u8]) -> Result<(), NoiseError> {
+        Ok(())
+    }
+
+    /// Returns the number of messages exchanged during the handshake, which may be at
+    /// most [`MAX_HANDSHAKE_MESSAGES`].
+    ///
+    /// By default, this looks up the handshake pattern from [`Handshake::params`] with
+    /// [`pattern_message_count`].
+    fn message_count(&self) -> usize {
+        match self.params() {
+            Ok(params) => pattern_message_count(params.handshake.pattern),
+            Err(_) => 0,
+        }
+    }
 
     /// Creates the initiator's first message. This begins the Noise conversation.
     ///
@@ -190,3 +332,105 @@ pub trait Handshake {
         }
     }
 }
+
+/// Read the initiator's first message, which failed to decrypt under the responder's
+/// primary static key, under each of its other keys in turn. Returns a fresh state
+/// which read the message, and the length of its cleartext, or leaves the primary key
+/// selected and fails if none could.
+pub(crate) fn trial_local_keys(
+    handshake: &mut impl Handshake,
+    message: &[u8],
+    clear_buf: &mut [u8],
+) -> Result<(HandshakeState, usize), NoiseError> {
+    for index in 1..handshake.local_key_count() {
+        handshake.select_local_key(index);
+        let mut state = build_state(handshake, false)?;
+        if let Ok(n) = state.read_message(message, clear_buf) {
+            info!(
+                "[responder] initiator's first message decrypted under fallback static key {}",
+                index
+            );
+            return Ok((state, n));
+        }
+    }
+    handshake.select_local_key(0);
+    Err(snow::Error::Decrypt.into())
+}
+
+/// Build one side's handshake state, once the handshake's protocol name is known to be
+/// valid.
+pub(crate) fn build_state(
+    handshake: &impl Handshake,
+    initiator: bool,
+) -> Result<HandshakeState, NoiseError> {
+    handshake.params()?;
+    let builder = handshake.new_builder();
+    let state = if initiator {
+        builder.build_initiator()?
+    } else {
+        builder.build_responder()?
+    };
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A handshake whose name has a typo in its hash function.
+    struct Misnamed(NNpsk0);
+
+    impl Handshake for Misnamed {
+        fn name(&self) -> String {
+            "Noise_NNpsk0_25519_ChaChaPoly_SHA521".to_string()
+        }
+
+        fn new_builder(&self) -> snow::Builder<'_> {
+            self.0.new_builder()
+        }
+    }
+
+    #[test]
+    fn pattern_message_counts() {
+        for (pattern, count) in [
+            (HandshakePattern::N, 1),
+            (HandshakePattern::NN, 2),
+            (HandshakePattern::IK, 2),
+            (HandshakePattern::XX, 3),
+            (HandshakePattern::KX1, 3),
+            (HandshakePattern::X1K, 4),
+        ] {
+            assert_eq!(pattern_message_count(pattern), count, "{:?}", pattern);
+        }
+    }
+
+    #[test]
+    fn message_count_is_parsed_from_name() {
+        assert_eq!(
+            NNpsk0::try_new(&[0xFF; PSK_LEN]).unwrap().message_count(),
+            2
+        );
+        let initiator = nn_psk2::Initiator::try_new("alice", &[0xFF; PSK_LEN]).unwrap();
+        assert_eq!(NNpsk2::new(initiator).message_count(), 2);
+    }
+
+    #[test]
+    fn params_are_parsed_from_name() {
+        let params = NNpsk0::try_new(&[0xFF; PSK_LEN]).unwrap().params().unwrap();
+        assert_eq!(params.handshake.pattern, HandshakePattern::NN);
+        assert_eq!(params.hash, DEFAULT_HASH_CHOICE);
+    }
+
+    #[test]
+    fn malformed_name_is_rejected_before_building_state() {
+        let handshake = Misnamed(NNpsk0::try_new(&[0xFF; PSK_LEN]).unwrap());
+        match build_state(&handshake, true) {
+            Err(NoiseError::Handshake(e)) => {
+                assert_eq!(e.handshake_pattern, handshake.name());
+                assert!(e.description.contains("invalid Noise protocol name"));
+            }
+            result => panic!("expected handshake error, got {:?}", result.err()),
+        }
+        assert_eq!(handshake.message_count(), 0);
+    }
+}
@@ -4,50 +4,125 @@
 //! which is mixed into the handshake before any communication takes place.
 //! Every message is protected by the PSK.
 
+use std::fmt;
+
 use snow::params::{
     BaseChoice, HandshakeChoice, HandshakeModifier, HandshakeModifierList, HandshakePattern,
     NoiseParams,
 };
 
-use super::{CryptoChoices, Handshake};
+use super::{CryptoChoices, Handshake, PSK_LEN};
+use crate::{
+    base64,
+    errors::{NoiseError, PskError},
+    hex,
+};
+
+/// Checks that the given PSK is acceptable for use with [`NNpsk0`], without
+/// conducting a handshake. The PSK must be exactly [`PSK_LEN`] bytes, and must not be
+/// all zeros.
+///
+/// This is the same validation applied by [`NNpsk0::try_new`]. It can be used at startup
+/// to fail fast on a misconfigured PSK, rather than on the first connection.
+pub fn validate_psk(psk: &[u8]) -> Result<(), NoiseError> {
+    if psk.len() != PSK_LEN {
+        return Err(PskError::InvalidLength(psk.len()).into());
+    }
+    if psk.iter().all(|&b| b == 0) {
+        return Err(PskError::AllZero.into());
+    }
+    Ok(())
+}
 
 /// Represents an `NNpsk0` handshake, where both parties have a pre-shared key (PSK)
 /// which they can use to identify and authenticate each other during the handshake.
-#[derive(Clone, Copy, Debug)]
-pub struct NNpsk0<'a> {
+///
+/// The handshake owns a copy of the PSK, so it can be stored in a long-lived
+/// [`HandshakeConfig`][crate::HandshakeConfig] and cloned for each connection. The PSK
+/// can only be set through the constructors, so it has always passed [`validate_psk`].
+#[derive(Clone)]
+pub struct NNpsk0 {
     /// The pre-shared key (PSK) known to both initiator and responder.
-    pub psk: &'a [u8],
+    psk: [u8; PSK_LEN],
     /// The cryptographic primitives needed for the handshake.
     pub choices: CryptoChoices,
 }
 
-impl<'a> NNpsk0<'a> {
-    /// Constructs an `NNpsk0` handshake using the given PSK.
-    pub fn new(psk: &'a [u8]) -> Self {
-        assert!(
-            psk.len() >= 16,
-            "PSK length {} is unsafe, not enough entropy",
-            psk.len()
-        );
+impl NNpsk0 {
+    /// Constructs an `NNpsk0` handshake using a copy of the given PSK.
+    ///
+    /// Panics if the PSK is rejected by [`validate_psk`], which includes the all-zero
+    /// PSK this used to accept.
+    #[deprecated(note = "use try_new")]
+    pub fn new(psk: &[u8]) -> Self {
+        #[allow(deprecated)]
+        NNpsk0::new_custom(psk, CryptoChoices::default())
+    }
 
-        NNpsk0 {
-            psk,
-            choices: CryptoChoices::default(),
+    /// Constructs an `NNpsk0` handshake using a copy of the given PSK and ciphersuite
+    /// parameters.
+    ///
+    /// Panics if the PSK is rejected by [`validate_psk`], which includes the all-zero
+    /// PSK this used to accept.
+    #[deprecated(note = "use try_new_custom")]
+    pub fn new_custom(psk: &[u8], choices: CryptoChoices) -> Self {
+        match NNpsk0::try_new_custom(psk, choices) {
+            Ok(handshake) => handshake,
+            Err(e) => panic!("{}", e),
         }
     }
 
-    /// Constructs an `NNpsk0` handshake using the given PSK and ciphersuite parameters.
-    pub fn new_custom(psk: &'a [u8], choices: CryptoChoices) -> Self {
-        NNpsk0 { psk, choices }
+    /// Constructs an `NNpsk0` handshake using a copy of the given PSK, failing if the
+    /// PSK is rejected by [`validate_psk`].
+    pub fn try_new(psk: &[u8]) -> Result<Self, NoiseError> {
+        NNpsk0::try_new_custom(psk, CryptoChoices::default())
+    }
+
+    /// Constructs an `NNpsk0` handshake using a copy of the given PSK and ciphersuite
+    /// parameters, failing if the PSK is rejected by [`validate_psk`].
+    pub fn try_new_custom(psk: &[u8], choices: CryptoChoices) -> Result<Self, NoiseError> {
+        validate_psk(psk)?;
+        let mut owned_psk = [0u8; PSK_LEN];
+        owned_psk.copy_from_slice(psk);
+        Ok(NNpsk0 {
+            psk: owned_psk,
+            choices,
+        })
+    }
+
+    /// Returns the pre-shared key (PSK) known to both initiator and responder.
+    pub fn psk(&self) -> &[u8; PSK_LEN] {
+        &self.psk
+    }
+
+    /// Constructs an `NNpsk0` handshake from a PSK encoded as a hex string, such as one
+    /// read from a config file. Surrounding whitespace is ignored.
+    pub fn from_hex(psk: &str) -> Result<Self, NoiseError> {
+        NNpsk0::try_new(&hex::decode(psk.trim()).ok_or(PskError::InvalidHex)?)
+    }
+
+    /// Constructs an `NNpsk0` handshake from a PSK encoded as a standard base64 string,
+    /// with or without padding. Surrounding whitespace is ignored.
+    pub fn from_base64(psk: &str) -> Result<Self, NoiseError> {
+        NNpsk0::try_new(&base64::decode(psk.trim()).ok_or(PskError::InvalidBase64)?)
     }
 }
 
-impl<'a> Handshake for NNpsk0<'a> {
+impl fmt::Debug for NNpsk0 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The PSK is left out, so it doesn't end up in logs.
+        f.debug_struct("NNpsk0")
+            .field("choices", &self.choices)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Handshake for NNpsk0 {
     fn name(&self) -> String {
         self.choices.stringify_with_pattern("NNpsk0")
     }
 
-    fn new_builder(&self) -> snow::Builder {
+    fn new_builder(&self) -> snow::Builder<'_> {
         let params = NoiseParams {
             name: self.name(),
             base: BaseChoice::Noise,
@@ -61,6 +136,128 @@ impl<'a> Handshake for NNpsk0<'a> {
             cipher: self.choices.cipher,
             hash: self.choices.hash,
         };
-        snow::Builder::new(params).psk(0, self.psk)
+        snow::Builder::new(params).psk(0, &self.psk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_psk_checks_length() {
+        assert!(validate_psk(&[0xFF; 32]).is_ok());
+
+        for len in [0, 16, 31, 33, 64] {
+            match validate_psk(&vec![0xFF; len]) {
+                Err(NoiseError::InvalidPsk(PskError::InvalidLength(n))) => assert_eq!(n, len),
+                result => panic!("expected invalid length error, got {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn validate_psk_rejects_all_zero() {
+        assert!(matches!(
+            validate_psk(&[0; PSK_LEN]),
+            Err(NoiseError::InvalidPsk(PskError::AllZero))
+        ));
+        let mut psk = [0; PSK_LEN];
+        psk[PSK_LEN - 1] = 1;
+        assert!(validate_psk(&psk).is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "PSK length 16 is invalid")]
+    #[allow(deprecated)]
+    fn new_rejects_invalid_psk() {
+        NNpsk0::new(&[0xFF; 16]);
+    }
+
+    #[test]
+    fn try_new_rejects_invalid_psk() {
+        assert_eq!(
+            NNpsk0::try_new(&[0xAB; PSK_LEN]).unwrap().psk,
+            [0xAB; PSK_LEN]
+        );
+        assert!(matches!(
+            NNpsk0::try_new(&[0xFF; 16]),
+            Err(NoiseError::InvalidPsk(PskError::InvalidLength(16)))
+        ));
+        assert!(matches!(
+            NNpsk0::try_new(&[0; PSK_LEN]),
+            Err(NoiseError::InvalidPsk(PskError::AllZero))
+        ));
+    }
+
+    #[test]
+    fn debug_omits_psk() {
+        let handshake = NNpsk0::try_new(&[0xAB; PSK_LEN]).unwrap();
+        let debug = format!("{:?}", handshake);
+        assert!(debug.starts_with("NNpsk0"), "{}", debug);
+        assert!(!debug.contains("171"), "{}", debug);
+    }
+
+    #[test]
+    fn from_hex_decodes_psk() {
+        let psk: Vec<u8> = (0..PSK_LEN as u8).collect();
+        let hex: String = psk.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(NNpsk0::from_hex(&hex).unwrap().psk[..], psk[..]);
+        let upper = format!("  {}\n", hex.to_uppercase());
+        assert_eq!(NNpsk0::from_hex(&upper).unwrap().psk[..], psk[..]);
+    }
+
+    #[test]
+    fn from_hex_rejects_invalid_psk() {
+        for (hex, expected) in [
+            ("ff".repeat(PSK_LEN - 1) + "f", PskError::InvalidHex),
+            ("ff".repeat(PSK_LEN - 1) + "fg", PskError::InvalidHex),
+            ("ff".repeat(16), PskError::InvalidLength(16)),
+            ("00".repeat(PSK_LEN), PskError::AllZero),
+        ] {
+            match NNpsk0::from_hex(&hex) {
+                Err(NoiseError::InvalidPsk(e)) => assert_eq!(e, expected, "{}", hex),
+                result => panic!("expected PSK error for {}, got {:?}", hex, result),
+            }
+        }
+    }
+
+    #[test]
+    fn from_base64_decodes_psk() {
+        // 32 bytes counting up from zero.
+        let encoded = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+        let psk: Vec<u8> = (0..PSK_LEN as u8).collect();
+        assert_eq!(NNpsk0::from_base64(encoded).unwrap().psk[..], psk[..]);
+        let unpadded = encoded.trim_end_matches('=');
+        assert_eq!(NNpsk0::from_base64(unpadded).unwrap().psk[..], psk[..]);
+    }
+
+    #[test]
+    fn from_base64_rejects_invalid_psk() {
+        for (encoded, expected) in [
+            (
+                "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8===",
+                PskError::InvalidBase64,
+            ),
+            (
+                "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh*=",
+                PskError::InvalidBase64,
+            ),
+            // The final character carries bits past the end of the last byte.
+            (
+                "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh9=",
+                PskError::InvalidBase64,
+            ),
+            ("AAECAwQFBgcICQoLDA0ODw==", PskError::InvalidLength(16)),
+            (
+                "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=",
+                PskError::AllZero,
+            ),
+        ] {
+            match NNpsk0::from_base64(encoded) {
+                Err(NoiseError::InvalidPsk(e)) => assert_eq!(e, expected, "{}", encoded),
+                result => panic!("expected PSK error for {}, got {:?}", encoded, result),
+            }
+        }
     }
 }
@@ -10,6 +10,8 @@
 //! That and every successive message is protected by the PSK, but the
 //! initiator's first identity message is not protected or authenticated.
 
+use std::fmt;
+
 use snow::{
     params::{
         BaseChoice, HandshakeChoice, HandshakeModifier, HandshakeModifierList, HandshakePattern,
@@ -18,25 +20,72 @@ use snow::{
     HandshakeState,
 };
 
-use crate::errors::NoiseError;
+use crate::errors::{HandshakeError, NoiseError};
 
-use super::{CryptoChoices, Handshake};
+use super::{CryptoChoices, Handshake, PSK_LEN};
 
 /// The `Initiator` is the [`NNpsk2`] party responsible for sending the first message
 /// including her own identity. The initiator should already know the PSK.
-#[derive(Clone, Copy, Debug)]
-pub struct Initiator<'p> {
+///
+/// The PSK can only be set through the constructors, so it has always passed
+/// [`validate_psk`][crate::validate_psk].
+#[derive(Clone)]
+pub struct Initiator {
     /// The identity given in plaintext to the responder.
-    pub identity: &'p [u8],
-    /// The PSK which will be mixed into the handshake after the first initial message.
-    /// The responder should be able to look up the same PSK using the value of the
-    /// [`Initiator::identity`] field.
-    pub psk: &'p [u8],
+    pub identity: Vec<u8>,
+    psk: [u8; PSK_LEN],
+}
+
+impl Initiator {
+    /// Construct a new [`Initiator`] from an identity and a copy of the PSK associated
+    /// with it.
+    ///
+    /// Panics if the PSK is rejected by [`validate_psk`][crate::validate_psk].
+    #[deprecated(note = "use try_new")]
+    pub fn new(identity: impl Into<Vec<u8>>, psk: &[u8]) -> Self {
+        match Initiator::try_new(identity, psk) {
+            Ok(initiator) => initiator,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Construct a new [`Initiator`] from an identity and a copy of the PSK associated
+    /// with it, failing if the PSK is rejected by [`validate_psk`][crate::validate_psk].
+    pub fn try_new(identity: impl Into<Vec<u8>>, psk: &[u8]) -> Result<Self, NoiseError> {
+        crate::validate_psk(psk)?;
+        let mut owned_psk = [0u8; PSK_LEN];
+        owned_psk.copy_from_slice(psk);
+        Ok(Initiator {
+            identity: identity.into(),
+            psk: owned_psk,
+        })
+    }
+
+    /// Returns the PSK which will be mixed into the handshake after the first initial
+    /// message. The responder should be able to look up the same PSK using the value of
+    /// the [`Initiator::identity`] field.
+    pub fn psk(&self) -> &[u8; PSK_LEN] {
+        &self.psk
+    }
+}
+
+impl fmt::Debug for Initiator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The PSK is left out, so it doesn't end up in logs.
+        f.debug_struct("Initiator")
+            .field("identity", &self.identity)
+            .finish_non_exhaustive()
+    }
 }
 
 /// The `Responder` is the [`NNpsk2`] party responsible for receiving the first message
 /// containing the [`Initiator`]'s identity. The responder looks up the PSK for the initiator
 /// based on their self-reported identity, using a closure.
+///
+/// A handshake can either borrow the `Responder` mutably, so that
+/// [`Responder::initiator_identity`] can be checked once the handshake completes, or
+/// take a clone of it, which is convenient when storing one in a
+/// [`HandshakeConfig`][crate::HandshakeConfig].
 #[derive(Clone, Debug)]
 pub struct Responder<F, T>
 where
@@ -71,6 +120,37 @@ where
     pub fn initiator_identity(&self) -> Option<&[u8]> {
         self.initiator_identity.as_ref().map(|vec| vec.as_ref())
     }
+
+    /// Looks up the PSK for the identity in the initiator's first message and
+    /// writes the reply.
+    fn reply(
+        &mut self,
+        handshake_pattern: &str,
+        responder: &mut HandshakeState,
+        recv_buf: &[u8],
+        send_buf: &mut [u8],
+    ) -> Result<usize, NoiseError> {
+        // Assume the initiator sent us their identity
+        let initiator_identity = recv_buf;
+
+        let error = |description: &str| HandshakeError {
+            description: description.to_string(),
+            handshake_pattern: handshake_pattern.to_string(),
+        };
+
+        if initiator_identity.is_empty() {
+            return Err(error(
+                "initiator did not send us their identity to look up a PSK",
+            ))?;
+        }
+
+        let psk = (self.find_psk)(initiator_identity)
+            .ok_or_else(|| error("found no PSK for initiator"))?;
+
+        self.initiator_identity = Some(Vec::from(initiator_identity));
+        responder.set_psk(2, psk.as_ref())?;
+        Ok(responder.write_message(&[], send_buf)?)
+    }
 }
 
 /// Represents an `NNpsk2` handshake, where the initiator already knows a PSK, but
@@ -100,16 +180,10 @@ impl<P> NNpsk2<P> {
     pub fn new_custom(party: P, choices: CryptoChoices) -> Self {
         NNpsk2 { party, choices }
     }
-}
 
-impl Handshake for NNpsk2<Initiator<'_>> {
-    fn name(&self) -> String {
-        self.choices.stringify_with_pattern("NNpsk2")
-    }
-
-    fn new_builder(&self) -> snow::Builder {
-        let params = NoiseParams {
-            name: self.name(),
+    fn params(&self) -> NoiseParams {
+        NoiseParams {
+            name: self.choices.stringify_with_pattern("NNpsk2"),
             base: BaseChoice::Noise,
             handshake: HandshakeChoice {
                 pattern: HandshakePattern::NN,
@@ -120,8 +194,17 @@ impl Handshake for NNpsk2<Initiator<'_>> {
             dh: self.choices.dh,
             cipher: self.choices.cipher,
             hash: self.choices.hash,
-        };
-        snow::Builder::new(params)
+        }
+    }
+}
+
+impl Handshake for NNpsk2<Initiator> {
+    fn name(&self) -> String {
+        self.choices.stringify_with_pattern("NNpsk2")
+    }
+
+    fn new_builder(&self) -> snow::Builder<'_> {
+        snow::Builder::new(self.params())
     }
 
     fn initiator_first_message(
@@ -135,8 +218,8 @@ impl Handshake for NNpsk2<Initiator<'_>> {
                 send_buf.len()
             )))?;
         }
-        let n = initiator.write_message(self.party.identity, send_buf)?;
-        initiator.set_psk(2, self.party.psk)?;
+        let n = initiator.write_message(&self.party.identity, send_buf)?;
+        initiator.set_psk(2, &self.party.psk)?;
         Ok(n)
     }
 }
@@ -150,21 +233,8 @@ where
         self.choices.stringify_with_pattern("NNpsk2")
     }
 
-    fn new_builder(&self) -> snow::Builder {
-        let params = NoiseParams {
-            name: self.name(),
-            base: BaseChoice::Noise,
-            handshake: HandshakeChoice {
-                pattern: HandshakePattern::NN,
-                modifiers: HandshakeModifierList {
-                    list: vec![HandshakeModifier::Psk(2)],
-                },
-            },
-            dh: self.choices.dh,
-            cipher: self.choices.cipher,
-            hash: self.choices.hash,
-        };
-        snow::Builder::new(params)
+    fn new_builder(&self) -> snow::Builder<'_> {
+        snow::Builder::new(self.params())
     }
 
     fn responder_first_message(
@@ -173,18 +243,31 @@ where
         recv_buf: &[u8],
         send_buf: &mut [u8],
     ) -> Result<usize, NoiseError> {
-        // Assume the initiator sent us their identity
-        let initiator_identity = &recv_buf[..];
+        let name = self.name();
+        self.party.reply(&name, responder, recv_buf, send_buf)
+    }
+}
 
-        if initiator_identity.len() == 0 {
-            return Err(self.error("initiator did not send us their identity to look up a PSK"))?;
-        }
+impl<F, T> Handshake for NNpsk2<Responder<F, T>>
+where
+    F: FnMut(&[u8]) -> Option<T>,
+    T: AsRef<[u8]>,
+{
+    fn name(&self) -> String {
+        self.choices.stringify_with_pattern("NNpsk2")
+    }
 
-        let psk = (self.party.find_psk)(initiator_identity)
-            .ok_or_else(|| self.error("found no PSK for initiator"))?;
+    fn new_builder(&self) -> snow::Builder<'_> {
+        snow::Builder::new(self.params())
+    }
 
-        self.party.initiator_identity = Some(Vec::from(initiator_identity));
-        responder.set_psk(2, psk.as_ref())?;
-        Ok(responder.write_message(&[], send_buf)?)
+    fn responder_first_message(
+        &mut self,
+        responder: &mut HandshakeState,
+        recv_buf: &[u8],
+        send_buf: &mut [u8],
+    ) -> Result<usize, NoiseError> {
+        let name = self.name();
+        self.party.reply(&name, responder, recv_buf, send_buf)
     }
 }
@@ -0,0 +1,97 @@
+//! This module provides [`SharedRng`], for handshakes which draw their randomness from a
+//! caller's random number generator in place of the OS.
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use rand_core::{CryptoRng, CryptoRngCore, RngCore};
+use snow::{
+    params::{CipherChoice, DHChoice, HashChoice},
+    resolvers::{BoxedCryptoResolver, CryptoResolver},
+    types::{Cipher, Dh, Hash, Random},
+};
+
+use super::backend_resolver;
+
+/// A cryptographically secure random number generator, shared by every clone.
+///
+/// A handshake clones its generator for each connection. If clones of a seeded
+/// generator each kept their own state, every connection would draw the same ephemeral
+/// keys, so clones of a `SharedRng` draw from one state behind a lock instead.
+///
+/// Implements [`snow::types::Random`], so a custom [`CryptoResolver`] can return it from
+/// `resolve_rng`.
+#[derive(Clone)]
+pub struct SharedRng(Arc<Mutex<dyn CryptoRngCore + Send>>);
+
+impl SharedRng {
+    /// Share the given generator. It must be cryptographically secure: the keys of every
+    /// handshake are only as unpredictable as its output.
+    pub fn new(rng: impl CryptoRngCore + Send + 'static) -> SharedRng {
+        SharedRng(Arc::new(Mutex::new(rng)))
+    }
+}
+
+impl RngCore for SharedRng {
+    fn next_u32(&mut self) -> u32 {
+        self.0.lock().unwrap().next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0.lock().unwrap().next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.lock().unwrap().fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.0.lock().unwrap().try_fill_bytes(dest)
+    }
+}
+
+impl CryptoRng for SharedRng {}
+
+impl Random for SharedRng {}
+
+impl fmt::Debug for SharedRng {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SharedRng")
+    }
+}
+
+/// Resolves the same primitives as [`snow::Builder::new`], with randomness drawn from a
+/// [`SharedRng`].
+pub(crate) struct RngResolver {
+    rng: SharedRng,
+    primitives: BoxedCryptoResolver,
+}
+
+impl RngResolver {
+    pub(crate) fn new(rng: SharedRng) -> RngResolver {
+        RngResolver {
+            rng,
+            primitives: backend_resolver(),
+        }
+    }
+}
+
+impl CryptoResolver for RngResolver {
+    fn resolve_rng(&self) -> Option<Box<dyn Random>> {
+        Some(Box::new(self.rng.clone()))
+    }
+
+    fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<dyn Dh>> {
+        self.primitives.resolve_dh(choice)
+    }
+
+    fn resolve_hash(&self, choice: &HashChoice) -> Option<Box<dyn Hash>> {
+        self.primitives.resolve_hash(choice)
+    }
+
+    fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<dyn Cipher>> {
+        self.primitives.resolve_cipher(choice)
+    }
+}
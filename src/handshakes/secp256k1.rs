@@ -0,0 +1,324 @@
+//! This module encapsulates the [`Secp256k1Handshake`], for peers which identify each
+//! other by secp256k1 keys, as is common in the Bitcoin ecosystem. Requires the
+//! `secp256k1` feature.
+//!
+//! The Noise specification doesn't define secp256k1, so these handshakes use the
+//! nonstandard `secp256k1` DH name, as in `Noise_XK_secp256k1_ChaChaPoly_SHA256`.
+//! Public keys are 33-byte compressed points. As in BOLT 8, a DH output is the SHA-256
+//! hash of the compressed shared point. However, [`snow`] mixes DH outputs which are as
+//! long as a public key, so the hash is followed by a zero byte. These handshakes
+//! therefore interoperate with other peers using this crate, but not with BOLT 8
+//! implementations. For those, use [`Bolt8Stream`][crate::Bolt8Stream], with the `bolt8`
+//! feature.
+
+use std::fmt;
+
+use ::secp256k1::{ecdh::SharedSecret, PublicKey, Secp256k1, SecretKey};
+use snow::{
+    params::{
+        BaseChoice, CipherChoice, DHChoice, HandshakeChoice, HandshakeModifierList,
+        HandshakePattern, HashChoice, NoiseParams,
+    },
+    resolvers::{CryptoResolver, DefaultResolver},
+    types::{Cipher, Dh, Hash, Random},
+};
+
+use super::{backend_resolver, cipher_name, hash_name, Handshake, DEFAULT_CIPHER_CHOICE};
+use crate::errors::NoiseError;
+
+/// The name of the secp256k1 DH function in protocol names.
+pub const SECP256K1_DH_NAME: &str = "secp256k1";
+
+/// The size of a compressed secp256k1 public key.
+pub const SECP256K1_PUBLIC_KEY_LEN: usize = 33;
+
+/// snow has no [`DHChoice`] for secp256k1, so secp256k1 handshakes are built with this
+/// placeholder, which [`Secp256k1Resolver`] resolves to secp256k1. snow doesn't
+/// implement Ed448, so the placeholder can't stand in for a curve which is really used.
+const PLACEHOLDER_DH_CHOICE: DHChoice = DHChoice::Ed448;
+
+/// A [`CryptoResolver`] which provides secp256k1 DH for [`Secp256k1Handshake`], and
+/// everything else from the [`CRYPTO_BACKEND`][super::CRYPTO_BACKEND].
+#[derive(Debug, Default)]
+pub struct Secp256k1Resolver;
+
+impl CryptoResolver for Secp256k1Resolver {
+    fn resolve_rng(&self) -> Option<Box<dyn Random>> {
+        DefaultResolver.resolve_rng()
+    }
+
+    fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<dyn Dh>> {
+        if *choice == PLACEHOLDER_DH_CHOICE {
+            return Some(Box::<Secp256k1Dh>::default());
+        }
+        backend_resolver().resolve_dh(choice)
+    }
+
+    fn resolve_hash(&self, choice: &HashChoice) -> Option<Box<dyn Hash>> {
+        backend_resolver().resolve_hash(choice)
+    }
+
+    fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<dyn Cipher>> {
+        backend_resolver().resolve_cipher(choice)
+    }
+}
+
+/// secp256k1 Diffie-Hellman, as described in the module documentation.
+struct Secp256k1Dh {
+    secret: Option<SecretKey>,
+    privkey: [u8; 32],
+    pubkey: [u8; SECP256K1_PUBLIC_KEY_LEN],
+}
+
+impl Default for Secp256k1Dh {
+    fn default() -> Self {
+        Secp256k1Dh {
+            secret: None,
+            privkey: [0; 32],
+            pubkey: [0; SECP256K1_PUBLIC_KEY_LEN],
+        }
+    }
+}
+
+impl Dh for Secp256k1Dh {
+    fn name(&self) -> &'static str {
+        SECP256K1_DH_NAME
+    }
+
+    fn pub_len(&self) -> usize {
+        SECP256K1_PUBLIC_KEY_LEN
+    }
+
+    fn priv_len(&self) -> usize {
+        32
+    }
+
+    fn set(&mut self, privkey: &[u8]) {
+        // An invalid key is kept unset, so that using it fails in `dh`.
+        *self = Secp256k1Dh::default();
+        if let Ok(secret) = SecretKey::from_slice(privkey) {
+            self.privkey = secret.secret_bytes();
+            self.pubkey =
+                PublicKey::from_secret_key(&Secp256k1::signing_only(), &secret).serialize();
+            self.secret = Some(secret);
+        }
+    }
+
+    fn generate(&mut self, rng: &mut dyn Random) {
+        // Almost every 32-byte string is a valid secret key.
+        let mut privkey = [0u8; 32];
+        loop {
+            rng.fill_bytes(&mut privkey);
+            if SecretKey::from_slice(&privkey).is_ok() {
+                self.set(&privkey);
+                return;
+            }
+        }
+    }
+
+    fn pubkey(&self) -> &[u8] {
+        &self.pubkey
+    }
+
+    fn privkey(&self) -> &[u8] {
+        &self.privkey
+    }
+
+    fn dh(&self, pubkey: &[u8], out: &mut [u8]) -> Result<(), snow::Error> {
+        let secret = self.secret.ok_or(snow::Error::Dh)?;
+        // snow passes keys in buffers which may be longer than the key.
+        let pubkey = pubkey
+            .get(..SECP256K1_PUBLIC_KEY_LEN)
+            .ok_or(snow::Error::Dh)?;
+        let public = PublicKey::from_slice(pubkey).map_err(|_| snow::Error::Dh)?;
+        let shared = SharedSecret::new(&public, &secret).secret_bytes();
+        out[..shared.len()].copy_from_slice(&shared);
+        out[shared.len()..SECP256K1_PUBLIC_KEY_LEN].fill(0);
+        Ok(())
+    }
+}
+
+/// A handshake in which both parties authenticate with secp256k1 static keys, using
+/// either the `XK` or `IK` pattern. In both, the initiator must know the responder's
+/// public key in advance, and the responder learns the initiator's public key, which is
+/// then available from [`NoiseStream::remote_static_key`][crate::NoiseStream::remote_static_key].
+///
+/// `XK` takes three messages, and hides the initiator's identity from anyone who doesn't
+/// hold the responder's key. `IK` takes two messages.
+#[derive(Clone)]
+pub struct Secp256k1Handshake {
+    pattern: HandshakePattern,
+    local_private_key: [u8; 32],
+    remote_public_key: Option<[u8; SECP256K1_PUBLIC_KEY_LEN]>,
+    /// The encryption cipher.
+    pub cipher: CipherChoice,
+    /// The hash function. Defaults to SHA-256, as in BOLT 8.
+    pub hash: HashChoice,
+}
+
+impl Secp256k1Handshake {
+    /// Constructs an `XK` initiator, which knows the responder's public key.
+    pub fn xk_initiator(local: &SecretKey, remote: &PublicKey) -> Self {
+        Secp256k1Handshake::new(HandshakePattern::XK, local, Some(remote))
+    }
+
+    /// Constructs an `XK` responder.
+    pub fn xk_responder(local: &SecretKey) -> Self {
+        Secp256k1Handshake::new(HandshakePattern::XK, local, None)
+    }
+
+    /// Constructs an `IK` initiator, which knows the responder's public key.
+    pub fn ik_initiator(local: &SecretKey, remote: &PublicKey) -> Self {
+        Secp256k1Handshake::new(HandshakePattern::IK, local, Some(remote))
+    }
+
+    /// Constructs an `IK` responder.
+    pub fn ik_responder(local: &SecretKey) -> Self {
+        Secp256k1Handshake::new(HandshakePattern::IK, local, None)
+    }
+
+    fn new(pattern: HandshakePattern, local: &SecretKey, remote: Option<&PublicKey>) -> Self {
+        Secp256k1Handshake {
+            pattern,
+            local_private_key: local.secret_bytes(),
+            remote_public_key: remote.map(PublicKey::serialize),
+            cipher: DEFAULT_CIPHER_CHOICE,
+            hash: HashChoice::SHA256,
+        }
+    }
+
+    fn noise_params(&self) -> NoiseParams {
+        let pattern = match self.pattern {
+            HandshakePattern::XK => "XK",
+            _ => "IK",
+        };
+        NoiseParams {
+            name: format!(
+                "Noise_{}_{}_{}_{}",
+                pattern,
+                SECP256K1_DH_NAME,
+                cipher_name(self.cipher),
+                hash_name(self.hash)
+            ),
+            base: BaseChoice::Noise,
+            handshake: HandshakeChoice {
+                pattern: self.pattern,
+                modifiers: HandshakeModifierList { list: vec![] },
+            },
+            dh: PLACEHOLDER_DH_CHOICE,
+            cipher: self.cipher,
+            hash: self.hash,
+        }
+    }
+}
+
+impl Handshake for Secp256k1Handshake {
+    fn name(&self) -> String {
+        self.noise_params().name
+    }
+
+    /// snow can't parse the nonstandard name, so the parameters are built directly.
+    fn params(&self) -> Result<NoiseParams, NoiseError> {
+        Ok(self.noise_params())
+    }
+
+    fn local_static_public_key(&self) -> Option<Vec<u8>> {
+        let mut dh = Secp256k1Dh::default();
+        dh.set(&self.local_private_key);
+        Some(dh.pubkey().to_vec())
+    }
+
+    fn new_builder(&self) -> snow::Builder<'_> {
+        let builder =
+            snow::Builder::with_resolver(self.noise_params(), Box::new(Secp256k1Resolver))
+                .local_private_key(&self.local_private_key);
+        match &self.remote_public_key {
+            Some(key) => builder.remote_public_key(key),
+            None => builder,
+        }
+    }
+}
+
+impl fmt::Debug for Secp256k1Handshake {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The private key is left out, so it doesn't end up in logs.
+        f.debug_struct("Secp256k1Handshake")
+            .field("pattern", &self.pattern)
+            .field("remote_public_key", &self.remote_public_key)
+            .field("cipher", &self.cipher)
+            .field("hash", &self.hash)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// The keys and first ECDH of the BOLT 8 initiator test vectors.
+    #[test]
+    fn dh_matches_bolt8_test_vectors() {
+        let mut local = Secp256k1Dh::default();
+        local.set(&[0x11; 32]);
+        assert_eq!(
+            local.pubkey(),
+            from_hex("034f355bdcb7cc0af728ef3cceb9615d90684bb5b2ca5f859ab0f0b704075871aa")
+        );
+
+        let mut ephemeral = Secp256k1Dh::default();
+        ephemeral.set(&[0x12; 32]);
+        assert_eq!(
+            ephemeral.pubkey(),
+            from_hex("036360e856310ce5d294e8be33fc807077dc56ac80d95d9cd4ddbd21325eff73f7")
+        );
+
+        let remote = from_hex("028d7500dd4c12685d1f568b4c2b5048e8534b873319f3a8daa612b469132ec7f7");
+        let mut out = [0xFF; SECP256K1_PUBLIC_KEY_LEN];
+        ephemeral.dh(&remote, &mut out).unwrap();
+        assert_eq!(
+            out[..32],
+            from_hex("1e2fb3c8fe8fb9f262f649f64d26ecf0f2c0a805a767cf02dc2d77a6ef1fdcc3")[..]
+        );
+        assert_eq!(out[32], 0);
+    }
+
+    #[test]
+    fn dh_is_symmetric() {
+        let (mut a, mut b) = (Secp256k1Dh::default(), Secp256k1Dh::default());
+        a.set(&[0x21; 32]);
+        b.set(&[0x22; 32]);
+        let (mut ab, mut ba) = ([0u8; 33], [0u8; 33]);
+        a.dh(b.pubkey(), &mut ab).unwrap();
+        b.dh(a.pubkey(), &mut ba).unwrap();
+        assert_eq!(ab, ba);
+    }
+
+    #[test]
+    fn dh_rejects_invalid_keys() {
+        let mut invalid = Secp256k1Dh::default();
+        invalid.set(&[0; 32]);
+        let mut valid = Secp256k1Dh::default();
+        valid.set(&[0x21; 32]);
+
+        let mut out = [0u8; 33];
+        assert!(invalid.dh(valid.pubkey(), &mut out).is_err());
+        // Not a valid encoding of a point.
+        assert!(valid.dh(&[0x05; 33], &mut out).is_err());
+    }
+
+    #[test]
+    fn protocol_names_use_secp256k1() {
+        let key = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let handshake = Secp256k1Handshake::xk_responder(&key);
+        assert_eq!(handshake.name(), "Noise_XK_secp256k1_ChaChaPoly_SHA256");
+        assert_eq!(handshake.message_count(), 3);
+        assert_eq!(Secp256k1Handshake::ik_responder(&key).message_count(), 2);
+    }
+}
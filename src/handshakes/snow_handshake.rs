@@ -0,0 +1,333 @@
+//! This module encapsulates the [`SnowHandshake`] adapter.
+//!
+//! A `SnowHandshake` runs any Noise protocol which [`snow`] supports, for protocols
+//! which the built-in handshakes don't cover. It sends no payloads of its own, so each
+//! handshake message carries only the Noise protocol's keys.
+
+use std::{fmt, sync::Arc};
+
+use snow::{
+    params::{DHChoice, NoiseParams},
+    resolvers::{CryptoResolver, DefaultResolver},
+};
+
+use super::{
+    backend_resolver,
+    key_ops::{KeyOpsResolver, StaticKeyOps, X25519_KEY_LEN},
+    nn_psk0::validate_psk,
+    rng::{RngResolver, SharedRng},
+    Handshake, PSK_LEN,
+};
+use crate::{
+    allowlist::AllowlistFile,
+    errors::{HandshakeError, NoiseError},
+    fingerprint::Fingerprint,
+};
+
+type BuilderFn = dyn Fn(NoiseParams) -> snow::Builder<'static> + Send + Sync;
+
+/// Passed to snow in place of a private key held by [`StaticKeyOps`], to switch on the
+/// static key. Never used in a DH.
+const KEY_OPS_PLACEHOLDER: [u8; X25519_KEY_LEN] = [0u8; X25519_KEY_LEN];
+
+/// A [`Handshake`] for any Noise protocol name, whose keys and [`snow::Builder`] are
+/// configured by the caller.
+///
+/// The protocol may use any handshake pattern of up to
+/// [`MAX_HANDSHAKE_MESSAGES`][super::MAX_HANDSHAKE_MESSAGES] messages. Once the
+/// handshake completes, [`NoiseStream::protocol_name`][crate::NoiseStream::protocol_name]
+/// and [`NoiseStream::remote_static_key`][crate::NoiseStream::remote_static_key] report
+/// what was negotiated.
+///
+/// ```no_run
+/// # async fn example(tcp_stream: tokio::net::TcpStream, server_key: &[u8]) -> Result<(), tokio_noise::NoiseError> {
+/// use tokio_noise::{handshakes::SnowHandshake, NoiseTcpStream};
+///
+/// let handshake = SnowHandshake::new("Noise_NK_25519_ChaChaPoly_SHA256")?
+///     .remote_public_key(server_key);
+/// let noise_stream = NoiseTcpStream::handshake_initiator(tcp_stream, handshake).await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Clones share the private keys and PSKs rather than copying them, so a handshake can
+/// be cloned for each connection from a long-lived
+/// [`HandshakeConfig`][crate::HandshakeConfig] cheaply.
+#[derive(Clone)]
+pub struct SnowHandshake {
+    params: NoiseParams,
+    local_private_key: Option<Arc<[u8]>>,
+    /// Operations on a primary static key held outside the process, in place of
+    /// `local_private_key`.
+    local_key_ops: Option<Arc<dyn StaticKeyOps>>,
+    /// Further static private keys which a responder accepts, during key rotation.
+    fallback_private_keys: Arc<Vec<Arc<[u8]>>>,
+    /// The key in use: 0 for `local_private_key`, otherwise a fallback key.
+    selected_key: usize,
+    remote_public_key: Option<Vec<u8>>,
+    psks: Arc<Vec<(u8, [u8; PSK_LEN])>>,
+    prologue: Vec<u8>,
+    expected_remote: Option<Fingerprint>,
+    remote_allowlist: Option<AllowlistFile>,
+    rng: Option<SharedRng>,
+    new_builder: Option<Arc<BuilderFn>>,
+}
+
+impl SnowHandshake {
+    /// Constructs a handshake for the given Noise protocol name, such as
+    /// `Noise_XX_25519_AESGCM_SHA256`, failing if the name isn't valid.
+    pub fn new(protocol_name: &str) -> Result<Self, NoiseError> {
+        let params = protocol_name.parse::<NoiseParams>().map_err(|e| {
+            NoiseError::Handshake(HandshakeError {
+                description: format!("invalid Noise protocol name {:?}: {}", protocol_name, e),
+                handshake_pattern: protocol_name.to_string(),
+            })
+        })?;
+        Ok(SnowHandshake {
+            params,
+            local_private_key: None,
+            local_key_ops: None,
+            fallback_private_keys: Arc::default(),
+            selected_key: 0,
+            remote_public_key: None,
+            psks: Arc::default(),
+            prologue: Vec::new(),
+            expected_remote: None,
+            remote_allowlist: None,
+            rng: None,
+            new_builder: None,
+        })
+    }
+
+    /// Sets our static private key, for patterns in which we have one.
+    pub fn local_private_key(mut self, key: &[u8]) -> Self {
+        self.local_private_key = Some(key.into());
+        self
+    }
+
+    /// Uses a static key held outside the process, such as in an HSM, in place of
+    /// [`local_private_key`][Self::local_private_key]. Each DH with the static key is
+    /// delegated to `ops`, while ephemeral keys are handled in software as usual. Read
+    /// the blocking caveats on [`StaticKeyOps`].
+    ///
+    /// Fails unless the protocol's DH function is `25519`. The key ops replace any
+    /// builder set with [`with_builder`][Self::with_builder], since they need a resolver
+    /// of their own.
+    pub fn local_key_ops(mut self, ops: Box<dyn StaticKeyOps>) -> Result<Self, NoiseError> {
+        if self.params.dh != DHChoice::Curve25519 {
+            return Err(NoiseError::Handshake(HandshakeError {
+                description: "static key operations require the 25519 DH function".to_string(),
+                handshake_pattern: self.params.name.clone(),
+            }));
+        }
+        self.local_key_ops = Some(Arc::from(ops));
+        Ok(self)
+    }
+
+    /// Adds a static private key which the responder also accepts, so that a key can be
+    /// rotated without a flag day. Initiators still pinned to an old key, in patterns
+    /// such as `NK`, `XK` and `IK`, keep connecting while initiators which know the new
+    /// [`local_private_key`][Self::local_private_key] move over.
+    ///
+    /// An initiator's first message is tried under the primary key first, then under each
+    /// fallback key in the order added. This costs one failed decryption per key tried.
+    /// [`NoiseStream::local_static_public_key`][crate::NoiseStream::local_static_public_key]
+    /// reports which key each handshake used, so old-key traffic can be watched draining.
+    pub fn fallback_local_private_key(mut self, key: &[u8]) -> Self {
+        Arc::make_mut(&mut self.fallback_private_keys).push(key.into());
+        self
+    }
+
+    /// Sets the peer's static public key, for patterns in which we know it in advance.
+    pub fn remote_public_key(mut self, key: &[u8]) -> Self {
+        self.remote_public_key = Some(key.to_vec());
+        self
+    }
+
+    /// Sets the PSK for the `psk` modifier at the given location, failing if the PSK is
+    /// rejected by [`validate_psk`].
+    pub fn psk(mut self, location: u8, psk: &[u8]) -> Result<Self, NoiseError> {
+        validate_psk(psk)?;
+        let mut owned_psk = [0u8; PSK_LEN];
+        owned_psk.copy_from_slice(psk);
+        let psks = Arc::make_mut(&mut self.psks);
+        psks.retain(|(l, _)| *l != location);
+        psks.push((location, owned_psk));
+        Ok(self)
+    }
+
+    /// Sets a prologue, which both parties must agree on for the handshake to succeed.
+    pub fn prologue(mut self, prologue: &[u8]) -> Self {
+        self.prologue = prologue.to_vec();
+        self
+    }
+
+    /// Requires the peer's static public key to be the given key, for patterns such as
+    /// `XX` and `IX` in which the peer transmits its key during the handshake. If the
+    /// peer presents a different key, the handshake is aborted before we send another
+    /// message, and fails with [`NoiseError::PeerKeyMismatch`].
+    pub fn expect_remote_static(self, key: &[u8]) -> Self {
+        self.expect_remote_fingerprint(Fingerprint::of(key))
+    }
+
+    /// Requires the peer's static public key to have the given [`Fingerprint`], as
+    /// [`expect_remote_static`][Self::expect_remote_static] does for a raw key.
+    pub fn expect_remote_fingerprint(mut self, fingerprint: Fingerprint) -> Self {
+        self.expected_remote = Some(fingerprint);
+        self
+    }
+
+    /// Requires the peer's static public key to be listed in the given
+    /// [`AllowlistFile`], which is checked for edits on each handshake. If it isn't,
+    /// the handshake is aborted before we send another message, and fails with
+    /// [`NoiseError::PeerNotAllowed`].
+    pub fn remote_allowlist(mut self, allowlist: AllowlistFile) -> Self {
+        self.remote_allowlist = Some(allowlist);
+        self
+    }
+
+    /// Sets the random number generator from which the handshake's ephemeral keys, and
+    /// keys from [`generate_keypair`][Self::generate_keypair], are drawn, in place of
+    /// the OS generator. This suits reproducible tests, or environments which mandate a
+    /// particular generator.
+    ///
+    /// The generator must be cryptographically secure, since keys are only as
+    /// unpredictable as its output. It is wrapped in a [`SharedRng`], so clones of this
+    /// handshake draw from the same state rather than repeating each other's keys.
+    pub fn rng(mut self, rng: impl rand_core::CryptoRngCore + Send + 'static) -> Self {
+        self.rng = Some(SharedRng::new(rng));
+        self
+    }
+
+    /// Generates a static keypair for this handshake's DH function, using the generator
+    /// set with [`rng`][Self::rng] or the OS generator by default.
+    pub fn generate_keypair(&self) -> Result<snow::Keypair, NoiseError> {
+        Ok(self.new_builder().generate_keypair()?)
+    }
+
+    /// Sets a closure which constructs the [`snow::Builder`] for each handshake from the
+    /// parsed protocol parameters, for example to use a custom
+    /// [`CryptoResolver`] with
+    /// [`snow::Builder::with_resolver`]. The keys and prologue set on this handshake are
+    /// then applied to the builder it returns.
+    ///
+    /// By default, [`snow::Builder::new`] is used. A builder set here takes precedence over
+    /// [`rng`][Self::rng], so the resolver it uses must supply the generator itself.
+    pub fn with_builder(
+        mut self,
+        new_builder: impl Fn(NoiseParams) -> snow::Builder<'static> + Send + Sync + 'static,
+    ) -> Self {
+        self.new_builder = Some(Arc::new(new_builder));
+        self
+    }
+
+    fn selected_private_key(&self) -> Option<&[u8]> {
+        match self.selected_key {
+            0 => self.local_private_key.as_deref(),
+            index => self
+                .fallback_private_keys
+                .get(index - 1)
+                .map(|key| &key[..]),
+        }
+    }
+}
+
+impl Handshake for SnowHandshake {
+    fn name(&self) -> String {
+        self.params.name.clone()
+    }
+
+    /// Derived from the private key with snow's default DH implementation, so this is
+    /// `None` if a custom resolver provides the DH function.
+    fn local_static_public_key(&self) -> Option<Vec<u8>> {
+        if let (0, Some(ops)) = (self.selected_key, &self.local_key_ops) {
+            return Some(ops.public_key().to_vec());
+        }
+        let key = self.selected_private_key()?;
+        let mut dh = DefaultResolver.resolve_dh(&self.params.dh)?;
+        dh.set(key);
+        Some(dh.pubkey().to_vec())
+    }
+
+    fn local_key_count(&self) -> usize {
+        1 + self.fallback_private_keys.len()
+    }
+
+    fn select_local_key(&mut self, index: usize) {
+        self.selected_key = index;
+    }
+
+    fn verify_remote_static(&self, remote_static_key: &[u8]) -> Result<(), NoiseError> {
+        match self.expected_remote {
+            Some(expected) if !expected.matches(remote_static_key) => {
+                Err(NoiseError::PeerKeyMismatch {
+                    expected,
+                    got: Fingerprint::of(remote_static_key),
+                })
+            }
+            _ => match &self.remote_allowlist {
+                Some(allowlist) => allowlist.verify(remote_static_key),
+                None => Ok(()),
+            },
+        }
+    }
+
+    fn new_builder(&self) -> snow::Builder<'_> {
+        let key_ops = match self.selected_key {
+            0 => self.local_key_ops.as_ref(),
+            _ => None,
+        };
+        let mut builder = match (key_ops, &self.new_builder) {
+            (Some(ops), _) => {
+                let primitives = match &self.rng {
+                    Some(rng) => Box::new(RngResolver::new(rng.clone())),
+                    None => backend_resolver(),
+                };
+                snow::Builder::with_resolver(
+                    self.params.clone(),
+                    Box::new(KeyOpsResolver::new(ops.clone(), primitives)),
+                )
+                .local_private_key(&KEY_OPS_PLACEHOLDER)
+            }
+            (None, Some(new_builder)) => new_builder(self.params.clone()),
+            (None, None) => match &self.rng {
+                Some(rng) => snow::Builder::with_resolver(
+                    self.params.clone(),
+                    Box::new(RngResolver::new(rng.clone())),
+                ),
+                None => snow::Builder::new(self.params.clone()),
+            },
+        };
+        if let Some(key) = self.selected_private_key() {
+            builder = builder.local_private_key(key);
+        }
+        if let Some(key) = &self.remote_public_key {
+            builder = builder.remote_public_key(key);
+        }
+        for (location, psk) in self.psks.iter() {
+            builder = builder.psk(*location, psk);
+        }
+        if !self.prologue.is_empty() {
+            builder = builder.prologue(&self.prologue);
+        }
+        builder
+    }
+}
+
+impl fmt::Debug for SnowHandshake {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Keys are left out, so they don't end up in logs.
+        f.debug_struct("SnowHandshake")
+            .field("protocol_name", &self.params.name)
+            .field("remote_public_key", &self.remote_public_key)
+            .field("local_key_ops", &self.local_key_ops.is_some())
+            .field("fallback_keys", &self.fallback_private_keys.len())
+            .field("expected_remote", &self.expected_remote)
+            .field(
+                "remote_allowlist",
+                &self.remote_allowlist.as_ref().map(AllowlistFile::path),
+            )
+            .field("rng", &self.rng)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,40 @@
+//! Lowercase hexadecimal, as used for PSKs and the serialized form of static keys.
+
+/// Encode bytes as lowercase hex.
+#[cfg(any(
+    test,
+    feature = "handshake-trace",
+    all(feature = "router", feature = "serde")
+))]
+pub(crate) fn encode(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decode hex of either case. Returns `None` if the string is malformed.
+pub(crate) fn decode(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    s.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let digit = |c: u8| (c as char).to_digit(16);
+            Some((digit(pair[0])? << 4 | digit(pair[1])?) as u8)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let data: Vec<u8> = (0..=255).collect();
+        assert_eq!(decode(&encode(&data)).unwrap(), data);
+        assert_eq!(encode(&[0x00, 0x0f, 0xab]), "000fab");
+        assert_eq!(decode("000FAB").unwrap(), [0x00, 0x0f, 0xab]);
+        assert_eq!(decode("abc"), None);
+        assert_eq!(decode("0g"), None);
+    }
+}
@@ -0,0 +1,62 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use hyper::rt::ReadBufCursor;
+use tokio::io::{self, AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::stream::NoiseStream;
+
+/// Lets hyper serve or send HTTP over a Noise stream directly, without wrapping it in
+/// `hyper_util::rt::TokioIo`. Available with the `hyper` feature.
+///
+/// ```no_run
+/// # async fn example(noise_stream: tokio_noise::NoiseTcpStream) -> Result<(), hyper::Error> {
+/// let (sender, conn) = hyper::client::conn::http1::handshake::<_, String>(noise_stream).await?;
+/// # let _ = (sender, conn);
+/// # Ok(())
+/// # }
+/// ```
+impl<S: AsyncRead + AsyncWrite + Unpin> hyper::rt::Read for NoiseStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        mut buf: ReadBufCursor<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        // hyper hands over memory which may be uninitialized. Decrypted frames are only
+        // ever copied into a `ReadBuf`, which tracks how much of it has been written.
+        //
+        // SAFETY: `ReadBuf` never de-initializes memory, so no uninitialized bytes are
+        // exposed to hyper as a result of this read.
+        let n = unsafe {
+            let mut tokio_buf = ReadBuf::uninit(buf.as_mut());
+            match AsyncRead::poll_read(self, cx, &mut tokio_buf) {
+                Poll::Ready(Ok(())) => tokio_buf.filled().len(),
+                poll => return poll,
+            }
+        };
+        // SAFETY: the first `n` bytes of the cursor were filled by the read above.
+        unsafe { buf.advance(n) };
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Available with the `hyper` feature.
+impl<S: AsyncRead + AsyncWrite + Unpin> hyper::rt::Write for NoiseStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        AsyncWrite::poll_write(self, cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        AsyncWrite::poll_flush(self, cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        AsyncWrite::poll_shutdown(self, cx)
+    }
+}
@@ -40,14 +40,93 @@
 //!     Ok(())
 //! }
 //! ```
+//!
+//! ## Timers
+//!
+//! Every delay and deadline in this library, such as the listener's
+//! [tarpit][TarpitConfig] and [`NoiseStream::recv_deadline`], is measured with
+//! [`tokio::time`]. Tests can therefore pause the clock with tokio's `test-util`
+//! feature, and advance it to check timing deterministically.
 
 #![warn(missing_docs)]
 
+mod allowlist;
+mod base64;
+#[cfg(feature = "blocking")]
+mod blocking;
+#[cfg(feature = "bolt8")]
+mod bolt8;
+mod builder;
+mod config;
+mod connector;
+mod datagram;
 mod errors;
+mod events;
+mod fingerprint;
 pub mod handshakes;
+mod hex;
+#[cfg(feature = "hyper")]
+mod hyper_rt;
+mod listener;
+mod log_context;
+mod one_way;
+mod proxy_protocol;
+mod resolver;
+#[cfg(feature = "router")]
+mod router;
+#[cfg(feature = "session-export")]
+mod session;
+mod sniff;
+mod stats;
+mod stream;
+mod tarpit;
 mod tcp;
+mod ticket;
+mod transport;
+#[cfg(feature = "typed")]
+mod typed;
+#[cfg(unix)]
+mod unix;
+#[cfg(feature = "websocket")]
+mod websocket;
 
+pub use allowlist::*;
+#[cfg(feature = "blocking")]
+pub use blocking::*;
+#[cfg(feature = "bolt8")]
+pub use bolt8::*;
+pub use builder::*;
+pub use config::*;
+pub use connector::*;
+pub use datagram::*;
 pub use errors::*;
+pub use events::*;
+pub use fingerprint::*;
+pub use handshakes::nn_psk0::validate_psk;
+pub use listener::*;
+pub use log_context::LogContext;
+pub use one_way::*;
+pub use proxy_protocol::*;
+pub use resolver::*;
+#[cfg(feature = "router")]
+pub use router::*;
+#[cfg(feature = "session-export")]
+pub use session::*;
+pub use sniff::*;
+pub use stats::*;
+pub use stream::*;
+pub use tarpit::*;
 pub use tcp::*;
+pub use ticket::*;
+pub use transport::*;
+#[cfg(feature = "typed")]
+pub use typed::*;
+#[cfg(unix)]
+pub use unix::*;
+#[cfg(feature = "websocket")]
+pub use websocket::*;
 
 pub use snow;
+
+#[cfg(feature = "secp256k1")]
+pub use secp256k1;
@@ -0,0 +1,551 @@
+use log::{debug, warn};
+use std::{fmt, io, net::SocketAddr, sync::Arc};
+#[cfg(feature = "incoming")]
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+#[cfg(feature = "incoming")]
+use crate::config::BoxFuture;
+use crate::{
+    builder::NoiseBuilder,
+    config::{AuditHook, HandshakeConfig, InterMessageHook, PeerInfo},
+    errors::{NoiseError, ProxyHeaderError},
+    handshakes::Handshake,
+    proxy_protocol::{ProxyHeader, ProxyProtocolConfig},
+    tarpit::Tarpit,
+    tarpit::TarpitConfig,
+    tcp::NoiseTcpStream,
+};
+
+type AcceptFilterFn = dyn Fn(SocketAddr) -> bool + Send + Sync;
+
+/// Decides which source addresses a listener accepts connections from.
+struct AcceptFilter(Box<AcceptFilterFn>);
+
+impl fmt::Debug for AcceptFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("AcceptFilter")
+    }
+}
+
+/// A TCP listener which hands out incoming connections ready for a Noise handshake.
+///
+/// Each accepted connection is returned as an [`IncomingConnection`], whose handshake
+/// can be run on a separate task so that one slow peer does not hold up the accept loop.
+#[derive(Debug)]
+pub struct NoiseTcpListener {
+    tcp: TcpListener,
+    builder: NoiseBuilder,
+    tarpit: Option<Arc<Tarpit>>,
+    accept_filter: Option<AcceptFilter>,
+    proxy_protocol: Option<ProxyProtocolConfig>,
+}
+
+impl NoiseTcpListener {
+    /// Wrap an existing [`TcpListener`].
+    pub fn new(listener: TcpListener) -> NoiseTcpListener {
+        NoiseTcpListener {
+            tcp: listener,
+            builder: NoiseBuilder::default(),
+            tarpit: None,
+            accept_filter: None,
+            proxy_protocol: None,
+        }
+    }
+
+    /// Bind a new TCP listener to the given address.
+    pub async fn bind(addr: impl ToSocketAddrs) -> Result<NoiseTcpListener, io::Error> {
+        Ok(NoiseTcpListener::new(TcpListener::bind(addr).await?))
+    }
+
+    /// Set the configuration used for streams created from accepted connections.
+    pub fn set_builder(&mut self, builder: NoiseBuilder) {
+        self.builder = builder;
+    }
+
+    /// Enable tarpitting of source addresses which fail handshakes.
+    ///
+    /// Once enabled, a connection from an address with recent handshake failures is
+    /// held for an increasing delay before its handshake begins, and a failed handshake
+    /// is held open for a further delay before the failure is reported and the socket
+    /// closed. Successful handshakes are never delayed, and reset the address's record.
+    pub fn set_tarpit(&mut self, config: TarpitConfig) {
+        self.tarpit = Some(Arc::new(Tarpit::new(config)));
+    }
+
+    /// Set a filter which decides, by source address, whether to accept each connection.
+    ///
+    /// The filter runs as soon as a connection is accepted, before any handshake work.
+    /// Connections it rejects are closed straight away and never returned by
+    /// [`accept`][Self::accept], which waits for the next connection instead. This is a
+    /// cheap first line of defense against known-bad sources, so the filter itself
+    /// should be cheap too.
+    ///
+    /// ```no_run
+    /// # async fn example() -> std::io::Result<()> {
+    /// use tokio_noise::NoiseTcpListener;
+    ///
+    /// let mut listener = NoiseTcpListener::bind("0.0.0.0:9000").await?;
+    /// listener.set_accept_filter(|addr| addr.ip().is_loopback());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_accept_filter(
+        &mut self,
+        filter: impl Fn(SocketAddr) -> bool + Send + Sync + 'static,
+    ) {
+        self.accept_filter = Some(AcceptFilter(Box::new(filter)));
+    }
+
+    /// Expect every connection to start with a [PROXY protocol] header, version 1 or 2,
+    /// as sent by load balancers such as HAProxy and AWS NLB to pass on the address of
+    /// the original client.
+    ///
+    /// The header is read on the connection's own task, before its handshake begins, or
+    /// explicitly with [`IncomingConnection::read_proxy_header`]. A connection without a
+    /// valid header fails with [`NoiseError::ProxyHeader`]. Once read, the client's
+    /// address stands in for the proxy's in the tarpit, the [admission
+    /// hook][HandshakeConfig::admit], the [audit hook][HandshakeConfig::on_handshake],
+    /// and the context of errors, and is available from the established stream's
+    /// [`proxied_peer_addr`][crate::NoiseStream::proxied_peer_addr]. The [accept
+    /// filter][Self::set_accept_filter] runs before the header is read, so it sees the
+    /// proxy's address.
+    ///
+    /// Only enable this behind a proxy which always sends a header, and don't let
+    /// clients connect around it, since a client can send a header of its own to claim
+    /// any address.
+    ///
+    /// [PROXY protocol]: https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt
+    pub fn set_proxy_protocol(&mut self, config: ProxyProtocolConfig) {
+        self.proxy_protocol = Some(config);
+    }
+
+    /// Returns the tarpit in use by this listener, if any.
+    pub fn tarpit(&self) -> Option<&Arc<Tarpit>> {
+        self.tarpit.as_ref()
+    }
+
+    /// Accept a new TCP connection. The Noise handshake is not conducted until
+    /// [`IncomingConnection::handshake`] is called.
+    ///
+    /// Connections rejected by the [accept filter][Self::set_accept_filter] are closed
+    /// and skipped.
+    pub async fn accept(&self) -> Result<IncomingConnection, io::Error> {
+        loop {
+            let (socket, peer_addr) = self.tcp.accept().await?;
+            if let Some(incoming) = self.admit(socket, peer_addr) {
+                return Ok(incoming);
+            }
+        }
+    }
+
+    /// Apply the accept filter to a newly accepted socket, closing it if rejected.
+    fn admit(&self, socket: TcpStream, peer_addr: SocketAddr) -> Option<IncomingConnection> {
+        if let Some(AcceptFilter(filter)) = &self.accept_filter {
+            if !filter(peer_addr) {
+                debug!("rejected TCP connection from {}", peer_addr);
+                return None;
+            }
+        }
+        debug!("accepted TCP connection from {}", peer_addr);
+        Some(IncomingConnection {
+            socket,
+            peer_addr,
+            builder: self.builder.clone(),
+            tarpit: self.tarpit.clone(),
+            proxy_protocol: self.proxy_protocol,
+            proxy_header: None,
+        })
+    }
+
+    /// Returns a [`Stream`][futures_core::Stream] of connections which have completed
+    /// their handshakes, using clones of the handshake in the given [`HandshakeConfig`].
+    /// Requires the `incoming` feature.
+    ///
+    /// Handshakes run concurrently, up to
+    /// [`DEFAULT_MAX_CONCURRENT_HANDSHAKES`] at a time unless changed with
+    /// [`Incoming::max_concurrent_handshakes`], and connections are yielded in the order
+    /// their handshakes finish. While the limit is reached, no more connections are
+    /// accepted.
+    ///
+    /// A failed handshake is yielded as [`AcceptError::Handshake`][crate::AcceptError::Handshake],
+    /// and the stream carries on. Errors accepting a single connection, such as one reset
+    /// by the peer before it was accepted, are skipped. Any other error from the listener
+    /// is yielded as [`AcceptError::Listener`][crate::AcceptError::Listener], after which
+    /// no more connections are accepted, and the stream ends once the handshakes already
+    /// under way have finished. Dropping the stream abandons those handshakes.
+    ///
+    /// ```no_run
+    /// # async fn example() -> std::io::Result<()> {
+    /// use futures_util::StreamExt;
+    /// use tokio_noise::{handshakes::NNpsk0, HandshakeConfig, NoiseTcpListener};
+    ///
+    /// let listener = NoiseTcpListener::bind("0.0.0.0:9000").await?;
+    /// let config = HandshakeConfig::new(NNpsk0::try_new(&[0xFF; 32]).unwrap());
+    /// listener
+    ///     .incoming(config)
+    ///     .for_each_concurrent(None, |result| async move {
+    ///         match result {
+    ///             Ok((mut stream, _peer_addr)) => {
+    ///                 let _ = stream.send(b"hello").await;
+    ///             }
+    ///             Err(e) => eprintln!("{}", e),
+    ///         }
+    ///     })
+    ///     .await;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "incoming")]
+    pub fn incoming<H>(&self, config: HandshakeConfig<H>) -> Incoming<'_, H>
+    where
+        H: Handshake + Clone + Send + Sync + 'static,
+    {
+        Incoming {
+            listener: self,
+            config: Arc::new(config),
+            pending: Vec::new(),
+            max_concurrent: DEFAULT_MAX_CONCURRENT_HANDSHAKES,
+            listener_failed: false,
+        }
+    }
+
+    /// Wraps [`TcpListener::local_addr`].
+    pub fn local_addr(&self) -> Result<SocketAddr, io::Error> {
+        self.tcp.local_addr()
+    }
+
+    /// Returns a reference to the underlying [`TcpListener`].
+    pub fn get_ref(&self) -> &TcpListener {
+        &self.tcp
+    }
+
+    /// Consume the listener, returning the underlying [`TcpListener`].
+    pub fn into_inner(self) -> TcpListener {
+        self.tcp
+    }
+}
+
+/// How many handshakes an [`Incoming`] stream runs at once by default.
+#[cfg(feature = "incoming")]
+pub const DEFAULT_MAX_CONCURRENT_HANDSHAKES: usize = 64;
+
+/// A handshake under way in an [`Incoming`] stream.
+#[cfg(feature = "incoming")]
+type PendingHandshake = BoxFuture<'static, (SocketAddr, Result<NoiseTcpStream, NoiseError>)>;
+
+/// A stream of connections accepted by a [`NoiseTcpListener`] which have completed their
+/// handshakes, returned by [`NoiseTcpListener::incoming`]. Requires the `incoming`
+/// feature.
+#[cfg(feature = "incoming")]
+pub struct Incoming<'a, H> {
+    listener: &'a NoiseTcpListener,
+    config: Arc<HandshakeConfig<H>>,
+    pending: Vec<PendingHandshake>,
+    max_concurrent: usize,
+    listener_failed: bool,
+}
+
+#[cfg(feature = "incoming")]
+impl<H> Incoming<'_, H> {
+    /// Set how many handshakes may run at once. Defaults to
+    /// [`DEFAULT_MAX_CONCURRENT_HANDSHAKES`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is zero.
+    pub fn max_concurrent_handshakes(mut self, max: usize) -> Self {
+        assert!(max > 0, "max_concurrent_handshakes must be at least 1");
+        self.max_concurrent = max;
+        self
+    }
+
+    /// Returns how many handshakes are under way.
+    pub fn pending_handshakes(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+#[cfg(feature = "incoming")]
+impl<H> fmt::Debug for Incoming<'_, H> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Incoming")
+            .field("listener", &self.listener)
+            .field("pending", &self.pending.len())
+            .field("max_concurrent", &self.max_concurrent)
+            .field("listener_failed", &self.listener_failed)
+            .finish()
+    }
+}
+
+/// Whether an accept error concerns only the connection being accepted, leaving the
+/// listener usable.
+#[cfg(feature = "incoming")]
+fn is_connection_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::Interrupted
+    )
+}
+
+#[cfg(feature = "incoming")]
+impl<H> futures_core::Stream for Incoming<'_, H>
+where
+    H: Handshake + Clone + Send + Sync + 'static,
+{
+    type Item = Result<(NoiseTcpStream, SocketAddr), crate::errors::AcceptError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        use crate::errors::AcceptError;
+
+        let this = self.get_mut();
+
+        while !this.listener_failed && this.pending.len() < this.max_concurrent {
+            match this.listener.tcp.poll_accept(cx) {
+                Poll::Ready(Ok((socket, peer_addr))) => {
+                    let Some(incoming) = this.listener.admit(socket, peer_addr) else {
+                        continue;
+                    };
+                    let config = this.config.clone();
+                    this.pending.push(Box::pin(async move {
+                        let mut incoming = incoming;
+                        if let Err(e) = incoming.read_proxy_header().await {
+                            return (peer_addr, Err(e));
+                        }
+                        let peer_addr = incoming.client_addr();
+                        (peer_addr, incoming.handshake_with(&config).await)
+                    }));
+                }
+                Poll::Ready(Err(e)) if is_connection_error(&e) => {
+                    debug!("skipping failed TCP accept: {}", e);
+                }
+                Poll::Ready(Err(e)) => {
+                    warn!("listener failed; no longer accepting connections: {}", e);
+                    this.listener_failed = true;
+                    return Poll::Ready(Some(Err(AcceptError::Listener(e))));
+                }
+                Poll::Pending => break,
+            }
+        }
+
+        for i in 0..this.pending.len() {
+            if let Poll::Ready((peer_addr, result)) = this.pending[i].as_mut().poll(cx) {
+                drop(this.pending.swap_remove(i));
+                return Poll::Ready(Some(match result {
+                    Ok(stream) => Ok((stream, peer_addr)),
+                    Err(error) => Err(AcceptError::Handshake { peer_addr, error }),
+                }));
+            }
+        }
+
+        if this.listener_failed && this.pending.is_empty() {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// A TCP connection accepted by a [`NoiseTcpListener`], awaiting its Noise handshake.
+#[derive(Debug)]
+pub struct IncomingConnection {
+    pub(crate) socket: TcpStream,
+    peer_addr: SocketAddr,
+    builder: NoiseBuilder,
+    tarpit: Option<Arc<Tarpit>>,
+    proxy_protocol: Option<ProxyProtocolConfig>,
+    pub(crate) proxy_header: Option<Box<ProxyHeader>>,
+}
+
+impl IncomingConnection {
+    /// Returns the address of the remote peer. Behind a proxy, this is the proxy's
+    /// address; see [`proxied_peer_addr`][Self::proxied_peer_addr].
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    /// Read the connection's PROXY protocol header, if the listener
+    /// [expects one][NoiseTcpListener::set_proxy_protocol] and it has not been read
+    /// already. Returns the header, or `None` if the listener expects none.
+    ///
+    /// The handshake methods and [`sniff`][Self::sniff] read the header themselves, so
+    /// this need only be called to learn the client's address beforehand, such as for a
+    /// client which sniffing hands to the plaintext fallback, as a bare socket. Run this
+    /// on the connection's own task, as it waits on the client.
+    pub async fn read_proxy_header(&mut self) -> Result<Option<&ProxyHeader>, NoiseError> {
+        let Some(config) = self.proxy_protocol else {
+            return Ok(None);
+        };
+        if self.proxy_header.is_none() {
+            let header = tokio::time::timeout(config.timeout, ProxyHeader::read(&mut self.socket))
+                .await
+                .unwrap_or(Err(ProxyHeaderError::Timeout.into()))
+                .map_err(|e| e.with_context("responder", Some(self.peer_addr)))?;
+            debug!(
+                "connection from {} is proxied for {}",
+                self.peer_addr,
+                header
+                    .source
+                    .map_or("an unknown client".to_string(), |addr| addr.to_string()),
+            );
+            self.proxy_header = Some(Box::new(header));
+        }
+        Ok(self.proxy_header.as_deref())
+    }
+
+    /// Returns the connection's PROXY protocol header, once
+    /// [read][Self::read_proxy_header].
+    pub fn proxy_header(&self) -> Option<&ProxyHeader> {
+        self.proxy_header.as_deref()
+    }
+
+    /// Returns the address of the original client, as given by the connection's PROXY
+    /// protocol header, once [read][Self::read_proxy_header]. This is `None` if the
+    /// header carries no address, as for the proxy's own health checks.
+    pub fn proxied_peer_addr(&self) -> Option<SocketAddr> {
+        self.proxy_header.as_deref()?.source
+    }
+
+    /// The address of the client: the proxied address if known, or else the peer's.
+    pub(crate) fn client_addr(&self) -> SocketAddr {
+        self.proxied_peer_addr().unwrap_or(self.peer_addr)
+    }
+
+    /// Conduct the Noise handshake as the responder, using the given [`Handshake`] protocol.
+    ///
+    /// If the listener has a tarpit enabled, this may wait before starting the handshake,
+    /// and before returning an error if the handshake fails.
+    pub async fn handshake(self, handshake: impl Handshake) -> Result<NoiseTcpStream, NoiseError> {
+        let builder = self.builder.clone();
+        self.run_handshake(&builder, handshake, None).await
+    }
+
+    /// Conduct the Noise handshake as the responder, using a clone of the handshake in
+    /// the given [`HandshakeConfig`].
+    ///
+    /// The config's [`NoiseBuilder`] options are used for the resulting stream, in place
+    /// of those set with [`NoiseTcpListener::set_builder`]. The listener's tarpit, if
+    /// any, still applies, though peers turned away by the config's
+    /// [admission hook][HandshakeConfig::admit] are dropped before it is consulted.
+    pub async fn handshake_with<H: Handshake + Clone>(
+        mut self,
+        config: &HandshakeConfig<H>,
+    ) -> Result<NoiseTcpStream, NoiseError> {
+        if let Err(e) = self.read_proxy_header().await {
+            return AuditHook::audit(config.audit_hook(), Some(self.peer_addr), async { Err(e) })
+                .await;
+        }
+        let peer_addr = Some(self.client_addr());
+        let handshake = async {
+            config.admit_peer_info(|| PeerInfo {
+                addr: peer_addr,
+                ..PeerInfo::of(&self.socket)
+            })?;
+            self.run_handshake(
+                config.builder(),
+                config.handshake().clone(),
+                config.inter_message_hook(),
+            )
+            .await
+        };
+        AuditHook::audit(config.audit_hook(), peer_addr, handshake).await
+    }
+
+    async fn run_handshake(
+        mut self,
+        builder: &NoiseBuilder,
+        handshake: impl Handshake,
+        hook: Option<&InterMessageHook>,
+    ) -> Result<NoiseTcpStream, NoiseError> {
+        self.read_proxy_header().await?;
+        let client_addr = self.client_addr();
+        let Some(tarpit) = self.tarpit.take() else {
+            return self.respond(builder, handshake, hook).await;
+        };
+
+        let ip = client_addr.ip();
+        let delay = tarpit.current_delay(ip);
+        if !delay.is_zero() {
+            debug!("delaying handshake from {} by {:?}", client_addr, delay);
+            tokio::time::sleep(delay).await;
+        }
+
+        let result = self.respond(builder, handshake, hook).await;
+        match result {
+            Ok(stream) => {
+                tarpit.record_success(ip);
+                Ok(stream)
+            }
+            Err(e) => {
+                // Keep the socket open while we wait, so the peer can't learn of the
+                // failure any sooner. A failed key confirmation means the peer already
+                // knows, but still counts against it.
+                let delay = tarpit.record_failure(ip);
+                warn!(
+                    "handshake from {} failed; delaying failure by {:?}",
+                    client_addr, delay
+                );
+                tokio::time::sleep(delay).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Conduct the handshake and establish the stream, attributing it and its errors to
+    /// the proxied client, if any.
+    async fn respond(
+        mut self,
+        builder: &NoiseBuilder,
+        handshake: impl Handshake,
+        hook: Option<&InterMessageHook>,
+    ) -> Result<NoiseTcpStream, NoiseError> {
+        let client_addr = self.proxied_peer_addr();
+        let recontext = |e: NoiseError| match client_addr {
+            Some(addr) => e.without_context().with_context("responder", Some(addr)),
+            None => e,
+        };
+        let handshaked = builder
+            .run_responder(&mut self.socket, handshake, hook)
+            .await
+            .map_err(recontext)?;
+        let mut stream = builder
+            .establish("responder".to_string(), self.socket, handshaked)
+            .await
+            .map_err(recontext)?;
+        if client_addr.is_some() {
+            stream.peer_addr = client_addr;
+        }
+        stream.proxy_header = self.proxy_header;
+        Ok(stream)
+    }
+
+    /// Conduct the Noise handshake as the responder, as [`handshake`][Self::handshake]
+    /// does, and then hand the stream to the router's handler for the peer. Requires the
+    /// `router` feature.
+    ///
+    /// Returns the handler's task, or fails with [`NoiseError::UnknownPeer`] if the
+    /// router rejected the peer.
+    #[cfg(feature = "router")]
+    pub async fn handshake_and_route(
+        self,
+        handshake: impl Handshake,
+        router: &crate::router::Router,
+    ) -> Result<tokio::task::JoinHandle<()>, NoiseError> {
+        let mut this = self;
+        this.read_proxy_header().await?;
+        let peer_addr = this.client_addr();
+        let stream = this.handshake(handshake).await?;
+        router.dispatch(stream, peer_addr).await
+    }
+
+    /// Consume the connection without conducting a handshake, returning the raw TCP socket.
+    pub fn into_inner(self) -> TcpStream {
+        self.socket
+    }
+}
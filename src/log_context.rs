@@ -0,0 +1,114 @@
+//! This module provides [`LogContext`], key-value pairs which tag the log messages of a
+//! stream.
+
+use std::fmt;
+
+use crate::stream::sanitize_name;
+
+/// Key-value pairs which tag each log message of a stream, alongside its
+/// [name][crate::NoiseStream::name], such as a connection id or the peer's key
+/// fingerprint, so that a server's logs can be correlated with its other records.
+///
+/// The pairs follow the name in the brackets which start each message, in the order
+/// they were added:
+///
+/// ```text
+/// [responder conn=42 peer=SHA256:Zmh6rfhivXdsj8GLjp+OIAiXFIVu4jOzkCpZHQ1fKSU] peer closed stream
+/// ```
+///
+/// Values are escaped in the same way as names, and quoted if they are empty or contain
+/// whitespace, `=` or `"`, so that every pair can be parsed back out of a log line.
+///
+/// Set a context for handshakes and the streams they create with
+/// [`NoiseBuilder::log_context`][crate::NoiseBuilder::log_context], or on an established
+/// stream with [`NoiseStream::set_log_context`][crate::NoiseStream::set_log_context].
+///
+/// ```
+/// use tokio_noise::{Fingerprint, LogContext};
+///
+/// let context = LogContext::new()
+///     .with("conn", 42)
+///     .with("peer", Fingerprint::of(&[7u8; 32]))
+///     .with("user", "Jo Bloggs");
+/// assert_eq!(context.get("conn"), Some("42"));
+/// assert!(context.to_string().starts_with("conn=42 peer=SHA256:"));
+/// assert!(context.to_string().ends_with(r#" user="Jo Bloggs""#));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LogContext {
+    fields: Vec<(String, String)>,
+}
+
+impl LogContext {
+    /// Construct an empty context.
+    pub fn new() -> LogContext {
+        LogContext::default()
+    }
+
+    /// Add a key-value pair, replacing the value of any pair with the same key.
+    pub fn with(mut self, key: impl Into<String>, value: impl fmt::Display) -> LogContext {
+        self.insert(key, value);
+        self
+    }
+
+    /// Add a key-value pair in place, replacing the value of any pair with the same key.
+    pub fn insert(&mut self, key: impl Into<String>, value: impl fmt::Display) {
+        let key = sanitize_name(key.into());
+        let value = sanitize_name(value.to_string());
+        match self.fields.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, v)) => *v = value,
+            None => self.fields.push((key, value)),
+        }
+    }
+
+    /// Returns the value for the given key, if any.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.iter().find(|(k, _)| *k == key).map(|(_, v)| v)
+    }
+
+    /// Returns an iterator over the key-value pairs, in the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Returns whether the context has no pairs.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+impl fmt::Display for LogContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (key, value)) in self.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            let quote = value.is_empty()
+                || value
+                    .chars()
+                    .any(|c| c.is_whitespace() || c == '=' || c == '"');
+            match quote {
+                true => write!(f, "{}={:?}", key, value)?,
+                false => write!(f, "{}={}", key, value)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A stream's name and log context, as shown at the start of each of its log messages.
+#[derive(Clone, Debug)]
+pub(crate) struct LogLabel {
+    pub(crate) name: String,
+    pub(crate) context: LogContext,
+}
+
+impl fmt::Display for LogLabel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name)?;
+        if !self.context.is_empty() {
+            write!(f, " {}", self.context)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,196 @@
+use std::{
+    ops::Deref,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    time::Instant,
+};
+
+use crate::errors::NoiseError;
+use crate::stream::NoiseStream;
+
+/// The initiator's side of a stream established by a one-way handshake pattern such as
+/// `N`, which can only send. It implements [`AsyncWrite`] but not [`AsyncRead`].
+///
+/// Dereferences to the inner [`NoiseStream`] for its accessors, such as
+/// [`stats`][NoiseStream::stats]. See
+/// [`NoiseStream::handshake_initiator_n`] and [`NoiseStream::into_send_only`].
+pub struct SendOnlyNoiseStream<S: AsyncRead + AsyncWrite + Unpin>(NoiseStream<S>);
+
+/// The responder's side of a stream established by a one-way handshake pattern such as
+/// `N`, which can only receive. It implements [`AsyncRead`] but not [`AsyncWrite`].
+///
+/// Dereferences to the inner [`NoiseStream`] for its accessors, such as
+/// [`closed_reason`][NoiseStream::closed_reason]. See
+/// [`NoiseStream::handshake_responder_n`] and [`NoiseStream::into_recv_only`].
+pub struct RecvOnlyNoiseStream<S: AsyncRead + AsyncWrite + Unpin>(NoiseStream<S>);
+
+impl<S: AsyncRead + AsyncWrite + Unpin> NoiseStream<S> {
+    /// Narrow the stream to one which can only send. This fails, returning the stream
+    /// unchanged, if it is the responder's side of a one-way handshake.
+    ///
+    /// A two-way stream can be narrowed too, though it then never reads the peer's
+    /// close, so its [`close`][SendOnlyNoiseStream::close] returns without waiting.
+    // Either variant holds the whole stream, so boxing the error would save nothing.
+    #[allow(clippy::result_large_err)]
+    pub fn into_send_only(self) -> Result<SendOnlyNoiseStream<S>, NoiseStream<S>> {
+        match self.can_send() {
+            true => Ok(SendOnlyNoiseStream(self)),
+            false => Err(self),
+        }
+    }
+
+    /// Narrow the stream to one which can only receive. This fails, returning the
+    /// stream unchanged, if it is the initiator's side of a one-way handshake.
+    ///
+    /// A two-way stream can be narrowed too, though it then never sends a close notify:
+    /// [`shutdown`][RecvOnlyNoiseStream::shutdown] just closes the transport.
+    #[allow(clippy::result_large_err)]
+    pub fn into_recv_only(self) -> Result<RecvOnlyNoiseStream<S>, NoiseStream<S>> {
+        match self.can_recv() {
+            true => Ok(RecvOnlyNoiseStream(self)),
+            false => Err(self),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> SendOnlyNoiseStream<S> {
+    pub(crate) fn from_initiator(stream: NoiseStream<S>) -> SendOnlyNoiseStream<S> {
+        SendOnlyNoiseStream(stream)
+    }
+
+    /// See [`NoiseStream::send`].
+    pub async fn send(&mut self, cleartext: &[u8]) -> Result<(), NoiseError> {
+        self.0.send(cleartext).await
+    }
+
+    /// See [`NoiseStream::send_deadline`].
+    pub async fn send_deadline(
+        &mut self,
+        cleartext: &[u8],
+        deadline: Instant,
+    ) -> Result<(), NoiseError> {
+        self.0.send_deadline(cleartext, deadline).await
+    }
+
+    /// See [`NoiseStream::close_with_error`].
+    pub async fn close_with_error(&mut self, code: u32, reason: &str) -> Result<(), NoiseError> {
+        self.0.close_with_error(code, reason).await
+    }
+
+    /// Flush all buffered data, send a close notify, and shut down the transport. There
+    /// is no reply to wait for, so this returns once the close is sent.
+    pub async fn close(mut self) -> Result<(), NoiseError> {
+        AsyncWriteExt::shutdown(&mut self.0).await?;
+        Ok(())
+    }
+
+    /// Returns a mutable reference to the underlying transport.
+    ///
+    /// Reading from or writing to the transport directly will corrupt the Noise session.
+    pub fn get_mut(&mut self) -> &mut S {
+        self.0.get_mut()
+    }
+
+    /// Unwrap the inner stream, on which reads fail with [`NoiseError::OneWay`] if the
+    /// handshake was one-way.
+    pub fn into_inner(self) -> NoiseStream<S> {
+        self.0
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> RecvOnlyNoiseStream<S> {
+    pub(crate) fn from_responder(stream: NoiseStream<S>) -> RecvOnlyNoiseStream<S> {
+        RecvOnlyNoiseStream(stream)
+    }
+
+    /// See [`NoiseStream::recv`].
+    pub async fn recv(&mut self, output: &mut [u8]) -> Result<usize, NoiseError> {
+        self.0.recv(output).await
+    }
+
+    /// See [`NoiseStream::recv_deadline`].
+    pub async fn recv_deadline(
+        &mut self,
+        output: &mut [u8],
+        deadline: Instant,
+    ) -> Result<usize, NoiseError> {
+        self.0.recv_deadline(output, deadline).await
+    }
+
+    /// Read and discard anything more the peer sends until it closes the stream, then
+    /// shut down the transport. If the peer closes with an error, that error is returned.
+    pub async fn close(mut self) -> Result<(), NoiseError> {
+        let mut discard = [0u8; 4096];
+        while self.0.read(&mut discard).await? > 0 {}
+        self.shutdown().await
+    }
+
+    /// Shut down the transport for writing, without sending anything to the peer.
+    pub async fn shutdown(&mut self) -> Result<(), NoiseError> {
+        AsyncWriteExt::shutdown(self.0.get_mut()).await?;
+        Ok(())
+    }
+
+    /// Returns a mutable reference to the underlying transport.
+    ///
+    /// Reading from or writing to the transport directly will corrupt the Noise session.
+    pub fn get_mut(&mut self) -> &mut S {
+        self.0.get_mut()
+    }
+
+    /// Unwrap the inner stream, on which writes fail with [`NoiseError::OneWay`] if the
+    /// handshake was one-way.
+    pub fn into_inner(self) -> NoiseStream<S> {
+        self.0
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Deref for SendOnlyNoiseStream<S> {
+    type Target = NoiseStream<S>;
+
+    fn deref(&self) -> &NoiseStream<S> {
+        &self.0
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Deref for RecvOnlyNoiseStream<S> {
+    type Target = NoiseStream<S>;
+
+    fn deref(&self) -> &NoiseStream<S> {
+        &self.0
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for SendOnlyNoiseStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for RecvOnlyNoiseStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut io::ReadBuf<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
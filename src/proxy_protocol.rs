@@ -0,0 +1,347 @@
+//! This module parses [PROXY protocol] headers, which load balancers such as HAProxy
+//! and AWS NLB prepend to a connection to pass on the address of the original client.
+//!
+//! [PROXY protocol]: https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::errors::{NoiseError, ProxyHeaderError};
+
+/// The signature which starts every version 2 header.
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// The prefix which starts every version 1 header.
+const V1_PREFIX: &[u8] = b"PROXY ";
+
+/// The longest a version 1 header may be, including its CRLF.
+const V1_MAX_LEN: usize = 107;
+
+/// Both versions of header are at least this long, so this much can be read before
+/// knowing which version is being sent.
+const MIN_HEADER_LEN: usize = V2_SIGNATURE.len();
+
+/// The length of the fixed part of a version 2 header, before its addresses.
+const V2_FIXED_LEN: usize = 16;
+
+/// The length of the address block of each version 2 address family.
+const V2_INET_LEN: usize = 12;
+const V2_INET6_LEN: usize = 36;
+const V2_UNIX_LEN: usize = 216;
+
+/// Configures a [`NoiseTcpListener`][crate::NoiseTcpListener] to expect a PROXY protocol
+/// header at the start of every connection. See
+/// [`set_proxy_protocol`][crate::NoiseTcpListener::set_proxy_protocol].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProxyProtocolConfig {
+    /// How long to wait for the whole header before failing with
+    /// [`ProxyHeaderError::Timeout`].
+    pub timeout: Duration,
+}
+
+impl Default for ProxyProtocolConfig {
+    fn default() -> Self {
+        ProxyProtocolConfig {
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// A type-length-value field from the end of a version 2 PROXY protocol header, such as
+/// the TLS SNI or the AWS VPC endpoint id the client connected through.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProxyTlv {
+    /// The type of the field, such as `0x02` for the authority (SNI) or `0xEA` for AWS.
+    pub kind: u8,
+    /// The value of the field.
+    pub value: Vec<u8>,
+}
+
+/// A PROXY protocol header, as sent by a load balancer ahead of the client's own bytes.
+///
+/// Both the human-readable version 1 and the binary version 2 are supported.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProxyHeader {
+    /// The version of the protocol the header was sent with, 1 or 2.
+    pub version: u8,
+    /// The address of the original client. This is `None` if the proxy didn't know it,
+    /// for a version 2 header which addresses Unix sockets, or for one sent by the proxy
+    /// on its own behalf, such as for a health check.
+    pub source: Option<SocketAddr>,
+    /// The address the original client connected to, if known.
+    pub destination: Option<SocketAddr>,
+    /// The TLVs which follow the addresses of a version 2 header, in order.
+    pub tlvs: Vec<ProxyTlv>,
+}
+
+impl ProxyHeader {
+    /// Returns the value of the first TLV of the given type, if any.
+    pub fn tlv(&self, kind: u8) -> Option<&[u8]> {
+        self.tlvs
+            .iter()
+            .find(|tlv| tlv.kind == kind)
+            .map(|tlv| tlv.value.as_slice())
+    }
+
+    /// Parse a header from the start of `bytes`, returning it with the number of bytes
+    /// it took up. Fails with [`ProxyHeaderError::Incomplete`] if `bytes` ends before the
+    /// header does.
+    ///
+    /// ```
+    /// use tokio_noise::ProxyHeader;
+    ///
+    /// let bytes = b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nhello";
+    /// let (header, len) = ProxyHeader::parse(bytes).unwrap();
+    /// assert_eq!(header.source, Some("192.0.2.1:56324".parse().unwrap()));
+    /// assert_eq!(&bytes[len..], b"hello");
+    /// ```
+    pub fn parse(bytes: &[u8]) -> Result<(ProxyHeader, usize), ProxyHeaderError> {
+        if bytes.starts_with(&V2_SIGNATURE) {
+            return parse_v2(bytes);
+        }
+        if bytes.starts_with(V1_PREFIX) {
+            return parse_v1(bytes);
+        }
+        let prefix_len = bytes.len().min(MIN_HEADER_LEN);
+        if V2_SIGNATURE.starts_with(&bytes[..prefix_len])
+            || V1_PREFIX.starts_with(&bytes[..prefix_len.min(V1_PREFIX.len())])
+        {
+            return Err(ProxyHeaderError::Incomplete);
+        }
+        Err(ProxyHeaderError::Missing)
+    }
+
+    /// Read a header from the start of `reader`, consuming exactly the header's bytes,
+    /// so that whatever follows it, such as the client's first handshake message, can
+    /// be read as usual.
+    ///
+    /// A reader which closes partway through the header fails with an IO error of kind
+    /// [`UnexpectedEof`][std::io::ErrorKind::UnexpectedEof]. This sets no timeout.
+    pub async fn read<R: AsyncRead + Unpin>(reader: &mut R) -> Result<ProxyHeader, NoiseError> {
+        let mut buf = vec![0u8; MIN_HEADER_LEN];
+        reader.read_exact(&mut buf).await?;
+        if buf.starts_with(&V2_SIGNATURE) {
+            buf.resize(V2_FIXED_LEN, 0);
+            reader.read_exact(&mut buf[MIN_HEADER_LEN..]).await?;
+            let len = u16::from_be_bytes([buf[14], buf[15]]) as usize;
+            buf.resize(V2_FIXED_LEN + len, 0);
+            reader.read_exact(&mut buf[V2_FIXED_LEN..]).await?;
+        } else if buf.starts_with(V1_PREFIX) {
+            // Version 1 headers end at the first CRLF, so read a byte at a time to
+            // leave everything after it unread.
+            while !buf.ends_with(b"\r\n") {
+                if buf.len() == V1_MAX_LEN {
+                    return Err(ProxyHeaderError::Malformed.into());
+                }
+                buf.push(reader.read_u8().await?);
+            }
+        }
+        let (header, _) = ProxyHeader::parse(&buf)?;
+        Ok(header)
+    }
+}
+
+fn parse_v1(bytes: &[u8]) -> Result<(ProxyHeader, usize), ProxyHeaderError> {
+    let searched = &bytes[..bytes.len().min(V1_MAX_LEN)];
+    let Some(end) = searched.windows(2).position(|w| w == b"\r\n") else {
+        return match bytes.len() < V1_MAX_LEN {
+            true => Err(ProxyHeaderError::Incomplete),
+            false => Err(ProxyHeaderError::Malformed),
+        };
+    };
+    let line = std::str::from_utf8(&bytes[V1_PREFIX.len()..end])
+        .map_err(|_| ProxyHeaderError::Malformed)?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    let (source, destination) = match fields[..] {
+        // Anything may follow UNKNOWN, and must be ignored.
+        ["UNKNOWN", ..] => (None, None),
+        [family @ ("TCP4" | "TCP6"), src_ip, dst_ip, src_port, dst_port] => {
+            let ip = |s: &str| -> Result<IpAddr, ProxyHeaderError> {
+                let ip = match family {
+                    "TCP4" => s.parse::<Ipv4Addr>().map(IpAddr::from),
+                    _ => s.parse::<Ipv6Addr>().map(IpAddr::from),
+                };
+                ip.map_err(|_| ProxyHeaderError::Malformed)
+            };
+            let port = |s: &str| -> Result<u16, ProxyHeaderError> {
+                // Ports are written in decimal, without leading zeros.
+                match s.starts_with('0') && s != "0" {
+                    true => Err(ProxyHeaderError::Malformed),
+                    false => s.parse().map_err(|_| ProxyHeaderError::Malformed),
+                }
+            };
+            (
+                Some(SocketAddr::new(ip(src_ip)?, port(src_port)?)),
+                Some(SocketAddr::new(ip(dst_ip)?, port(dst_port)?)),
+            )
+        }
+        _ => return Err(ProxyHeaderError::Malformed),
+    };
+    let header = ProxyHeader {
+        version: 1,
+        source,
+        destination,
+        tlvs: Vec::new(),
+    };
+    Ok((header, end + 2))
+}
+
+fn parse_v2(bytes: &[u8]) -> Result<(ProxyHeader, usize), ProxyHeaderError> {
+    if bytes.len() < V2_FIXED_LEN {
+        return Err(ProxyHeaderError::Incomplete);
+    }
+    let (version, command) = (bytes[12] >> 4, bytes[12] & 0x0F);
+    if version != 2 {
+        return Err(ProxyHeaderError::UnsupportedVersion(version));
+    }
+    let family = bytes[13] >> 4;
+    let len = u16::from_be_bytes([bytes[14], bytes[15]]) as usize;
+    let Some(block) = bytes.get(V2_FIXED_LEN..V2_FIXED_LEN + len) else {
+        return Err(ProxyHeaderError::Incomplete);
+    };
+    let header_len = V2_FIXED_LEN + len;
+
+    let mut header = ProxyHeader {
+        version: 2,
+        source: None,
+        destination: None,
+        tlvs: Vec::new(),
+    };
+    match command {
+        // The proxy's own connection, such as a health check. Its address block must
+        // be ignored.
+        0x0 => return Ok((header, header_len)),
+        0x1 => {}
+        _ => return Err(ProxyHeaderError::Malformed),
+    }
+
+    let addrs_len = match family {
+        // An unknown or unspecified family, whose address block must be ignored.
+        0x0 => return Ok((header, header_len)),
+        0x1 => V2_INET_LEN,
+        0x2 => V2_INET6_LEN,
+        0x3 => V2_UNIX_LEN,
+        _ => return Err(ProxyHeaderError::Malformed),
+    };
+    let Some(addrs) = block.get(..addrs_len) else {
+        return Err(ProxyHeaderError::Malformed);
+    };
+    let port = |at: usize| u16::from_be_bytes([addrs[at], addrs[at + 1]]);
+    match family {
+        0x1 => {
+            let src: [u8; 4] = addrs[0..4].try_into().expect("4-byte slice");
+            let dst: [u8; 4] = addrs[4..8].try_into().expect("4-byte slice");
+            header.source = Some(SocketAddr::new(src.into(), port(8)));
+            header.destination = Some(SocketAddr::new(dst.into(), port(10)));
+        }
+        0x2 => {
+            let src: [u8; 16] = addrs[0..16].try_into().expect("16-byte slice");
+            let dst: [u8; 16] = addrs[16..32].try_into().expect("16-byte slice");
+            header.source = Some(SocketAddr::new(src.into(), port(32)));
+            header.destination = Some(SocketAddr::new(dst.into(), port(34)));
+        }
+        _ => {}
+    }
+
+    let mut tlvs = &block[addrs_len..];
+    while !tlvs.is_empty() {
+        let Some(&[kind, len_hi, len_lo]) = tlvs.get(..3) else {
+            return Err(ProxyHeaderError::Malformed);
+        };
+        let len = u16::from_be_bytes([len_hi, len_lo]) as usize;
+        let Some(value) = tlvs.get(3..3 + len) else {
+            return Err(ProxyHeaderError::Malformed);
+        };
+        header.tlvs.push(ProxyTlv {
+            kind,
+            value: value.to_vec(),
+        });
+        tlvs = &tlvs[3 + len..];
+    }
+    Ok((header, header_len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn v1_headers() {
+        let (header, len) =
+            ProxyHeader::parse(b"PROXY TCP6 2001:db8::1 2001:db8::2 65535 0\r\n").unwrap();
+        assert_eq!(len, 44);
+        assert_eq!(header.source, Some("[2001:db8::1]:65535".parse().unwrap()));
+        assert_eq!(header.destination, Some("[2001:db8::2]:0".parse().unwrap()));
+
+        let (header, _) = ProxyHeader::parse(b"PROXY UNKNOWN ignored\r\n").unwrap();
+        assert_eq!(header.source, None);
+
+        for bad in [
+            &b"PROXY TCP4 2001:db8::1 192.0.2.2 1 2\r\n"[..],
+            b"PROXY TCP4 192.0.2.1 192.0.2.2 01 2\r\n",
+            b"PROXY TCP4 192.0.2.1 192.0.2.2 1 65536\r\n",
+            b"PROXY TCP4 192.0.2.1 192.0.2.2 1\r\n",
+            b"PROXY UDP4 192.0.2.1 192.0.2.2 1 2\r\n",
+            &[b"PROXY UNKNOWN ".as_slice(), &[b'x'; 100]].concat(),
+        ] {
+            assert_eq!(
+                ProxyHeader::parse(bad).map(drop),
+                Err(ProxyHeaderError::Malformed)
+            );
+        }
+        assert_eq!(
+            ProxyHeader::parse(b"PROXY TCP4 192.0.2.1").map(drop),
+            Err(ProxyHeaderError::Incomplete)
+        );
+        assert_eq!(
+            ProxyHeader::parse(b"GET / HTTP/1.1\r\n").map(drop),
+            Err(ProxyHeaderError::Missing)
+        );
+    }
+
+    #[test]
+    fn v2_headers() {
+        let mut bytes = V2_SIGNATURE.to_vec();
+        bytes.extend_from_slice(&[0x21, 0x11, 0, 12 + 7]);
+        bytes.extend_from_slice(&[192, 0, 2, 1, 198, 51, 100, 1, 0xDC, 0x04, 0x01, 0xBB]);
+        bytes.extend_from_slice(&[0x02, 0, 4, b'n', b'o', b'i', b's']);
+        let (header, len) = ProxyHeader::parse(&bytes).unwrap();
+        assert_eq!(len, bytes.len());
+        assert_eq!(header.source, Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(
+            header.destination,
+            Some("198.51.100.1:443".parse().unwrap())
+        );
+        assert_eq!(header.tlv(0x02), Some(&b"nois"[..]));
+
+        assert_eq!(
+            ProxyHeader::parse(&bytes[..bytes.len() - 1]).map(drop),
+            Err(ProxyHeaderError::Incomplete)
+        );
+
+        // A TLV which overruns the address block.
+        let mut overrun = bytes.clone();
+        overrun[V2_FIXED_LEN + V2_INET_LEN + 2] = 5;
+        assert_eq!(
+            ProxyHeader::parse(&overrun).map(drop),
+            Err(ProxyHeaderError::Malformed)
+        );
+
+        // A LOCAL command carries no addresses, whatever its address block holds.
+        let mut local = bytes.clone();
+        local[12] = 0x20;
+        let (header, _) = ProxyHeader::parse(&local).unwrap();
+        assert_eq!((header.source, header.tlvs.len()), (None, 0));
+
+        let mut v3 = bytes;
+        v3[12] = 0x31;
+        assert_eq!(
+            ProxyHeader::parse(&v3).map(drop),
+            Err(ProxyHeaderError::UnsupportedVersion(3))
+        );
+    }
+}
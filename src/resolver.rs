@@ -0,0 +1,209 @@
+//! This module provides [`Resolver`], through which a [`NoiseConnector`] looks up the
+//! addresses of a host name.
+//!
+//! [`NoiseConnector`]: crate::NoiseConnector
+
+use std::{
+    fmt, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+};
+
+use crate::config::BoxFuture;
+
+/// Looks up the socket addresses of a host name, for a
+/// [`NoiseConnector`][crate::NoiseConnector] to connect to.
+///
+/// The connector tries the returned addresses in order, moving on to the next whenever
+/// one can't be reached, so a resolver controls which are preferred, such as IPv6
+/// before IPv4, by the order it returns them in. An error, or an empty list, fails the
+/// connection as [`NoiseError::Connect`][crate::NoiseError::Connect].
+///
+/// Implement this to use an internal resolver, or to put a timeout on each query. The
+/// default, [`SystemResolver`], uses the system's resolver as
+/// [`tokio::net::lookup_host`] does.
+///
+/// ```
+/// use std::{io, net::SocketAddr};
+/// use tokio_noise::{BoxFuture, Resolver};
+///
+/// /// Resolves every host to the same address.
+/// struct Fixed(SocketAddr);
+///
+/// impl Resolver for Fixed {
+///     fn resolve<'a>(
+///         &'a self,
+///         _host: &'a str,
+///         port: u16,
+///     ) -> BoxFuture<'a, io::Result<Vec<SocketAddr>>> {
+///         Box::pin(async move { Ok(vec![SocketAddr::new(self.0.ip(), port)]) })
+///     }
+/// }
+/// ```
+pub trait Resolver: Send + Sync {
+    /// Returns the socket addresses of `host` with the given port, in the order they
+    /// should be tried.
+    fn resolve<'a>(
+        &'a self,
+        host: &'a str,
+        port: u16,
+    ) -> BoxFuture<'a, io::Result<Vec<SocketAddr>>>;
+}
+
+impl fmt::Debug for dyn Resolver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Resolver")
+    }
+}
+
+/// Resolves host names with the system's resolver, on tokio's blocking thread pool, as
+/// [`tokio::net::lookup_host`] does.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve<'a>(
+        &'a self,
+        host: &'a str,
+        port: u16,
+    ) -> BoxFuture<'a, io::Result<Vec<SocketAddr>>> {
+        Box::pin(async move { Ok(tokio::net::lookup_host((host, port)).await?.collect()) })
+    }
+}
+
+/// Resolves host names asynchronously with [`hickory_resolver`], without blocking a
+/// thread on each query.
+///
+/// The resolver's own options, such as its name servers, timeouts, and whether to
+/// prefer IPv6, decide which addresses are returned and in what order.
+#[cfg(feature = "hickory-dns")]
+#[derive(Clone)]
+pub struct HickoryResolver {
+    resolver: hickory_resolver::TokioAsyncResolver,
+}
+
+#[cfg(feature = "hickory-dns")]
+impl HickoryResolver {
+    /// Construct a resolver configured from the system's configuration, such as
+    /// `/etc/resolv.conf` on Unix.
+    pub fn from_system_conf() -> io::Result<HickoryResolver> {
+        let resolver = hickory_resolver::TokioAsyncResolver::tokio_from_system_conf()
+            .map_err(io::Error::other)?;
+        Ok(HickoryResolver { resolver })
+    }
+
+    /// Construct a resolver with the given configuration and options.
+    pub fn new(
+        config: hickory_resolver::config::ResolverConfig,
+        options: hickory_resolver::config::ResolverOpts,
+    ) -> HickoryResolver {
+        HickoryResolver {
+            resolver: hickory_resolver::TokioAsyncResolver::tokio(config, options),
+        }
+    }
+}
+
+#[cfg(feature = "hickory-dns")]
+impl From<hickory_resolver::TokioAsyncResolver> for HickoryResolver {
+    fn from(resolver: hickory_resolver::TokioAsyncResolver) -> HickoryResolver {
+        HickoryResolver { resolver }
+    }
+}
+
+#[cfg(feature = "hickory-dns")]
+impl fmt::Debug for HickoryResolver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HickoryResolver").finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "hickory-dns")]
+impl Resolver for HickoryResolver {
+    fn resolve<'a>(
+        &'a self,
+        host: &'a str,
+        port: u16,
+    ) -> BoxFuture<'a, io::Result<Vec<SocketAddr>>> {
+        Box::pin(async move {
+            let lookup = self
+                .resolver
+                .lookup_ip(host)
+                .await
+                .map_err(io::Error::other)?;
+            Ok(lookup.iter().map(|ip| SocketAddr::new(ip, port)).collect())
+        })
+    }
+}
+
+/// An address which a [`NoiseConnector`][crate::NoiseConnector] can connect to: either
+/// a socket address, or a host name and port, given as a tuple or as `"host:port"`,
+/// which it looks up with its [`Resolver`].
+///
+/// This is implemented for the same types as [`tokio::net::ToSocketAddrs`], and can't
+/// be implemented outside this crate.
+pub trait ConnectAddr: sealed::ConnectAddrPriv {}
+
+pub(crate) mod sealed {
+    use super::*;
+
+    /// Where to connect, before any name is resolved.
+    pub enum ConnectTarget {
+        Addr(SocketAddr),
+        Host(String, u16),
+    }
+
+    pub trait ConnectAddrPriv {
+        fn to_target(&self) -> io::Result<ConnectTarget>;
+    }
+}
+
+use sealed::{ConnectAddrPriv, ConnectTarget};
+
+macro_rules! impl_connect_addr {
+    ($ty:ty, $self:ident => $target:expr) => {
+        impl ConnectAddr for $ty {}
+        impl ConnectAddrPriv for $ty {
+            fn to_target(&$self) -> io::Result<ConnectTarget> {
+                $target
+            }
+        }
+    };
+}
+
+impl_connect_addr!(SocketAddr, self => Ok(ConnectTarget::Addr(*self)));
+impl_connect_addr!(SocketAddrV4, self => Ok(ConnectTarget::Addr((*self).into())));
+impl_connect_addr!(SocketAddrV6, self => Ok(ConnectTarget::Addr((*self).into())));
+impl_connect_addr!((IpAddr, u16), self => Ok(ConnectTarget::Addr((*self).into())));
+impl_connect_addr!((Ipv4Addr, u16), self => Ok(ConnectTarget::Addr((*self).into())));
+impl_connect_addr!((Ipv6Addr, u16), self => Ok(ConnectTarget::Addr((*self).into())));
+impl_connect_addr!((&str, u16), self => Ok(host_target(self.0, self.1)));
+impl_connect_addr!((String, u16), self => Ok(host_target(&self.0, self.1)));
+impl_connect_addr!(str, self => str_target(self));
+impl_connect_addr!(String, self => str_target(self));
+
+impl<T: ConnectAddr + ?Sized> ConnectAddr for &T {}
+impl<T: ConnectAddr + ?Sized> ConnectAddrPriv for &T {
+    fn to_target(&self) -> io::Result<ConnectTarget> {
+        (**self).to_target()
+    }
+}
+
+/// IP address literals are connected to directly, without a lookup.
+fn host_target(host: &str, port: u16) -> ConnectTarget {
+    match host.parse::<IpAddr>() {
+        Ok(ip) => ConnectTarget::Addr(SocketAddr::new(ip, port)),
+        Err(_) => ConnectTarget::Host(host.to_string(), port),
+    }
+}
+
+fn str_target(s: &str) -> io::Result<ConnectTarget> {
+    if let Ok(addr) = s.parse::<SocketAddr>() {
+        return Ok(ConnectTarget::Addr(addr));
+    }
+    match s.rsplit_once(':').map(|(host, port)| (host, port.parse())) {
+        Some((host, Ok(port))) => Ok(host_target(host, port)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid socket address",
+        )),
+    }
+}
@@ -0,0 +1,389 @@
+use log::{debug, warn};
+use std::{
+    collections::HashSet,
+    fmt,
+    future::Future,
+    hash::{Hash, Hasher},
+    net::SocketAddr,
+    str::FromStr,
+    sync::Arc,
+};
+use tokio::task::JoinHandle;
+
+use crate::{
+    config::BoxFuture,
+    errors::{FingerprintError, NoiseError},
+    fingerprint::Fingerprint,
+    tcp::NoiseTcpStream,
+};
+
+/// The error code sent by a [`Router`] to a peer which matched no route, unless
+/// overridden with [`Router::reject_code`].
+pub const UNKNOWN_PEER_CODE: u32 = 403;
+
+/// Who a peer is, as established by its handshake.
+///
+/// Two identities are equal, and hash alike, if they have the same
+/// [`remote_fingerprint`][Self::remote_fingerprint], whatever address or protocol they
+/// connected with, so an identity can key per-peer state. Every anonymous peer is
+/// therefore equal to every other.
+///
+/// Displays for log lines as the fingerprint, or `anonymous peer`, followed by the
+/// protocol name and address where known:
+///
+/// ```text
+/// SHA256:Zmh6rfhivXdsj8GLjp+OIAiXFIVu4jOzkCpZHQ1fKSU via Noise_XX_25519_ChaChaPoly_SHA256 from 127.0.0.1:9000
+/// ```
+///
+/// The fingerprint alone, such as an allowlist entry, parses into an identity with
+/// only a fingerprint.
+///
+/// With the `serde` feature, an identity serializes as a struct of the fields below, in
+/// this order, any of which may be null or, when deserializing, missing. Keys are
+/// lowercase hex, and the fingerprint and address are strings in human-readable formats.
+/// This representation is stable.
+///
+/// ```text
+/// {
+///     "fingerprint": "SHA256:...",
+///     "remote_static_key": "0123...",
+///     "local_static_key": "4567...",
+///     "protocol_name": "Noise_XX_25519_ChaChaPoly_SHA256",
+///     "peer_addr": "127.0.0.1:9000"
+/// }
+/// ```
+///
+/// A fingerprint which doesn't match the remote static key fails to deserialize, and
+/// one which is missing is computed from the key.
+#[derive(Clone, Debug)]
+pub struct PeerIdentity {
+    /// The peer's static public key, if the handshake pattern transmits or presumes one.
+    pub remote_static_key: Option<Vec<u8>>,
+    /// The fingerprint of the peer's static public key.
+    pub remote_fingerprint: Option<Fingerprint>,
+    /// Our static public key, which identifies which of our identities the peer reached.
+    pub local_static_key: Option<Vec<u8>>,
+    /// The Noise protocol name of the handshake.
+    pub protocol_name: Option<String>,
+    /// The address of the peer, if known.
+    pub peer_addr: Option<SocketAddr>,
+}
+
+impl PeerIdentity {
+    /// Describe the peer of an established stream.
+    pub fn of(stream: &NoiseTcpStream, peer_addr: SocketAddr) -> PeerIdentity {
+        let remote_static_key = stream.remote_static_key();
+        PeerIdentity {
+            remote_fingerprint: remote_static_key.map(Fingerprint::of),
+            remote_static_key: remote_static_key.map(<[u8]>::to_vec),
+            local_static_key: stream.local_static_public_key().map(<[u8]>::to_vec),
+            protocol_name: stream.protocol_name().map(str::to_string),
+            peer_addr: Some(peer_addr),
+        }
+    }
+}
+
+impl PartialEq for PeerIdentity {
+    fn eq(&self, other: &PeerIdentity) -> bool {
+        self.remote_fingerprint == other.remote_fingerprint
+    }
+}
+
+impl Eq for PeerIdentity {}
+
+impl Hash for PeerIdentity {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.remote_fingerprint.hash(state);
+    }
+}
+
+impl fmt::Display for PeerIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.remote_fingerprint {
+            Some(fingerprint) => write!(f, "{}", fingerprint)?,
+            None => f.write_str("anonymous peer")?,
+        }
+        if let Some(protocol_name) = &self.protocol_name {
+            write!(f, " via {}", protocol_name)?;
+        }
+        if let Some(peer_addr) = &self.peer_addr {
+            write!(f, " from {}", peer_addr)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for PeerIdentity {
+    type Err = FingerprintError;
+
+    /// Parses a [`Fingerprint`] into an identity with only a fingerprint.
+    fn from_str(s: &str) -> Result<PeerIdentity, FingerprintError> {
+        Ok(PeerIdentity {
+            remote_static_key: None,
+            remote_fingerprint: Some(s.parse()?),
+            local_static_key: None,
+            protocol_name: None,
+            peer_addr: None,
+        })
+    }
+}
+
+/// The serialized form of a [`PeerIdentity`]. Changing it breaks anything persisted.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PeerIdentityRepr {
+    fingerprint: Option<Fingerprint>,
+    remote_static_key: Option<String>,
+    local_static_key: Option<String>,
+    protocol_name: Option<String>,
+    peer_addr: Option<SocketAddr>,
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for PeerIdentity {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        PeerIdentityRepr {
+            fingerprint: self.remote_fingerprint,
+            remote_static_key: self.remote_static_key.as_deref().map(crate::hex::encode),
+            local_static_key: self.local_static_key.as_deref().map(crate::hex::encode),
+            protocol_name: self.protocol_name.clone(),
+            peer_addr: self.peer_addr,
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for PeerIdentity {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<PeerIdentity, D::Error> {
+        use serde::de::Error;
+
+        let repr = PeerIdentityRepr::deserialize(deserializer)?;
+        let decode_key = |key: Option<String>, field: &str| {
+            key.map(|key| {
+                crate::hex::decode(&key)
+                    .ok_or_else(|| D::Error::custom(format!("{} is not valid hex", field)))
+            })
+            .transpose()
+        };
+        let remote_static_key = decode_key(repr.remote_static_key, "remote_static_key")?;
+        let remote_fingerprint = match (&remote_static_key, repr.fingerprint) {
+            (Some(key), Some(fingerprint)) if !fingerprint.matches(key) => {
+                return Err(D::Error::custom(
+                    "fingerprint does not match remote_static_key",
+                ));
+            }
+            (Some(key), _) => Some(Fingerprint::of(key)),
+            (None, fingerprint) => fingerprint,
+        };
+        Ok(PeerIdentity {
+            remote_static_key,
+            remote_fingerprint,
+            local_static_key: decode_key(repr.local_static_key, "local_static_key")?,
+            protocol_name: repr.protocol_name,
+            peer_addr: repr.peer_addr,
+        })
+    }
+}
+
+type PredicateFn = dyn Fn(&PeerIdentity) -> bool + Send + Sync;
+
+/// Decides whether a [`Router`] route accepts a peer.
+#[derive(Clone)]
+pub enum KeyMatcher {
+    /// Matches a peer with exactly this static key.
+    Exact(Vec<u8>),
+    /// Matches a peer whose static key is any of these.
+    AnyOf(HashSet<Vec<u8>>),
+    /// Matches a peer whose static key has any of these fingerprints.
+    Fingerprints(Vec<Fingerprint>),
+    /// Matches a peer for which the predicate returns true.
+    Predicate(Arc<PredicateFn>),
+}
+
+impl KeyMatcher {
+    /// Matches a peer with exactly this static key.
+    pub fn exact(key: impl Into<Vec<u8>>) -> KeyMatcher {
+        KeyMatcher::Exact(key.into())
+    }
+
+    /// Matches a peer whose static key is any of the given keys.
+    pub fn any_of<K: Into<Vec<u8>>>(keys: impl IntoIterator<Item = K>) -> KeyMatcher {
+        KeyMatcher::AnyOf(keys.into_iter().map(Into::into).collect())
+    }
+
+    /// Matches a peer whose static key has any of the given fingerprints, such as
+    /// those parsed from an allowlist.
+    pub fn fingerprints(fingerprints: impl IntoIterator<Item = Fingerprint>) -> KeyMatcher {
+        KeyMatcher::Fingerprints(fingerprints.into_iter().collect())
+    }
+
+    /// Matches a peer for which the predicate returns true.
+    pub fn predicate(f: impl Fn(&PeerIdentity) -> bool + Send + Sync + 'static) -> KeyMatcher {
+        KeyMatcher::Predicate(Arc::new(f))
+    }
+
+    /// Returns whether the peer matches. A peer without a static key matches only a
+    /// predicate.
+    pub fn matches(&self, peer: &PeerIdentity) -> bool {
+        match (self, &peer.remote_static_key) {
+            (KeyMatcher::Exact(expected), Some(key)) => expected == key,
+            (KeyMatcher::AnyOf(keys), Some(key)) => keys.contains(key),
+            (KeyMatcher::Fingerprints(fingerprints), Some(key)) => {
+                let fingerprint = Fingerprint::of(key);
+                // Every entry is compared, so the time taken doesn't reveal which matched.
+                fingerprints
+                    .iter()
+                    .fold(false, |found, f| found | (*f == fingerprint))
+            }
+            (KeyMatcher::Predicate(f), _) => f(peer),
+            (_, None) => false,
+        }
+    }
+}
+
+impl fmt::Debug for KeyMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeyMatcher::Exact(key) => f.debug_tuple("Exact").field(key).finish(),
+            KeyMatcher::AnyOf(keys) => f.debug_tuple("AnyOf").field(keys).finish(),
+            KeyMatcher::Fingerprints(fingerprints) => {
+                f.debug_tuple("Fingerprints").field(fingerprints).finish()
+            }
+            KeyMatcher::Predicate(_) => f.write_str("Predicate"),
+        }
+    }
+}
+
+type HandlerFn = dyn Fn(NoiseTcpStream, PeerIdentity) -> BoxFuture<'static, ()> + Send + Sync;
+
+/// Dispatches authenticated connections to handlers by the identity of the peer, so that
+/// each handler can trust who it's talking to without checking again. Requires the
+/// `router` feature.
+///
+/// Routes are tried in the order they were added, and the connection is handed to the
+/// first whose [`KeyMatcher`] accepts the peer, on a newly spawned task. A connection
+/// which matches no route goes to the default handler if there is one, and is otherwise
+/// closed with [`NoiseStream::close_with_error`][crate::NoiseStream::close_with_error].
+///
+/// ```no_run
+/// # async fn example(listener: tokio_noise::NoiseTcpListener, alice: Vec<u8>, server_key: Vec<u8>) -> Result<(), tokio_noise::NoiseError> {
+/// use tokio_noise::{handshakes::SnowHandshake, KeyMatcher, Router};
+///
+/// let router = Router::new().route(KeyMatcher::exact(alice), |stream, peer| async move {
+///     // Serve tenant Alice.
+///     # drop((stream, peer));
+/// });
+/// let handshake = SnowHandshake::new("Noise_XX_25519_ChaChaPoly_SHA256")?
+///     .local_private_key(&server_key);
+///
+/// loop {
+///     let connection = listener.accept().await?;
+///     let (router, handshake) = (router.clone(), handshake.clone());
+///     tokio::spawn(async move { connection.handshake_and_route(handshake, &router).await });
+/// }
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Router {
+    routes: Vec<(KeyMatcher, Arc<HandlerFn>)>,
+    default: Option<Arc<HandlerFn>>,
+    reject_code: u32,
+}
+
+impl Router {
+    /// Construct a router with no routes, which rejects every peer.
+    pub fn new() -> Router {
+        Router {
+            routes: Vec::new(),
+            default: None,
+            reject_code: UNKNOWN_PEER_CODE,
+        }
+    }
+
+    /// Add a route, whose handler is called for peers which the matcher accepts and no
+    /// earlier route did.
+    pub fn route<F, Fut>(mut self, matcher: KeyMatcher, handler: F) -> Router
+    where
+        F: Fn(NoiseTcpStream, PeerIdentity) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.routes.push((matcher, box_handler(handler)));
+        self
+    }
+
+    /// Set the handler for peers which match no route, in place of rejecting them.
+    pub fn default_route<F, Fut>(mut self, handler: F) -> Router
+    where
+        F: Fn(NoiseTcpStream, PeerIdentity) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.default = Some(box_handler(handler));
+        self
+    }
+
+    /// Set the error code sent to peers which match no route. Defaults to
+    /// [`UNKNOWN_PEER_CODE`].
+    pub fn reject_code(mut self, code: u32) -> Router {
+        self.reject_code = code;
+        self
+    }
+
+    /// Hand an established stream to the handler for its peer, returning the handler's
+    /// task. If no route matches and there is no default handler, the stream is closed
+    /// with the reject code, and [`NoiseError::UnknownPeer`] is returned.
+    pub async fn dispatch(
+        &self,
+        mut stream: NoiseTcpStream,
+        peer_addr: SocketAddr,
+    ) -> Result<JoinHandle<()>, NoiseError> {
+        let peer = PeerIdentity::of(&stream, peer_addr);
+        let handler = self
+            .routes
+            .iter()
+            .find(|(matcher, _)| matcher.matches(&peer))
+            .map(|(_, handler)| handler)
+            .or(self.default.as_ref());
+
+        match handler {
+            Some(handler) => {
+                debug!("routing connection from {}", peer);
+                Ok(tokio::spawn(handler(stream, peer)))
+            }
+            None => {
+                warn!("rejecting connection from unknown peer {}", peer);
+                stream
+                    .close_with_error(self.reject_code, "unknown peer")
+                    .await?;
+                Err(NoiseError::UnknownPeer)
+            }
+        }
+    }
+}
+
+impl Default for Router {
+    fn default() -> Router {
+        Router::new()
+    }
+}
+
+impl fmt::Debug for Router {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Router")
+            .field(
+                "routes",
+                &self.routes.iter().map(|(m, _)| m).collect::<Vec<_>>(),
+            )
+            .field("has_default", &self.default.is_some())
+            .field("reject_code", &self.reject_code)
+            .finish()
+    }
+}
+
+fn box_handler<F, Fut>(handler: F) -> Arc<HandlerFn>
+where
+    F: Fn(NoiseTcpStream, PeerIdentity) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    Arc::new(move |stream, peer| Box::pin(handler(stream, peer)) as BoxFuture<'static, ()>)
+}
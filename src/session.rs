@@ -0,0 +1,322 @@
+use std::fmt;
+
+use snow::params::NoiseParams;
+
+use crate::{
+    errors::{NoiseError, SessionError},
+    handshakes::backend_resolver,
+};
+
+/// The length of each transport key.
+const SESSION_KEY_LEN: usize = 32;
+
+/// The version of the format written by [`SessionSnapshot::to_bytes`].
+const SNAPSHOT_VERSION: u8 = 2;
+
+const FLAG_INITIATOR: u8 = 1 << 0;
+const FLAG_SENT_PREAMBLE: u8 = 1 << 1;
+const FLAG_SENT_CLOSE: u8 = 1 << 2;
+const FLAG_RECEIVED_CLOSE_NOTIFY: u8 = 1 << 3;
+
+/// The transport keys derived by a handshake, one for each direction. Snow can't read
+/// them back out of a transport state, so they're kept from the split.
+#[derive(Clone)]
+pub(crate) struct SessionKeys {
+    /// The key for messages from the initiator to the responder.
+    pub(crate) initiator: [u8; SESSION_KEY_LEN],
+    /// The key for messages from the responder to the initiator.
+    pub(crate) responder: [u8; SESSION_KEY_LEN],
+}
+
+impl SessionKeys {
+    /// Take the keys from a finished handshake, before it enters transport mode.
+    pub(crate) fn split(state: &mut snow::HandshakeState) -> SessionKeys {
+        let (initiator, responder) = state.dangerously_get_raw_split();
+        SessionKeys {
+            initiator,
+            responder,
+        }
+    }
+}
+
+impl fmt::Debug for SessionKeys {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SessionKeys(..)")
+    }
+}
+
+/// The complete state of an established [`NoiseStream`][crate::NoiseStream], apart
+/// from its transport, for handing the stream to another process. Available with the
+/// `session-export` feature.
+///
+/// Created by [`NoiseStream::export_session`][crate::NoiseStream::export_session], and
+/// resumed by [`NoiseStream::import_session`][crate::NoiseStream::import_session]. A
+/// snapshot holds the session's transport keys, so anyone who reads it can decrypt
+/// and forge the stream's traffic. Send it only over a channel as trusted as the
+/// process itself, such as a unix socket between the old and new processes of an
+/// upgrade.
+///
+/// A snapshot must be imported at most once, since two streams resumed from it would
+/// encrypt different data under the same nonces.
+#[derive(Clone)]
+pub struct SessionSnapshot {
+    pub(crate) name: String,
+    pub(crate) initiator: bool,
+    pub(crate) protocol_name: String,
+    pub(crate) handshake_hash: Vec<u8>,
+    pub(crate) local_static_key: Option<Vec<u8>>,
+    pub(crate) remote_static_key: Option<Vec<u8>>,
+    pub(crate) keys: SessionKeys,
+    pub(crate) sending_nonce: u64,
+    pub(crate) receiving_nonce: u64,
+    pub(crate) sending_sequence: u32,
+    pub(crate) receiving_sequence: u32,
+    pub(crate) frame_size: usize,
+    pub(crate) sent_preamble: bool,
+    pub(crate) peer_framing_version: Option<u8>,
+    pub(crate) peer_frame_size: Option<usize>,
+    pub(crate) sent_close: bool,
+    pub(crate) received_close_notify: bool,
+    pub(crate) peer_close: Option<(u32, String)>,
+    /// Cleartext which has been decrypted but not yet read.
+    pub(crate) read_overflow: Vec<u8>,
+    /// Ciphertext which has been received but not yet decrypted.
+    pub(crate) unprocessed: Vec<u8>,
+    /// Ciphertext which has been encrypted but not yet written to the transport.
+    pub(crate) unsent: Vec<u8>,
+}
+
+impl SessionSnapshot {
+    /// Returns the name of the exported stream.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns true if the exported stream was the initiator of its handshake.
+    pub fn is_initiator(&self) -> bool {
+        self.initiator
+    }
+
+    /// Returns the Noise protocol name of the handshake which established the stream.
+    pub fn protocol_name(&self) -> &str {
+        &self.protocol_name
+    }
+
+    /// Encode the snapshot to bytes, to be decoded by [`from_bytes`][Self::from_bytes].
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut flags = 0;
+        for (set, flag) in [
+            (self.initiator, FLAG_INITIATOR),
+            (self.sent_preamble, FLAG_SENT_PREAMBLE),
+            (self.sent_close, FLAG_SENT_CLOSE),
+            (self.received_close_notify, FLAG_RECEIVED_CLOSE_NOTIFY),
+        ] {
+            if set {
+                flags |= flag;
+            }
+        }
+
+        let mut out = vec![SNAPSHOT_VERSION, flags];
+        put_bytes(&mut out, self.name.as_bytes());
+        put_bytes(&mut out, self.protocol_name.as_bytes());
+        put_bytes(&mut out, &self.handshake_hash);
+        put_optional_bytes(&mut out, self.local_static_key.as_deref());
+        put_optional_bytes(&mut out, self.remote_static_key.as_deref());
+        out.extend_from_slice(&self.keys.initiator);
+        out.extend_from_slice(&self.keys.responder);
+        out.extend_from_slice(&self.sending_nonce.to_be_bytes());
+        out.extend_from_slice(&self.receiving_nonce.to_be_bytes());
+        out.extend_from_slice(&self.sending_sequence.to_be_bytes());
+        out.extend_from_slice(&self.receiving_sequence.to_be_bytes());
+        out.extend_from_slice(&(self.frame_size as u32).to_be_bytes());
+        match self.peer_framing_version {
+            Some(version) => out.extend_from_slice(&[1, version]),
+            None => out.push(0),
+        }
+        match self.peer_frame_size {
+            Some(size) => {
+                out.push(1);
+                out.extend_from_slice(&(size as u32).to_be_bytes());
+            }
+            None => out.push(0),
+        }
+        match &self.peer_close {
+            Some((code, reason)) => {
+                out.push(1);
+                out.extend_from_slice(&code.to_be_bytes());
+                put_bytes(&mut out, reason.as_bytes());
+            }
+            None => out.push(0),
+        }
+        put_bytes(&mut out, &self.read_overflow);
+        put_bytes(&mut out, &self.unprocessed);
+        put_bytes(&mut out, &self.unsent);
+        out
+    }
+
+    /// Decode a snapshot encoded by [`to_bytes`][Self::to_bytes]. Fails with
+    /// [`SessionError::Malformed`] if the bytes are truncated, or were not produced by a
+    /// compatible version of this crate.
+    pub fn from_bytes(bytes: &[u8]) -> Result<SessionSnapshot, SessionError> {
+        let mut r = Reader(bytes);
+        if r.u8()? != SNAPSHOT_VERSION {
+            return Err(SessionError::Malformed);
+        }
+        let flags = r.u8()?;
+        let snapshot = SessionSnapshot {
+            initiator: flags & FLAG_INITIATOR != 0,
+            sent_preamble: flags & FLAG_SENT_PREAMBLE != 0,
+            sent_close: flags & FLAG_SENT_CLOSE != 0,
+            received_close_notify: flags & FLAG_RECEIVED_CLOSE_NOTIFY != 0,
+            name: r.string()?,
+            protocol_name: r.string()?,
+            handshake_hash: r.bytes()?.to_vec(),
+            local_static_key: r.optional_bytes()?,
+            remote_static_key: r.optional_bytes()?,
+            keys: SessionKeys {
+                initiator: r.array()?,
+                responder: r.array()?,
+            },
+            sending_nonce: u64::from_be_bytes(r.array()?),
+            receiving_nonce: u64::from_be_bytes(r.array()?),
+            sending_sequence: u32::from_be_bytes(r.array()?),
+            receiving_sequence: u32::from_be_bytes(r.array()?),
+            frame_size: u32::from_be_bytes(r.array()?) as usize,
+            peer_framing_version: match r.u8()? {
+                0 => None,
+                _ => Some(r.u8()?),
+            },
+            peer_frame_size: match r.u8()? {
+                0 => None,
+                _ => Some(u32::from_be_bytes(r.array()?) as usize),
+            },
+            peer_close: match r.u8()? {
+                0 => None,
+                _ => Some((u32::from_be_bytes(r.array()?), r.string()?)),
+            },
+            read_overflow: r.bytes()?.to_vec(),
+            unprocessed: r.bytes()?.to_vec(),
+            unsent: r.bytes()?.to_vec(),
+        };
+        if !r.0.is_empty() {
+            return Err(SessionError::Malformed);
+        }
+        Ok(snapshot)
+    }
+
+    /// Build a transport state with the exported session's keys.
+    ///
+    /// Snow can only create a transport state from a handshake, so a throwaway `NN`
+    /// handshake with the session's cipher and hash is run locally, and its keys
+    /// replaced. The state is stateless, so the stream resumes at the exported nonces
+    /// by passing them explicitly.
+    pub(crate) fn transport_state(&self) -> Result<snow::StatelessTransportState, NoiseError> {
+        // Noise_<pattern>_<dh>_<cipher>_<hash>
+        let mut parts = self.protocol_name.rsplitn(3, '_');
+        let (Some(hash), Some(cipher)) = (parts.next(), parts.next()) else {
+            return Err(SessionError::Malformed.into());
+        };
+        let params: NoiseParams = format!("Noise_NN_25519_{}_{}", cipher, hash)
+            .parse()
+            .map_err(|_| SessionError::Malformed)?;
+
+        let mut initiator =
+            snow::Builder::with_resolver(params.clone(), backend_resolver()).build_initiator()?;
+        let mut responder =
+            snow::Builder::with_resolver(params, backend_resolver()).build_responder()?;
+        let mut message = [0u8; 128];
+        let n = initiator.write_message(&[], &mut message)?;
+        responder.read_message(&message[..n], &mut [])?;
+        let n = responder.write_message(&[], &mut message)?;
+        initiator.read_message(&message[..n], &mut [])?;
+
+        let mut state =
+            if self.initiator { initiator } else { responder }.into_stateless_transport_mode()?;
+        state.rekey_manually(Some(&self.keys.initiator), Some(&self.keys.responder));
+        Ok(state)
+    }
+}
+
+impl fmt::Debug for SessionSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The keys are left out, so snapshots don't end up in logs.
+        f.debug_struct("SessionSnapshot")
+            .field("name", &self.name)
+            .field("initiator", &self.initiator)
+            .field("protocol_name", &self.protocol_name)
+            .field("sending_nonce", &self.sending_nonce)
+            .field("receiving_nonce", &self.receiving_nonce)
+            .field("buffered_read", &self.read_overflow.len())
+            .field("unprocessed", &self.unprocessed.len())
+            .field("unsent", &self.unsent.len())
+            .finish()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for SessionSnapshot {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.to_bytes())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SessionSnapshot {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = <Vec<u8>>::deserialize(deserializer)?;
+        SessionSnapshot::from_bytes(&bytes).map_err(serde::de::Error::custom)
+    }
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    out.extend_from_slice(bytes);
+}
+
+fn put_optional_bytes(out: &mut Vec<u8>, bytes: Option<&[u8]>) {
+    match bytes {
+        Some(bytes) => {
+            out.push(1);
+            put_bytes(out, bytes);
+        }
+        None => out.push(0),
+    }
+}
+
+/// Reads the fields of an encoded snapshot in turn.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], SessionError> {
+        if self.0.len() < n {
+            return Err(SessionError::Malformed);
+        }
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], SessionError> {
+        Ok(self.take(N)?.try_into().expect("N-byte slice"))
+    }
+
+    fn u8(&mut self) -> Result<u8, SessionError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], SessionError> {
+        let len = u32::from_be_bytes(self.array()?) as usize;
+        self.take(len)
+    }
+
+    fn optional_bytes(&mut self) -> Result<Option<Vec<u8>>, SessionError> {
+        match self.u8()? {
+            0 => Ok(None),
+            _ => Ok(Some(self.bytes()?.to_vec())),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, SessionError> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| SessionError::Malformed)
+    }
+}
@@ -0,0 +1,218 @@
+use log::debug;
+use std::{fmt, io, sync::Arc, time::Duration};
+use tokio::{
+    net::TcpStream,
+    time::{sleep_until, timeout_at, Instant},
+};
+
+use crate::{
+    listener::IncomingConnection,
+    proxy_protocol::ProxyHeader,
+    stream::{read_u16, HANDSHAKE_LEN_SIZE, MAX_FRAME_SIZE},
+};
+
+/// The shortest first handshake message the default classifier accepts: one DH public
+/// key, which every pattern's first message starts with, or ends with for `psk0`.
+const MIN_FIRST_MESSAGE_LEN: usize = 32;
+
+/// How long to wait for more bytes when a peek returns fewer than the classifier needs.
+/// A peek returns as soon as any bytes are available, so waiting on it alone would spin.
+const PEEK_RETRY_INTERVAL: Duration = Duration::from_millis(5);
+
+type ClassifyFn = dyn Fn(&[u8]) -> bool + Send + Sync;
+
+/// Decides whether the first bytes of a connection are a Noise handshake. Clones share
+/// the same classifier.
+#[derive(Clone)]
+struct Classifier(Arc<ClassifyFn>);
+
+impl fmt::Debug for Classifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Classifier")
+    }
+}
+
+/// What to do with a connection which sends too little to classify before the
+/// [`SniffConfig::timeout`] passes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SniffTimeout {
+    /// Hand the connection to the plaintext fallback, as for protocols in which the
+    /// server speaks first.
+    Plaintext,
+    /// Close the connection, and fail with [`io::ErrorKind::TimedOut`].
+    Drop,
+}
+
+/// Configures how [`IncomingConnection::sniff`] tells Noise clients apart from plaintext
+/// clients on the same port, for migrating a plaintext service to Noise.
+///
+/// By default, a connection is taken for Noise if its first two bytes are the length
+/// prefix of a plausible first handshake message: at least 32 bytes, and no larger than
+/// a frame. Text protocols such as HTTP start with printable ASCII, which reads as a far
+/// larger length. Connections which send nothing within 5 seconds go to the plaintext
+/// fallback.
+#[derive(Clone, Debug)]
+pub struct SniffConfig {
+    peek_len: usize,
+    classifier: Option<Classifier>,
+    timeout: Duration,
+    on_timeout: SniffTimeout,
+}
+
+impl Default for SniffConfig {
+    fn default() -> Self {
+        SniffConfig {
+            peek_len: HANDSHAKE_LEN_SIZE,
+            classifier: None,
+            timeout: Duration::from_secs(5),
+            on_timeout: SniffTimeout::Plaintext,
+        }
+    }
+}
+
+impl SniffConfig {
+    /// Create a config with the default classifier and timeout.
+    pub fn new() -> SniffConfig {
+        SniffConfig::default()
+    }
+
+    /// Replace the default classifier. It is passed the first `peek_len` bytes of each
+    /// connection, or fewer if the client closed its side before sending them all, and
+    /// returns true if they are the start of a Noise handshake.
+    ///
+    /// ```
+    /// use tokio_noise::SniffConfig;
+    ///
+    /// // Legacy clients always open with a JSON object.
+    /// let config = SniffConfig::new().classifier(1, |bytes| bytes != b"{");
+    /// ```
+    pub fn classifier(
+        mut self,
+        peek_len: usize,
+        classifier: impl Fn(&[u8]) -> bool + Send + Sync + 'static,
+    ) -> SniffConfig {
+        self.peek_len = peek_len;
+        self.classifier = Some(Classifier(Arc::new(classifier)));
+        self
+    }
+
+    /// Sets how long to wait for a connection's first bytes. Defaults to 5 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> SniffConfig {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets what to do with a connection which times out before it can be classified.
+    /// Defaults to [`SniffTimeout::Plaintext`].
+    pub fn on_timeout(mut self, on_timeout: SniffTimeout) -> SniffConfig {
+        self.on_timeout = on_timeout;
+        self
+    }
+
+    fn is_noise(&self, bytes: &[u8]) -> bool {
+        match &self.classifier {
+            Some(Classifier(classifier)) => classifier(bytes),
+            None => looks_like_handshake(bytes),
+        }
+    }
+}
+
+/// The default classifier: whether the bytes begin with the length prefix of a
+/// plausible first handshake message.
+fn looks_like_handshake(bytes: &[u8]) -> bool {
+    if bytes.len() < HANDSHAKE_LEN_SIZE {
+        return false;
+    }
+    let len = read_u16(bytes) as usize;
+    (MIN_FIRST_MESSAGE_LEN..=MAX_FRAME_SIZE).contains(&len)
+}
+
+/// An accepted connection, classified by [`IncomingConnection::sniff`].
+#[derive(Debug)]
+pub enum Sniffed {
+    /// The connection opened with a Noise handshake, which is yet to be conducted.
+    Noise(IncomingConnection),
+    /// The connection is not Noise. Nothing has been read from it except the
+    /// [PROXY protocol][crate::NoiseTcpListener::set_proxy_protocol] header, if the
+    /// listener expects one, which comes with it so the client's address isn't lost.
+    Plaintext(TcpStream, Option<ProxyHeader>),
+}
+
+impl IncomingConnection {
+    /// Peek at the connection's first bytes, without consuming them, to tell whether the
+    /// client is starting a Noise handshake or speaking some plaintext protocol. See
+    /// [`SniffConfig`].
+    ///
+    /// ```no_run
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// use tokio_noise::{handshakes::NNpsk0, NoiseTcpListener, SniffConfig, Sniffed};
+    ///
+    /// let listener = NoiseTcpListener::bind("0.0.0.0:9000").await?;
+    /// let sniff_config = SniffConfig::new();
+    /// let incoming = listener.accept().await?;
+    /// match incoming.sniff(&sniff_config).await? {
+    ///     Sniffed::Noise(incoming) => {
+    ///         let noise_stream = incoming.handshake(NNpsk0::try_new(&[0xFF; 32])?).await?;
+    ///     }
+    ///     Sniffed::Plaintext(tcp_stream, _) => { /* serve the legacy protocol */ }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// Run this on the connection's own task, as it waits on the client. If the listener
+    /// expects a [PROXY protocol][crate::NoiseTcpListener::set_proxy_protocol] header,
+    /// it is read first, and the bytes after it are classified.
+    pub async fn sniff(mut self, config: &SniffConfig) -> Result<Sniffed, io::Error> {
+        self.read_proxy_header().await?;
+        let mut buf = vec![0u8; config.peek_len];
+        let deadline = Instant::now() + config.timeout;
+        let is_noise = match peek_until(&self.socket, &mut buf, deadline).await? {
+            Some(n) => config.is_noise(&buf[..n]),
+            None if config.on_timeout == SniffTimeout::Plaintext => false,
+            None => {
+                debug!("dropped silent connection from {}", self.peer_addr());
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "connection sent nothing to classify before the timeout",
+                ));
+            }
+        };
+        debug!(
+            "classified connection from {} as {}",
+            self.peer_addr(),
+            if is_noise { "noise" } else { "plaintext" }
+        );
+        match is_noise {
+            true => Ok(Sniffed::Noise(self)),
+            false => Ok(Sniffed::Plaintext(
+                self.socket,
+                self.proxy_header.map(|header| *header),
+            )),
+        }
+    }
+}
+
+/// Peek until `buf` is full, the client closes its side without sending anything, or
+/// the deadline passes. Returns how many bytes were peeked, or `None` if the deadline
+/// passed before any arrived.
+///
+/// A client which closes its side partway through is only noticed at the deadline,
+/// since peeking at the bytes it did send never reports EOF.
+async fn peek_until(
+    socket: &TcpStream,
+    buf: &mut [u8],
+    deadline: Instant,
+) -> Result<Option<usize>, io::Error> {
+    let mut peeked = 0;
+    loop {
+        match timeout_at(deadline, socket.peek(buf)).await {
+            Ok(result) => peeked = result?,
+            Err(_) => return Ok(Some(peeked).filter(|&n| n > 0)),
+        }
+        if peeked == buf.len() || peeked == 0 {
+            return Ok(Some(peeked));
+        }
+        sleep_until(deadline.min(Instant::now() + PEEK_RETRY_INTERVAL)).await;
+    }
+}
@@ -0,0 +1,159 @@
+#[cfg(feature = "profiling")]
+use std::time::Duration;
+
+/// A snapshot of counters describing the lifetime of a [`NoiseTcpStream`][crate::NoiseTcpStream].
+///
+/// Returned by [`NoiseTcpStream::stats`][crate::NoiseTcpStream::stats].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NoiseStats {
+    /// The total number of received frames which failed to decrypt.
+    pub decrypt_failures: u64,
+    /// The number of received frames which failed to decrypt since the last frame
+    /// which decrypted successfully.
+    pub consecutive_decrypt_failures: u32,
+    /// The total number of ciphertext bytes read from the underlying transport since the
+    /// handshake completed, including any which are still buffered awaiting decryption.
+    pub socket_bytes_read: u64,
+    /// The total number of ciphertext bytes written to the underlying transport since
+    /// the handshake completed.
+    pub socket_bytes_written: u64,
+    /// The total number of plaintext bytes delivered to the reader.
+    pub plaintext_bytes_read: u64,
+    /// The total number of plaintext bytes accepted from the writer.
+    pub plaintext_bytes_written: u64,
+    /// The number of ciphertext bytes encrypted but not yet accepted by the transport,
+    /// when the snapshot was taken.
+    pub pending_write_bytes: usize,
+    /// The number of plaintext bytes decrypted but not yet read, when the snapshot was
+    /// taken.
+    pub buffered_read_bytes: usize,
+    /// The time spent encrypting each frame sent. Requires the `profiling` feature.
+    #[cfg(feature = "profiling")]
+    pub encrypt_latency: LatencyHistogram,
+    /// The time spent decrypting each frame received, including any retries under
+    /// later nonces. Requires the `profiling` feature.
+    #[cfg(feature = "profiling")]
+    pub decrypt_latency: LatencyHistogram,
+}
+
+impl NoiseStats {
+    /// Returns the ratio of ciphertext bytes read from the transport to plaintext bytes
+    /// delivered, or `None` if no plaintext has been delivered yet.
+    ///
+    /// This includes the overhead of framing, padding, and authentication tags, and can
+    /// be used to judge how well the frame size suits the traffic on a connection.
+    pub fn read_overhead_ratio(&self) -> Option<f64> {
+        ratio(self.socket_bytes_read, self.plaintext_bytes_read)
+    }
+
+    /// Returns the ratio of ciphertext bytes written to the transport to plaintext bytes
+    /// accepted, or `None` if no plaintext has been written yet.
+    pub fn write_overhead_ratio(&self) -> Option<f64> {
+        ratio(self.socket_bytes_written, self.plaintext_bytes_written)
+    }
+}
+
+fn ratio(numerator: u64, denominator: u64) -> Option<f64> {
+    if denominator == 0 {
+        return None;
+    }
+    Some(numerator as f64 / denominator as f64)
+}
+
+/// The number of buckets in a [`LatencyHistogram`].
+#[cfg(feature = "profiling")]
+pub const LATENCY_BUCKETS: usize = 32;
+
+/// A histogram of durations, in buckets whose bounds are powers of two nanoseconds.
+/// Bucket `i` counts durations of at least `2^i` and less than `2^(i+1)` nanoseconds,
+/// except that the first bucket also counts durations under one nanosecond, and the
+/// last counts every duration from about two seconds up. Requires the `profiling`
+/// feature.
+#[cfg(feature = "profiling")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    buckets: [u64; LATENCY_BUCKETS],
+    total: Duration,
+}
+
+#[cfg(feature = "profiling")]
+impl LatencyHistogram {
+    /// Count one duration.
+    pub(crate) fn record(&mut self, duration: Duration) {
+        let nanos = duration.as_nanos().max(1);
+        let bucket = (nanos.ilog2() as usize).min(LATENCY_BUCKETS - 1);
+        self.buckets[bucket] += 1;
+        self.total += duration;
+    }
+
+    /// Returns the count in each bucket.
+    pub fn buckets(&self) -> &[u64; LATENCY_BUCKETS] {
+        &self.buckets
+    }
+
+    /// Returns the number of durations counted.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Returns the sum of the durations counted.
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+
+    /// Returns the mean of the durations counted, or `None` if there are none.
+    pub fn mean(&self) -> Option<Duration> {
+        match self.count() {
+            0 => None,
+            count => Some(self.total / count as u32),
+        }
+    }
+
+    /// Returns an upper bound on the given quantile of the durations counted, such as
+    /// `0.99` for the 99th percentile, or `None` if there are none. The bound is the
+    /// upper edge of the bucket the quantile falls in, so it overestimates by up to a
+    /// factor of two. `quantile` is clamped between zero and one.
+    pub fn quantile(&self, quantile: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((quantile.clamp(0.0, 1.0) * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        let bucket = self.buckets.iter().position(|&n| {
+            seen += n;
+            seen >= rank
+        })?;
+        Some(Duration::from_nanos(2u64 << bucket))
+    }
+}
+
+#[cfg(all(test, feature = "profiling"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latency_histogram_buckets_by_powers_of_two() {
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.mean(), None);
+        assert_eq!(histogram.quantile(0.5), None);
+
+        for nanos in [0, 1, 3, 1000, 1023, 1024] {
+            histogram.record(Duration::from_nanos(nanos));
+        }
+        histogram.record(Duration::from_secs(60));
+
+        let buckets = histogram.buckets();
+        assert_eq!(buckets[0], 2);
+        assert_eq!(buckets[1], 1);
+        assert_eq!(buckets[9], 2);
+        assert_eq!(buckets[10], 1);
+        assert_eq!(buckets[LATENCY_BUCKETS - 1], 1);
+        assert_eq!(histogram.count(), 7);
+
+        assert_eq!(histogram.quantile(0.0), Some(Duration::from_nanos(2)));
+        assert_eq!(histogram.quantile(0.5), Some(Duration::from_nanos(1024)));
+        assert_eq!(histogram.quantile(0.85), Some(Duration::from_nanos(2048)));
+        assert_eq!(histogram.quantile(1.0), Some(Duration::from_nanos(1 << 32)));
+    }
+}
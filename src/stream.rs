@@ -0,0 +1,3637 @@
+use bytes::{Buf, BytesMut};
+use log::{debug, error, info, trace, warn};
+use snow::params::NoiseParams;
+use std::{
+    cell::Cell,
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+use tokio::{
+    io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    time::Instant,
+};
+
+use crate::builder::NoiseBuilder;
+use crate::config::InterMessageHook;
+use crate::errors::NoiseError;
+use crate::events::{CloseReason, EventsHook};
+use crate::fingerprint::Fingerprint;
+use crate::handshakes::{
+    build_state, trial_local_keys, CryptoChoices, Handshake, NNpsk0, SnowHandshake,
+    MAX_HANDSHAKE_MESSAGES,
+};
+use crate::log_context::{LogContext, LogLabel};
+use crate::one_way::{RecvOnlyNoiseStream, SendOnlyNoiseStream};
+use crate::proxy_protocol::ProxyHeader;
+use crate::stats::NoiseStats;
+use crate::transport::Transport;
+
+/// The largest size of a ciphertext frame on the wire, which is also the default.
+/// See [`FrameSizing`][crate::FrameSizing].
+pub const MAX_FRAME_SIZE: usize = 2048;
+
+/// The smallest size of a ciphertext frame on the wire which can be configured.
+/// See [`FrameSizing`][crate::FrameSizing].
+pub const MIN_FRAME_SIZE: usize = 128;
+
+/// Ciphertext packet fields.
+const CIPHERTEXT_TAG_SIZE: usize = 16;
+
+/// Plaintext packet fields and maximum total size.
+const PLAINTEXT_KIND_SIZE: usize = 1;
+const PLAINTEXT_LEN_SIZE: usize = 2;
+const PLAINTEXT_HEADER_SIZE: usize = PLAINTEXT_KIND_SIZE + PLAINTEXT_LEN_SIZE;
+const PLAINTEXT_PACKET_SIZE: usize = MAX_FRAME_SIZE - CIPHERTEXT_TAG_SIZE;
+
+/// The bytes of each frame which are not available for the caller's data.
+const FRAME_OVERHEAD: usize = CIPHERTEXT_TAG_SIZE + PLAINTEXT_HEADER_SIZE;
+
+/// The size of the preamble packet on the wire. The preamble is sent before the peer
+/// knows our frame size, so its size is fixed, and must stay fixed in future framing
+/// versions so that they can be told apart.
+const PREAMBLE_PACKET_SIZE: usize = 64;
+
+/// The size of the length prefix sent before each handshake message. Handshake
+/// messages vary in size, and the transport may split or coalesce them, so the
+/// receiver needs the length to know where each message ends.
+pub(crate) const HANDSHAKE_LEN_SIZE: usize = 2;
+
+/// The version of the transport framing used by this library. Each side declares its
+/// framing version in a preamble packet at the start of its transport messages, and
+/// rejects a peer which uses a different version with
+/// [`NoiseError::FramingVersionMismatch`].
+///
+/// Version 2 shrank the preamble to a fixed 64 bytes, in which each side also declares
+/// the size of the frames it sends. Peers using version 1 or older, which sent a full
+/// size preamble or none at all, fail to decrypt our first packet and vice versa.
+pub const FRAMING_VERSION: u8 = 2;
+
+/// The size of the application sequence number at the start of each data frame's
+/// payload, when enabled with [`NoiseBuilder::sequence_numbers`].
+pub const SEQUENCE_NUMBER_SIZE: usize = 4;
+
+/// Set in the options byte of a preamble if the sender's data frames carry sequence
+/// numbers.
+const PREAMBLE_OPTION_SEQUENCE_NUMBERS: u8 = 1 << 0;
+
+/// The kinds of packet which can be sent over the transport. The kind is the first
+/// byte of each plaintext packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+enum PacketKind {
+    /// Application data.
+    Data = 0,
+    /// Declares the sender's framing version and options. Always the first packet
+    /// sent in each direction.
+    Preamble = 1,
+    /// Announces that the sender is closing the stream. An empty payload is a close
+    /// notify, sent on shutdown, which the receiver reads as a clean EOF. Otherwise the
+    /// payload holds an application error code and reason. See
+    /// [`NoiseStream::close_with_error`].
+    Close = 2,
+}
+
+impl PacketKind {
+    fn from_u8(kind: u8) -> Option<PacketKind> {
+        match kind {
+            0 => Some(PacketKind::Data),
+            1 => Some(PacketKind::Preamble),
+            2 => Some(PacketKind::Close),
+            _ => None,
+        }
+    }
+}
+
+/// A fatal error which has shut down a stream.
+#[derive(Clone, Copy, Debug)]
+enum Poison {
+    TooManyDecryptFailures,
+    FramingVersionMismatch {
+        remote: Option<u8>,
+    },
+    UnsupportedFrameSize {
+        size: usize,
+    },
+    SequenceGap {
+        expected: u32,
+        got: u32,
+    },
+    /// The peer's preamble disagrees with ours about whether data frames carry sequence
+    /// numbers. Set if the peer uses them.
+    SequenceNumbersMismatch {
+        remote: bool,
+    },
+    NonceMismatch {
+        expected: u64,
+        got: u64,
+    },
+    /// An authenticated frame declared more plaintext than it can hold.
+    FrameLengthExceeded {
+        len: usize,
+        max: usize,
+    },
+    /// The stream's state has been exported, to be resumed elsewhere.
+    #[cfg(feature = "session-export")]
+    Exported,
+}
+
+impl From<Poison> for NoiseError {
+    fn from(poison: Poison) -> NoiseError {
+        match poison {
+            Poison::TooManyDecryptFailures => NoiseError::TooManyDecryptFailures,
+            Poison::FramingVersionMismatch { remote } => NoiseError::FramingVersionMismatch {
+                local: FRAMING_VERSION,
+                remote,
+            },
+            Poison::UnsupportedFrameSize { size } => NoiseError::UnsupportedFrameSize { size },
+            Poison::SequenceGap { expected, got } => NoiseError::SequenceGap { expected, got },
+            Poison::NonceMismatch { expected, got } => NoiseError::NonceMismatch { expected, got },
+            Poison::SequenceNumbersMismatch { remote } => {
+                NoiseError::SequenceNumbersMismatch { remote }
+            }
+            Poison::FrameLengthExceeded { len, max } => {
+                NoiseError::FrameLengthExceeded { len, max }
+            }
+            #[cfg(feature = "session-export")]
+            Poison::Exported => NoiseError::Session(crate::SessionError::Exported),
+        }
+    }
+}
+
+/// The transport state which encrypts and decrypts a stream's frames.
+pub(crate) enum Cipher {
+    /// A transport state from a handshake, which keeps its own nonces.
+    Stateful(snow::TransportState),
+    /// A stateless transport state, with its nonces kept alongside. Unlike a stateful
+    /// one, it can resume at any sending nonce, as an imported session must.
+    #[cfg(feature = "session-export")]
+    Stateless {
+        state: snow::StatelessTransportState,
+        sending_nonce: u64,
+        receiving_nonce: u64,
+    },
+}
+
+impl From<snow::TransportState> for Cipher {
+    fn from(state: snow::TransportState) -> Cipher {
+        Cipher::Stateful(state)
+    }
+}
+
+impl Cipher {
+    fn is_initiator(&self) -> bool {
+        match self {
+            Cipher::Stateful(state) => state.is_initiator(),
+            #[cfg(feature = "session-export")]
+            Cipher::Stateless { state, .. } => state.is_initiator(),
+        }
+    }
+
+    fn get_remote_static(&self) -> Option<&[u8]> {
+        match self {
+            Cipher::Stateful(state) => state.get_remote_static(),
+            #[cfg(feature = "session-export")]
+            Cipher::Stateless { state, .. } => state.get_remote_static(),
+        }
+    }
+
+    fn sending_nonce(&self) -> u64 {
+        match self {
+            Cipher::Stateful(state) => state.sending_nonce(),
+            #[cfg(feature = "session-export")]
+            Cipher::Stateless { sending_nonce, .. } => *sending_nonce,
+        }
+    }
+
+    fn receiving_nonce(&self) -> u64 {
+        match self {
+            Cipher::Stateful(state) => state.receiving_nonce(),
+            #[cfg(feature = "session-export")]
+            Cipher::Stateless {
+                receiving_nonce, ..
+            } => *receiving_nonce,
+        }
+    }
+
+    fn set_receiving_nonce(&mut self, nonce: u64) {
+        match self {
+            Cipher::Stateful(state) => state.set_receiving_nonce(nonce),
+            #[cfg(feature = "session-export")]
+            Cipher::Stateless {
+                receiving_nonce, ..
+            } => *receiving_nonce = nonce,
+        }
+    }
+
+    /// Encrypt `payload` into `message` under the sending nonce, advancing it.
+    fn write_message(&mut self, payload: &[u8], message: &mut [u8]) -> Result<usize, snow::Error> {
+        match self {
+            Cipher::Stateful(state) => state.write_message(payload, message),
+            #[cfg(feature = "session-export")]
+            Cipher::Stateless {
+                state,
+                sending_nonce,
+                ..
+            } => {
+                let n = state.write_message(*sending_nonce, payload, message)?;
+                *sending_nonce += 1;
+                Ok(n)
+            }
+        }
+    }
+
+    /// Decrypt `payload` into `message` under the receiving nonce, advancing it if the
+    /// message is authentic.
+    fn read_message(&mut self, payload: &[u8], message: &mut [u8]) -> Result<usize, snow::Error> {
+        match self {
+            Cipher::Stateful(state) => state.read_message(payload, message),
+            #[cfg(feature = "session-export")]
+            Cipher::Stateless {
+                state,
+                receiving_nonce,
+                ..
+            } => {
+                let n = state.read_message(*receiving_nonce, payload, message)?;
+                *receiving_nonce += 1;
+                Ok(n)
+            }
+        }
+    }
+}
+
+/// The maximum length in bytes of the reason sent by [`NoiseStream::close_with_error`].
+/// Together with the 4-byte code, it fits within a frame of [`MIN_FRAME_SIZE`].
+pub const MAX_CLOSE_REASON_LEN: usize = 100;
+
+/// The size of the buffer which ciphertext is read into from the transport.
+const RECV_BUF_SIZE: usize = 16 * MAX_FRAME_SIZE;
+
+/// The maximum gap by which a remote side can increment our receiving nonce. Each frame
+/// which fails to decrypt under the expected nonce is retried under each of the next
+/// this many nonces. See [`NoiseStream::skip_sending_nonce`].
+pub const NONCE_JUMP_LIMIT: u64 = 10;
+
+/// Represents a transport stream, such as a [`tokio::net::TcpStream`], wrapped with a
+/// layer of [Noise](https://noiseprotocol.org/) encryption applied on top.
+///
+/// See [`NoiseTcpStream`][crate::NoiseTcpStream] for the common case of a TCP transport.
+pub struct NoiseStream<S: AsyncRead + AsyncWrite + Unpin> {
+    label: LogLabel,
+    transport: S,
+    noise: Cipher,
+    /// What the handshake which established the stream negotiated, if known.
+    handshake_info: Option<HandshakeInfo>,
+    read_overflow_buf: BytesMut,
+    unprocessed_buf: RecvBuf,
+    /// Outgoing ciphertext which the underlying transport has not yet
+    /// accepted. Packets are encrypted directly onto the end of this buffer,
+    /// and it is handed to the socket as-is. The Noise nonce has already
+    /// advanced for these bytes, so they must be flushed verbatim and in order
+    /// to keep the on-wire stream framed and the peer's nonce in sync. Drained
+    /// by `poll_drain_write_buf`. New packets are appended only while this
+    /// holds less than the configured write high watermark, so it is bounded
+    /// to the watermark plus one packet.
+    write_buf: BytesMut,
+    /// Scratch space for building the plaintext of each outgoing packet. Everything
+    /// past the header and payload of the last packet is zero, so that padding doesn't
+    /// need to be rewritten for every packet.
+    plaintext_buf: Box<[u8; PLAINTEXT_PACKET_SIZE]>,
+    config: NoiseBuilder,
+    stats: NoiseStats,
+    /// The size of each data frame we send, fixed for the life of the stream.
+    frame_size: usize,
+    /// Set once we've queued our preamble packet for sending.
+    sent_preamble: bool,
+    /// The framing version declared by the peer's preamble, once received.
+    peer_framing_version: Option<u8>,
+    /// The size of each data frame the peer sends, as declared in its preamble.
+    peer_frame_size: Option<usize>,
+    /// Set once we've queued a close packet. Nothing may be sent after it.
+    sent_close: bool,
+    /// Set once `poll_write` has hit the write high watermark, until the buffer drains
+    /// below the low watermark.
+    write_blocked: bool,
+    /// Set once the peer has sent a close notify, after which reads return EOF.
+    received_close_notify: bool,
+    /// The error code and reason sent by the peer when it closed the stream.
+    peer_close: Option<(u32, String)>,
+    /// Set after a fatal error, such as too many consecutive frames failing to decrypt.
+    /// All further reads and writes fail fast with the corresponding [`NoiseError`].
+    poisoned: Option<Poison>,
+    /// A fatal error found after data which the caller has not yet read. The stream is
+    /// poisoned with it once that data has been returned.
+    pending_poison: Option<Poison>,
+    /// The sequence number of the next data frame we send, if enabled.
+    sending_sequence: u32,
+    /// The sequence number expected of the next data frame received, if enabled.
+    receiving_sequence: u32,
+    /// Set once the stream's close has been reported to the events handler.
+    reported_close: bool,
+    /// Set if the handshake pattern is one-way, such as `N`, so that only the initiator
+    /// sends and only the responder receives.
+    one_way: bool,
+    /// When application data was last read or written, or when the stream was
+    /// established if none has been.
+    last_activity: Instant,
+    /// The transport's peer address, if it has one, attached to the errors which reads
+    /// and writes return.
+    pub(crate) peer_addr: Option<SocketAddr>,
+    /// The PROXY protocol header which preceded the handshake, if the stream was
+    /// accepted by a listener expecting one.
+    pub(crate) proxy_header: Option<Box<ProxyHeader>>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> NoiseStream<S> {
+    /// Instantiate a new encrypted stream using the given noise transport state machine.
+    /// The name can be any arbitrary identifier for the stream - it is only used for logging.
+    ///
+    /// The transport's maximum segment size is not known here, so
+    /// [`FrameSizing::Auto`][crate::FrameSizing::Auto] falls back to the largest frames.
+    pub fn new(name: String, socket: S, noise: snow::TransportState) -> NoiseStream<S> {
+        NoiseStream::from_parts(
+            name,
+            socket,
+            noise.into(),
+            None,
+            BytesMut::new(),
+            NoiseBuilder::default(),
+            None,
+        )
+    }
+
+    /// Assemble a stream, resolving the configured frame sizing against the transport's
+    /// maximum segment size, if known.
+    pub(crate) fn from_parts(
+        name: String,
+        socket: S,
+        noise: Cipher,
+        handshake_info: Option<HandshakeInfo>,
+        read_overflow_buf: BytesMut,
+        config: NoiseBuilder,
+        mss: Option<usize>,
+    ) -> NoiseStream<S> {
+        let name = sanitize_name(name);
+        let frame_size = config.frame_sizing.frame_size(mss);
+        let one_way = handshake_info
+            .as_ref()
+            .is_some_and(|info| info.params.handshake.pattern.is_oneway());
+        let label = LogLabel {
+            name,
+            context: config.log_context.clone(),
+        };
+        debug!("[{}] sending {}-byte frames", label, frame_size);
+        NoiseStream {
+            label,
+            transport: socket,
+            noise,
+            handshake_info,
+            read_overflow_buf,
+            unprocessed_buf: RecvBuf::new(RECV_BUF_SIZE),
+            write_buf: BytesMut::with_capacity(MAX_FRAME_SIZE),
+            plaintext_buf: Box::new([0u8; PLAINTEXT_PACKET_SIZE]),
+            config,
+            stats: NoiseStats::default(),
+            frame_size,
+            sent_preamble: false,
+            peer_framing_version: None,
+            peer_frame_size: None,
+            sent_close: false,
+            write_blocked: false,
+            received_close_notify: false,
+            peer_close: None,
+            poisoned: None,
+            pending_poison: None,
+            sending_sequence: 0,
+            receiving_sequence: 0,
+            reported_close: false,
+            one_way,
+            last_activity: Instant::now(),
+            peer_addr: None,
+            proxy_header: None,
+        }
+    }
+
+    /// Conduct a Noise handshake over the given TCP socket as the initiator,
+    /// using a custom [`Handshake`] protocol.
+    ///
+    /// Use [`NoiseBuilder::handshake_initiator`] to customize the resulting stream. For
+    /// a callback with the outcome and duration of each handshake, such as for
+    /// connection accounting, conduct it with a
+    /// [`HandshakeConfig`][crate::HandshakeConfig] set up with
+    /// [`on_handshake`][crate::HandshakeConfig::on_handshake].
+    pub async fn handshake_initiator(
+        socket: S,
+        handshake: impl Handshake,
+    ) -> Result<NoiseStream<S>, NoiseError>
+    where
+        S: Transport,
+    {
+        NoiseBuilder::default()
+            .handshake_initiator(socket, handshake)
+            .await
+    }
+
+    /// Conduct a Noise handshake over the given TCP socket as the responder,
+    /// using a custom [`Handshake`] protocol.
+    ///
+    /// Use [`NoiseBuilder::handshake_responder`] to customize the resulting stream. For
+    /// a callback with the outcome and duration of each handshake, use a
+    /// [`HandshakeConfig`][crate::HandshakeConfig] set up with
+    /// [`on_handshake`][crate::HandshakeConfig::on_handshake].
+    pub async fn handshake_responder(
+        socket: S,
+        handshake: impl Handshake,
+    ) -> Result<NoiseStream<S>, NoiseError>
+    where
+        S: Transport,
+    {
+        NoiseBuilder::default()
+            .handshake_responder(socket, handshake)
+            .await
+    }
+
+    /// Drives the initiator's side of a handshake over a borrowed socket, returning the
+    /// transport state and any cleartext received alongside the final handshake message.
+    pub(crate) async fn run_initiator(
+        socket: &mut S,
+        handshake: impl Handshake,
+        hook: Option<&InterMessageHook>,
+        log_context: &LogContext,
+    ) -> Result<Handshaked, NoiseError> {
+        let initiator = build_state(&handshake, true)?;
+        Self::run_handshake(socket, handshake, initiator, hook, None, log_context)
+            .await?
+            .into_transport_mode()
+    }
+
+    /// Drives the responder's side of a handshake over a borrowed socket, returning the
+    /// transport state and any cleartext received alongside the final handshake message.
+    /// The first message must arrive within `first_message_timeout`, if set.
+    pub(crate) async fn run_responder(
+        socket: &mut S,
+        handshake: impl Handshake,
+        hook: Option<&InterMessageHook>,
+        first_message_timeout: Option<Duration>,
+        log_context: &LogContext,
+    ) -> Result<Handshaked, NoiseError> {
+        let responder = build_state(&handshake, false)?;
+        Self::run_handshake(
+            socket,
+            handshake,
+            responder,
+            hook,
+            first_message_timeout,
+            log_context,
+        )
+        .await?
+        .into_transport_mode()
+    }
+
+    /// Exchanges each of the handshake's messages in turn, writing those which the pattern
+    /// gives to the role of `state` and reading the rest. Returns the finished handshake
+    /// state, and any cleartext received alongside the final handshake message. If
+    /// `first_message_timeout` is set and the first message is ours to read, it fails
+    /// with [`NoiseError::HandshakeTimeout`] unless that message arrives in time. Log
+    /// messages are tagged with the role and `log_context`.
+    pub(crate) async fn run_handshake(
+        socket: &mut S,
+        mut handshake: impl Handshake,
+        mut state: snow::HandshakeState,
+        hook: Option<&InterMessageHook>,
+        first_message_timeout: Option<Duration>,
+        log_context: &LogContext,
+    ) -> Result<Handshaked<snow::HandshakeState>, NoiseError> {
+        let role = if state.is_initiator() {
+            "initiator"
+        } else {
+            "responder"
+        };
+        let label = LogLabel {
+            name: role.to_string(),
+            context: log_context.clone(),
+        };
+        let params = handshake.params()?;
+        let message_count = handshake.message_count();
+        if message_count > MAX_HANDSHAKE_MESSAGES {
+            return Err(handshake
+                .error(format_args!(
+                    "handshake uses {} messages, but at most {} are supported",
+                    message_count, MAX_HANDSHAKE_MESSAGES
+                ))
+                .into());
+        }
+
+        let mut scratch = HandshakeScratch::take();
+        let HandshakeScratch {
+            cipher_buf,
+            clear_buf,
+        } = &mut *scratch;
+        let mut read_clear_n = 0;
+        let mut sent_n = 0;
+        let mut received_last_message = false;
+        let mut verified_remote_static = false;
+
+        for index in 0..message_count {
+            if index > 0 {
+                InterMessageHook::run(hook).await;
+            }
+
+            // The pattern decides whose turn it is to write, and each side's messages go
+            // through its own hooks in order. Each reply is built from the cleartext of
+            // the message before it.
+            if state.is_my_turn() {
+                let recv_buf = &clear_buf[..read_clear_n];
+                let (len_buf, send_buf) = cipher_buf.split_at_mut(HANDSHAKE_LEN_SIZE);
+                let wrote_n = match (state.is_initiator(), sent_n) {
+                    (true, 0) => handshake.initiator_first_message(&mut state, send_buf)?,
+                    (true, _) => {
+                        handshake.initiator_second_message(&mut state, recv_buf, send_buf)?
+                    }
+                    (false, 0) => {
+                        handshake.responder_first_message(&mut state, recv_buf, send_buf)?
+                    }
+                    (false, _) => {
+                        handshake.responder_second_message(&mut state, recv_buf, send_buf)?
+                    }
+                };
+                sent_n += 1;
+                // The send buffer is smaller than 64KiB, so the length always fits.
+                write_u16(len_buf, wrote_n as u16);
+                // The length and message go out in a single write. Buffered transports
+                // may hold the message back until flushed, leaving both sides waiting
+                // on each other.
+                #[cfg(feature = "handshake-trace")]
+                trace_handshake_message(&label, "sent", index, message_count, &send_buf[..wrote_n]);
+                socket
+                    .write_all(&cipher_buf[..HANDSHAKE_LEN_SIZE + wrote_n])
+                    .await?;
+                socket.flush().await?;
+                received_last_message = false;
+                debug!(
+                    "[{}] sent {}-byte handshake message {} of {}",
+                    label,
+                    wrote_n,
+                    index + 1,
+                    message_count
+                );
+            } else {
+                let read = read_handshake_message(socket, cipher_buf, index);
+                let read_cipher_n = match first_message_timeout.filter(|_| index == 0) {
+                    Some(timeout) => tokio::time::timeout(timeout, read)
+                        .await
+                        .map_err(|_| NoiseError::HandshakeTimeout)??,
+                    None => read.await?,
+                };
+                let message = &cipher_buf[..read_cipher_n];
+                #[cfg(feature = "handshake-trace")]
+                trace_handshake_message(&label, "received", index, message_count, message);
+                read_clear_n = match state.read_message(message, clear_buf) {
+                    // The initiator may have encrypted to another of our static keys.
+                    Err(snow::Error::Decrypt) if index == 0 && handshake.local_key_count() > 1 => {
+                        let (trial_state, n) =
+                            trial_local_keys(&mut handshake, message, clear_buf)?;
+                        state = trial_state;
+                        n
+                    }
+                    result => result?,
+                };
+                received_last_message = true;
+                if let (false, Some(key)) = (verified_remote_static, state.get_remote_static()) {
+                    handshake.verify_remote_static(key)?;
+                    verified_remote_static = true;
+                }
+                debug!(
+                    "[{}] received {}-byte handshake message {} of {}",
+                    label,
+                    read_cipher_n,
+                    index + 1,
+                    message_count
+                );
+            }
+        }
+
+        ensure_handshake_finished(&state, &handshake, message_count)?;
+
+        // If the peer sent the final message, the caller reads its cleartext first. It
+        // rarely sends any, so the buffer is only allocated when it does.
+        let read_overflow_buf = if received_last_message && read_clear_n > 0 {
+            BytesMut::from(&clear_buf[..read_clear_n])
+        } else {
+            BytesMut::new()
+        };
+        info!("[{}] completed noise handshake", label);
+        let info = HandshakeInfo {
+            params,
+            local_static_key: handshake.local_static_public_key(),
+            remote_static_key: None,
+            handshake_hash: state.get_handshake_hash().to_vec(),
+            #[cfg(feature = "session-export")]
+            session_keys: None,
+        };
+        Ok(Handshaked {
+            state,
+            info,
+            read_overflow_buf,
+            sent_last_message: !received_last_message,
+        })
+    }
+
+    /// Confirm that both sides derived the same keys, as part of the handshake.
+    ///
+    /// The side which received the final handshake message sends its preamble straight
+    /// away, and the side which sent it waits to receive and decrypt that preamble.
+    /// Either way, both sides learn of mismatched keys before the handshake returns.
+    ///
+    /// One-way handshakes skip this, since the responder can't send a preamble back.
+    pub(crate) async fn confirm_keys(&mut self, sent_last_message: bool) -> Result<(), NoiseError> {
+        if self.one_way {
+            return Ok(());
+        }
+        if !sent_last_message {
+            self.queue_preamble()?;
+            AsyncWriteExt::flush(self).await?;
+            return Ok(());
+        }
+
+        std::future::poll_fn(|cx| {
+            Pin::new(&mut *self).poll_read_frames(cx, &mut io::ReadBuf::new(&mut []), true)
+        })
+        .await?;
+        if self.peer_framing_version.is_none() {
+            return Err(NoiseError::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "transport closed before the peer confirmed the handshake",
+            )));
+        }
+        debug!("[{}] peer confirmed the handshake", self.label);
+        Ok(())
+    }
+
+    /// Conduct an `NNpsk0` handshake as the Noise initiator.
+    ///
+    /// This presumes the initiator and responder both have access to the same pre-shared key (PSK),
+    /// which is used for authentication and encryption of the proceeding handshake, which establishes
+    /// a session key with perfect-forward secrecy. Fails with [`NoiseError::InvalidPsk`]
+    /// before any messages are sent if the PSK is rejected by
+    /// [`validate_psk`][crate::validate_psk].
+    pub async fn handshake_initiator_psk0(
+        socket: S,
+        psk: &[u8],
+    ) -> Result<NoiseStream<S>, NoiseError>
+    where
+        S: Transport,
+    {
+        NoiseStream::handshake_initiator(socket, NNpsk0::try_new(psk)?).await
+    }
+
+    /// Conduct an `NNpsk0` handshake as the Noise responder.
+    ///
+    /// This presumes the initiator and responder both have access to the same pre-shared key (PSK),
+    /// which is used for authentication and encryption of the proceeding handshake, which establishes
+    /// a session key with perfect-forward secrecy. Fails with [`NoiseError::InvalidPsk`]
+    /// before any messages are sent if the PSK is rejected by
+    /// [`validate_psk`][crate::validate_psk].
+    pub async fn handshake_responder_psk0(
+        socket: S,
+        psk: &[u8],
+    ) -> Result<NoiseStream<S>, NoiseError>
+    where
+        S: Transport,
+    {
+        NoiseStream::handshake_responder(socket, NNpsk0::try_new(psk)?).await
+    }
+
+    /// Conduct an `NNpsk0` handshake as the Noise responder, as
+    /// [`handshake_responder_psk0`][Self::handshake_responder_psk0] does, but fail with
+    /// [`NoiseError::HandshakeTimeout`] if the initiator's first message doesn't arrive
+    /// within `timeout`. See [`NoiseBuilder::first_message_timeout`].
+    pub async fn handshake_responder_psk0_with_first_message_timeout(
+        socket: S,
+        psk: &[u8],
+        timeout: Duration,
+    ) -> Result<NoiseStream<S>, NoiseError>
+    where
+        S: Transport,
+    {
+        NoiseBuilder::default()
+            .first_message_timeout(timeout)
+            .handshake_responder(socket, NNpsk0::try_new(psk)?)
+            .await
+    }
+
+    /// Conduct a one-way `N` handshake as the Noise initiator, with a responder whose
+    /// static public key is already known. The handshake is a single message, so no
+    /// reply from the responder is awaited.
+    ///
+    /// The resulting stream can only send, since the responder can't reply over it, and
+    /// the keys can't be confirmed even if [`NoiseBuilder::key_confirmation`] is set. The
+    /// initiator is not authenticated, and its messages are only as fresh as the
+    /// responder's static key, since there is no ephemeral key from the responder.
+    ///
+    /// Other one-way patterns, such as `K` and `X`, can be conducted with a
+    /// [`SnowHandshake`] and narrowed with [`into_send_only`][Self::into_send_only]. Reads
+    /// from a one-way stream which isn't narrowed fail with [`NoiseError::OneWay`].
+    pub async fn handshake_initiator_n(
+        socket: S,
+        server_static_pubkey: &[u8],
+    ) -> Result<SendOnlyNoiseStream<S>, NoiseError>
+    where
+        S: Transport,
+    {
+        let handshake = SnowHandshake::new(&CryptoChoices::default().stringify_with_pattern("N"))?
+            .remote_public_key(server_static_pubkey);
+        let stream = NoiseStream::handshake_initiator(socket, handshake).await?;
+        Ok(SendOnlyNoiseStream::from_initiator(stream))
+    }
+
+    /// Conduct a one-way `N` handshake as the Noise responder, with the static keypair
+    /// whose public key the initiator knows.
+    ///
+    /// The resulting stream can only receive, and shutting it down closes the transport
+    /// without sending a close notify. See
+    /// [`handshake_initiator_n`][Self::handshake_initiator_n].
+    pub async fn handshake_responder_n(
+        socket: S,
+        server_keypair: &snow::Keypair,
+    ) -> Result<RecvOnlyNoiseStream<S>, NoiseError>
+    where
+        S: Transport,
+    {
+        let handshake = SnowHandshake::new(&CryptoChoices::default().stringify_with_pattern("N"))?
+            .local_private_key(&server_keypair.private);
+        let stream = NoiseStream::handshake_responder(socket, handshake).await?;
+        Ok(RecvOnlyNoiseStream::from_responder(stream))
+    }
+
+    /// Send some arbitrary data over the noise-encrypted channel.
+    ///
+    /// Noise messages are chunked and padded into fixed-size packets for easier transmission
+    /// control. The stream is flushed afterwards, so that no ciphertext is left buffered
+    /// waiting on the socket when this returns.
+    pub async fn send(&mut self, cleartext: &[u8]) -> Result<(), NoiseError> {
+        AsyncWriteExt::write_all(self, cleartext).await?;
+        AsyncWriteExt::flush(self).await?;
+        Ok(())
+    }
+
+    /// Like [`send`][Self::send], but fails with [`NoiseError::DeadlineExceeded`] if the
+    /// data isn't sent by `deadline`. Nothing is sent if the deadline has already passed.
+    ///
+    /// If the deadline passes partway through, a prefix of `cleartext` may already have
+    /// been encrypted and queued. The stream remains usable, and the queued data will
+    /// reach the peer with the next write or flush.
+    pub async fn send_deadline(
+        &mut self,
+        cleartext: &[u8],
+        deadline: Instant,
+    ) -> Result<(), NoiseError> {
+        with_deadline(deadline, self.send(cleartext)).await
+    }
+
+    /// Close the stream with an application error code and reason, so that the peer
+    /// can tell why the connection ended. The peer's reads return any data sent before
+    /// this call, and then fail with [`NoiseError::ClosedByPeer`].
+    ///
+    /// The close is authenticated like any other frame, so it can't be forged by an
+    /// attacker on the network. Reasons longer than [`MAX_CLOSE_REASON_LEN`] bytes are
+    /// truncated. Once the close is sent, the transport is shut down for writing.
+    ///
+    /// To close without an error, shut the stream down instead, which sends a close
+    /// notify that the peer reads as a clean EOF.
+    pub async fn close_with_error(&mut self, code: u32, reason: &str) -> Result<(), NoiseError> {
+        if let Some(poison) = self.poisoned {
+            return Err(poison.into());
+        }
+        if self.sent_close {
+            return Err(closed_error().into());
+        }
+        if !self.can_send() {
+            return Err(NoiseError::OneWay { send: true });
+        }
+
+        let mut reason_len = reason.len().min(MAX_CLOSE_REASON_LEN);
+        while !reason.is_char_boundary(reason_len) {
+            reason_len -= 1;
+        }
+        let mut payload = Vec::with_capacity(4 + reason_len);
+        payload.extend_from_slice(&code.to_be_bytes());
+        payload.extend_from_slice(&reason.as_bytes()[..reason_len]);
+
+        self.queue_preamble()?;
+        self.encrypt_frame(PacketKind::Close, &payload)?;
+        self.sent_close = true;
+        self.report_close(|| CloseReason::LocalError {
+            code,
+            reason: reason[..reason_len].to_string(),
+        });
+        debug!("[{}] closing stream with error code {}", self.label, code);
+        AsyncWriteExt::shutdown(self).await?;
+        Ok(())
+    }
+
+    /// Close the stream cleanly, and wait for the peer to close its side too.
+    ///
+    /// This flushes all buffered data, sends a close notify, and shuts down the transport
+    /// for writing, just as shutting down the stream does. It then reads and discards
+    /// anything more the peer sends, until the peer closes the stream. Closing a socket
+    /// with unread data in its receive buffer can make the OS reset the connection, which
+    /// may discard data the peer has not yet read, so waiting for EOF ensures the peer
+    /// receives everything sent before the close.
+    ///
+    /// This waits for as long as the peer keeps its side open, so consider wrapping it in
+    /// a timeout. If the peer closes with an error, that error is returned.
+    ///
+    /// The initiator of a one-way handshake can't read, so it returns once shut down.
+    pub async fn close(mut self) -> Result<(), NoiseError> {
+        AsyncWriteExt::shutdown(&mut self).await?;
+        if !self.can_recv() {
+            return Ok(());
+        }
+        debug!(
+            "[{}] closed stream; draining until the peer closes",
+            self.label
+        );
+
+        let mut discard = [0u8; 4096];
+        while AsyncReadExt::read(&mut self, &mut discard).await? > 0 {}
+        Ok(())
+    }
+
+    /// Returns the error code and reason the peer sent when it closed the stream with
+    /// [`close_with_error`][Self::close_with_error], once received.
+    pub fn closed_reason(&self) -> Option<(u32, &str)> {
+        self.peer_close
+            .as_ref()
+            .map(|(code, reason)| (*code, reason.as_str()))
+    }
+
+    /// Receive some arbitrary data over the noise-encrypted channel, returning the number
+    /// of bytes written to `output`.
+    ///
+    /// Data which has already been received and decrypted is returned without waiting on
+    /// the transport. `Ok(0)` is returned only if `output` is empty, or once the peer has
+    /// sent a close notify and every byte it sent before it has been returned. If the
+    /// transport closes without a close notify, the final read fails with
+    /// [`NoiseError::UnexpectedClose`] instead, unless
+    /// [`allow_unexpected_close`][NoiseBuilder::allow_unexpected_close] is set. If it
+    /// closes partway through a frame, the final read fails with
+    /// [`io::ErrorKind::UnexpectedEof`].
+    pub async fn recv(&mut self, output: &mut [u8]) -> Result<usize, NoiseError> {
+        Ok(AsyncReadExt::read(self, output).await?)
+    }
+
+    /// Like [`recv`][Self::recv], but fails with [`NoiseError::DeadlineExceeded`] if no
+    /// data arrives by `deadline`. Nothing is read if the deadline has already passed.
+    ///
+    /// This is cancel-safe: when the deadline passes, any partially received frame stays
+    /// buffered, and is returned by a later read once the rest of it arrives.
+    pub async fn recv_deadline(
+        &mut self,
+        output: &mut [u8],
+        deadline: Instant,
+    ) -> Result<usize, NoiseError> {
+        with_deadline(deadline, self.recv(output)).await
+    }
+
+    /// Returns the number of unprocessed ciphertext bytes currently buffered and awaiting
+    /// follow up in the stream.
+    ///
+    /// Sometimes a stream will receive a partial ciphertext packet and must buffer
+    /// it, awaiting the remainder from the remote side before decryption can occur.
+    /// Ciphertext is also read from the transport in large batches, so whole packets
+    /// may remain buffered when a read fills the caller's output buffer.
+    ///
+    /// If a partial packet remains buffered after the remote side has finished sending,
+    /// it may indicate a synchronicity failure between the local and remote sides of the
+    /// connection.
+    pub fn unprocessed_ciphertext_len(&self) -> usize {
+        self.unprocessed_buf.len()
+    }
+
+    /// Returns the number of encrypted ciphertext bytes buffered and awaiting transmission
+    /// because the TCP socket could not accept them yet.
+    #[deprecated(note = "use pending_write_bytes")]
+    pub fn buffered_ciphertext_len(&self) -> usize {
+        self.pending_write_bytes()
+    }
+
+    /// Returns the exact number of ciphertext bytes which have been encrypted but not
+    /// yet accepted by the transport. A scheduler can stop producing data for a
+    /// connection whose count keeps growing, as its peer isn't keeping up.
+    ///
+    /// This is bounded by the [write high watermark][Self::write_high_watermark], plus
+    /// at most one frame. Flushing the stream drains the buffer entirely.
+    pub fn pending_write_bytes(&self) -> usize {
+        self.write_buf.len()
+    }
+
+    /// Returns the exact number of plaintext bytes which have been decrypted but not yet
+    /// consumed by a read, because the caller's buffer was too small or data was read
+    /// ahead. These can be read without waiting on the transport.
+    pub fn buffered_read_bytes(&self) -> usize {
+        self.read_overflow_buf.len()
+    }
+
+    /// Moves every decrypted byte which hasn't been read yet out of the stream, for
+    /// example to replay them to another handler along with the rest of the
+    /// connection. Returns an empty vector if none are buffered.
+    ///
+    /// Unlike [`recv`][Self::recv], which may wait on the transport, this never touches
+    /// the transport or the nonces, so ciphertext which hasn't been decrypted yet stays
+    /// buffered for the next read.
+    pub fn take_buffered(&mut self) -> Vec<u8> {
+        let buffered = self.read_overflow_buf.to_vec();
+        self.read_overflow_buf.clear();
+        self.stats.plaintext_bytes_read += buffered.len() as u64;
+        buffered
+    }
+
+    /// Returns the number of buffered ciphertext bytes above which writes apply
+    /// backpressure. See [`NoiseBuilder::write_high_watermark`].
+    pub fn write_high_watermark(&self) -> usize {
+        self.config.write_high_watermark
+    }
+
+    /// Returns the number of buffered ciphertext bytes below which writes resume after
+    /// backpressure. See [`NoiseBuilder::write_low_watermark`].
+    pub fn write_low_watermark(&self) -> usize {
+        self.config.resolved_write_low_watermark()
+    }
+
+    /// Change the [write high watermark][NoiseBuilder::write_high_watermark] of an
+    /// established stream, for example to let a bulk transfer buffer more than an
+    /// interactive session. A low watermark configured above the new limit is lowered to
+    /// it. Data already buffered is kept, and drains as usual.
+    pub fn set_write_buffer_limit(&mut self, limit: usize) {
+        self.config.write_high_watermark = limit;
+    }
+
+    /// Returns the nonce which the next frame sent will be encrypted with.
+    pub fn sending_nonce(&self) -> u64 {
+        self.noise.sending_nonce()
+    }
+
+    /// Advance the sending nonce to `to`, so that the next frame is encrypted with it and
+    /// the nonces in between are never used. A sender which can't be sure whether a
+    /// frame reached the peer, such as after a failed write, can skip ahead rather than
+    /// risk reusing a nonce.
+    ///
+    /// Frames don't carry their nonce, so the peer finds the new one by retrying a frame
+    /// which fails to decrypt under each of the next [`NONCE_JUMP_LIMIT`] nonces. The
+    /// nonce can therefore advance by at most that many past
+    /// [`sending_nonce`][Self::sending_nonce], and never move backwards, or this fails
+    /// with [`NoiseError::InvalidNonceSkip`].
+    pub fn skip_sending_nonce(&mut self, to: u64) -> Result<(), NoiseError> {
+        let from = self.noise.sending_nonce();
+        if to < from || to - from > NONCE_JUMP_LIMIT {
+            return Err(NoiseError::InvalidNonceSkip { from, to });
+        }
+        // The transport state can only advance its nonce by encrypting, so burn the
+        // skipped nonces on empty messages which are never sent.
+        let mut tag = [0u8; CIPHERTEXT_TAG_SIZE];
+        for _ in from..to {
+            self.noise.write_message(&[], &mut tag)?;
+        }
+        debug!(
+            "[{}] skipped sending nonce from {} to {}",
+            self.label, from, to
+        );
+        Ok(())
+    }
+
+    /// Returns the application sequence number of the next data frame sent. See
+    /// [`NoiseBuilder::sequence_numbers`].
+    pub fn sending_sequence(&self) -> u32 {
+        self.sending_sequence
+    }
+
+    /// Returns the application sequence number expected of the next data frame received.
+    /// See [`NoiseBuilder::sequence_numbers`].
+    pub fn receiving_sequence(&self) -> u32 {
+        self.receiving_sequence
+    }
+
+    /// Continue application sequence numbers from an earlier session, so that frames
+    /// lost or repeated across a reconnect are detected. Call this before any data is
+    /// sent or received, with the [`sending_sequence`][Self::sending_sequence] and
+    /// [`receiving_sequence`][Self::receiving_sequence] the earlier stream ended with.
+    /// The peer must do likewise with its own. See [`NoiseBuilder::sequence_numbers`].
+    pub fn resume_sequences(&mut self, sending: u32, receiving: u32) {
+        self.sending_sequence = sending;
+        self.receiving_sequence = receiving;
+    }
+
+    /// Returns how long it has been since application data was last read from or written
+    /// to the stream, or since the stream was established if none has been. Packets the
+    /// stream sends for itself, such as its preamble and close notify, don't count.
+    ///
+    /// A server can sweep its connections periodically and close those which have been
+    /// idle for too long.
+    pub fn idle_duration(&self) -> Duration {
+        self.last_activity.elapsed()
+    }
+
+    /// Returns a reference to the underlying transport.
+    ///
+    /// Reading from or writing to the transport directly will corrupt the Noise session.
+    pub fn get_ref(&self) -> &S {
+        &self.transport
+    }
+
+    /// Returns a mutable reference to the underlying transport.
+    ///
+    /// Reading from or writing to the transport directly will corrupt the Noise session.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.transport
+    }
+
+    /// Returns a snapshot of the stream's counters.
+    pub fn stats(&self) -> NoiseStats {
+        NoiseStats {
+            pending_write_bytes: self.pending_write_bytes(),
+            buffered_read_bytes: self.buffered_read_bytes(),
+            ..self.stats
+        }
+    }
+
+    /// Returns true if the stream has been shut down after too many consecutive frames
+    /// failed to decrypt. See [`NoiseBuilder::max_decrypt_failures`].
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.is_some()
+    }
+
+    /// Returns the name which identifies this stream in log messages.
+    pub fn name(&self) -> &str {
+        &self.label.name
+    }
+
+    /// Rename the stream in log messages, for example once the peer's identity is known
+    /// from its static key or an application-level login. Control characters, such as
+    /// newlines, are escaped so that a name derived from peer input can't forge log
+    /// lines.
+    pub fn set_name(&mut self, name: impl Into<String>) {
+        let name = sanitize_name(name.into());
+        debug!("[{}] renamed stream to {:?}", self.label, name);
+        self.label.name = name;
+    }
+
+    /// Returns the key-value pairs which tag this stream's log messages. See
+    /// [`LogContext`].
+    pub fn log_context(&self) -> &LogContext {
+        &self.label.context
+    }
+
+    /// Replace the key-value pairs which tag this stream's log messages, for example to
+    /// add the peer's key fingerprint once the handshake has authenticated it. See
+    /// [`LogContext`].
+    pub fn set_log_context(&mut self, context: LogContext) {
+        self.label.context = context;
+    }
+
+    /// Returns the Noise protocol name of the handshake which established this stream,
+    /// such as `Noise_NNpsk0_25519_ChaChaPoly_SHA512`, or `None` if the stream was
+    /// created with [`NoiseStream::new`] from a bare transport state.
+    pub fn protocol_name(&self) -> Option<&str> {
+        self.params().map(|params| params.name.as_str())
+    }
+
+    /// Returns the protocol parameters negotiated by the handshake which established this
+    /// stream, or `None` if the stream was created with [`NoiseStream::new`].
+    pub fn params(&self) -> Option<&NoiseParams> {
+        self.handshake_info.as_ref().map(|info| &info.params)
+    }
+
+    /// Returns the hash of the handshake which established this stream, which is unique
+    /// to the session and identical on both sides. It can bind application-level
+    /// authentication to this session. This is `None` if the stream was created with
+    /// [`NoiseStream::new`].
+    pub fn handshake_hash(&self) -> Option<&[u8]> {
+        self.handshake_info
+            .as_ref()
+            .map(|info| info.handshake_hash.as_slice())
+    }
+
+    /// Returns the peer's static public key, if the handshake pattern authenticated one.
+    /// The built-in `NN` handshakes don't use static keys, so this returns `None` for
+    /// them. See [`SnowHandshake`][crate::handshakes::SnowHandshake].
+    pub fn remote_static_key(&self) -> Option<&[u8]> {
+        self.noise.get_remote_static().or_else(|| {
+            self.handshake_info
+                .as_ref()
+                .and_then(|info| info.remote_static_key.as_deref())
+        })
+    }
+
+    /// Returns the [`Fingerprint`] of the peer's static public key, if the handshake
+    /// pattern authenticated one. See [`remote_static_key`][Self::remote_static_key].
+    pub fn peer_fingerprint(&self) -> Option<Fingerprint> {
+        self.remote_static_key().map(Fingerprint::of)
+    }
+
+    /// Returns our static public key in the handshake which established this stream, as
+    /// reported by [`Handshake::local_static_public_key`]. This is `None` for handshakes
+    /// without a local static key, such as the built-in `NN` handshakes, and for streams
+    /// created with [`NoiseStream::new`].
+    pub fn local_static_public_key(&self) -> Option<&[u8]> {
+        self.handshake_info
+            .as_ref()
+            .and_then(|info| info.local_static_key.as_deref())
+    }
+
+    /// Returns the framing version declared by the peer, or `None` if no packets have
+    /// been received from the peer yet. See [`FRAMING_VERSION`].
+    pub fn peer_framing_version(&self) -> Option<u8> {
+        self.peer_framing_version
+    }
+
+    /// Returns the size on the wire of each frame of data this stream sends.
+    /// See [`NoiseBuilder::frame_sizing`].
+    pub fn frame_size(&self) -> usize {
+        self.frame_size
+    }
+
+    /// Returns the size on the wire of each frame of data the peer sends, or `None`
+    /// if no packets have been received from the peer yet.
+    pub fn peer_frame_size(&self) -> Option<usize> {
+        self.peer_frame_size
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> NoiseStream<S> {
+    /// Report an error to the events handler, if any, and convert it for the caller to
+    /// return.
+    fn report_error(&self, error: NoiseError) -> io::Error {
+        if let Some(EventsHook(events)) = &self.config.events {
+            events.on_error(&self.label.name, &error);
+        }
+        error.into()
+    }
+
+    /// Attach the stream's name and peer address to an error which a read or write is
+    /// about to return. Does nothing if the transport has no peer address.
+    fn error_context<T>(&self, poll: Poll<Result<T, io::Error>>) -> Poll<Result<T, io::Error>> {
+        match (poll, self.peer_addr) {
+            (Poll::Ready(Err(e)), Some(peer_addr)) => Poll::Ready(Err(NoiseError::from(e)
+                .with_context(&self.label.name, Some(peer_addr))
+                .into())),
+            (poll, _) => poll,
+        }
+    }
+
+    /// Report the close of the stream to the events handler, if any, unless an earlier
+    /// close was already reported.
+    fn report_close(&mut self, reason: impl FnOnce() -> CloseReason) {
+        if self.reported_close {
+            return;
+        }
+        self.reported_close = true;
+        if let Some(EventsHook(events)) = &self.config.events {
+            events.on_close(&self.label.name, &reason());
+        }
+    }
+
+    /// Count a received frame which could not be decrypted under any acceptable nonce,
+    /// poisoning the stream if too many have failed in a row. The receiving nonce is
+    /// rewound so that a genuine frame following the bad one can still be decrypted.
+    fn record_decrypt_failure(&mut self, starting_nonce: u64) -> io::Error {
+        self.noise.set_receiving_nonce(starting_nonce);
+        self.stats.decrypt_failures += 1;
+        self.stats.consecutive_decrypt_failures += 1;
+
+        if self.stats.consecutive_decrypt_failures >= self.config.max_decrypt_failures {
+            error!(
+                "[{}] {} consecutive decryption failures; closing stream",
+                self.label, self.stats.consecutive_decrypt_failures
+            );
+            self.poisoned = Some(Poison::TooManyDecryptFailures);
+            return self.report_error(NoiseError::TooManyDecryptFailures);
+        }
+        self.report_error(snow::Error::Decrypt.into())
+    }
+
+    /// Poison the stream after the peer declared an incompatible framing version.
+    fn framing_mismatch(&mut self, remote: Option<u8>) -> io::Error {
+        error!(
+            "[{}] peer framing version {:?} is incompatible with ours ({}); closing stream",
+            self.label, remote, FRAMING_VERSION
+        );
+        let poison = Poison::FramingVersionMismatch { remote };
+        self.poisoned = Some(poison);
+        self.report_error(poison.into())
+    }
+
+    /// Poison the stream after the peer declared a frame size we can't receive.
+    fn unsupported_frame_size(&mut self, size: usize) -> io::Error {
+        error!(
+            "[{}] peer frame size {} is unsupported; closing stream",
+            self.label, size
+        );
+        let poison = Poison::UnsupportedFrameSize { size };
+        self.poisoned = Some(poison);
+        self.report_error(poison.into())
+    }
+
+    /// Encrypt one frame of plaintext directly onto the end of `write_buf`, so that
+    /// the ciphertext needs no further copying before it is handed to the socket.
+    fn encrypt_frame(&mut self, kind: PacketKind, chunk: &[u8]) -> Result<(), io::Error> {
+        let packet_size = match kind {
+            PacketKind::Preamble => PREAMBLE_PACKET_SIZE,
+            PacketKind::Data | PacketKind::Close => self.frame_size,
+        };
+        let sequenced = kind == PacketKind::Data && self.config.sequence_numbers;
+        let prefix_len = if sequenced { SEQUENCE_NUMBER_SIZE } else { 0 };
+        let plaintext = &mut self.plaintext_buf[..packet_size - CIPHERTEXT_TAG_SIZE];
+        let payload_start = PLAINTEXT_HEADER_SIZE + prefix_len;
+        let used_len = payload_start + chunk.len();
+        plaintext[0] = kind as u8;
+        write_u16(
+            &mut plaintext[PLAINTEXT_KIND_SIZE..PLAINTEXT_HEADER_SIZE],
+            (prefix_len + chunk.len()) as u16,
+        );
+        if sequenced {
+            plaintext[PLAINTEXT_HEADER_SIZE..payload_start]
+                .copy_from_slice(&self.sending_sequence.to_be_bytes());
+        }
+        plaintext[payload_start..used_len].copy_from_slice(chunk);
+
+        let nonce = self.noise.sending_nonce();
+        let start = self.write_buf.len();
+        self.write_buf.resize(start + packet_size, 0);
+
+        #[cfg(feature = "profiling")]
+        let started = std::time::Instant::now();
+        let result = self
+            .noise
+            .write_message(plaintext, &mut self.write_buf[start..]);
+        #[cfg(feature = "profiling")]
+        self.stats.encrypt_latency.record(started.elapsed());
+        plaintext[..used_len].fill(0);
+        match result {
+            Ok(wrote_n) => {
+                self.write_buf.truncate(start + wrote_n);
+                if sequenced {
+                    self.sending_sequence = self.sending_sequence.wrapping_add(1);
+                }
+                trace!(
+                    "[{}] encrypted {:?} frame; plaintext={} ciphertext={} nonce={}",
+                    self.label,
+                    kind,
+                    chunk.len(),
+                    wrote_n,
+                    nonce
+                );
+                Ok(())
+            }
+            Err(e) => {
+                self.write_buf.truncate(start);
+                Err(NoiseError::from(e).into())
+            }
+        }
+    }
+
+    /// Whether we may send, which is all but the responder of a one-way handshake.
+    pub(crate) fn can_send(&self) -> bool {
+        !self.one_way || self.noise.is_initiator()
+    }
+
+    /// Whether we may receive, which is all but the initiator of a one-way handshake.
+    pub(crate) fn can_recv(&self) -> bool {
+        !self.one_way || !self.noise.is_initiator()
+    }
+
+    /// Queue our preamble packet, declaring the framing we use, unless it was already
+    /// sent. It must be the first packet we send.
+    fn queue_preamble(&mut self) -> Result<(), io::Error> {
+        if !self.sent_preamble {
+            let mut preamble = [FRAMING_VERSION, 0, 0, 0];
+            write_u16(&mut preamble[1..3], self.frame_size as u16);
+            if self.config.sequence_numbers {
+                preamble[3] |= PREAMBLE_OPTION_SEQUENCE_NUMBERS;
+            }
+            self.encrypt_frame(PacketKind::Preamble, &preamble)?;
+            self.sent_preamble = true;
+        }
+        Ok(())
+    }
+
+    /// Queue a close notify, unless a close packet was already sent.
+    fn queue_close_notify(&mut self) -> Result<(), io::Error> {
+        if !self.sent_close {
+            self.queue_preamble()?;
+            self.encrypt_frame(PacketKind::Close, &[])?;
+            self.sent_close = true;
+            self.report_close(|| CloseReason::Shutdown);
+        }
+        Ok(())
+    }
+
+    /// Write as much of `write_buf` to the socket as it will accept. The Noise
+    /// nonce already advanced for these bytes, so they are written verbatim and
+    /// in order to preserve the packet framing the peer expects. Returns
+    /// `Ready(Ok(()))` once the buffer is empty, `Pending` (surfacing
+    /// backpressure) while the socket can't take it.
+    fn poll_drain_write_buf(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        while !self.write_buf.is_empty() {
+            match AsyncWrite::poll_write(Pin::new(&mut self.transport), cx, &self.write_buf) {
+                Poll::Ready(Ok(0)) => {
+                    let e = io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "underlying writer accepted none of the buffered noise ciphertext",
+                    );
+                    return Poll::Ready(Err(self.report_error(NoiseError::Io(e))));
+                }
+                Poll::Ready(Ok(sent_n)) => {
+                    trace!("[{}] sent {} bytes of ciphertext", self.label, sent_n);
+                    self.write_buf.advance(sent_n);
+                    self.stats.socket_bytes_written += sent_n as u64;
+                }
+                Poll::Ready(Err(e)) if e.kind() == io::ErrorKind::Interrupted => {
+                    trace!("[{}] transport write interrupted; retrying", self.label);
+                }
+                Poll::Ready(Err(e)) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return self.transport_would_block(cx);
+                }
+                Poll::Ready(Err(e)) => {
+                    return Poll::Ready(Err(self.report_error(NoiseError::Io(e))))
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Treat a `WouldBlock` error from the transport as not being ready. A transport
+    /// which returns it, rather than `Pending`, has not registered our waker, so wake
+    /// ourselves to try again on the next poll.
+    fn transport_would_block<T>(&self, cx: &mut Context<'_>) -> Poll<T> {
+        trace!("[{}] transport would block; retrying later", self.label);
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for NoiseStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let poll = self.poll_write_frames(cx, buf);
+        self.error_context(poll)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let poll = self.poll_flush_frames(cx);
+        self.error_context(poll)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        let poll = self.poll_shutdown_frames(cx);
+        self.error_context(poll)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> NoiseStream<S> {
+    /// Encrypt as much of `buf` as the write buffer allows, and start sending it.
+    fn poll_write_frames(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        if let Some(poison) = self.poisoned {
+            return Poll::Ready(Err(NoiseError::from(poison).into()));
+        }
+        if self.sent_close {
+            return Poll::Ready(Err(closed_error()));
+        }
+        // A clean close notify only ends the peer's half of the stream, but a peer which
+        // closed with an error reads nothing more, so fail before writing to it.
+        if self.peer_close.is_some() {
+            return Poll::Ready(Err(peer_closed_error()));
+        }
+        if !self.can_send() {
+            return Poll::Ready(Err(NoiseError::OneWay { send: true }.into()));
+        }
+        #[cfg(feature = "coop")]
+        let coop = std::task::ready!(tokio::task::coop::poll_proceed(cx));
+
+        // Flush any ciphertext left over from previous writes first, so packets
+        // reach the peer in order and the nonce stays in sync. If the socket
+        // can't take it yet, keep buffering new packets behind it until the high
+        // watermark, then surface backpressure to the caller until the buffer drains
+        // below the low watermark. The drain registered our waker with the socket
+        // before returning `Pending`, so each wakeup drains some more.
+        match self.poll_drain_write_buf(cx) {
+            Poll::Ready(Ok(())) => self.write_blocked = false,
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => {
+                let limit = match self.write_blocked {
+                    true => self.config.resolved_write_low_watermark(),
+                    false => self.config.write_high_watermark,
+                };
+                if self.write_buf.len() >= limit {
+                    trace!(
+                        "[{}] poll_write pending; {} bytes buffered",
+                        self.label,
+                        self.write_buf.len()
+                    );
+                    self.write_blocked = true;
+                    return Poll::Pending;
+                }
+                self.write_blocked = false;
+            }
+        }
+
+        if let Err(e) = self.queue_preamble() {
+            return Poll::Ready(Err(e));
+        }
+
+        // Encrypt as much of the caller's data as the watermark allows, so that it
+        // reaches the socket in as few writes as possible. At least one frame is
+        // always produced, even for an empty buffer.
+        let mut max_chunk_len = self.frame_size - FRAME_OVERHEAD;
+        if self.config.sequence_numbers {
+            max_chunk_len -= SEQUENCE_NUMBER_SIZE;
+        }
+        let mut consumed = 0;
+        loop {
+            let chunk_len = (buf.len() - consumed).min(max_chunk_len);
+            if let Err(e) = self.encrypt_frame(PacketKind::Data, &buf[consumed..][..chunk_len]) {
+                return Poll::Ready(Err(e));
+            }
+            consumed += chunk_len;
+            self.stats.plaintext_bytes_written += chunk_len as u64;
+            if consumed == buf.len() || self.write_buf.len() >= self.config.write_high_watermark {
+                break;
+            }
+        }
+
+        // The packets are encrypted and the nonce has advanced, so every
+        // ciphertext byte MUST reach the peer. Make a start on sending them now,
+        // and report the plaintext as fully consumed. Whatever the socket doesn't
+        // accept stays in `write_buf`, to be sent by the drain above on the next
+        // poll, or by `poll_flush`.
+        if let Poll::Ready(Err(e)) = self.poll_drain_write_buf(cx) {
+            return Poll::Ready(Err(e));
+        }
+        if consumed > 0 {
+            self.last_activity = Instant::now();
+        }
+        #[cfg(feature = "coop")]
+        coop.made_progress();
+        Poll::Ready(Ok(consumed))
+    }
+
+    /// Send all buffered ciphertext, then flush the transport.
+    fn poll_flush_frames(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        match self.poll_drain_write_buf(cx) {
+            Poll::Ready(Ok(())) => {}
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => return Poll::Pending,
+        }
+        loop {
+            match AsyncWrite::poll_flush(Pin::new(&mut self.transport), cx) {
+                Poll::Ready(Err(e)) if e.kind() == io::ErrorKind::Interrupted => {}
+                Poll::Ready(Err(e)) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return self.transport_would_block(cx);
+                }
+                poll => return poll,
+            }
+        }
+    }
+    /// Send all buffered ciphertext and a close notify, then shut down the transport.
+    fn poll_shutdown_frames(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        // Every buffered packet must reach the peer before the transport is shut
+        // down, or the tail of the stream would be lost. Callers such as hyper and
+        // `copy_bidirectional` shut down without flushing first. The close notify
+        // goes last, so the peer knows it received everything. The responder of a
+        // one-way handshake can't send one, and just closes the transport.
+        if self.poisoned.is_none() && self.can_send() {
+            if let Err(e) = self.queue_close_notify() {
+                return Poll::Ready(Err(e));
+            }
+        }
+        match self.poll_drain_write_buf(cx) {
+            Poll::Ready(Ok(())) => {}
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => return Poll::Pending,
+        }
+        AsyncWrite::poll_shutdown(Pin::new(&mut self.transport), cx)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for NoiseStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        output_buf: &mut io::ReadBuf<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        let initial_filled = output_buf.filled().len();
+        let poll = self.as_mut().poll_read_frames(cx, output_buf, false);
+        if output_buf.filled().len() > initial_filled {
+            self.last_activity = Instant::now();
+        }
+        self.error_context(poll)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> NoiseStream<S> {
+    /// Read and decrypt frames into `output_buf`. If `until_preamble` is set, this instead
+    /// returns as soon as the peer's preamble has been received, without reading further.
+    fn poll_read_frames(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        output_buf: &mut io::ReadBuf<'_>,
+        until_preamble: bool,
+    ) -> Poll<Result<(), io::Error>> {
+        if let Some(poison) = self.poisoned {
+            return Poll::Ready(Err(NoiseError::from(poison).into()));
+        }
+        if !self.can_recv() {
+            return Poll::Ready(Err(NoiseError::OneWay { send: false }.into()));
+        }
+
+        // Opportunistically flush any ciphertext left over from a previous
+        // partial write. A request/response caller that has finished writing
+        // and now only awaits a read would otherwise never drive
+        // `write_buf` out (`poll_write`/`poll_flush` are the only
+        // other drain points), so the peer never receives the full request and
+        // never replies — a deadlock. After a partial write `transport.poll_write`
+        // returned `Ready`, so no write-readiness waker is even registered;
+        // draining here both makes progress and re-arms that waker on `Pending`.
+        // We deliberately ignore the drain's backpressure: a full send buffer
+        // must not block reads. A hard write error means the connection is
+        // broken, so surface it.
+        if let Poll::Ready(Err(e)) = self.poll_drain_write_buf(cx) {
+            return Poll::Ready(Err(e));
+        }
+
+        let this = &mut *self;
+        let initial_filled = output_buf.filled().len();
+
+        // Serve cleartext left over from a previous read first.
+        if !this.read_overflow_buf.is_empty() {
+            let n_overflow = this.read_overflow_buf.len().min(output_buf.remaining());
+            output_buf.put_slice(&this.read_overflow_buf[..n_overflow]);
+            this.read_overflow_buf.advance(n_overflow);
+            this.stats.plaintext_bytes_read += n_overflow as u64;
+            trace!(
+                "[{}] popped {} bytes from overflow buffer",
+                this.label,
+                n_overflow
+            );
+        }
+
+        // Likewise, report a fatal error found after data which has now been read.
+        if let Some(poison) = this.pending_poison {
+            if output_buf.filled().len() > initial_filled || !this.read_overflow_buf.is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            this.pending_poison = None;
+            this.poisoned = Some(poison);
+            return Poll::Ready(Err(this.report_error(poison.into())));
+        }
+
+        // Once the peer has closed, report EOF or its error after any data it sent.
+        if this.received_close_notify {
+            return Poll::Ready(Ok(()));
+        }
+        if let Some((code, reason)) = &this.peer_close {
+            if output_buf.filled().len() > initial_filled {
+                return Poll::Ready(Ok(()));
+            }
+            return Poll::Ready(Err(NoiseError::ClosedByPeer {
+                code: *code,
+                reason: reason.clone(),
+            }
+            .into()));
+        }
+
+        let mut frames_read = 0;
+        loop {
+            // Once the caller's buffer is full, keep decrypting frames which have already
+            // arrived into the overflow buffer, up to the read-ahead limit. Only a read
+            // which ran out of buffered cleartext and decrypted a frame reads ahead, so
+            // reads served from memory, or into a buffer with no room at all, don't touch
+            // the transport.
+            if until_preamble {
+                if this.peer_framing_version.is_some() {
+                    break;
+                }
+            } else if output_buf.remaining() == 0
+                && (frames_read == 0 || this.read_overflow_buf.len() >= this.config.read_ahead)
+            {
+                break;
+            }
+
+            // The peer's preamble declares the size of the frames which follow it.
+            let packet_size = this.peer_frame_size.unwrap_or(PREAMBLE_PACKET_SIZE);
+
+            // Read more ciphertext from the transport, unless a complete frame
+            // is already buffered.
+            if this.unprocessed_buf.len() < packet_size {
+                match this.unprocessed_buf.poll_fill(&mut this.transport, cx) {
+                    Poll::Ready(Ok(0)) => {
+                        // EOF. A partial frame left over means the peer's stream was cut
+                        // short, which must not be mistaken for a clean close. Any data
+                        // already read is returned first.
+                        let partial_len = this.unprocessed_buf.len();
+                        if partial_len > 0 && output_buf.filled().len() == initial_filled {
+                            let e = io::Error::new(
+                                io::ErrorKind::UnexpectedEof,
+                                format!(
+                                    "transport closed with a partial {}-byte frame buffered",
+                                    partial_len
+                                ),
+                            );
+                            return Poll::Ready(Err(this.report_error(NoiseError::Io(e))));
+                        }
+                        this.report_close(|| CloseReason::TransportClosed);
+                        // Without a close notify, the peer may not have sent everything
+                        // it meant to.
+                        if this.config.allow_unexpected_close
+                            || output_buf.filled().len() > initial_filled
+                        {
+                            break;
+                        }
+                        return Poll::Ready(Err(this.report_error(NoiseError::UnexpectedClose)));
+                    }
+                    Poll::Ready(Ok(n)) => {
+                        this.stats.socket_bytes_read += n as u64;
+                        continue;
+                    }
+                    Poll::Ready(Err(e)) => {
+                        return Poll::Ready(Err(this.report_error(NoiseError::Io(e))))
+                    }
+                    Poll::Pending if output_buf.filled().len() > initial_filled => break,
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+
+            // Each frame decrypted spends a unit of tokio's cooperative budget, so that a
+            // caller reading in a loop yields to other tasks even if every read is
+            // served from ciphertext which is already buffered.
+            #[cfg(feature = "coop")]
+            let coop = match tokio::task::coop::poll_proceed(cx) {
+                Poll::Ready(coop) => coop,
+                Poll::Pending if output_buf.filled().len() > initial_filled => break,
+                Poll::Pending => return Poll::Pending,
+            };
+
+            // A data frame which fits whole in the caller's buffer is decrypted straight
+            // into it, and its header shifted out afterwards. Otherwise the frame goes
+            // through a scratch buffer, and anything which doesn't fit overflows.
+            let returned_data = output_buf.filled().len() > initial_filled;
+            let output_room = output_buf.remaining();
+            let cleartext_len = packet_size - CIPHERTEXT_TAG_SIZE;
+            let direct = this.peer_framing_version.is_some() && output_room >= cleartext_len;
+            let mut output = output_buf.initialize_unfilled_to(output_room.min(cleartext_len));
+            let mut scratch;
+            let cleartext = if direct {
+                std::mem::take(&mut output)
+            } else {
+                scratch = [0u8; PLAINTEXT_PACKET_SIZE];
+                &mut scratch[..cleartext_len]
+            };
+
+            let ciphertext = &this.unprocessed_buf.data()[..packet_size];
+
+            let starting_nonce = this.noise.receiving_nonce();
+            let mut n_attempts = 0;
+
+            #[cfg(feature = "profiling")]
+            let started = std::time::Instant::now();
+            let read_n = loop {
+                match this.noise.read_message(ciphertext, cleartext) {
+                    Ok(read_n) => break read_n,
+
+                    // Sometimes the remote side will encounter a problem sending, and for safety
+                    // they cannot reuse nonces. So they specify which nonce they used in each
+                    // message. As long as the nonce claimed by the remote side is no lower than
+                    // the nonce in our local state, and not higher than some sane limit,
+                    // it is safe to update our receiving nonce to match.
+                    Err(snow::Error::Decrypt) if n_attempts < NONCE_JUMP_LIMIT => {
+                        n_attempts += 1;
+                        warn!(
+                            "[{}] decryption failed; attempts={} nonce={}; retrying",
+                            this.label,
+                            n_attempts,
+                            this.noise.receiving_nonce()
+                        );
+                        this.noise.set_receiving_nonce(starting_nonce + n_attempts);
+                        continue;
+                    }
+
+                    Err(e) => {
+                        error!(
+                            "[{}] poll_read ERROR; ciphertext={} nonce={}; error message: {}",
+                            this.label,
+                            ciphertext.len(),
+                            this.noise.receiving_nonce(),
+                            e
+                        );
+                        // Pop the bad frame, so it isn't retried on the next read.
+                        this.unprocessed_buf.consume(packet_size);
+                        if e == snow::Error::Decrypt {
+                            return Poll::Ready(Err(this.record_decrypt_failure(starting_nonce)));
+                        }
+                        return Poll::Ready(Err(NoiseError::from(e).into()));
+                    }
+                };
+            };
+            #[cfg(feature = "profiling")]
+            this.stats.decrypt_latency.record(started.elapsed());
+            #[cfg(feature = "coop")]
+            coop.made_progress();
+            this.unprocessed_buf.consume(packet_size);
+
+            // With strict nonces, the frame only decrypted under a later nonce, so the
+            // frames in between were lost. It is discarded, and the stream poisoned.
+            if n_attempts > 0 && this.config.strict_nonce {
+                let (expected, got) = (starting_nonce, starting_nonce + n_attempts);
+                error!(
+                    "[{}] received frame with nonce {}, expected {}; closing stream",
+                    this.label, got, expected
+                );
+                this.noise.set_receiving_nonce(starting_nonce);
+                let poison = Poison::NonceMismatch { expected, got };
+                if returned_data || !this.read_overflow_buf.is_empty() {
+                    this.pending_poison = Some(poison);
+                    break;
+                }
+                this.poisoned = Some(poison);
+                return Poll::Ready(Err(this.report_error(poison.into())));
+            }
+            this.stats.consecutive_decrypt_failures = 0;
+
+            assert_eq!(
+                read_n,
+                cleartext.len(),
+                "should have decrypted exactly {} plaintext bytes, got {}",
+                cleartext.len(),
+                read_n
+            );
+
+            let kind = cleartext[0];
+            let plaintext_len =
+                read_u16(&cleartext[PLAINTEXT_KIND_SIZE..PLAINTEXT_HEADER_SIZE]) as usize;
+            let plaintext_max_len = packet_size - FRAME_OVERHEAD;
+            if plaintext_len > plaintext_max_len {
+                let poison = Poison::FrameLengthExceeded {
+                    len: plaintext_len,
+                    max: plaintext_max_len,
+                };
+                error!(
+                    "[{}] {}; closing stream",
+                    this.label,
+                    NoiseError::from(poison)
+                );
+                // Data from earlier frames is returned before the error.
+                if returned_data || !this.read_overflow_buf.is_empty() {
+                    this.pending_poison = Some(poison);
+                    break;
+                }
+                this.poisoned = Some(poison);
+                return Poll::Ready(Err(this.report_error(poison.into())));
+            }
+
+            let message = &cleartext[PLAINTEXT_HEADER_SIZE..][..plaintext_len];
+
+            match (PacketKind::from_u8(kind), this.peer_framing_version) {
+                (Some(PacketKind::Data), Some(_)) => {}
+                (Some(PacketKind::Preamble), None) => {
+                    // The version, then the peer's frame size. Later versions may
+                    // append more options to the preamble.
+                    let remote = message.first().copied();
+                    if remote != Some(FRAMING_VERSION) {
+                        return Poll::Ready(Err(this.framing_mismatch(remote)));
+                    }
+                    let peer_frame_size = message.get(1..3).map_or(0, read_u16) as usize;
+                    if !(MIN_FRAME_SIZE..=MAX_FRAME_SIZE).contains(&peer_frame_size) {
+                        return Poll::Ready(Err(this.unsupported_frame_size(peer_frame_size)));
+                    }
+                    let options = message.get(3).copied().unwrap_or(0);
+                    let sequenced = options & PREAMBLE_OPTION_SEQUENCE_NUMBERS != 0;
+                    if sequenced != this.config.sequence_numbers {
+                        let poison = Poison::SequenceNumbersMismatch { remote: sequenced };
+                        error!(
+                            "[{}] {}; closing stream",
+                            this.label,
+                            NoiseError::from(poison)
+                        );
+                        this.poisoned = Some(poison);
+                        return Poll::Ready(Err(this.report_error(poison.into())));
+                    }
+                    debug!(
+                        "[{}] peer uses framing version {} with {}-byte frames",
+                        this.label, FRAMING_VERSION, peer_frame_size
+                    );
+                    this.peer_framing_version = remote;
+                    this.peer_frame_size = Some(peer_frame_size);
+                    continue;
+                }
+                // The peer's first packet must be a preamble.
+                (_, None) => return Poll::Ready(Err(this.framing_mismatch(None))),
+                (Some(PacketKind::Close), Some(_)) if message.is_empty() => {
+                    debug!("[{}] peer closed stream", this.label);
+                    this.received_close_notify = true;
+                    this.report_close(|| CloseReason::PeerShutdown);
+                    break;
+                }
+                (Some(PacketKind::Close), Some(_)) => {
+                    let code = message.get(..4).map_or(0, |code| {
+                        u32::from_be_bytes(code.try_into().expect("4-byte slice"))
+                    });
+                    let reason = String::from_utf8_lossy(message.get(4..).unwrap_or_default());
+                    debug!(
+                        "[{}] peer closed stream with error code {}: {}",
+                        this.label, code, reason
+                    );
+                    let reason = reason.into_owned();
+                    this.peer_close = Some((code, reason.clone()));
+                    this.report_close(|| CloseReason::PeerError {
+                        code,
+                        reason: reason.clone(),
+                    });
+                    if returned_data {
+                        break;
+                    }
+                    return Poll::Ready(Err(NoiseError::ClosedByPeer { code, reason }.into()));
+                }
+                (Some(PacketKind::Preamble), Some(_)) => {
+                    let e = io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "received a second framing preamble packet",
+                    );
+                    return Poll::Ready(Err(this.report_error(NoiseError::Io(e))));
+                }
+                (None, Some(_)) => {
+                    let e = io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("received packet of unknown kind {}", kind),
+                    );
+                    return Poll::Ready(Err(this.report_error(NoiseError::Io(e))));
+                }
+            }
+
+            let message = if this.config.sequence_numbers {
+                let Some((sequence, message)) = message.split_first_chunk::<SEQUENCE_NUMBER_SIZE>()
+                else {
+                    let e = io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "received data frame without a sequence number",
+                    );
+                    return Poll::Ready(Err(this.report_error(NoiseError::Io(e))));
+                };
+                let got = u32::from_be_bytes(*sequence);
+                let expected = this.receiving_sequence;
+                if got != expected {
+                    error!(
+                        "[{}] received sequence number {}, expected {}; closing stream",
+                        this.label, got, expected
+                    );
+                    // Data from earlier frames is returned before the error.
+                    let poison = Poison::SequenceGap { expected, got };
+                    if returned_data || !this.read_overflow_buf.is_empty() {
+                        this.pending_poison = Some(poison);
+                        break;
+                    }
+                    this.poisoned = Some(poison);
+                    return Poll::Ready(Err(this.report_error(poison.into())));
+                }
+                this.receiving_sequence = expected.wrapping_add(1);
+                message
+            } else {
+                message
+            };
+
+            trace!(
+                "[{}] poll_read OK; plaintext={} output_room={} nonce={}",
+                this.label,
+                message.len(),
+                output_room,
+                this.noise.receiving_nonce() - 1
+            );
+
+            // A frame decrypted in place only needs its header shifted out, and always
+            // fits. Otherwise put as much as fits into the output buffer, and keep the rest
+            // for the next read.
+            let n_output = message.len().min(output_room);
+            this.stats.plaintext_bytes_read += n_output as u64;
+            if direct {
+                let message_start = PLAINTEXT_HEADER_SIZE + plaintext_len - n_output;
+                cleartext.copy_within(message_start..message_start + n_output, 0);
+            } else {
+                output[..n_output].copy_from_slice(&message[..n_output]);
+                if n_output < message.len() {
+                    this.read_overflow_buf
+                        .extend_from_slice(&message[n_output..]);
+                    trace!(
+                        "[{}] pushed {} bytes to the read_overflow_buf",
+                        this.label,
+                        message.len() - n_output
+                    );
+                }
+            }
+            output_buf.advance(n_output);
+
+            // Yield back to the runtime for fairness, even if more data is available.
+            frames_read += 1;
+            if frames_read >= this.config.max_frames_per_poll {
+                trace!(
+                    "[{}] poll_read yielding after {} frames",
+                    this.label,
+                    frames_read
+                );
+                if output_buf.filled().len() == initial_filled {
+                    // Returning no data would look like EOF, so ask to be polled again.
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                break;
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "session-export")]
+impl<S: AsyncRead + AsyncWrite + Unpin> NoiseStream<S> {
+    /// Capture the stream's state, to be resumed with
+    /// [`import_session`][Self::import_session] over the same transport, typically in
+    /// another process which has been passed the transport's file descriptor. Available
+    /// with the `session-export` feature.
+    ///
+    /// The snapshot includes the transport keys and nonces, data which has been
+    /// received but not yet read, and ciphertext which has been written but not yet
+    /// flushed. Statistics and configuration are not included, so import with a
+    /// [`NoiseBuilder`] configured like the exported stream's, in particular its
+    /// [`sequence_numbers`][NoiseBuilder::sequence_numbers].
+    ///
+    /// Once exported, the stream can no longer be used. Further reads and writes fail
+    /// with [`SessionError::Exported`][crate::SessionError::Exported], and dropping it
+    /// sends nothing to the peer, so the transport can be handed over intact. Fails with
+    /// [`SessionError::KeysUnavailable`][crate::SessionError::KeysUnavailable] if the
+    /// stream was not established by a handshake, or with the stream's error if it has
+    /// been poisoned.
+    pub fn export_session(&mut self) -> Result<crate::SessionSnapshot, NoiseError> {
+        if let Some(poison) = self.poisoned.or(self.pending_poison) {
+            return Err(poison.into());
+        }
+        let (info, keys) = match &self.handshake_info {
+            Some(
+                info @ HandshakeInfo {
+                    session_keys: Some(keys),
+                    ..
+                },
+            ) => (info, keys.clone()),
+            _ => return Err(crate::SessionError::KeysUnavailable.into()),
+        };
+        let snapshot = crate::SessionSnapshot {
+            name: self.label.name.clone(),
+            initiator: self.noise.is_initiator(),
+            protocol_name: info.params.name.clone(),
+            handshake_hash: info.handshake_hash.clone(),
+            local_static_key: info.local_static_key.clone(),
+            remote_static_key: self.remote_static_key().map(<[u8]>::to_vec),
+            keys,
+            sending_nonce: self.noise.sending_nonce(),
+            receiving_nonce: self.noise.receiving_nonce(),
+            sending_sequence: self.sending_sequence,
+            receiving_sequence: self.receiving_sequence,
+            frame_size: self.frame_size,
+            sent_preamble: self.sent_preamble,
+            peer_framing_version: self.peer_framing_version,
+            peer_frame_size: self.peer_frame_size,
+            sent_close: self.sent_close,
+            received_close_notify: self.received_close_notify,
+            peer_close: self.peer_close.clone(),
+            read_overflow: self.read_overflow_buf.to_vec(),
+            unprocessed: self.unprocessed_buf.data().to_vec(),
+            unsent: self.write_buf.to_vec(),
+        };
+        self.poisoned = Some(Poison::Exported);
+        debug!(
+            "[{}] exported session at sending nonce {}, receiving nonce {}",
+            self.label, snapshot.sending_nonce, snapshot.receiving_nonce
+        );
+        Ok(snapshot)
+    }
+
+    /// Resume a stream exported by [`export_session`][Self::export_session], over the
+    /// same transport. Available with the `session-export` feature.
+    ///
+    /// Use [`NoiseBuilder::import_session`] to configure the resumed stream.
+    pub fn import_session(
+        socket: S,
+        snapshot: crate::SessionSnapshot,
+    ) -> Result<NoiseStream<S>, NoiseError>
+    where
+        S: Transport,
+    {
+        NoiseBuilder::default().import_session(socket, snapshot)
+    }
+
+    /// Assemble a stream from a snapshot, with the given configuration.
+    pub(crate) fn from_snapshot(
+        socket: S,
+        snapshot: crate::SessionSnapshot,
+        config: NoiseBuilder,
+    ) -> Result<NoiseStream<S>, NoiseError> {
+        if !(MIN_FRAME_SIZE..=MAX_FRAME_SIZE).contains(&snapshot.frame_size)
+            || snapshot.unprocessed.len() > RECV_BUF_SIZE
+        {
+            return Err(crate::SessionError::Malformed.into());
+        }
+        let params: NoiseParams = snapshot
+            .protocol_name
+            .parse()
+            .map_err(|_| crate::SessionError::Malformed)?;
+        let noise = Cipher::Stateless {
+            state: snapshot.transport_state()?,
+            sending_nonce: snapshot.sending_nonce,
+            receiving_nonce: snapshot.receiving_nonce,
+        };
+        let info = HandshakeInfo {
+            params,
+            local_static_key: snapshot.local_static_key,
+            remote_static_key: snapshot.remote_static_key,
+            handshake_hash: snapshot.handshake_hash,
+            session_keys: Some(snapshot.keys),
+        };
+
+        let mut stream = NoiseStream::from_parts(
+            snapshot.name,
+            socket,
+            noise,
+            Some(info),
+            BytesMut::from(&snapshot.read_overflow[..]),
+            config,
+            None,
+        );
+        let unprocessed = &mut stream.unprocessed_buf;
+        unprocessed.buf[..snapshot.unprocessed.len()].copy_from_slice(&snapshot.unprocessed);
+        unprocessed.end = snapshot.unprocessed.len();
+        stream.write_buf.extend_from_slice(&snapshot.unsent);
+        stream.frame_size = snapshot.frame_size;
+        stream.sending_sequence = snapshot.sending_sequence;
+        stream.receiving_sequence = snapshot.receiving_sequence;
+        stream.sent_preamble = snapshot.sent_preamble;
+        stream.peer_framing_version = snapshot.peer_framing_version;
+        stream.peer_frame_size = snapshot.peer_frame_size;
+        stream.sent_close = snapshot.sent_close;
+        stream.received_close_notify = snapshot.received_close_notify;
+        stream.peer_close = snapshot.peer_close;
+        debug!(
+            "[{}] imported session at sending nonce {}",
+            stream.label,
+            stream.noise.sending_nonce()
+        );
+        Ok(stream)
+    }
+}
+
+/// What a completed handshake negotiated, besides the transport keys.
+#[derive(Clone, Debug)]
+pub(crate) struct HandshakeInfo {
+    /// The protocol parameters, whose name is the Noise protocol name.
+    pub(crate) params: NoiseParams,
+    /// Our static public key, if the handshake has one.
+    pub(crate) local_static_key: Option<Vec<u8>>,
+    /// The peer's static public key, for a stream whose transport state doesn't know
+    /// it, having been imported from a snapshot.
+    pub(crate) remote_static_key: Option<Vec<u8>>,
+    /// The handshake hash.
+    pub(crate) handshake_hash: Vec<u8>,
+    /// The transport keys, kept so that the stream can be exported.
+    #[cfg(feature = "session-export")]
+    pub(crate) session_keys: Option<crate::session::SessionKeys>,
+}
+
+/// The outcome of a completed handshake, from which a stream is assembled.
+pub(crate) struct Handshaked<T = snow::TransportState> {
+    /// The handshake state, or the transport state derived from it.
+    pub(crate) state: T,
+    /// What the handshake negotiated.
+    pub(crate) info: HandshakeInfo,
+    /// Cleartext received alongside the final handshake message.
+    pub(crate) read_overflow_buf: BytesMut,
+    /// Whether we sent the final handshake message, and so can't yet know whether the
+    /// peer derived the same keys.
+    pub(crate) sent_last_message: bool,
+}
+
+impl Handshaked<snow::HandshakeState> {
+    #[cfg_attr(not(feature = "session-export"), allow(unused_mut))]
+    fn into_transport_mode(mut self) -> Result<Handshaked, NoiseError> {
+        #[cfg(feature = "session-export")]
+        {
+            self.info.session_keys = Some(crate::session::SessionKeys::split(&mut self.state));
+        }
+        Ok(Handshaked {
+            state: self.state.into_transport_mode()?,
+            info: self.info,
+            read_overflow_buf: self.read_overflow_buf,
+            sent_last_message: self.sent_last_message,
+        })
+    }
+}
+
+/// Escape control characters in a stream name, which is interpolated into log messages.
+pub(crate) fn sanitize_name(name: String) -> String {
+    if !name.chars().any(char::is_control) {
+        return name;
+    }
+    name.chars()
+        .map(|c| match c.is_control() {
+            true => c.escape_default().to_string(),
+            false => c.to_string(),
+        })
+        .collect()
+}
+
+/// Buffers for the messages of a handshake. The ciphertext buffer holds each message
+/// sent or received in turn, and the cleartext buffer holds the payload of the last
+/// message received, from which the next message is built.
+///
+/// Each thread keeps one set of buffers for reuse, so that a server accepting many
+/// connections doesn't allocate them for each handshake.
+struct HandshakeScratch {
+    cipher_buf: [u8; MAX_FRAME_SIZE],
+    clear_buf: [u8; PLAINTEXT_PACKET_SIZE],
+}
+
+thread_local! {
+    static HANDSHAKE_SCRATCH: Cell<Option<Box<HandshakeScratch>>> = const { Cell::new(None) };
+}
+
+impl HandshakeScratch {
+    /// Take this thread's buffers, or allocate new ones if they're in use by another
+    /// handshake. They're returned when the guard is dropped, on whichever thread that
+    /// happens.
+    fn take() -> ScratchGuard {
+        let scratch = HANDSHAKE_SCRATCH.with(Cell::take).unwrap_or_else(|| {
+            Box::new(HandshakeScratch {
+                cipher_buf: [0u8; MAX_FRAME_SIZE],
+                clear_buf: [0u8; PLAINTEXT_PACKET_SIZE],
+            })
+        });
+        ScratchGuard(Some(scratch))
+    }
+}
+
+/// Returns [`HandshakeScratch`] buffers to the thread's cache when dropped.
+struct ScratchGuard(Option<Box<HandshakeScratch>>);
+
+impl std::ops::Deref for ScratchGuard {
+    type Target = HandshakeScratch;
+
+    fn deref(&self) -> &HandshakeScratch {
+        self.0.as_ref().unwrap()
+    }
+}
+
+impl std::ops::DerefMut for ScratchGuard {
+    fn deref_mut(&mut self) -> &mut HandshakeScratch {
+        self.0.as_mut().unwrap()
+    }
+}
+
+impl Drop for ScratchGuard {
+    fn drop(&mut self) {
+        // The previous cleartext may hold secrets, such as a peer's credentials.
+        let mut scratch = self.0.take().unwrap();
+        scratch.clear_buf.fill(0);
+        // The cache may already be gone if the thread is exiting.
+        let _ = HANDSHAKE_SCRATCH.try_with(|cache| cache.set(Some(scratch)));
+    }
+}
+
+/// Read one length-prefixed handshake message into `cipher_buf`, returning its length.
+/// `index` counts messages from zero, and is only used to describe errors.
+async fn read_handshake_message<S: AsyncRead + Unpin>(
+    socket: &mut S,
+    cipher_buf: &mut [u8],
+    index: usize,
+) -> Result<usize, NoiseError> {
+    let truncated = |received, expected| NoiseError::HandshakeTruncated {
+        message: index + 1,
+        received,
+        expected,
+    };
+
+    let mut len_buf = [0u8; HANDSHAKE_LEN_SIZE];
+    let received = read_until_eof(socket, &mut len_buf).await?;
+    if received < HANDSHAKE_LEN_SIZE {
+        return Err(truncated(received, None));
+    }
+    let len = read_u16(&len_buf) as usize;
+    if len > cipher_buf.len() {
+        return Err(NoiseError::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "peer sent a {}-byte handshake message, but at most {} bytes are supported",
+                len,
+                cipher_buf.len()
+            ),
+        )));
+    }
+
+    let received = read_until_eof(socket, &mut cipher_buf[..len]).await?;
+    if received < len {
+        return Err(truncated(received, Some(len)));
+    }
+    Ok(len)
+}
+
+/// Log the exact bytes of a handshake message as they appear on the wire, length prefix
+/// included, for debugging interoperability with other implementations. Handshake
+/// messages carry only public keys and encrypted payloads, unlike transport frames,
+/// which are never logged.
+#[cfg(feature = "handshake-trace")]
+fn trace_handshake_message(
+    label: &LogLabel,
+    direction: &str,
+    index: usize,
+    message_count: usize,
+    message: &[u8],
+) {
+    trace!(
+        "[{}] handshake message {} of {} {}: {:04x}{}",
+        label,
+        index + 1,
+        message_count,
+        direction,
+        message.len(),
+        crate::hex::encode(message)
+    );
+}
+
+/// Fill `buf` from the socket, returning how many bytes were read before EOF, if it
+/// arrived first.
+async fn read_until_eof<S: AsyncRead + Unpin>(
+    socket: &mut S,
+    buf: &mut [u8],
+) -> Result<usize, io::Error> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match socket.read(&mut buf[filled..]).await? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+/// Check that a handshake finished within the messages we exchanged, so that a pattern
+/// which needs more messages than we support fails with a clear error.
+fn ensure_handshake_finished(
+    state: &snow::HandshakeState,
+    handshake: &impl Handshake,
+    messages_exchanged: usize,
+) -> Result<(), NoiseError> {
+    if state.is_handshake_finished() {
+        return Ok(());
+    }
+    Err(NoiseError::HandshakeIncomplete {
+        pattern: handshake.name(),
+        messages_exchanged,
+    })
+}
+
+/// Runs `future` until `deadline`, failing with [`NoiseError::DeadlineExceeded`] if it
+/// isn't done by then. If the deadline has already passed, `future` is never polled.
+pub(crate) async fn with_deadline<T>(
+    deadline: Instant,
+    future: impl Future<Output = Result<T, NoiseError>>,
+) -> Result<T, NoiseError> {
+    if Instant::now() >= deadline {
+        return Err(NoiseError::DeadlineExceeded);
+    }
+    tokio::time::timeout_at(deadline, future)
+        .await
+        .unwrap_or(Err(NoiseError::DeadlineExceeded))
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Drop for NoiseStream<S> {
+    /// Makes a best-effort attempt to close the stream cleanly if it was dropped without
+    /// being shut down, by sending any buffered ciphertext and a close notify, and then
+    /// shutting down the transport. Each step is attempted at most once without waiting,
+    /// so whatever the transport won't accept immediately is abandoned.
+    ///
+    /// Nothing is sent if the stream never sent any data, because the peer may still be
+    /// reading the final handshake message, and would fail to decrypt it if the close
+    /// notify arrived in the same read.
+    fn drop(&mut self) {
+        if !self.sent_preamble || self.sent_close || self.poisoned.is_some() {
+            return;
+        }
+        let mut cx = Context::from_waker(Waker::noop());
+        if self.queue_close_notify().is_err() {
+            return;
+        }
+        match self.poll_drain_write_buf(&mut cx) {
+            Poll::Ready(Ok(())) => {
+                debug!("[{}] sent close notify on drop", self.label);
+                let _ = AsyncWrite::poll_shutdown(Pin::new(&mut self.transport), &mut cx);
+            }
+            _ => debug!(
+                "[{}] dropped with {} bytes of ciphertext unsent",
+                self.label,
+                self.write_buf.len()
+            ),
+        }
+    }
+}
+
+fn closed_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "cannot write to a noise stream after it was closed",
+    )
+}
+
+fn peer_closed_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "cannot write to a noise stream after the peer closed it with an error",
+    )
+}
+
+fn write_u16(buf: &mut [u8], n: u16) {
+    buf.copy_from_slice(&n.to_be_bytes());
+}
+
+pub(crate) fn read_u16(buf: &[u8]) -> u16 {
+    let mut array = [0u8; 2];
+    array.copy_from_slice(buf);
+    u16::from_be_bytes(array)
+}
+
+/// A fixed-size buffer of ciphertext which has been read from the transport but not
+/// yet decrypted. It is allocated once per stream, and filled with large reads so
+/// that many frames can be decrypted per read from the transport.
+struct RecvBuf {
+    buf: Box<[u8]>,
+    start: usize,
+    end: usize,
+}
+
+impl RecvBuf {
+    fn new(capacity: usize) -> RecvBuf {
+        RecvBuf {
+            buf: vec![0u8; capacity].into_boxed_slice(),
+            start: 0,
+            end: 0,
+        }
+    }
+
+    /// The buffered ciphertext.
+    fn data(&self) -> &[u8] {
+        &self.buf[self.start..self.end]
+    }
+
+    fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Drop `n` bytes from the front of the buffer.
+    fn consume(&mut self, n: usize) {
+        assert!(n <= self.len());
+        self.start += n;
+        if self.start == self.end {
+            self.start = 0;
+            self.end = 0;
+        }
+    }
+
+    /// Read as much as fits from `reader` onto the end of the buffer, returning the
+    /// number of bytes read. Zero means EOF.
+    fn poll_fill<R: AsyncRead + Unpin>(
+        &mut self,
+        reader: &mut R,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<usize, io::Error>> {
+        // Move any partial frame back to the front to make room.
+        if self.end == self.buf.len() {
+            self.buf.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+        }
+        let mut read_buf = io::ReadBuf::new(&mut self.buf[self.end..]);
+        match AsyncRead::poll_read(Pin::new(reader), cx, &mut read_buf) {
+            Poll::Ready(Ok(())) => {
+                let n = read_buf.filled().len();
+                self.end += n;
+                Poll::Ready(Ok(n))
+            }
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::DEFAULT_MAX_FRAMES_PER_POLL;
+    use crate::tcp::NoiseTcpStream;
+    use http_body_util::BodyExt;
+    use hyper::{Request, Response};
+    use hyper_util::rt::TokioIo;
+    use std::{future::Future, time::Duration};
+    use tokio::{
+        net::{TcpListener, TcpStream},
+        task::spawn,
+    };
+
+    async fn run_client_server_test<T1, T2, F1, F2>(server_run: T1, client_run: T2)
+    where
+        T1: Send + 'static + FnOnce(NoiseTcpStream) -> F1,
+        T2: Send + 'static + FnOnce(NoiseTcpStream) -> F2,
+        F1: Send + Future<Output = ()>,
+        F2: Send + Future<Output = ()>,
+    {
+        let psk = [10u8; 32];
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let srv = spawn(async move {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            let noise_stream = NoiseTcpStream::handshake_responder_psk0(tcp_stream, &psk)
+                .await
+                .expect("noise handshake failed on server side");
+
+            server_run(noise_stream).await;
+        });
+
+        let tcp_stream = TcpStream::connect(&addr).await.unwrap();
+        let noise_stream = NoiseTcpStream::handshake_initiator_psk0(tcp_stream, &psk)
+            .await
+            .expect("noise handshake failed on client side");
+
+        client_run(noise_stream).await;
+
+        srv.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn send_and_recv_small() {
+        let server_run = |mut noise_stream: NoiseTcpStream| async move {
+            let mut ok_buf = [0u8; 2];
+            let n = noise_stream
+                .recv(&mut ok_buf)
+                .await
+                .expect("server failed to receive OK");
+
+            assert_eq!(n, ok_buf.len());
+            assert_eq!(&ok_buf, b"OK");
+
+            noise_stream
+                .send(&ok_buf)
+                .await
+                .expect("server failed to reply OK");
+        };
+
+        let client_run = |mut noise_stream: NoiseTcpStream| async move {
+            noise_stream
+                .send(b"OK")
+                .await
+                .expect("client failed to send OK");
+
+            let mut ok_buf = [0u8; 2];
+            let n = noise_stream
+                .recv(&mut ok_buf)
+                .await
+                .expect("client failed to receive OK");
+
+            assert_eq!(n, ok_buf.len());
+            assert_eq!(&ok_buf, b"OK");
+        };
+
+        run_client_server_test(server_run, client_run).await;
+    }
+
+    #[tokio::test]
+    async fn recv_yields_after_max_frames_per_poll() {
+        const N_FRAMES: usize = DEFAULT_MAX_FRAMES_PER_POLL * 2 + 1;
+
+        let server_run = |mut noise_stream: NoiseTcpStream| async move {
+            noise_stream
+                .recv(&mut [0u8; 1])
+                .await
+                .expect("server failed to wait for client");
+            for _ in 0..N_FRAMES {
+                noise_stream
+                    .send(b"frame")
+                    .await
+                    .expect("server failed to send frame");
+            }
+            noise_stream
+                .recv(&mut [0u8; 1])
+                .await
+                .expect("server failed to wait for client");
+        };
+
+        let client_run = |mut noise_stream: NoiseTcpStream| async move {
+            noise_stream
+                .send(b"?")
+                .await
+                .expect("client failed to request frames");
+
+            // Give every frame time to arrive in the socket's receive buffer.
+            tokio::time::sleep(Duration::from_millis(100)).await;
+
+            let mut buf = [0u8; 4096];
+            for expected_frames in [DEFAULT_MAX_FRAMES_PER_POLL, DEFAULT_MAX_FRAMES_PER_POLL, 1] {
+                let n = noise_stream
+                    .recv(&mut buf)
+                    .await
+                    .expect("client failed to receive frames");
+                assert_eq!(n, expected_frames * b"frame".len());
+            }
+
+            noise_stream
+                .send(b"!")
+                .await
+                .expect("client failed to reply");
+        };
+
+        run_client_server_test(server_run, client_run).await;
+    }
+
+    #[tokio::test]
+    async fn recv_returns_overflow_without_waiting_for_transport() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (client, server) = tokio::join!(
+            NoiseStream::handshake_initiator_psk0(client, &[10u8; 32]),
+            NoiseStream::handshake_responder_psk0(server, &[10u8; 32]),
+        );
+        let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+        client.send(b"hello world").await.unwrap();
+
+        let mut buf = [0u8; 64];
+        let n = server.recv(&mut buf[..5]).await.unwrap();
+        assert_eq!(&buf[..n], b"hello");
+
+        // The rest of the frame is already decrypted, so it must be returned even
+        // though nothing more is coming from the transport.
+        let n = tokio::time::timeout(Duration::from_secs(1), server.recv(&mut buf))
+            .await
+            .expect("recv blocked on the transport")
+            .unwrap();
+        assert_eq!(&buf[..n], b" world");
+        assert_eq!(server.peer_framing_version(), Some(FRAMING_VERSION));
+    }
+
+    #[tokio::test]
+    async fn read_returns_every_arrived_frame_without_waiting_for_more() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (client, server) = tokio::join!(
+            NoiseStream::handshake_initiator_psk0(client, &[10u8; 32]),
+            NoiseStream::handshake_responder_psk0(server, &[10u8; 32]),
+        );
+        let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+        // Two frames arrive, then the peer pauses with the transport still open.
+        client.send(b"first").await.unwrap();
+        client.send(b"second").await.unwrap();
+
+        // A single poll with room for far more than two frames delivers both, and
+        // returns rather than waiting for a third.
+        let mut buf = [0u8; 4 * MAX_FRAME_SIZE];
+        let mut read_buf = io::ReadBuf::new(&mut buf);
+        let poll = std::future::poll_fn(|cx| {
+            Poll::Ready(Pin::new(&mut server).poll_read(cx, &mut read_buf))
+        })
+        .await;
+        assert!(matches!(poll, Poll::Ready(Ok(()))), "{:?}", poll);
+        assert_eq!(read_buf.filled(), b"firstsecond");
+
+        // With nothing buffered, the next read waits on the transport.
+        let poll = std::future::poll_fn(|cx| {
+            Poll::Ready(Pin::new(&mut server).poll_read(cx, &mut io::ReadBuf::new(&mut buf)))
+        })
+        .await;
+        assert!(poll.is_pending());
+    }
+
+    /// Completes a handshake with a `NoiseStream` responder over a duplex pipe, returning
+    /// the initiator's raw transport state, so that tests can send hand-crafted packets.
+    async fn raw_initiator_pair() -> (
+        tokio::io::DuplexStream,
+        snow::TransportState,
+        NoiseStream<tokio::io::DuplexStream>,
+    ) {
+        let psk = [10u8; 32];
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let log_context = LogContext::new();
+        let (initiator, server) = tokio::join!(
+            NoiseStream::run_initiator(
+                &mut client,
+                NNpsk0::try_new(&psk).unwrap(),
+                None,
+                &log_context
+            ),
+            NoiseStream::handshake_responder_psk0(server, &psk),
+        );
+        (client, initiator.unwrap().state, server.unwrap())
+    }
+
+    /// Encrypts a packet of `packet_size` bytes with the given plaintext header and
+    /// payload, and writes it to `pipe`.
+    async fn send_raw_packet(
+        pipe: &mut tokio::io::DuplexStream,
+        noise: &mut snow::TransportState,
+        packet_size: usize,
+        header: &[u8],
+        payload: &[u8],
+    ) {
+        let mut plaintext = vec![0u8; packet_size - CIPHERTEXT_TAG_SIZE];
+        plaintext[..header.len()].copy_from_slice(header);
+        plaintext[header.len()..][..payload.len()].copy_from_slice(payload);
+        let mut ciphertext = [0u8; MAX_FRAME_SIZE];
+        let n = noise.write_message(&plaintext, &mut ciphertext).unwrap();
+        pipe.write_all(&ciphertext[..n]).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn recv_rejects_different_framing_version() {
+        let (mut pipe, mut noise, mut server) = raw_initiator_pair().await;
+
+        let preamble_header = [PacketKind::Preamble as u8, 0, 3];
+        send_raw_packet(
+            &mut pipe,
+            &mut noise,
+            PREAMBLE_PACKET_SIZE,
+            &preamble_header,
+            &[FRAMING_VERSION + 1, 8, 0],
+        )
+        .await;
+
+        match server.recv(&mut [0u8; 64]).await {
+            Err(NoiseError::FramingVersionMismatch {
+                local: FRAMING_VERSION,
+                remote: Some(remote),
+            }) => assert_eq!(remote, FRAMING_VERSION + 1),
+            result => panic!("expected framing version mismatch, got {:?}", result),
+        }
+        assert!(server.is_poisoned());
+        assert!(matches!(
+            server.send(b"hello").await,
+            Err(NoiseError::FramingVersionMismatch { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn recv_rejects_frame_longer_than_its_packet() {
+        #[derive(Default)]
+        struct Errors(std::sync::Mutex<Vec<String>>);
+        impl crate::ConnectionEvents for Errors {
+            fn on_error(&self, _stream: &str, error: &NoiseError) {
+                self.0.lock().unwrap().push(error.to_string());
+            }
+        }
+
+        let (mut pipe, mut noise, mut server) = raw_initiator_pair().await;
+        let errors = std::sync::Arc::new(Errors::default());
+        server.config.events = Some(EventsHook(errors.clone()));
+
+        let preamble_header = [PacketKind::Preamble as u8, 0, 3];
+        let preamble = [
+            FRAMING_VERSION,
+            (MAX_FRAME_SIZE >> 8) as u8,
+            MAX_FRAME_SIZE as u8,
+        ];
+        send_raw_packet(
+            &mut pipe,
+            &mut noise,
+            PREAMBLE_PACKET_SIZE,
+            &preamble_header,
+            &preamble,
+        )
+        .await;
+        let max = MAX_FRAME_SIZE - FRAME_OVERHEAD;
+        let len = max as u16 + 1;
+        let data_header = [PacketKind::Data as u8, (len >> 8) as u8, len as u8];
+        send_raw_packet(&mut pipe, &mut noise, MAX_FRAME_SIZE, &data_header, &[]).await;
+
+        let message = format!(
+            "Noise frame specifies plaintext length={}; exceeds maximum of {}",
+            len, max
+        );
+        match server.recv(&mut [0u8; 64]).await {
+            Err(e @ NoiseError::FrameLengthExceeded { .. }) => {
+                assert_eq!(e.kind(), crate::NoiseErrorKind::Protocol);
+                assert!(!e.is_transient());
+                assert_eq!(e.to_string(), message);
+            }
+            result => panic!("expected an invalid length error, got {:?}", result),
+        }
+        assert!(server.is_poisoned());
+        assert!(server.send(b"hello").await.is_err());
+        let errors = errors.0.lock().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].ends_with(&message), "{}", errors[0]);
+    }
+
+    #[tokio::test]
+    async fn padding_is_zeroed_between_packets() {
+        let (mut pipe, mut noise, mut server) = raw_initiator_pair().await;
+
+        server.send(&[0xFF; 100]).await.unwrap();
+        server.send(b"hi").await.unwrap();
+
+        let mut ciphertext = [0u8; MAX_FRAME_SIZE];
+        let mut plaintext = [0u8; PLAINTEXT_PACKET_SIZE];
+        pipe.read_exact(&mut ciphertext[..PREAMBLE_PACKET_SIZE])
+            .await
+            .unwrap();
+        noise
+            .read_message(&ciphertext[..PREAMBLE_PACKET_SIZE], &mut plaintext)
+            .unwrap();
+
+        for payload in [&[0xFF; 100][..], b"hi"] {
+            pipe.read_exact(&mut ciphertext).await.unwrap();
+            let n = noise.read_message(&ciphertext, &mut plaintext).unwrap();
+            assert_eq!(n, PLAINTEXT_PACKET_SIZE);
+            let body = &plaintext[PLAINTEXT_HEADER_SIZE..n];
+            let (data, padding) = body.split_at(payload.len());
+            assert_eq!(data, payload);
+            assert!(padding.iter().all(|&b| b == 0));
+        }
+    }
+
+    #[tokio::test]
+    async fn recv_rejects_peer_without_preamble() {
+        let (mut pipe, mut noise, mut server) = raw_initiator_pair().await;
+
+        let data_header = [PacketKind::Data as u8, 0, 5];
+        send_raw_packet(
+            &mut pipe,
+            &mut noise,
+            PREAMBLE_PACKET_SIZE,
+            &data_header,
+            b"hello",
+        )
+        .await;
+
+        match server.recv(&mut [0u8; 64]).await {
+            Err(NoiseError::FramingVersionMismatch {
+                local: FRAMING_VERSION,
+                remote: None,
+            }) => {}
+            result => panic!("expected framing version mismatch, got {:?}", result),
+        }
+        assert_eq!(server.peer_framing_version(), None);
+    }
+
+    #[tokio::test]
+    async fn recv_rejects_unsupported_frame_size() {
+        let (mut pipe, mut noise, mut server) = raw_initiator_pair().await;
+
+        let preamble_header = [PacketKind::Preamble as u8, 0, 3];
+        let mut preamble = [FRAMING_VERSION, 0, 0];
+        write_u16(&mut preamble[1..], (MAX_FRAME_SIZE + 1) as u16);
+        send_raw_packet(
+            &mut pipe,
+            &mut noise,
+            PREAMBLE_PACKET_SIZE,
+            &preamble_header,
+            &preamble,
+        )
+        .await;
+
+        match server.recv(&mut [0u8; 64]).await {
+            Err(NoiseError::UnsupportedFrameSize { size }) => {
+                assert_eq!(size, MAX_FRAME_SIZE + 1)
+            }
+            result => panic!("expected unsupported frame size, got {:?}", result),
+        }
+        assert!(server.is_poisoned());
+        assert_eq!(server.peer_frame_size(), None);
+    }
+
+    #[tokio::test]
+    async fn recv_returns_buffered_data_before_eof() {
+        const SIZE: usize = 5000;
+
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (client, server) = tokio::join!(
+            NoiseStream::handshake_initiator_psk0(client, &[10u8; 32]),
+            NoiseStream::handshake_responder_psk0(server, &[10u8; 32]),
+        );
+        let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+        // The peer closes with several frames still unread.
+        client.send(&[0xAB; SIZE]).await.unwrap();
+        client.shutdown().await.unwrap();
+        drop(client);
+
+        let mut buf = [0u8; 100];
+        let mut total = 0;
+        loop {
+            let n = server.recv(&mut buf).await.unwrap();
+            if n == 0 {
+                break;
+            }
+            assert!(buf[..n].iter().all(|&b| b == 0xAB));
+            total += n;
+        }
+        assert_eq!(total, SIZE);
+        assert_eq!(server.unprocessed_ciphertext_len(), 0);
+
+        // EOF is reported consistently once drained.
+        assert_eq!(server.recv(&mut buf).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn recv_into_empty_buffer_returns_zero() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (client, server) = tokio::join!(
+            NoiseStream::handshake_initiator_psk0(client, &[10u8; 32]),
+            NoiseStream::handshake_responder_psk0(server, &[10u8; 32]),
+        );
+        let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+        client.send(b"hello").await.unwrap();
+        assert_eq!(server.recv(&mut []).await.unwrap(), 0);
+
+        // Nothing was consumed by the empty read.
+        let mut buf = [0u8; 64];
+        let n = server.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"hello");
+    }
+
+    #[tokio::test]
+    async fn recv_rejects_frame_truncated_by_eof() {
+        let (mut pipe, mut noise, mut server) = raw_initiator_pair().await;
+
+        let preamble_header = [PacketKind::Preamble as u8, 0, 3];
+        let mut preamble = [FRAMING_VERSION, 0, 0];
+        write_u16(&mut preamble[1..], MAX_FRAME_SIZE as u16);
+        send_raw_packet(
+            &mut pipe,
+            &mut noise,
+            PREAMBLE_PACKET_SIZE,
+            &preamble_header,
+            &preamble,
+        )
+        .await;
+        pipe.write_all(&[0u8; 100]).await.unwrap();
+        drop(pipe);
+
+        match server.recv(&mut [0u8; 64]).await {
+            Err(NoiseError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+            result => panic!("expected unexpected EOF, got {:?}", result),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn deadline_in_past_fails_without_io() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (client, server) = tokio::join!(
+            NoiseStream::handshake_initiator_psk0(client, &[10u8; 32]),
+            NoiseStream::handshake_responder_psk0(server, &[10u8; 32]),
+        );
+        let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+        client.send(b"hello").await.unwrap();
+        let past = Instant::now();
+        tokio::time::advance(Duration::from_secs(1)).await;
+
+        let mut buf = [0u8; 64];
+        assert!(matches!(
+            server.recv_deadline(&mut buf, past).await,
+            Err(NoiseError::DeadlineExceeded)
+        ));
+        assert!(matches!(
+            client.send_deadline(b"world", past).await,
+            Err(NoiseError::DeadlineExceeded)
+        ));
+        assert_eq!(client.stats().plaintext_bytes_written, 5);
+
+        // The data sent earlier is still there to be read.
+        let n = server.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"hello");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn deadline_expires_mid_frame() {
+        const TIMEOUT: Duration = Duration::from_secs(1);
+
+        let (mut pipe, mut noise, mut server) = raw_initiator_pair().await;
+
+        let preamble_header = [PacketKind::Preamble as u8, 0, 3];
+        let mut preamble = [FRAMING_VERSION, 0, 0];
+        write_u16(&mut preamble[1..], MAX_FRAME_SIZE as u16);
+        send_raw_packet(
+            &mut pipe,
+            &mut noise,
+            PREAMBLE_PACKET_SIZE,
+            &preamble_header,
+            &preamble,
+        )
+        .await;
+
+        let mut plaintext = [0u8; PLAINTEXT_PACKET_SIZE];
+        plaintext[..PLAINTEXT_HEADER_SIZE].copy_from_slice(&[PacketKind::Data as u8, 0, 5]);
+        plaintext[PLAINTEXT_HEADER_SIZE..][..5].copy_from_slice(b"hello");
+        let mut ciphertext = [0u8; MAX_FRAME_SIZE];
+        noise.write_message(&plaintext, &mut ciphertext).unwrap();
+        pipe.write_all(&ciphertext[..1000]).await.unwrap();
+
+        let start = Instant::now();
+        let mut buf = [0u8; 64];
+        assert!(matches!(
+            server.recv_deadline(&mut buf, start + TIMEOUT).await,
+            Err(NoiseError::DeadlineExceeded)
+        ));
+        assert_eq!(start.elapsed(), TIMEOUT);
+
+        // The partial frame was kept, so the frame decrypts once the rest arrives.
+        pipe.write_all(&ciphertext[1000..]).await.unwrap();
+        let n = server
+            .recv_deadline(&mut buf, Instant::now() + TIMEOUT)
+            .await
+            .unwrap();
+        assert_eq!(&buf[..n], b"hello");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn deadline_is_met() {
+        const DELAY: Duration = Duration::from_millis(500);
+
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (client, server) = tokio::join!(
+            NoiseStream::handshake_initiator_psk0(client, &[10u8; 32]),
+            NoiseStream::handshake_responder_psk0(server, &[10u8; 32]),
+        );
+        let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+        let deadline = Instant::now() + DELAY * 2;
+        let send = async {
+            tokio::time::sleep(DELAY).await;
+            client.send_deadline(b"hello", deadline).await
+        };
+        let mut buf = [0u8; 64];
+        let (sent, received) = tokio::join!(send, server.recv_deadline(&mut buf, deadline));
+        sent.unwrap();
+        assert_eq!(&buf[..received.unwrap()], b"hello");
+    }
+
+    #[test]
+    fn unfinished_handshake_is_reported() {
+        let handshake = NNpsk0::try_new(&[10u8; 32]).unwrap();
+        let initiator = handshake.new_builder().build_initiator().unwrap();
+
+        match ensure_handshake_finished(&initiator, &handshake, 4) {
+            Err(NoiseError::HandshakeIncomplete {
+                pattern,
+                messages_exchanged,
+            }) => {
+                assert_eq!(pattern, handshake.name());
+                assert_eq!(messages_exchanged, 4);
+            }
+            result => panic!("expected incomplete handshake, got {:?}", result),
+        }
+    }
+
+    /// Wraps an `NNpsk0` handshake, misreporting its message count.
+    struct MiscountedHandshake(NNpsk0, usize);
+
+    impl Handshake for MiscountedHandshake {
+        fn name(&self) -> String {
+            self.0.name()
+        }
+        fn new_builder(&self) -> snow::Builder<'_> {
+            self.0.new_builder()
+        }
+        fn message_count(&self) -> usize {
+            self.1
+        }
+    }
+
+    #[tokio::test]
+    async fn handshake_stops_after_message_count() {
+        let (mut client, _server) = tokio::io::duplex(64 * 1024);
+        let handshake = MiscountedHandshake(NNpsk0::try_new(&[10u8; 32]).unwrap(), 1);
+
+        match NoiseStream::run_initiator(&mut client, handshake, None, &LogContext::new()).await {
+            Err(NoiseError::HandshakeIncomplete {
+                messages_exchanged, ..
+            }) => assert_eq!(messages_exchanged, 1),
+            result => panic!("expected incomplete handshake, got {:?}", result.err()),
+        }
+    }
+
+    #[tokio::test]
+    async fn handshake_rejects_too_many_messages() {
+        let (mut client, _server) = tokio::io::duplex(64 * 1024);
+        let handshake = MiscountedHandshake(NNpsk0::try_new(&[10u8; 32]).unwrap(), 5);
+
+        match NoiseStream::run_initiator(&mut client, handshake, None, &LogContext::new()).await {
+            Err(NoiseError::Handshake(e)) => {
+                assert_eq!(
+                    e.handshake_pattern,
+                    NNpsk0::try_new(&[10u8; 32]).unwrap().name()
+                )
+            }
+            result => panic!("expected handshake error, got {:?}", result.err()),
+        }
+    }
+
+    #[tokio::test]
+    async fn stats_count_socket_and_plaintext_bytes() {
+        const SIZE: usize = 5000;
+
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (client, server) = tokio::join!(
+            NoiseStream::handshake_initiator_psk0(client, &[10u8; 32]),
+            NoiseStream::handshake_responder_psk0(server, &[10u8; 32]),
+        );
+        let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+        client.send(&[0xFF; SIZE]).await.unwrap();
+        let mut buf = [0u8; SIZE];
+        server.read_exact(&mut buf).await.unwrap();
+
+        // A preamble packet, then the data split across three packets.
+        let expected_socket_bytes = (PREAMBLE_PACKET_SIZE + 3 * MAX_FRAME_SIZE) as u64;
+
+        let client_stats = client.stats();
+        assert_eq!(client_stats.plaintext_bytes_written, SIZE as u64);
+        assert_eq!(client_stats.socket_bytes_written, expected_socket_bytes);
+        assert_eq!(
+            client_stats.write_overhead_ratio(),
+            Some(expected_socket_bytes as f64 / SIZE as f64)
+        );
+        assert_eq!(client_stats.read_overhead_ratio(), None);
+
+        let server_stats = server.stats();
+        assert_eq!(server_stats.plaintext_bytes_read, SIZE as u64);
+        assert_eq!(server_stats.socket_bytes_read, expected_socket_bytes);
+        assert_eq!(server_stats.socket_bytes_written, 0);
+    }
+
+    #[tokio::test]
+    async fn peer_follows_skipped_sending_nonce() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (client, server) = tokio::join!(
+            NoiseStream::handshake_initiator_psk0(client, &[10u8; 32]),
+            NoiseStream::handshake_responder_psk0(server, &[10u8; 32]),
+        );
+        let (mut client, mut server) = (client.unwrap(), server.unwrap());
+        let mut buf = [0u8; 16];
+
+        client.send(b"one").await.unwrap();
+        let nonce = client.sending_nonce();
+        client.skip_sending_nonce(nonce + NONCE_JUMP_LIMIT).unwrap();
+        assert_eq!(client.sending_nonce(), nonce + NONCE_JUMP_LIMIT);
+        client.send(b"two").await.unwrap();
+        // Skipping to the current nonce is a no-op.
+        client.skip_sending_nonce(client.sending_nonce()).unwrap();
+        client.send(b"three").await.unwrap();
+
+        let mut received = Vec::new();
+        while received.len() < b"onetwothree".len() {
+            let n = server.recv(&mut buf).await.unwrap();
+            received.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(received, b"onetwothree");
+        assert_eq!(server.stats().consecutive_decrypt_failures, 0);
+
+        let nonce = client.sending_nonce();
+        for to in [nonce - 1, nonce + NONCE_JUMP_LIMIT + 1] {
+            match client.skip_sending_nonce(to) {
+                Err(NoiseError::InvalidNonceSkip { from, to: got }) => {
+                    assert_eq!((from, got), (nonce, to))
+                }
+                result => panic!("expected the skip to {} to fail, got {:?}", to, result),
+            }
+        }
+        assert_eq!(client.sending_nonce(), nonce);
+    }
+
+    #[tokio::test]
+    async fn strict_nonce_rejects_skipped_nonce() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let builder = NoiseBuilder::new().strict_nonce(true);
+        let (client, server) = tokio::join!(
+            NoiseStream::handshake_initiator_psk0(client, &[10u8; 32]),
+            builder.handshake_responder(
+                server,
+                crate::handshakes::NNpsk0::try_new(&[10u8; 32]).unwrap()
+            ),
+        );
+        let (mut client, mut server) = (client.unwrap(), server.unwrap());
+        let mut buf = [0u8; 16];
+
+        client.send(b"one").await.unwrap();
+        let nonce = client.sending_nonce();
+        client.skip_sending_nonce(nonce + 3).unwrap();
+        client.send(b"two").await.unwrap();
+
+        // Data from before the skip is still delivered.
+        let n = server.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"one");
+        match server.recv(&mut buf).await {
+            Err(NoiseError::NonceMismatch { expected, got }) => {
+                assert_eq!((expected, got), (nonce, nonce + 3))
+            }
+            result => panic!("expected a nonce mismatch, got {:?}", result),
+        }
+        assert!(server.is_poisoned());
+    }
+
+    #[cfg(feature = "session-export")]
+    #[tokio::test]
+    async fn imported_session_resumes_at_a_distant_nonce() {
+        const NONCE: u64 = 1 << 40;
+
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (client, server) = tokio::join!(
+            NoiseStream::handshake_initiator_psk0(client, &[10u8; 32]),
+            NoiseStream::handshake_responder_psk0(server, &[10u8; 32]),
+        );
+        let (mut client, mut server) = (client.unwrap(), server.unwrap());
+        let mut buf = [0u8; 16];
+        client.send(b"hello").await.unwrap();
+        server.recv(&mut buf).await.unwrap();
+        server.send(b"hi").await.unwrap();
+        client.recv(&mut buf).await.unwrap();
+
+        // As if the client had sent a great many frames before the handoff.
+        let client_snapshot = crate::SessionSnapshot {
+            sending_nonce: NONCE,
+            ..client.export_session().unwrap()
+        };
+        let server_snapshot = crate::SessionSnapshot {
+            receiving_nonce: NONCE,
+            ..server.export_session().unwrap()
+        };
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let mut client = NoiseStream::import_session(client, client_snapshot.clone()).unwrap();
+        let mut server = NoiseStream::import_session(server, server_snapshot).unwrap();
+        assert_eq!(client.sending_nonce(), NONCE);
+
+        client.send(b"resumed").await.unwrap();
+        let n = server.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"resumed");
+        assert_eq!(client.sending_nonce(), NONCE + 1);
+        server.send(b"ok").await.unwrap();
+        let n = client.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"ok");
+
+        // A snapshot at the last nonce imports, but can send nothing more.
+        let snapshot = crate::SessionSnapshot {
+            sending_nonce: u64::MAX,
+            ..client_snapshot
+        };
+        let (transport, _) = tokio::io::duplex(64);
+        let mut client = NoiseStream::import_session(transport, snapshot).unwrap();
+        let e = client.send(b"hello").await.unwrap_err();
+        assert_eq!(e.kind(), crate::NoiseErrorKind::NonceExhausted);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_duration_counts_from_last_data() {
+        const IDLE: Duration = Duration::from_secs(30);
+
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (client, server) = tokio::join!(
+            NoiseStream::handshake_initiator_psk0(client, &[10u8; 32]),
+            NoiseStream::handshake_responder_psk0(server, &[10u8; 32]),
+        );
+        let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+        tokio::time::advance(IDLE).await;
+        assert_eq!(client.idle_duration(), IDLE);
+        client.send(b"hello").await.unwrap();
+        assert_eq!(client.idle_duration(), Duration::ZERO);
+        assert_eq!(server.idle_duration(), IDLE);
+
+        tokio::time::advance(IDLE).await;
+        let mut buf = [0u8; 16];
+        server.recv(&mut buf).await.unwrap();
+        assert_eq!(server.idle_duration(), Duration::ZERO);
+
+        // The close notify isn't application data.
+        tokio::time::advance(IDLE).await;
+        client.shutdown().await.unwrap();
+        assert_eq!(server.recv(&mut buf).await.unwrap(), 0);
+        assert_eq!(client.idle_duration(), 2 * IDLE);
+        assert_eq!(server.idle_duration(), IDLE);
+    }
+
+    #[tokio::test]
+    async fn zero_capacity_read_does_not_touch_transport() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (client, server) = tokio::join!(
+            NoiseStream::handshake_initiator_psk0(client, &[10u8; 32]),
+            NoiseStream::handshake_responder_psk0(server, &[10u8; 32]),
+        );
+        let (mut client, mut server) = (client.unwrap(), server.unwrap());
+        client.send(b"hello").await.unwrap();
+
+        assert_eq!(server.read(&mut []).await.unwrap(), 0);
+        assert_eq!(server.stats().socket_bytes_read, 0);
+        assert_eq!(server.noise.receiving_nonce(), 0);
+
+        // Nor does it disturb cleartext already buffered.
+        let mut buf = [0u8; 2];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(server.read(&mut []).await.unwrap(), 0);
+        let mut buf = [0u8; 3];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"llo");
+    }
+
+    #[test]
+    fn names_are_sanitized() {
+        assert_eq!(sanitize_name("alice@10.0.0.1".into()), "alice@10.0.0.1");
+        assert_eq!(
+            sanitize_name("bob\n[responder] fake line\x1b[0m".into()),
+            "bob\\n[responder] fake line\\u{1b}[0m"
+        );
+    }
+
+    #[tokio::test]
+    async fn set_name_renames_stream() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (client, _server) = tokio::join!(
+            NoiseStream::handshake_initiator_psk0(client, &[10u8; 32]),
+            NoiseStream::handshake_responder_psk0(server, &[10u8; 32]),
+        );
+        let mut client = client.unwrap();
+        assert_eq!(client.name(), "initiator");
+
+        client.set_name("tenant-a");
+        assert_eq!(client.name(), "tenant-a");
+        client.set_name(String::from("tenant\r\nb"));
+        assert_eq!(client.name(), "tenant\\r\\nb");
+    }
+
+    #[test]
+    fn handshake_scratch_is_reused_and_cleared() {
+        let mut first = HandshakeScratch::take();
+        first.clear_buf[0] = 0xFF;
+        let first_ptr: *const HandshakeScratch = &*first;
+
+        // A concurrent handshake on the same thread gets its own buffers.
+        let second = HandshakeScratch::take();
+        assert_ne!(first_ptr, &*second as *const HandshakeScratch);
+        drop(second);
+        drop(first);
+
+        let reused = HandshakeScratch::take();
+        assert_eq!(first_ptr, &*reused as *const HandshakeScratch);
+        assert_eq!(reused.clear_buf[0], 0);
+    }
+
+    #[tokio::test]
+    async fn drop_sends_close_notify() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (client, server) = tokio::join!(
+            NoiseStream::handshake_initiator_psk0(client, &[10u8; 32]),
+            NoiseStream::handshake_responder_psk0(server, &[10u8; 32]),
+        );
+        let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+        client.send(b"goodbye").await.unwrap();
+        drop(client);
+
+        let mut buf = [0u8; 64];
+        let n = server.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"goodbye");
+        assert_eq!(server.recv(&mut buf).await.unwrap(), 0);
+        assert!(server.received_close_notify);
+        assert_eq!(server.closed_reason(), None);
+
+        // The peer is gone, so the server's close notify can't be delivered.
+        drop(server);
+    }
+
+    #[tokio::test]
+    async fn write_after_shutdown_fails() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (client, server) = tokio::join!(
+            NoiseStream::handshake_initiator_psk0(client, &[10u8; 32]),
+            NoiseStream::handshake_responder_psk0(server, &[10u8; 32]),
+        );
+        let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+        client.shutdown().await.unwrap();
+        match client.send(b"too late").await {
+            Err(NoiseError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::BrokenPipe),
+            result => panic!("expected broken pipe, got {:?}", result),
+        }
+
+        let mut buf = [0u8; 64];
+        assert_eq!(server.recv(&mut buf).await.unwrap(), 0);
+        assert!(server.received_close_notify);
+
+        // The client's close is only a half-close, so the server can still write.
+        server.send(b"reply").await.unwrap();
+        let n = client.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"reply");
+    }
+
+    #[tokio::test]
+    async fn write_after_peer_error_close_fails() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (client, server) = tokio::join!(
+            NoiseStream::handshake_initiator_psk0(client, &[10u8; 32]),
+            NoiseStream::handshake_responder_psk0(server, &[10u8; 32]),
+        );
+        let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+        client.close_with_error(7, "go away").await.unwrap();
+        let mut buf = [0u8; 64];
+        assert!(matches!(
+            server.recv(&mut buf).await,
+            Err(NoiseError::ClosedByPeer { code: 7, .. })
+        ));
+
+        // write_all gives up at once, rather than writing to a peer which has gone.
+        let error = server.write_all(&[0u8; 100_000]).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(server.stats().plaintext_bytes_written, 0);
+    }
+
+    #[test]
+    fn drop_outside_runtime_does_not_panic() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let (client, server) = runtime.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let (client, server) = tokio::join!(
+                async {
+                    let tcp_stream = TcpStream::connect(addr).await.unwrap();
+                    NoiseTcpStream::handshake_initiator_psk0(tcp_stream, &[10u8; 32]).await
+                },
+                async {
+                    let (tcp_stream, _) = listener.accept().await.unwrap();
+                    NoiseTcpStream::handshake_responder_psk0(tcp_stream, &[10u8; 32]).await
+                },
+            );
+            (client.unwrap(), server.unwrap())
+        });
+        drop(runtime);
+        drop(client);
+        drop(server);
+    }
+
+    #[tokio::test]
+    async fn send_and_recv_large() {
+        const BIG_SIZE: usize = 200_000;
+
+        let server_run = |mut noise_stream: NoiseTcpStream| async move {
+            let mut n = 0;
+            let mut big_buf = [0u8; BIG_SIZE];
+            while n < big_buf.len() {
+                n += noise_stream
+                    .recv(&mut big_buf[n..])
+                    .await
+                    .expect("server failed to receive big chunk of data");
+            }
+
+            assert_eq!(n, BIG_SIZE);
+            assert_eq!(big_buf, [0xFF; BIG_SIZE]);
+
+            noise_stream
+                .send(&big_buf)
+                .await
+                .expect("server failed to reply with big chunk of data");
+        };
+
+        let client_run = |mut noise_stream: NoiseTcpStream| async move {
+            let mut big_buf = [0xFF; BIG_SIZE];
+            noise_stream
+                .send(&big_buf)
+                .await
+                .expect("client failed to send big chunk of data");
+
+            let mut n = 0;
+            while n < big_buf.len() {
+                n += noise_stream
+                    .recv(&mut big_buf[n..])
+                    .await
+                    .expect("client failed to receive big chunk of data");
+            }
+
+            assert_eq!(n, BIG_SIZE);
+            assert_eq!(big_buf, [0xFF; BIG_SIZE]);
+        };
+
+        run_client_server_test(server_run, client_run).await;
+    }
+
+    #[tokio::test]
+    async fn http1_get() {
+        let server_run = |noise_stream: NoiseTcpStream| async move {
+            async fn service_fn(
+                _req: Request<hyper::body::Incoming>,
+            ) -> Result<Response<String>, hyper::Error> {
+                let resp = Response::new("Hello world!".to_string());
+                Ok(resp)
+            }
+
+            hyper::server::conn::http1::Builder::new()
+                .serve_connection(
+                    TokioIo::new(noise_stream),
+                    hyper::service::service_fn(service_fn),
+                )
+                .await
+                .expect("error serving HTTP1 GET request");
+        };
+
+        let client_run = |noise_stream: NoiseTcpStream| async move {
+            let (mut sender, conn) =
+                hyper::client::conn::http1::handshake(TokioIo::new(noise_stream))
+                    .await
+                    .expect("client failed to run HTTP1 handshake");
+
+            // Spawn a task to poll the connection, driving the HTTP state
+            let driver = spawn(async move {
+                conn.await.expect("client connection driver failed");
+            });
+
+            // Create an HTTP request with an empty body
+            let req = Request::builder().body("".to_string()).unwrap();
+
+            let res = sender
+                .send_request(req)
+                .await
+                .expect("client failed to send HTTP1 GET request");
+
+            assert_eq!(res.status(), 200);
+
+            let response_bytes = res
+                .collect()
+                .await
+                .expect("client error reading response body")
+                .to_bytes();
+
+            // Close the connection
+            drop(sender);
+            driver.await.unwrap();
+
+            assert_eq!(response_bytes, b"Hello world!".as_ref());
+        };
+
+        run_client_server_test(server_run, client_run).await;
+    }
+
+    #[tokio::test]
+    async fn http1_post() {
+        let server_run = |noise_stream: NoiseTcpStream| async move {
+            async fn service_fn(
+                req: Request<hyper::body::Incoming>,
+            ) -> Result<Response<String>, hyper::Error> {
+                let request_bytes = req
+                    .collect()
+                    .await
+                    .expect("server error reading request body")
+                    .to_bytes();
+
+                assert_eq!(request_bytes, b"Client says hi".as_ref());
+
+                let resp = Response::new("Hello client!".to_string());
+                Ok(resp)
+            }
+
+            hyper::server::conn::http1::Builder::new()
+                .serve_connection(
+                    TokioIo::new(noise_stream),
+                    hyper::service::service_fn(service_fn),
+                )
+                .await
+                .expect("error serving HTTP1 POST request");
+        };
+
+        let client_run = |noise_stream: NoiseTcpStream| async move {
+            let (mut sender, conn) =
+                hyper::client::conn::http1::handshake(TokioIo::new(noise_stream))
+                    .await
+                    .expect("client failed to run HTTP1 handshake");
+
+            // Spawn a task to poll the connection, driving the HTTP state
+            let driver = spawn(async move {
+                conn.await.expect("client connection driver failed");
+            });
+
+            // Create an HTTP POST request with body
+            let req = Request::builder()
+                .method("POST")
+                .body("Client says hi".to_string())
+                .unwrap();
+
+            let res = sender
+                .send_request(req)
+                .await
+                .expect("client failed to send HTTP1 POST request");
+
+            assert_eq!(res.status(), 200);
+
+            let response_bytes = res
+                .collect()
+                .await
+                .expect("client error reading response body")
+                .to_bytes();
+
+            // Close the connection
+            drop(sender);
+            driver.await.unwrap();
+
+            assert_eq!(response_bytes, b"Hello client!".as_ref());
+        };
+
+        run_client_server_test(server_run, client_run).await;
+    }
+
+    #[tokio::test]
+    async fn http1_post_large() {
+        let server_run = |noise_stream: NoiseTcpStream| async move {
+            async fn service_fn(
+                req: Request<hyper::body::Incoming>,
+            ) -> Result<Response<String>, hyper::Error> {
+                let expected_body = "hello".repeat(3000);
+
+                let request_bytes: bytes::Bytes = req
+                    .collect()
+                    .await
+                    .expect("server error reading request body")
+                    .to_bytes();
+
+                assert_eq!(
+                    String::from_utf8_lossy(&request_bytes).as_ref(),
+                    expected_body
+                );
+
+                let resp = Response::new(expected_body);
+                Ok(resp)
+            }
+
+            hyper::server::conn::http1::Builder::new()
+                .serve_connection(
+                    TokioIo::new(noise_stream),
+                    hyper::service::service_fn(service_fn),
+                )
+                .await
+                .expect("error serving HTTP1 POST request");
+        };
+
+        let client_run = |noise_stream: NoiseTcpStream| async move {
+            let (mut sender, conn) =
+                hyper::client::conn::http1::handshake(TokioIo::new(noise_stream))
+                    .await
+                    .expect("client failed to run HTTP1 handshake");
+
+            // Spawn a task to poll the connection, driving the HTTP state
+            let driver = spawn(async move {
+                conn.await.expect("client connection driver failed");
+            });
+
+            let expected_body = "hello".repeat(3000);
+
+            // Create an HTTP POST request with body
+            let req = Request::builder()
+                .method("POST")
+                .body(expected_body.clone())
+                .unwrap();
+
+            let res = sender
+                .send_request(req)
+                .await
+                .expect("client failed to send HTTP1 POST request");
+
+            assert_eq!(res.status(), 200);
+
+            let response_bytes = res
+                .collect()
+                .await
+                .expect("client error reading response body")
+                .to_bytes();
+
+            // Close the connection
+            drop(sender);
+            driver.await.unwrap();
+
+            assert_eq!(response_bytes, expected_body.as_bytes());
+        };
+
+        run_client_server_test(server_run, client_run).await;
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::IpAddr,
+    sync::Mutex,
+    time::Duration,
+};
+
+/// Configures how a [`Tarpit`] slows down source addresses which keep failing handshakes.
+///
+/// After `n` consecutive failures from the same address, the delay imposed on that
+/// address is `base_delay * multiplier^(n-1)`, capped at `max_delay`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TarpitConfig {
+    /// The delay imposed after the first failed handshake from an address.
+    pub base_delay: Duration,
+    /// The factor by which the delay grows with each further consecutive failure.
+    pub multiplier: f64,
+    /// The largest delay which will ever be imposed on an address.
+    pub max_delay: Duration,
+    /// The maximum number of source addresses tracked at once. When the table is full,
+    /// the least recently seen address is forgotten to make room for a new one.
+    pub max_tracked_addrs: usize,
+}
+
+impl Default for TarpitConfig {
+    fn default() -> Self {
+        TarpitConfig {
+            base_delay: Duration::from_millis(100),
+            multiplier: 2.0,
+            max_delay: Duration::from_secs(30),
+            max_tracked_addrs: 10_000,
+        }
+    }
+}
+
+impl TarpitConfig {
+    /// Returns the delay imposed on an address after the given number of consecutive
+    /// handshake failures.
+    pub fn delay_after(&self, failures: u32) -> Duration {
+        if failures == 0 {
+            return Duration::ZERO;
+        }
+        let factor = self.multiplier.powi(failures.saturating_sub(1) as i32);
+        let delay = self.base_delay.as_secs_f64() * factor;
+        if !delay.is_finite() || delay >= self.max_delay.as_secs_f64() {
+            return self.max_delay;
+        }
+        Duration::from_secs_f64(delay)
+    }
+}
+
+#[derive(Debug)]
+struct TarpitEntry {
+    failures: u32,
+    last_seen: u64,
+}
+
+#[derive(Debug, Default)]
+struct TarpitTable {
+    entries: HashMap<IpAddr, TarpitEntry>,
+    /// Orders tracked addresses by the tick at which they were last seen, for LRU eviction.
+    recency: BTreeMap<u64, IpAddr>,
+    tick: u64,
+}
+
+/// Tracks consecutive handshake failures per source IP address, and computes
+/// increasing delays to impose on addresses which keep failing.
+///
+/// This raises the cost of brute-forcing a pre-shared key against a responder:
+/// each wrong guess from the same address takes exponentially longer to be
+/// rejected. A successful handshake clears the address's record, so legitimate
+/// peers are never delayed once they authenticate.
+///
+/// A `Tarpit` is usually attached to a [`NoiseTcpListener`][crate::NoiseTcpListener]
+/// with [`set_tarpit`][crate::NoiseTcpListener::set_tarpit].
+#[derive(Debug)]
+pub struct Tarpit {
+    config: TarpitConfig,
+    table: Mutex<TarpitTable>,
+}
+
+impl Tarpit {
+    /// Construct a new tarpit with the given configuration.
+    pub fn new(config: TarpitConfig) -> Tarpit {
+        Tarpit {
+            config,
+            table: Mutex::new(TarpitTable::default()),
+        }
+    }
+
+    /// Returns the tarpit's configuration.
+    pub fn config(&self) -> &TarpitConfig {
+        &self.config
+    }
+
+    /// Returns the delay which should be imposed before beginning a new handshake
+    /// attempt from the given address. This is zero unless the address has
+    /// recently failed a handshake.
+    pub fn current_delay(&self, addr: IpAddr) -> Duration {
+        let table = self.table.lock().unwrap();
+        let failures = table.entries.get(&addr).map_or(0, |entry| entry.failures);
+        self.config.delay_after(failures)
+    }
+
+    /// Returns the number of consecutive handshake failures recorded for the given address.
+    pub fn failures(&self, addr: IpAddr) -> u32 {
+        let table = self.table.lock().unwrap();
+        table.entries.get(&addr).map_or(0, |entry| entry.failures)
+    }
+
+    /// Returns the number of source addresses currently tracked.
+    pub fn tracked_addrs(&self) -> usize {
+        self.table.lock().unwrap().entries.len()
+    }
+
+    /// Records a failed handshake from the given address, and returns the delay which
+    /// should be imposed before reporting the failure.
+    pub fn record_failure(&self, addr: IpAddr) -> Duration {
+        let mut table = self.table.lock().unwrap();
+        table.tick += 1;
+        let tick = table.tick;
+
+        let failures = match table.entries.get_mut(&addr) {
+            Some(entry) => {
+                let last_seen = entry.last_seen;
+                entry.failures = entry.failures.saturating_add(1);
+                entry.last_seen = tick;
+                let failures = entry.failures;
+                table.recency.remove(&last_seen);
+                failures
+            }
+            None => {
+                if self.config.max_tracked_addrs == 0 {
+                    return self.config.delay_after(1);
+                }
+                while table.entries.len() >= self.config.max_tracked_addrs {
+                    let Some((_, oldest)) = table.recency.pop_first() else {
+                        break;
+                    };
+                    table.entries.remove(&oldest);
+                }
+                table.entries.insert(
+                    addr,
+                    TarpitEntry {
+                        failures: 1,
+                        last_seen: tick,
+                    },
+                );
+                1
+            }
+        };
+        table.recency.insert(tick, addr);
+
+        self.config.delay_after(failures)
+    }
+
+    /// Records a successful handshake from the given address, forgetting any
+    /// previous failures.
+    pub fn record_success(&self, addr: IpAddr) {
+        let mut table = self.table.lock().unwrap();
+        if let Some(entry) = table.entries.remove(&addr) {
+            table.recency.remove(&entry.last_seen);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn ip(n: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, n))
+    }
+
+    #[test]
+    fn delays_grow_exponentially_up_to_the_cap() {
+        let tarpit = Tarpit::new(TarpitConfig {
+            base_delay: Duration::from_secs(1),
+            multiplier: 2.0,
+            max_delay: Duration::from_secs(10),
+            max_tracked_addrs: 16,
+        });
+
+        assert_eq!(tarpit.current_delay(ip(1)), Duration::ZERO);
+        let delays: Vec<u64> = (0..6)
+            .map(|_| tarpit.record_failure(ip(1)).as_secs())
+            .collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 10, 10]);
+        assert_eq!(tarpit.failures(ip(1)), 6);
+        assert_eq!(tarpit.current_delay(ip(1)), Duration::from_secs(10));
+
+        // Other addresses are unaffected.
+        assert_eq!(tarpit.current_delay(ip(2)), Duration::ZERO);
+        assert_eq!(tarpit.record_failure(ip(2)), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn success_clears_failures() {
+        let tarpit = Tarpit::new(TarpitConfig::default());
+        tarpit.record_failure(ip(1));
+        tarpit.record_failure(ip(1));
+        tarpit.record_success(ip(1));
+        assert_eq!(tarpit.failures(ip(1)), 0);
+        assert_eq!(tarpit.current_delay(ip(1)), Duration::ZERO);
+        assert_eq!(tarpit.tracked_addrs(), 0);
+    }
+
+    #[test]
+    fn table_evicts_least_recently_seen() {
+        let tarpit = Tarpit::new(TarpitConfig {
+            max_tracked_addrs: 3,
+            ..TarpitConfig::default()
+        });
+        tarpit.record_failure(ip(1));
+        tarpit.record_failure(ip(2));
+        tarpit.record_failure(ip(3));
+
+        // Touch ip(1) so that ip(2) becomes the least recently seen.
+        tarpit.record_failure(ip(1));
+        tarpit.record_failure(ip(4));
+
+        assert_eq!(tarpit.tracked_addrs(), 3);
+        assert_eq!(tarpit.failures(ip(1)), 2);
+        assert_eq!(tarpit.failures(ip(2)), 0);
+        assert_eq!(tarpit.failures(ip(3)), 1);
+        assert_eq!(tarpit.failures(ip(4)), 1);
+    }
+}
@@ -0,0 +1,342 @@
+use snow::{
+    params::{CipherChoice, HashChoice},
+    resolvers::{CryptoResolver, DefaultResolver},
+};
+use std::{collections::VecDeque, fmt, sync::Mutex, time::Duration};
+use tokio::time::Instant;
+
+use crate::errors::TicketError;
+
+/// The size of each [`TicketKeyRing`] key in bytes.
+pub const TICKET_KEY_LEN: usize = 32;
+
+/// The number of bytes by which a sealed ticket is longer than its contents: a 4-byte
+/// key id, a 16-byte salt and a 16-byte authentication tag.
+pub const TICKET_OVERHEAD: usize = KEY_ID_LEN + SALT_LEN + TAG_LEN;
+
+const KEY_ID_LEN: usize = 4;
+const SALT_LEN: usize = 16;
+const TAG_LEN: usize = 16;
+
+/// The first byte of exported key material, so the format can change.
+const EXPORT_VERSION: u8 = 1;
+
+#[derive(Clone)]
+struct TicketKey {
+    id: u32,
+    key: [u8; TICKET_KEY_LEN],
+}
+
+impl TicketKey {
+    /// Generate a key whose id is not among `taken`.
+    fn generate(taken: &[u32]) -> TicketKey {
+        let mut rng = DefaultResolver
+            .resolve_rng()
+            .expect("the default resolver provides a generator");
+        let mut key = [0u8; TICKET_KEY_LEN];
+        rng.fill_bytes(&mut key);
+        let id = loop {
+            let id = rng.next_u32();
+            if !taken.contains(&id) {
+                break id;
+            }
+        };
+        TicketKey { id, key }
+    }
+
+    /// Derive the cipher for one ticket from the key and the ticket's salt, so that
+    /// servers sharing a key never need to coordinate nonces.
+    fn cipher(&self, salt: &[u8]) -> Box<dyn snow::types::Cipher> {
+        let mut hash = DefaultResolver
+            .resolve_hash(&HashChoice::SHA256)
+            .expect("the default resolver provides SHA-256");
+        let mut subkey = [0u8; TICKET_KEY_LEN];
+        hash.hmac(&self.key, salt, &mut subkey);
+        let mut cipher = DefaultResolver
+            .resolve_cipher(&CipherChoice::ChaChaPoly)
+            .expect("the default resolver provides ChaChaPoly");
+        cipher.set(&subkey);
+        cipher
+    }
+}
+
+struct TicketKeys {
+    current: TicketKey,
+    created: Instant,
+    /// Retired keys, most recent first, which still open tickets but no longer seal them.
+    previous: VecDeque<TicketKey>,
+}
+
+impl TicketKeys {
+    fn ids(&self) -> Vec<u32> {
+        std::iter::once(&self.current)
+            .chain(&self.previous)
+            .map(|key| key.id)
+            .collect()
+    }
+}
+
+/// Holds the keys which seal and open session resumption tickets, so that they can be
+/// rotated without invalidating every outstanding ticket at once.
+///
+/// New tickets are sealed under the current key. Rotating retires the current key to
+/// a list of previous keys, which still open tickets until enough further rotations
+/// push them out. A ticket whose key has aged out fails to open with
+/// [`TicketError::UnknownKey`], and its holder should fall back to a full handshake.
+///
+/// A ticket is opaque to its holder: its contents are encrypted and authenticated with
+/// ChaChaPoly, under a key derived from the ring's key and a random salt, and prefixed
+/// with the id of the key. Servers in a fleet can share a ring by
+/// [exporting][Self::export] its keys from one and [importing][Self::import] them into
+/// the others, so that a ticket issued by any of them is accepted by all.
+///
+/// ```
+/// use tokio_noise::TicketKeyRing;
+///
+/// let ring = TicketKeyRing::new(2);
+/// let ticket = ring.seal(b"session state");
+/// ring.rotate();
+/// assert_eq!(ring.open(&ticket).unwrap(), b"session state");
+/// ```
+pub struct TicketKeyRing {
+    previous_keys: usize,
+    rotation_interval: Option<Duration>,
+    keys: Mutex<TicketKeys>,
+}
+
+impl TicketKeyRing {
+    /// Construct a ring with a freshly generated key, which keeps up to `previous_keys`
+    /// retired keys to open older tickets with.
+    pub fn new(previous_keys: usize) -> TicketKeyRing {
+        TicketKeyRing {
+            previous_keys,
+            rotation_interval: None,
+            keys: Mutex::new(TicketKeys {
+                current: TicketKey::generate(&[]),
+                created: Instant::now(),
+                previous: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Rotate the key automatically once it has sealed tickets for the given interval.
+    /// This is checked whenever a ticket is sealed. A ticket then stays valid for
+    /// between `previous_keys` and `previous_keys + 1` intervals.
+    pub fn rotation_interval(mut self, interval: Duration) -> TicketKeyRing {
+        self.rotation_interval = Some(interval);
+        self
+    }
+
+    /// Generate a new current key, and retire the old one. The oldest retired key is
+    /// dropped if there are more than `previous_keys`, so its tickets no longer open.
+    pub fn rotate(&self) {
+        let mut keys = self.keys.lock().unwrap();
+        self.rotate_locked(&mut keys);
+    }
+
+    fn rotate_locked(&self, keys: &mut TicketKeys) {
+        let current = TicketKey::generate(&keys.ids());
+        let retired = std::mem::replace(&mut keys.current, current);
+        keys.created = Instant::now();
+        keys.previous.push_front(retired);
+        keys.previous.truncate(self.previous_keys);
+    }
+
+    /// Returns the id of the key which seals new tickets.
+    pub fn current_key_id(&self) -> u32 {
+        self.keys.lock().unwrap().current.id
+    }
+
+    /// Returns the ids of every key which opens tickets, starting with the current key
+    /// and then the retired keys from newest to oldest.
+    pub fn key_ids(&self) -> Vec<u32> {
+        self.keys.lock().unwrap().ids()
+    }
+
+    /// Seal the contents of a ticket under the current key, first rotating it if it is
+    /// older than the [rotation interval][Self::rotation_interval]. The ticket is
+    /// [`TICKET_OVERHEAD`] bytes longer than its contents.
+    pub fn seal(&self, contents: &[u8]) -> Vec<u8> {
+        let mut keys = self.keys.lock().unwrap();
+        if let Some(interval) = self.rotation_interval {
+            if keys.created.elapsed() >= interval {
+                self.rotate_locked(&mut keys);
+            }
+        }
+
+        let mut ticket = vec![0u8; TICKET_OVERHEAD + contents.len()];
+        let (header, ciphertext) = ticket.split_at_mut(KEY_ID_LEN + SALT_LEN);
+        header[..KEY_ID_LEN].copy_from_slice(&keys.current.id.to_be_bytes());
+        DefaultResolver
+            .resolve_rng()
+            .expect("the default resolver provides a generator")
+            .fill_bytes(&mut header[KEY_ID_LEN..]);
+        let cipher = keys.current.cipher(&header[KEY_ID_LEN..]);
+        cipher.encrypt(0, header, contents, ciphertext);
+        ticket
+    }
+
+    /// Open a ticket sealed by [`seal`][Self::seal] under the current key or a retired
+    /// key still in the ring, returning its contents.
+    pub fn open(&self, ticket: &[u8]) -> Result<Vec<u8>, TicketError> {
+        if ticket.len() < TICKET_OVERHEAD {
+            return Err(TicketError::Invalid);
+        }
+        let (header, ciphertext) = ticket.split_at(KEY_ID_LEN + SALT_LEN);
+        let id = u32::from_be_bytes(header[..KEY_ID_LEN].try_into().unwrap());
+        let cipher = {
+            let keys = self.keys.lock().unwrap();
+            let key = std::iter::once(&keys.current)
+                .chain(&keys.previous)
+                .find(|key| key.id == id)
+                .ok_or(TicketError::UnknownKey(id))?;
+            key.cipher(&header[KEY_ID_LEN..])
+        };
+        let mut contents = vec![0u8; ciphertext.len() - TAG_LEN];
+        cipher
+            .decrypt(0, header, ciphertext, &mut contents)
+            .map_err(|_| TicketError::Invalid)?;
+        Ok(contents)
+    }
+
+    /// Export every key in the ring, to be [imported][Self::import] by the other
+    /// servers in a fleet. The result is secret key material, and must be transferred
+    /// and stored as such.
+    pub fn export(&self) -> Vec<u8> {
+        let keys = self.keys.lock().unwrap();
+        let mut exported = vec![EXPORT_VERSION];
+        exported.extend_from_slice(&(1 + keys.previous.len() as u32).to_be_bytes());
+        for key in std::iter::once(&keys.current).chain(&keys.previous) {
+            exported.extend_from_slice(&key.id.to_be_bytes());
+            exported.extend_from_slice(&key.key);
+        }
+        exported
+    }
+
+    /// Replace the keys in the ring with those exported by another ring. Its current key
+    /// becomes this ring's current key, and its newest retired keys are kept, up to this
+    /// ring's limit. The rotation interval restarts from now.
+    pub fn import(&self, exported: &[u8]) -> Result<(), TicketError> {
+        const ENTRY_LEN: usize = KEY_ID_LEN + TICKET_KEY_LEN;
+        let (&[EXPORT_VERSION, ref count @ ..], entries) = exported
+            .split_first_chunk::<5>()
+            .ok_or(TicketError::MalformedKeys)?
+        else {
+            return Err(TicketError::MalformedKeys);
+        };
+        let count = u32::from_be_bytes(*count) as usize;
+        if count == 0 || entries.len() != count * ENTRY_LEN {
+            return Err(TicketError::MalformedKeys);
+        }
+        let mut imported = entries.chunks(ENTRY_LEN).map(|entry| TicketKey {
+            id: u32::from_be_bytes(entry[..KEY_ID_LEN].try_into().unwrap()),
+            key: entry[KEY_ID_LEN..].try_into().unwrap(),
+        });
+
+        let mut keys = self.keys.lock().unwrap();
+        keys.current = imported.next().unwrap();
+        keys.created = Instant::now();
+        keys.previous = imported.take(self.previous_keys).collect();
+        Ok(())
+    }
+}
+
+impl fmt::Debug for TicketKeyRing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Only key ids are shown, so keys don't end up in logs.
+        f.debug_struct("TicketKeyRing")
+            .field("previous_keys", &self.previous_keys)
+            .field("rotation_interval", &self.rotation_interval)
+            .field("key_ids", &self.key_ids())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tickets_open_until_their_key_ages_out() {
+        let ring = TicketKeyRing::new(1);
+        let ticket = ring.seal(b"resume me");
+        assert_eq!(ticket.len(), TICKET_OVERHEAD + b"resume me".len());
+        assert_eq!(ring.open(&ticket).unwrap(), b"resume me");
+
+        let first_key = ring.current_key_id();
+        ring.rotate();
+        assert_eq!(ring.key_ids().len(), 2);
+        assert_eq!(ring.open(&ticket).unwrap(), b"resume me");
+
+        ring.rotate();
+        assert!(!ring.key_ids().contains(&first_key));
+        assert_eq!(ring.open(&ticket), Err(TicketError::UnknownKey(first_key)));
+    }
+
+    #[test]
+    fn tampered_tickets_are_rejected() {
+        let ring = TicketKeyRing::new(1);
+        let ticket = ring.seal(b"resume me");
+        for i in KEY_ID_LEN..ticket.len() {
+            let mut tampered = ticket.clone();
+            tampered[i] ^= 1;
+            assert_eq!(
+                ring.open(&tampered),
+                Err(TicketError::Invalid),
+                "byte {}",
+                i
+            );
+        }
+        assert_eq!(
+            ring.open(&ticket[..TICKET_OVERHEAD - 1]),
+            Err(TicketError::Invalid)
+        );
+        // A ticket sealed by an unrelated ring names a key this one doesn't have.
+        let other = TicketKeyRing::new(1).seal(b"resume me");
+        assert!(matches!(ring.open(&other), Err(TicketError::UnknownKey(_))));
+    }
+
+    #[test]
+    fn fleet_shares_keys_by_export() {
+        let leader = TicketKeyRing::new(2);
+        let ticket = leader.seal(b"resume me");
+        leader.rotate();
+
+        let follower = TicketKeyRing::new(2);
+        follower.import(&leader.export()).unwrap();
+        assert_eq!(follower.key_ids(), leader.key_ids());
+        assert_eq!(follower.open(&ticket).unwrap(), b"resume me");
+        assert_eq!(leader.open(&follower.seal(b"both")).unwrap(), b"both");
+
+        // A follower keeping fewer keys drops the oldest.
+        let small = TicketKeyRing::new(0);
+        small.import(&leader.export()).unwrap();
+        assert_eq!(small.key_ids(), [leader.current_key_id()]);
+
+        let exported = leader.export();
+        for malformed in [&[][..], &exported[..1], &exported[..exported.len() - 1]] {
+            assert_eq!(small.import(malformed), Err(TicketError::MalformedKeys));
+        }
+        assert_eq!(
+            small.import(&[EXPORT_VERSION, 0, 0, 0, 0]),
+            Err(TicketError::MalformedKeys)
+        );
+        let mut future = exported.clone();
+        future[0] += 1;
+        assert_eq!(small.import(&future), Err(TicketError::MalformedKeys));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keys_rotate_on_schedule() {
+        let ring = TicketKeyRing::new(1).rotation_interval(Duration::from_secs(3600));
+        let first_key = ring.current_key_id();
+        ring.seal(b"");
+        tokio::time::advance(Duration::from_secs(3599)).await;
+        ring.seal(b"");
+        assert_eq!(ring.current_key_id(), first_key);
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        ring.seal(b"");
+        assert_eq!(ring.key_ids()[1], first_key);
+    }
+}
@@ -0,0 +1,144 @@
+use log::warn;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use tokio::net::unix::UCred;
+use tokio::{
+    io::{self, AsyncRead, AsyncWrite, BufWriter, DuplexStream},
+    net::TcpStream,
+};
+
+/// A byte stream which a [`NoiseStream`][crate::NoiseStream] can conduct a handshake
+/// over.
+///
+/// Besides reading and writing, a transport can report properties of the underlying
+/// connection which the stream uses to tune itself. Every method has a default, so a
+/// custom transport can be supported with an empty `impl Transport for MyStream {}`.
+pub trait Transport: AsyncRead + AsyncWrite + Unpin {
+    /// Returns the maximum segment size (MSS) of the connection, if known. Used by
+    /// [`FrameSizing::Auto`][crate::FrameSizing::Auto].
+    fn max_segment_size(&self) -> Option<usize> {
+        None
+    }
+
+    /// Returns whether Nagle's algorithm is disabled on the connection, or `None` if
+    /// the transport doesn't use it.
+    fn nodelay(&self) -> Option<bool> {
+        None
+    }
+
+    /// Disables or re-enables Nagle's algorithm on the connection. Does nothing on
+    /// transports which don't use it.
+    ///
+    /// See [`NoiseBuilder::nodelay`][crate::NoiseBuilder::nodelay].
+    fn set_nodelay(&self, _nodelay: bool) -> Result<(), io::Error> {
+        Ok(())
+    }
+
+    /// Returns the address of the remote peer, if the transport has one. Reported to
+    /// [`HandshakeConfig::on_handshake`][crate::HandshakeConfig::on_handshake].
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    /// Returns the credentials of the process at the other end of the connection, if the
+    /// transport is a local socket which can report them. Reported to
+    /// [`HandshakeConfig::admit`][crate::HandshakeConfig::admit]. Only available on Unix.
+    #[cfg(unix)]
+    fn peer_cred(&self) -> Option<UCred> {
+        None
+    }
+}
+
+impl Transport for TcpStream {
+    /// Queries the socket's `TCP_MAXSEG` option. Only supported on Linux.
+    fn max_segment_size(&self) -> Option<usize> {
+        #[cfg(target_os = "linux")]
+        match socket2::SockRef::from(self).tcp_mss() {
+            Ok(mss) => return Some(mss as usize),
+            Err(e) => warn!("failed to query TCP_MAXSEG: {}", e),
+        }
+        None
+    }
+
+    /// Queries the socket's `TCP_NODELAY` option.
+    fn nodelay(&self) -> Option<bool> {
+        match TcpStream::nodelay(self) {
+            Ok(nodelay) => Some(nodelay),
+            Err(e) => {
+                warn!("failed to query TCP_NODELAY: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Sets the socket's `TCP_NODELAY` option.
+    fn set_nodelay(&self, nodelay: bool) -> Result<(), io::Error> {
+        TcpStream::set_nodelay(self, nodelay)
+    }
+
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        TcpStream::peer_addr(self).ok()
+    }
+}
+
+#[cfg(unix)]
+impl Transport for tokio::net::UnixStream {
+    /// Queries the socket's peer credentials, such as with `SO_PEERCRED` on Linux.
+    fn peer_cred(&self) -> Option<UCred> {
+        match tokio::net::UnixStream::peer_cred(self) {
+            Ok(cred) => Some(cred),
+            Err(e) => {
+                warn!("failed to query peer credentials: {}", e);
+                None
+            }
+        }
+    }
+}
+
+impl Transport for DuplexStream {}
+
+impl<T: Transport> Transport for BufWriter<T> {
+    fn max_segment_size(&self) -> Option<usize> {
+        self.get_ref().max_segment_size()
+    }
+
+    fn nodelay(&self) -> Option<bool> {
+        self.get_ref().nodelay()
+    }
+
+    fn set_nodelay(&self, nodelay: bool) -> Result<(), io::Error> {
+        self.get_ref().set_nodelay(nodelay)
+    }
+
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        self.get_ref().peer_addr()
+    }
+
+    #[cfg(unix)]
+    fn peer_cred(&self) -> Option<UCred> {
+        self.get_ref().peer_cred()
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for &mut T {
+    fn max_segment_size(&self) -> Option<usize> {
+        (**self).max_segment_size()
+    }
+
+    fn nodelay(&self) -> Option<bool> {
+        (**self).nodelay()
+    }
+
+    fn set_nodelay(&self, nodelay: bool) -> Result<(), io::Error> {
+        (**self).set_nodelay(nodelay)
+    }
+
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        (**self).peer_addr()
+    }
+
+    #[cfg(unix)]
+    fn peer_cred(&self) -> Option<UCred> {
+        (**self).peer_cred()
+    }
+}
@@ -0,0 +1,297 @@
+use std::{
+    error::Error,
+    fmt,
+    marker::PhantomData,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use bytes::{Buf, BufMut, BytesMut};
+use futures_core::Stream;
+use futures_sink::Sink;
+use tokio::io::{self, AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::{errors::NoiseError, stream::NoiseStream, MAX_FRAME_SIZE};
+
+/// The size of the big-endian length which precedes each typed message.
+const MESSAGE_LEN_SIZE: usize = 4;
+
+/// The longest encoded message a [`TypedNoiseStream`] sends or receives by default: 1 MiB.
+pub const DEFAULT_MAX_MESSAGE_LEN: usize = 1 << 20;
+
+/// Encodes messages of type `T` to bytes and back, for a [`TypedNoiseStream`]. Available
+/// with the `typed` feature.
+///
+/// [`Json`] is provided with the `json` feature. Implement this trait to use another
+/// format, such as bincode or postcard.
+pub trait MessageFormat<T> {
+    /// The error returned when a message can't be encoded or decoded.
+    type Error: Error + Send + Sync + 'static;
+
+    /// Append the encoding of `message` to `buf`.
+    fn encode(&self, message: &T, buf: &mut Vec<u8>) -> Result<(), Self::Error>;
+
+    /// Decode a message from the whole of `bytes`.
+    fn decode(&self, bytes: &[u8]) -> Result<T, Self::Error>;
+}
+
+/// Encodes messages as JSON with [`serde_json`]. Available with the `json` feature.
+#[cfg(feature = "json")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> MessageFormat<T> for Json {
+    type Error = serde_json::Error;
+
+    fn encode(&self, message: &T, buf: &mut Vec<u8>) -> Result<(), serde_json::Error> {
+        serde_json::to_writer(buf, message)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<T, serde_json::Error> {
+        serde_json::from_slice(bytes)
+    }
+}
+
+/// A [`NoiseStream`] which sends and receives whole messages of type `T`, rather than
+/// bytes. Available with the `typed` feature.
+///
+/// Each message is encoded with the format `F`, and sent with a 4-byte big-endian length
+/// prefix, so it may span any number of frames. Besides [`send`][Self::send] and
+/// [`recv`][Self::recv], the stream implements [`Sink`] and [`Stream`].
+///
+/// ```no_run
+/// # async fn example(noise_stream: tokio_noise::NoiseTcpStream) -> Result<(), tokio_noise::NoiseError> {
+/// #[derive(serde::Serialize, serde::Deserialize)]
+/// enum Rpc {
+///     Ping(u64),
+///     Pong(u64),
+/// }
+///
+/// let mut typed = noise_stream.into_typed::<Rpc>();
+/// typed.send(&Rpc::Ping(1)).await?;
+/// if let Some(Rpc::Pong(n)) = typed.recv().await? {
+///     println!("pong {}", n);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct TypedNoiseStream<S: AsyncRead + AsyncWrite + Unpin, T, F> {
+    stream: NoiseStream<S>,
+    format: F,
+    max_message_len: usize,
+    /// Received bytes which don't yet make up a whole message.
+    read_buf: BytesMut,
+    /// Encoded messages which the stream has not yet accepted.
+    write_buf: BytesMut,
+    /// Scratch space for encoding each message.
+    encode_buf: Vec<u8>,
+    _message: PhantomData<fn(T) -> T>,
+}
+
+// No field is ever pinned, so the stream can be moved whatever the format.
+impl<S: AsyncRead + AsyncWrite + Unpin, T, F> Unpin for TypedNoiseStream<S, T, F> {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> NoiseStream<S> {
+    /// Send and receive messages of type `T` encoded as JSON, rather than bytes. Available
+    /// with the `json` feature.
+    #[cfg(feature = "json")]
+    pub fn into_typed<T>(self) -> TypedNoiseStream<S, T, Json>
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned,
+    {
+        self.into_typed_with(Json)
+    }
+
+    /// Send and receive messages of type `T` encoded with the given format, rather than
+    /// bytes. Available with the `typed` feature.
+    pub fn into_typed_with<T, F: MessageFormat<T>>(self, format: F) -> TypedNoiseStream<S, T, F> {
+        TypedNoiseStream {
+            stream: self,
+            format,
+            max_message_len: DEFAULT_MAX_MESSAGE_LEN,
+            read_buf: BytesMut::new(),
+            write_buf: BytesMut::new(),
+            encode_buf: Vec::new(),
+            _message: PhantomData,
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin, T, F: MessageFormat<T>> TypedNoiseStream<S, T, F> {
+    /// Set the longest encoded message which may be sent or received. Sending a longer
+    /// message fails with [`NoiseError::MessageTooLarge`], as does receiving one, before
+    /// any of it is buffered. Defaults to [`DEFAULT_MAX_MESSAGE_LEN`].
+    pub fn max_message_len(mut self, max: usize) -> Self {
+        self.max_message_len = max.min(u32::MAX as usize);
+        self
+    }
+
+    /// Encode and send a message, and flush it to the peer.
+    pub async fn send(&mut self, message: &T) -> Result<(), NoiseError> {
+        std::future::poll_fn(|cx| self.poll_drain(cx)).await?;
+        self.queue(message)?;
+        std::future::poll_fn(|cx| self.poll_flush_messages(cx)).await
+    }
+
+    /// Receive the next message, or `None` if the peer closed the stream between
+    /// messages.
+    pub async fn recv(&mut self) -> Result<Option<T>, NoiseError> {
+        std::future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    /// Flush all sent messages, send a close notify, and shut down the transport.
+    pub async fn close(mut self) -> Result<(), NoiseError> {
+        std::future::poll_fn(|cx| self.poll_close_messages(cx)).await
+    }
+
+    /// Returns a reference to the underlying Noise stream.
+    pub fn get_ref(&self) -> &NoiseStream<S> {
+        &self.stream
+    }
+
+    /// Returns a mutable reference to the underlying Noise stream.
+    ///
+    /// Reading from or writing to it directly will corrupt the message framing.
+    pub fn get_mut(&mut self) -> &mut NoiseStream<S> {
+        &mut self.stream
+    }
+
+    /// Unwrap the underlying Noise stream. Any part of a message which has been received
+    /// but not yet returned, or queued but not yet sent, is lost.
+    pub fn into_inner(self) -> NoiseStream<S> {
+        self.stream
+    }
+
+    /// Encode a message onto the end of the write buffer.
+    fn queue(&mut self, message: &T) -> Result<(), NoiseError> {
+        self.encode_buf.clear();
+        self.format
+            .encode(message, &mut self.encode_buf)
+            .map_err(|e| NoiseError::MessageFormat(Box::new(e)))?;
+        let len = self.encode_buf.len();
+        if len > self.max_message_len {
+            return Err(NoiseError::MessageTooLarge {
+                len,
+                max: self.max_message_len,
+            });
+        }
+        self.write_buf.reserve(MESSAGE_LEN_SIZE + len);
+        self.write_buf.put_u32(len as u32);
+        self.write_buf.put_slice(&self.encode_buf);
+        Ok(())
+    }
+
+    /// Write the whole of the write buffer to the Noise stream.
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), NoiseError>> {
+        while !self.write_buf.is_empty() {
+            let n = ready!(Pin::new(&mut self.stream).poll_write(cx, &self.write_buf))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::Error::from(io::ErrorKind::WriteZero).into()));
+            }
+            self.write_buf.advance(n);
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_flush_messages(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), NoiseError>> {
+        ready!(self.poll_drain(cx))?;
+        ready!(Pin::new(&mut self.stream).poll_flush(cx))?;
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close_messages(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), NoiseError>> {
+        ready!(self.poll_drain(cx))?;
+        ready!(Pin::new(&mut self.stream).poll_shutdown(cx))?;
+        Poll::Ready(Ok(()))
+    }
+
+    /// Read from the Noise stream until a whole message has arrived, and decode it.
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Result<Option<T>, NoiseError>> {
+        loop {
+            if self.read_buf.len() >= MESSAGE_LEN_SIZE {
+                let len = u32::from_be_bytes(
+                    self.read_buf[..MESSAGE_LEN_SIZE]
+                        .try_into()
+                        .expect("4-byte slice"),
+                ) as usize;
+                if len > self.max_message_len {
+                    return Poll::Ready(Err(NoiseError::MessageTooLarge {
+                        len,
+                        max: self.max_message_len,
+                    }));
+                }
+                if self.read_buf.len() >= MESSAGE_LEN_SIZE + len {
+                    let message = self.read_buf.split_to(MESSAGE_LEN_SIZE + len);
+                    return Poll::Ready(
+                        self.format
+                            .decode(&message[MESSAGE_LEN_SIZE..])
+                            .map(Some)
+                            .map_err(|e| NoiseError::MessageFormat(Box::new(e))),
+                    );
+                }
+            }
+
+            let start = self.read_buf.len();
+            self.read_buf.resize(start + MAX_FRAME_SIZE, 0);
+            let mut buf = ReadBuf::new(&mut self.read_buf[start..]);
+            let poll = Pin::new(&mut self.stream).poll_read(cx, &mut buf);
+            let n = buf.filled().len();
+            self.read_buf.truncate(start + n);
+            ready!(poll)?;
+            if n == 0 {
+                if self.read_buf.is_empty() {
+                    return Poll::Ready(Ok(None));
+                }
+                return Poll::Ready(Err(NoiseError::Io(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Noise stream closed partway through a typed message",
+                ))));
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin, T, F: MessageFormat<T>> Stream
+    for TypedNoiseStream<S, T, F>
+{
+    type Item = Result<T, NoiseError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_recv(cx).map(Result::transpose)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin, T, F: MessageFormat<T>> Sink<T>
+    for TypedNoiseStream<S, T, F>
+{
+    type Error = NoiseError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), NoiseError>> {
+        self.poll_drain(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, message: T) -> Result<(), NoiseError> {
+        self.queue(&message)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), NoiseError>> {
+        self.poll_flush_messages(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), NoiseError>> {
+        self.poll_close_messages(cx)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin, T, F: fmt::Debug> fmt::Debug for TypedNoiseStream<S, T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TypedNoiseStream")
+            .field("name", &self.stream.name())
+            .field("format", &self.format)
+            .field("max_message_len", &self.max_message_len)
+            .field("buffered_read", &self.read_buf.len())
+            .field("buffered_write", &self.write_buf.len())
+            .finish()
+    }
+}
@@ -0,0 +1,174 @@
+use std::{
+    future::{poll_fn, Future},
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd},
+        unix::net::UnixStream as StdUnixStream,
+    },
+    pin::pin,
+    process::Stdio,
+    task::Poll,
+};
+use tokio::{
+    io,
+    net::{
+        unix::{SocketAddr, UCred},
+        UnixStream,
+    },
+};
+
+use crate::{errors::NoiseError, handshakes::Handshake, stream::NoiseStream};
+
+/// A [`tokio::net::UnixStream`] wrapped with a layer of [Noise](https://noiseprotocol.org/)
+/// encryption applied on top. Only available on Unix.
+pub type NoiseUnixStream = NoiseStream<UnixStream>;
+
+impl NoiseStream<UnixStream> {
+    /// Wraps [`UnixStream::peer_cred`].
+    ///
+    /// To turn away peers by their credentials before any handshake work, use
+    /// [`HandshakeConfig::admit`][crate::HandshakeConfig::admit].
+    pub fn peer_cred(&self) -> Result<UCred, io::Error> {
+        self.get_ref().peer_cred()
+    }
+    /// Wraps [`UnixStream::local_addr`].
+    pub fn local_addr(&self) -> Result<SocketAddr, io::Error> {
+        self.get_ref().local_addr()
+    }
+    /// Wraps [`UnixStream::peer_addr`].
+    pub fn peer_addr(&self) -> Result<SocketAddr, io::Error> {
+        self.get_ref().peer_addr()
+    }
+}
+
+/// Create a connected pair of Unix sockets with [`UnixStream::pair`], and conduct a
+/// handshake over them, returning the initiator's stream and the responder's stream.
+/// Only available on Unix.
+///
+/// This suits channels between tasks or threads of one process. To talk to a child
+/// process, use [`child_socketpair`] instead, and conduct each side's handshake in its
+/// own process.
+pub async fn noise_socketpair(
+    initiator: impl Handshake,
+    responder: impl Handshake,
+) -> Result<(NoiseUnixStream, NoiseUnixStream), NoiseError> {
+    let (a, b) = UnixStream::pair()?;
+    let mut initiate = pin!(NoiseStream::handshake_initiator(a, initiator));
+    let mut respond = pin!(NoiseStream::handshake_responder(b, responder));
+    let (mut a, mut b) = (None, None);
+    // Each side waits on the other's messages, so both are driven together.
+    poll_fn(|cx| {
+        if a.is_none() {
+            a = poll_ready(initiate.as_mut().poll(cx));
+        }
+        if b.is_none() {
+            b = poll_ready(respond.as_mut().poll(cx));
+        }
+        match a.is_some() && b.is_some() {
+            true => Poll::Ready(()),
+            false => Poll::Pending,
+        }
+    })
+    .await;
+    Ok((
+        a.expect("initiator finished")?,
+        b.expect("responder finished")?,
+    ))
+}
+
+fn poll_ready<T>(poll: Poll<T>) -> Option<T> {
+    match poll {
+        Poll::Ready(output) => Some(output),
+        Poll::Pending => None,
+    }
+}
+
+/// Create a connected pair of Unix sockets for talking to a child process, returning
+/// this process's end and the end to hand to the child. Only available on Unix.
+///
+/// The child's end is not registered with the tokio runtime, and is closed on exec
+/// unless handed over explicitly, most simply as the child's stdin. The child then
+/// takes it back with [`inherit_unix_stream`], and both sides conduct a handshake as
+/// usual, as they would over a network. Even between processes on one host, this
+/// keeps the channel private from other local users who can trace or impersonate
+/// either end's socket, and gives local and remote workers the same API.
+///
+/// ```no_run
+/// # async fn parent() -> Result<(), tokio_noise::NoiseError> {
+/// use tokio_noise::{child_socketpair, handshakes::NNpsk0, NoiseStream};
+///
+/// let (socket, child_socket) = child_socketpair()?;
+/// let mut child = std::process::Command::new("worker")
+///     .stdin(child_socket)
+///     .spawn()?;
+/// let mut noise_stream = NoiseStream::handshake_initiator(socket, NNpsk0::try_new(&[0xFF; 32])?).await?;
+/// noise_stream.send(b"hello").await?;
+/// # Ok(())
+/// # }
+///
+/// // In the worker:
+/// # async fn worker() -> Result<(), tokio_noise::NoiseError> {
+/// use std::os::fd::AsFd;
+/// use tokio_noise::{inherit_unix_stream, handshakes::NNpsk0, NoiseStream};
+///
+/// let socket = inherit_unix_stream(std::io::stdin().as_fd())?;
+/// let mut noise_stream = NoiseStream::handshake_responder(socket, NNpsk0::try_new(&[0xFF; 32])?).await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// To hand it over as some other file descriptor, take it with
+/// [`ChildSocket::into_fd`], and arrange for it to survive exec, such as by
+/// duplicating it with `dup2` in a `pre_exec` hook.
+pub fn child_socketpair() -> Result<(UnixStream, ChildSocket), io::Error> {
+    let (ours, theirs) = StdUnixStream::pair()?;
+    ours.set_nonblocking(true)?;
+    Ok((UnixStream::from_std(ours)?, ChildSocket(theirs.into())))
+}
+
+/// The end of a [`child_socketpair`] to hand to a child process. Only available on
+/// Unix.
+///
+/// Convert it into a [`Stdio`] to pass it as one of the child's standard streams.
+/// Drop it once the child has been spawned, so that this process holds no copy of the
+/// child's end, and sees the channel close if the child exits.
+#[derive(Debug)]
+pub struct ChildSocket(OwnedFd);
+
+impl ChildSocket {
+    /// Returns the file descriptor of the child's end.
+    pub fn into_fd(self) -> OwnedFd {
+        self.0
+    }
+}
+
+impl AsFd for ChildSocket {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl AsRawFd for ChildSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl From<ChildSocket> for Stdio {
+    fn from(socket: ChildSocket) -> Stdio {
+        Stdio::from(socket.0)
+    }
+}
+
+/// Take up a Unix socket inherited from a parent process, such as the end of a
+/// [`child_socketpair`] passed as stdin. The file descriptor is duplicated, so the
+/// original is left open. Only available on Unix.
+///
+/// Must be called within a tokio runtime. Fails if the file descriptor is not a
+/// socket.
+pub fn inherit_unix_stream(fd: BorrowedFd<'_>) -> Result<UnixStream, io::Error> {
+    let socket = StdUnixStream::from(fd.try_clone_to_owned()?);
+    // Check that this is a connected socket, rather than a file or terminal.
+    socket.peer_addr()?;
+    socket.set_nonblocking(true)?;
+    UnixStream::from_std(socket)
+}
@@ -0,0 +1,191 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use bytes::{Buf, Bytes};
+use futures_core::Stream;
+use futures_sink::Sink;
+use log::trace;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_tungstenite::{
+    tungstenite::{Error as WsError, Message},
+    WebSocketStream,
+};
+
+use crate::{stream::NoiseStream, transport::Transport};
+
+/// A Noise stream running over a WebSocket connection, through a [`WebSocketTransport`].
+/// Available with the `websocket` feature.
+pub type NoiseWebSocketStream<S> = NoiseStream<WebSocketTransport<S>>;
+
+/// Adapts a [`WebSocketStream`], client or server, into a byte stream which a
+/// [`NoiseStream`] can run over. Available with the `websocket` feature.
+///
+/// Every write becomes one WebSocket binary message: a handshake message, or one or
+/// more whole Noise frames. Incoming binary messages are read back to back, so the
+/// Noise layer reassembles its frames however the peer or a proxy split them. Ping
+/// and pong messages are answered and skipped by the WebSocket layer, and a close
+/// message reads as EOF. Text messages are an error.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// use tokio_noise::{NoiseStream, WebSocketTransport};
+///
+/// let tcp_stream = tokio::net::TcpStream::connect("127.0.0.1:8000").await?;
+/// let (ws_stream, _) =
+///     tokio_tungstenite::client_async("ws://127.0.0.1:8000/noise", tcp_stream).await?;
+/// let psk = [0xFF; 32];
+/// let noise_stream =
+///     NoiseStream::handshake_initiator_psk0(WebSocketTransport::new(ws_stream), &psk).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct WebSocketTransport<S> {
+    inner: WebSocketStream<S>,
+    /// The unread remainder of the last binary message received.
+    read_buf: Bytes,
+    /// Set once a message has been sent but not yet flushed to the connection.
+    unflushed: bool,
+}
+
+impl<S> WebSocketTransport<S> {
+    /// Wrap an established WebSocket connection.
+    pub fn new(inner: WebSocketStream<S>) -> WebSocketTransport<S> {
+        WebSocketTransport {
+            inner,
+            read_buf: Bytes::new(),
+            unflushed: false,
+        }
+    }
+
+    /// Returns a reference to the WebSocket connection.
+    pub fn get_ref(&self) -> &WebSocketStream<S> {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the WebSocket connection. Sending messages
+    /// through it directly will corrupt the Noise stream running over this transport.
+    pub fn get_mut(&mut self) -> &mut WebSocketStream<S> {
+        &mut self.inner
+    }
+
+    /// Unwrap the WebSocket connection. Any part of a received message which has not
+    /// been read yet is lost.
+    pub fn into_inner(self) -> WebSocketStream<S> {
+        self.inner
+    }
+}
+
+impl<S> From<WebSocketStream<S>> for WebSocketTransport<S> {
+    fn from(inner: WebSocketStream<S>) -> WebSocketTransport<S> {
+        WebSocketTransport::new(inner)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> WebSocketTransport<S> {
+    /// Make progress on sending messages which were written but not yet flushed. A
+    /// WebSocket buffers what it sends until flushed, while the Noise layer expects
+    /// every byte it writes to go out without waiting for a flush.
+    fn poll_flush_sent(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        if self.unflushed {
+            ready!(Pin::new(&mut self.inner).poll_flush(cx)).map_err(ws_to_io_error)?;
+            self.unflushed = false;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WebSocketTransport<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        // Reading doesn't wait on sending, but must not leave it stalled either, in
+        // case the peer waits on what we sent before it replies.
+        if let Poll::Ready(Err(e)) = this.poll_flush_sent(cx) {
+            return Poll::Ready(Err(e));
+        }
+
+        while this.read_buf.is_empty() {
+            let message = match ready!(Pin::new(&mut this.inner).poll_next(cx)) {
+                Some(Ok(message)) => message,
+                None | Some(Err(WsError::ConnectionClosed | WsError::AlreadyClosed)) => {
+                    return Poll::Ready(Ok(()));
+                }
+                Some(Err(e)) => return Poll::Ready(Err(ws_to_io_error(e))),
+            };
+            match message {
+                Message::Binary(data) => this.read_buf = Bytes::from(data),
+                Message::Close(frame) => {
+                    trace!("WebSocket closed by peer: {:?}", frame);
+                    return Poll::Ready(Ok(()));
+                }
+                Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => {}
+                Message::Text(_) => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "received a WebSocket text message, expected binary",
+                    )));
+                }
+            }
+        }
+
+        let n = this.read_buf.len().min(buf.remaining());
+        buf.put_slice(&this.read_buf[..n]);
+        this.read_buf.advance(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WebSocketTransport<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = &mut *self;
+        ready!(Pin::new(&mut this.inner).poll_ready(cx)).map_err(ws_to_io_error)?;
+        Pin::new(&mut this.inner)
+            .start_send(Message::Binary(buf.to_vec()))
+            .map_err(ws_to_io_error)?;
+        this.unflushed = true;
+
+        // The message is accepted. Start sending it now; if the connection can't take
+        // it yet, the next write, read or flush finishes the job.
+        if let Poll::Ready(Err(e)) = this.poll_flush_sent(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        ready!(self.poll_flush_sent(cx))?;
+        Pin::new(&mut self.inner)
+            .poll_flush(cx)
+            .map_err(ws_to_io_error)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        match ready!(Pin::new(&mut self.inner).poll_close(cx)) {
+            Ok(()) | Err(WsError::ConnectionClosed | WsError::AlreadyClosed) => Poll::Ready(Ok(())),
+            Err(e) => Poll::Ready(Err(ws_to_io_error(e))),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Transport for WebSocketTransport<S> {}
+
+fn ws_to_io_error(e: WsError) -> io::Error {
+    match e {
+        WsError::Io(e) => e,
+        e => io::Error::other(e),
+    }
+}
@@ -0,0 +1,47 @@
+use std::net::{IpAddr, SocketAddr};
+use tokio::{
+    io::AsyncReadExt,
+    net::{TcpSocket, TcpStream},
+};
+use tokio_noise::{handshakes::NNpsk0, NoiseTcpListener, NoiseTcpStream};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+async fn connect_from(local_ip: &str, addr: SocketAddr) -> TcpStream {
+    let socket = TcpSocket::new_v4().unwrap();
+    socket
+        .bind(format!("{}:0", local_ip).parse().unwrap())
+        .unwrap();
+    socket.connect(addr).await.unwrap()
+}
+
+#[tokio::test]
+async fn rejected_sources_are_closed_before_the_handshake() {
+    let mut listener = NoiseTcpListener::bind("127.0.0.1:0").await.unwrap();
+    let blocked = IpAddr::from([127, 0, 0, 2]);
+    listener.set_accept_filter(move |addr| addr.ip() != blocked);
+    let addr = listener.local_addr().unwrap();
+
+    let server = tokio::spawn(async move {
+        let incoming = listener.accept().await.unwrap();
+        assert_eq!(incoming.peer_addr().ip().to_string(), "127.0.0.1");
+        let mut stream = incoming
+            .handshake(NNpsk0::try_new(&PSK).unwrap())
+            .await
+            .unwrap();
+        stream.send(b"welcome").await.unwrap();
+    });
+
+    // The rejected connection is closed without a word.
+    let mut rejected = connect_from("127.0.0.2", addr).await;
+    let mut buf = [0u8; 64];
+    assert_eq!(rejected.read(&mut buf).await.unwrap_or(0), 0);
+
+    let tcp_stream = connect_from("127.0.0.1", addr).await;
+    let mut client = NoiseTcpStream::handshake_initiator_psk0(tcp_stream, &PSK)
+        .await
+        .unwrap();
+    let n = client.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"welcome");
+    server.await.unwrap();
+}
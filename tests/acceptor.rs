@@ -0,0 +1,108 @@
+//! A `NoiseAcceptor` or `NoiseConnector` is set up once and cloned for every
+//! connection.
+
+use std::time::Duration;
+
+use tokio::{io::duplex, net::TcpListener};
+use tokio_noise::{
+    handshakes::SnowHandshake, HandshakeConfig, NoiseAcceptor, NoiseConnector, NoiseError,
+};
+
+const NAME: &str = "Noise_XX_25519_ChaChaPoly_SHA256";
+
+fn config() -> HandshakeConfig<SnowHandshake> {
+    let key = snow::Builder::new(NAME.parse().unwrap())
+        .generate_keypair()
+        .unwrap();
+    HandshakeConfig::new(
+        SnowHandshake::new(NAME)
+            .unwrap()
+            .local_private_key(&key.private),
+    )
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn one_acceptor_handles_concurrent_connections() {
+    const N_CONNECTIONS: usize = 100;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let acceptor = NoiseAcceptor::new(config()).timeout(Duration::from_secs(10));
+
+    let server = tokio::spawn(async move {
+        let mut tasks = Vec::new();
+        for _ in 0..N_CONNECTIONS {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            let acceptor = acceptor.clone();
+            tasks.push(tokio::spawn(async move {
+                let mut stream = acceptor.accept(tcp_stream).await.unwrap();
+                let mut buf = [0u8; 8];
+                let n = stream.recv(&mut buf).await.unwrap();
+                stream.send(&buf[..n]).await.unwrap();
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+    });
+
+    let connector = NoiseConnector::new(config());
+    let clients: Vec<_> = (0..N_CONNECTIONS as u64)
+        .map(|i| {
+            let connect = connector.connect(addr);
+            tokio::spawn(async move {
+                let mut stream = connect.await.unwrap();
+                stream.send(&i.to_be_bytes()).await.unwrap();
+                let mut buf = [0u8; 8];
+                let n = stream.recv(&mut buf).await.unwrap();
+                assert_eq!(buf[..n], i.to_be_bytes());
+            })
+        })
+        .collect();
+    for client in clients {
+        client.await.unwrap();
+    }
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn connector_is_reused_across_dials() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let acceptor = NoiseAcceptor::new(config());
+    let server = tokio::spawn(async move {
+        let mut keys = Vec::new();
+        for _ in 0..3 {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            let stream = acceptor.accept(tcp_stream).await.unwrap();
+            keys.push(stream.remote_static_key().unwrap().to_vec());
+        }
+        keys
+    });
+
+    let connector = NoiseConnector::new(config());
+    for _ in 0..3 {
+        let stream = connector.connect(addr).await.unwrap();
+        assert!(stream.remote_static_key().is_some());
+    }
+    // Every dial authenticated with the connector's one static key.
+    let keys = server.await.unwrap();
+    assert!(keys.windows(2).all(|pair| pair[0] == pair[1]));
+}
+
+#[tokio::test(start_paused = true)]
+async fn stalled_handshake_times_out() {
+    let acceptor = NoiseAcceptor::new(config()).timeout(Duration::from_secs(5));
+    let (_client, server) = duplex(1024);
+    let result = acceptor.accept(server).await;
+    assert!(matches!(result, Err(NoiseError::DeadlineExceeded)));
+
+    // Nothing answers the connector's handshake either.
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let connector = NoiseConnector::new(config()).timeout(Duration::from_secs(5));
+    let result = connector.connect(listener.local_addr().unwrap()).await;
+    assert!(matches!(
+        result.map_err(NoiseError::without_context),
+        Err(NoiseError::DeadlineExceeded)
+    ));
+}
@@ -0,0 +1,172 @@
+use std::{
+    fs,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use tokio::io::{duplex, DuplexStream};
+use tokio_noise::{
+    handshakes::SnowHandshake, AllowlistError, AllowlistFile, Fingerprint, NoiseError, NoiseStream,
+};
+
+const NAME: &str = "Noise_XX_25519_ChaChaPoly_SHA256";
+
+fn generate_keypair() -> snow::Keypair {
+    snow::Builder::new(NAME.parse().unwrap())
+        .generate_keypair()
+        .unwrap()
+}
+
+fn hex(key: &[u8]) -> String {
+    key.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Writes a file unique to this test process, returning its path.
+fn write_fixture(name: &str, contents: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("tokio-noise-{}-{}.allow", std::process::id(), name));
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+/// Rewrites a fixture, moving its modification time forward so the change is seen
+/// even on filesystems with coarse timestamps.
+fn edit_fixture(path: &PathBuf, contents: &str) {
+    fs::write(path, contents).unwrap();
+    let file = fs::File::options().write(true).open(path).unwrap();
+    file.set_modified(SystemTime::now() + Duration::from_secs(10))
+        .unwrap();
+}
+
+/// Runs an XX handshake from a client with the given key to a server which checks the
+/// allowlist, returning the client's stream and the server's result.
+async fn connect(
+    client_key: &snow::Keypair,
+    allowlist: &AllowlistFile,
+) -> (
+    NoiseStream<DuplexStream>,
+    Result<NoiseStream<DuplexStream>, NoiseError>,
+) {
+    let server_key = generate_keypair();
+    let (client, server) = duplex(64 * 1024);
+    let initiator = SnowHandshake::new(NAME)
+        .unwrap()
+        .local_private_key(&client_key.private);
+    let responder = SnowHandshake::new(NAME)
+        .unwrap()
+        .local_private_key(&server_key.private)
+        .remote_allowlist(allowlist.clone());
+    let (client, server) = tokio::join!(
+        NoiseStream::handshake_initiator(client, initiator),
+        NoiseStream::handshake_responder(server, responder),
+    );
+    (client.unwrap(), server)
+}
+
+async fn exchange_data(
+    client: &mut NoiseStream<DuplexStream>,
+    server: &mut NoiseStream<DuplexStream>,
+) {
+    client.send(b"hello").await.unwrap();
+    let mut buf = [0u8; 16];
+    let n = server.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hello");
+}
+
+#[test]
+fn malformed_fixtures_report_line_numbers() {
+    let key = hex(&[7u8; 32]);
+    let cases = [
+        ("odd-hex", format!("{}\n# fine\n{}f bad\n", key, key), 3),
+        ("not-hex", format!("\n\n{} ok\nnot-a-key comment\n", key), 4),
+        ("bad-fingerprint", "SHA256:@@@ laptop\n".to_string(), 1),
+        ("short-fingerprint", format!("{}\nSHA256:AAAA\n", key), 2),
+    ];
+    for (name, contents, line) in cases {
+        let path = write_fixture(name, &contents);
+        match AllowlistFile::open(&path) {
+            Err(e @ AllowlistError::Parse { .. }) => {
+                assert!(
+                    e.to_string().contains(&format!("line {} ", line)),
+                    "{}: {}",
+                    name,
+                    e
+                );
+            }
+            other => panic!("{}: expected a parse error, got {:?}", name, other),
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    let missing = std::env::temp_dir().join("tokio-noise-missing.allow");
+    assert!(matches!(
+        AllowlistFile::open(missing),
+        Err(AllowlistError::Io(_))
+    ));
+}
+
+#[tokio::test]
+async fn only_listed_keys_may_connect() {
+    let (by_hex, by_fingerprint, unlisted) =
+        (generate_keypair(), generate_keypair(), generate_keypair());
+    let path = write_fixture(
+        "listed",
+        &format!(
+            "# allowed clients\n{} by-hex\n\n{}  by-fingerprint\n",
+            hex(&by_hex.public),
+            Fingerprint::of(&by_fingerprint.public)
+        ),
+    );
+    let allowlist = AllowlistFile::open(&path).unwrap();
+    assert_eq!(allowlist.len(), 2);
+
+    for key in [&by_hex, &by_fingerprint] {
+        let (mut client, server) = connect(key, &allowlist).await;
+        exchange_data(&mut client, &mut server.unwrap()).await;
+    }
+
+    match connect(&unlisted, &allowlist).await.1 {
+        Err(NoiseError::PeerNotAllowed(got)) => {
+            assert_eq!(got, Fingerprint::of(&unlisted.public))
+        }
+        result => panic!("expected PeerNotAllowed, got {:?}", result.map(drop)),
+    }
+    fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn edits_apply_to_new_handshakes_only() {
+    let (alice, bob) = (generate_keypair(), generate_keypair());
+    let path = write_fixture("edited", &format!("{} alice\n", hex(&alice.public)));
+    let allowlist = AllowlistFile::open(&path).unwrap();
+    let (mut alice_client, alice_server) = connect(&alice, &allowlist).await;
+    let mut alice_server = alice_server.unwrap();
+    assert!(connect(&bob, &allowlist).await.1.is_err());
+
+    // Swap alice for bob. The change is noticed by the next handshake.
+    edit_fixture(&path, &format!("{} bob\n", hex(&bob.public)));
+    assert!(connect(&bob, &allowlist).await.1.is_ok());
+    assert!(matches!(
+        connect(&alice, &allowlist).await.1,
+        Err(NoiseError::PeerNotAllowed(_))
+    ));
+
+    // Alice's established stream is unaffected.
+    exchange_data(&mut alice_client, &mut alice_server).await;
+
+    // A broken edit leaves the previous keys in force, and reload reports the error.
+    edit_fixture(&path, "not a key\n");
+    assert!(allowlist.allows(&bob.public));
+    assert!(matches!(
+        allowlist.reload(),
+        Err(AllowlistError::Parse { line: 1, .. })
+    ));
+    assert!(allowlist.allows(&bob.public));
+
+    // Once fixed, reload picks the file up straight away.
+    fs::write(&path, format!("{} alice\n", hex(&alice.public))).unwrap();
+    allowlist.reload().unwrap();
+    assert!(allowlist.allows(&alice.public));
+    assert!(!allowlist.allows(&bob.public));
+    fs::remove_file(path).unwrap();
+}
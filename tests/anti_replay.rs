@@ -0,0 +1,144 @@
+//! A responder guarded by a `ReplayGuard` rejects recorded first handshake messages, and
+//! messages stamped outside its skew window.
+
+use std::time::{Duration, SystemTime};
+
+use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio_noise::{
+    handshakes::{AntiReplay, NNpsk0, ReplayGuard},
+    NoiseError, NoiseStream, ReplayError,
+};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+fn handshake(guard: &ReplayGuard) -> AntiReplay<NNpsk0> {
+    NNpsk0::try_new(&PSK).unwrap().anti_replay(guard.clone())
+}
+
+/// A guard whose clock is offset from the real one.
+fn skewed_guard(offset: Duration, ahead: bool) -> ReplayGuard {
+    ReplayGuard::new().clock(move || {
+        if ahead {
+            SystemTime::now() + offset
+        } else {
+            SystemTime::now() - offset
+        }
+    })
+}
+
+async fn connect_pair(
+    initiator: AntiReplay<NNpsk0>,
+    responder: AntiReplay<NNpsk0>,
+) -> (
+    Result<NoiseStream<DuplexStream>, NoiseError>,
+    Result<NoiseStream<DuplexStream>, NoiseError>,
+) {
+    let (client, server) = duplex(64 * 1024);
+    tokio::join!(
+        NoiseStream::handshake_initiator(client, initiator),
+        NoiseStream::handshake_responder(server, responder),
+    )
+}
+
+/// Record the initiator's first handshake message, with its length prefix, as an
+/// attacker on the wire could.
+async fn record_first_message(initiator: AntiReplay<NNpsk0>) -> Vec<u8> {
+    let (client, mut wire) = duplex(64 * 1024);
+    let initiator = tokio::spawn(NoiseStream::handshake_initiator(client, initiator));
+    let mut len = [0u8; 2];
+    wire.read_exact(&mut len).await.unwrap();
+    let mut message = vec![0u8; u16::from_be_bytes(len) as usize];
+    wire.read_exact(&mut message).await.unwrap();
+    initiator.abort();
+    [&len[..], &message].concat()
+}
+
+/// Send a recorded first message to a responder, returning the result of its handshake.
+async fn play(
+    message: &[u8],
+    responder: AntiReplay<NNpsk0>,
+) -> Result<NoiseStream<DuplexStream>, NoiseError> {
+    let (mut wire, server) = duplex(64 * 1024);
+    wire.write_all(message).await.unwrap();
+    let result = NoiseStream::handshake_responder(server, responder).await;
+    drop(wire);
+    result
+}
+
+fn expect_err(result: Result<NoiseStream<DuplexStream>, NoiseError>) -> NoiseError {
+    match result {
+        Ok(_) => panic!("handshake succeeded"),
+        Err(e) => e,
+    }
+}
+
+#[tokio::test]
+async fn handshake_with_fresh_payload_succeeds() {
+    let guard = ReplayGuard::new();
+    let (client, server) = connect_pair(handshake(&guard), handshake(&guard)).await;
+    let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+    client.send(b"hello").await.unwrap();
+    let mut buf = [0u8; 16];
+    let n = server.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hello");
+    assert_eq!(guard.cached_tokens(), 1);
+}
+
+#[tokio::test]
+async fn replay_within_window_is_rejected() {
+    let guard = ReplayGuard::new();
+    let message = record_first_message(handshake(&ReplayGuard::new())).await;
+
+    play(&message, handshake(&guard)).await.unwrap();
+    let e = expect_err(play(&message, handshake(&guard)).await);
+    assert!(matches!(e, NoiseError::Replay(ReplayError::Replayed)));
+}
+
+#[tokio::test]
+async fn stale_timestamp_is_rejected() {
+    let guard = ReplayGuard::new().max_skew(Duration::from_secs(30));
+    let initiator = handshake(&skewed_guard(Duration::from_secs(60), false));
+    let (_, server) = connect_pair(initiator, handshake(&guard)).await;
+    match expect_err(server) {
+        NoiseError::Replay(ReplayError::Stale(age)) => assert!(age >= Duration::from_secs(60)),
+        e => panic!("unexpected error: {}", e),
+    }
+    assert_eq!(guard.cached_tokens(), 0);
+}
+
+#[tokio::test]
+async fn future_timestamp_is_rejected() {
+    let guard = ReplayGuard::new().max_skew(Duration::from_secs(30));
+    let initiator = handshake(&skewed_guard(Duration::from_secs(60), true));
+    let (_, server) = connect_pair(initiator, handshake(&guard)).await;
+    assert!(matches!(
+        expect_err(server),
+        NoiseError::Replay(ReplayError::FromFuture(_))
+    ));
+}
+
+#[tokio::test]
+async fn skew_within_tolerance_is_accepted() {
+    let guard = ReplayGuard::new().max_skew(Duration::from_secs(30));
+    for ahead in [false, true] {
+        let initiator = handshake(&skewed_guard(Duration::from_secs(20), ahead));
+        let (client, server) = connect_pair(initiator, handshake(&guard)).await;
+        client.unwrap();
+        server.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn initiator_without_anti_replay_is_rejected() {
+    let guard = ReplayGuard::new();
+    let (client, server) = duplex(64 * 1024);
+    let (_, server) = tokio::join!(
+        NoiseStream::handshake_initiator(client, NNpsk0::try_new(&PSK).unwrap()),
+        NoiseStream::handshake_responder(server, handshake(&guard)),
+    );
+    assert!(matches!(
+        expect_err(server),
+        NoiseError::Replay(ReplayError::MissingPayload)
+    ));
+}
@@ -0,0 +1,243 @@
+use std::{pin::Pin, task::Poll, time::Duration};
+use tokio::{
+    io::{duplex, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream},
+    net::{TcpListener, TcpSocket, TcpStream},
+    time::timeout,
+};
+use tokio_noise::{handshakes::NNpsk0, NoiseBuilder, NoiseStream, NoiseTcpStream};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+/// The size of each encrypted transport frame on the wire.
+const FRAME_SIZE: usize = 2048;
+
+const WATERMARK: usize = 16 * 1024;
+
+#[tokio::test]
+async fn writes_to_stalled_peer_are_bounded_by_watermark() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let srv = tokio::spawn(async move {
+        let (tcp_stream, _) = listener.accept().await.unwrap();
+        NoiseTcpStream::handshake_responder_psk0(tcp_stream, &PSK)
+            .await
+            .unwrap()
+    });
+
+    let tcp_stream = TcpStream::connect(addr).await.unwrap();
+    let mut client = NoiseBuilder::new()
+        .write_high_watermark(WATERMARK)
+        .handshake_initiator(tcp_stream, NNpsk0::try_new(&PSK).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(client.write_high_watermark(), WATERMARK);
+
+    // The server doesn't read anything, so eventually the kernel buffers fill up
+    // and the writer must stall.
+    let mut server = srv.await.unwrap();
+    let chunk = [0xAB; 1024];
+    let mut total_written = 0;
+    loop {
+        match timeout(Duration::from_millis(200), client.write(&chunk)).await {
+            Ok(result) => total_written += result.unwrap(),
+            Err(_) => break,
+        }
+        assert!(client.pending_write_bytes() < WATERMARK + FRAME_SIZE);
+    }
+    assert!(client.pending_write_bytes() >= WATERMARK);
+
+    // Once the peer starts reading, flushing drains the buffer past the watermark.
+    let reader = tokio::spawn(async move {
+        let mut buf = vec![0u8; total_written];
+        server.read_exact(&mut buf).await.unwrap();
+        assert!(buf.iter().all(|&b| b == 0xAB));
+    });
+    client.flush().await.unwrap();
+    assert_eq!(client.pending_write_bytes(), 0);
+    reader.await.unwrap();
+}
+
+#[tokio::test]
+async fn shutdown_flushes_buffered_ciphertext() {
+    const SIZE: usize = 1024 * 1024;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let srv = tokio::spawn(async move {
+        let (tcp_stream, _) = listener.accept().await.unwrap();
+        let mut server = NoiseTcpStream::handshake_responder_psk0(tcp_stream, &PSK)
+            .await
+            .unwrap();
+        let mut received = Vec::new();
+        server.read_to_end(&mut received).await.unwrap();
+        received
+    });
+
+    // A tiny send buffer leaves plenty of ciphertext in the stream's own buffer.
+    let socket = TcpSocket::new_v4().unwrap();
+    socket.set_send_buffer_size(4096).unwrap();
+    let tcp_stream = socket.connect(addr).await.unwrap();
+    let mut client = NoiseTcpStream::handshake_initiator_psk0(tcp_stream, &PSK)
+        .await
+        .unwrap();
+
+    let data: Vec<u8> = (0..SIZE).map(|i| i as u8).collect();
+    client.write_all(&data).await.unwrap();
+    assert!(client.pending_write_bytes() > 0);
+    client.shutdown().await.unwrap();
+    assert_eq!(client.pending_write_bytes(), 0);
+
+    let received = timeout(Duration::from_secs(10), srv)
+        .await
+        .expect("server never saw EOF")
+        .unwrap();
+    assert_eq!(received.len(), SIZE);
+    assert!(received == data);
+}
+
+/// Polls a write once, returning the bytes accepted, or `None` if it is pending.
+async fn try_write(stream: &mut NoiseStream<DuplexStream>, buf: &[u8]) -> Option<usize> {
+    std::future::poll_fn(|cx| match Pin::new(&mut *stream).poll_write(cx, buf) {
+        Poll::Ready(result) => Poll::Ready(Some(result.unwrap())),
+        Poll::Pending => Poll::Ready(None),
+    })
+    .await
+}
+
+#[tokio::test]
+async fn large_write_is_encrypted_in_one_poll() {
+    let (client, server) = duplex(4096);
+    let builder = NoiseBuilder::new().write_high_watermark(WATERMARK);
+    let (client, _server) = tokio::join!(
+        builder.handshake_initiator(client, NNpsk0::try_new(&PSK).unwrap()),
+        NoiseStream::handshake_responder_psk0(server, &PSK),
+    );
+    let mut client = client.unwrap();
+
+    // One poll consumes many frames' worth of a large buffer, but no more than the
+    // watermark allows.
+    let data = vec![0xAB; 1024 * 1024];
+    let written = try_write(&mut client, &data).await.unwrap();
+    assert!(written > 4 * FRAME_SIZE, "only {} bytes written", written);
+    assert!(written < WATERMARK + FRAME_SIZE);
+    assert!(client.pending_write_bytes() < WATERMARK + FRAME_SIZE);
+}
+
+#[tokio::test]
+async fn writes_resume_below_low_watermark() {
+    const PIPE_SIZE: usize = 4096;
+    const LOW_WATERMARK: usize = 4 * 1024;
+
+    let (client, server) = duplex(PIPE_SIZE);
+    let builder = NoiseBuilder::new()
+        .write_high_watermark(WATERMARK)
+        .write_low_watermark(LOW_WATERMARK);
+    let (client, server) = tokio::join!(
+        builder.handshake_initiator(client, NNpsk0::try_new(&PSK).unwrap()),
+        NoiseStream::handshake_responder_psk0(server, &PSK),
+    );
+    let (mut client, mut server) = (client.unwrap(), server.unwrap());
+    assert_eq!(client.write_low_watermark(), LOW_WATERMARK);
+
+    // Nobody reads the pipe, so writes stall at the high watermark.
+    let chunk = [0xAB; 1024];
+    while try_write(&mut client, &chunk).await.is_some() {}
+    assert!(client.pending_write_bytes() >= WATERMARK);
+
+    // Each time the peer empties the pipe, the stream drains another pipe's worth of
+    // its buffer, but writes stay blocked until it falls below the low watermark,
+    // although it is already below the high watermark after the first.
+    let mut raw = vec![0u8; PIPE_SIZE];
+    let mut drains = 0;
+    loop {
+        server.get_mut().read_exact(&mut raw).await.unwrap();
+        drains += 1;
+        let buffered_before = client.pending_write_bytes();
+        match try_write(&mut client, &chunk).await {
+            Some(_) => {
+                assert!(buffered_before - PIPE_SIZE < LOW_WATERMARK);
+                break;
+            }
+            None => assert!(client.pending_write_bytes() >= LOW_WATERMARK),
+        }
+    }
+    assert!(drains > 1);
+
+    // Lowering the limit blocks writes again straight away.
+    client.set_write_buffer_limit(0);
+    assert_eq!(client.write_low_watermark(), 0);
+    assert!(try_write(&mut client, &chunk).await.is_none());
+}
+
+#[tokio::test]
+async fn buffered_byte_counts_track_a_stalled_reader() {
+    let (client, server) = duplex(4096);
+    let builder = NoiseBuilder::new().write_high_watermark(WATERMARK);
+    let (client, server) = tokio::join!(
+        builder.handshake_initiator(client, NNpsk0::try_new(&PSK).unwrap()),
+        NoiseStream::handshake_responder_psk0(server, &PSK),
+    );
+    let (mut client, mut server) = (client.unwrap(), server.unwrap());
+    assert_eq!(client.pending_write_bytes(), 0);
+
+    // While the server reads nothing, ciphertext piles up to the watermark.
+    let chunk = [0xAB; 1024];
+    let mut total_written = 0;
+    while let Some(n) = try_write(&mut client, &chunk).await {
+        total_written += n;
+    }
+    let pending = client.pending_write_bytes();
+    assert!((WATERMARK..WATERMARK + FRAME_SIZE).contains(&pending));
+    assert_eq!(client.stats().pending_write_bytes, pending);
+
+    // A short read leaves the rest of the frame decrypted and buffered.
+    let mut byte = [0u8; 1];
+    server.read_exact(&mut byte).await.unwrap();
+    let buffered = server.buffered_read_bytes();
+    assert!(buffered > 0);
+    assert_eq!(server.stats().buffered_read_bytes, buffered);
+
+    // Once the server reads again, everything drains.
+    let mut rest = vec![0u8; total_written - 1];
+    let (flushed, read) = tokio::join!(client.flush(), server.read_exact(&mut rest));
+    flushed.unwrap();
+    read.unwrap();
+    assert_eq!(client.pending_write_bytes(), 0);
+    assert_eq!(server.buffered_read_bytes(), 0);
+    assert_eq!(server.stats().buffered_read_bytes, 0);
+}
+
+#[tokio::test]
+async fn send_flushes_to_a_slow_peer() {
+    const SIZE: usize = 256 * 1024;
+
+    let (client, server) = duplex(4096);
+    let builder = NoiseBuilder::new().write_high_watermark(WATERMARK);
+    let (client, server) = tokio::join!(
+        builder.handshake_initiator(client, NNpsk0::try_new(&PSK).unwrap()),
+        NoiseStream::handshake_responder_psk0(server, &PSK),
+    );
+    let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+    // The server reads in small pieces, yielding in between, so the pipe is usually
+    // full and the client's writes keep stalling.
+    let reader = tokio::spawn(async move {
+        let mut received = Vec::with_capacity(SIZE);
+        let mut buf = [0u8; 512];
+        while received.len() < SIZE {
+            let n = server.read(&mut buf).await.unwrap();
+            received.extend_from_slice(&buf[..n]);
+            tokio::task::yield_now().await;
+        }
+        received
+    });
+
+    // `write_all` alone could return with up to the watermark still buffered, but
+    // `send` waits until all of it has been handed to the transport.
+    let data: Vec<u8> = (0..SIZE).map(|i| i as u8).collect();
+    client.send(&data).await.unwrap();
+    assert_eq!(client.pending_write_bytes(), 0);
+    assert!(reader.await.unwrap() == data);
+}
@@ -0,0 +1,56 @@
+#![cfg(feature = "blocking")]
+
+use std::io::{BufRead, BufReader, Read, Write};
+
+use tokio::{
+    io::{duplex, AsyncBufReadExt, AsyncWriteExt},
+    runtime::Runtime,
+};
+use tokio_noise::{NoiseStream, SyncNoiseStream};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+#[test]
+fn sync_caller_exchanges_lines() {
+    let rt = Runtime::new().unwrap();
+    let (client, server) = duplex(64 * 1024);
+
+    // An async server which echoes each line back in upper case.
+    let server = rt.spawn(async move {
+        let server = NoiseStream::handshake_responder_psk0(server, &PSK).await?;
+        let mut server = tokio::io::BufReader::new(server);
+        let mut line = String::new();
+        while server.read_line(&mut line).await? > 0 {
+            server
+                .get_mut()
+                .send(line.to_uppercase().as_bytes())
+                .await?;
+            line.clear();
+        }
+        Ok::<_, tokio_noise::NoiseError>(())
+    });
+
+    let client = rt
+        .block_on(NoiseStream::handshake_initiator_psk0(client, &PSK))
+        .unwrap();
+    let mut client = BufReader::new(SyncNoiseStream::new(client, rt.handle().clone()));
+
+    let mut response = String::new();
+    for request in ["hello\n", "world\n"] {
+        client.get_mut().write_all(request.as_bytes()).unwrap();
+        client.get_mut().flush().unwrap();
+        response.clear();
+        client.read_line(&mut response).unwrap();
+        assert_eq!(response, request.to_uppercase());
+    }
+
+    // Shutting down ends the server's loop, after which the client reads EOF.
+    let mut client = client.into_inner().into_inner();
+    rt.block_on(client.shutdown()).unwrap();
+    rt.block_on(server).unwrap().unwrap();
+    let mut rest = Vec::new();
+    SyncNoiseStream::new(client, rt.handle().clone())
+        .read_to_end(&mut rest)
+        .unwrap();
+    assert!(rest.is_empty());
+}
@@ -0,0 +1,69 @@
+#![cfg(feature = "bolt8")]
+
+use tokio::io::{duplex, DuplexStream};
+use tokio_noise::{
+    secp256k1::{PublicKey, Secp256k1, SecretKey},
+    Bolt8Stream, NoiseError, BOLT8_MAX_MESSAGE_LEN,
+};
+
+fn keypair(byte: u8) -> (SecretKey, PublicKey) {
+    let secret = SecretKey::from_slice(&[byte; 32]).unwrap();
+    (
+        secret,
+        PublicKey::from_secret_key(&Secp256k1::new(), &secret),
+    )
+}
+
+async fn connect_pair(
+    client_key: &SecretKey,
+    server_id: &PublicKey,
+    server_key: &SecretKey,
+) -> (
+    Result<Bolt8Stream<DuplexStream>, NoiseError>,
+    Result<Bolt8Stream<DuplexStream>, NoiseError>,
+) {
+    let (client, server) = duplex(256 * 1024);
+    tokio::join!(
+        Bolt8Stream::handshake_initiator(client, client_key, server_id),
+        Bolt8Stream::handshake_responder(server, server_key),
+    )
+}
+
+#[tokio::test]
+async fn messages_round_trip_across_key_rotations() {
+    let (client_key, server_key) = (keypair(0x11), keypair(0x21));
+    let (client, server) = connect_pair(&client_key.0, &server_key.1, &server_key.0).await;
+    let (mut client, mut server) = (client.unwrap(), server.unwrap());
+    assert_eq!(client.remote_node_id(), server_key.1);
+    assert_eq!(server.remote_node_id(), client_key.1);
+
+    // Each message takes two nonces, so this crosses several rotations each way.
+    for i in 0..1200u32 {
+        let message = i.to_be_bytes();
+        client.send_message(&message).await.unwrap();
+        assert_eq!(server.recv_message().await.unwrap(), message);
+        server.send_message(&message).await.unwrap();
+        assert_eq!(client.recv_message().await.unwrap(), message);
+    }
+
+    let empty: &[u8] = &[];
+    let largest = vec![0xAB; BOLT8_MAX_MESSAGE_LEN];
+    for message in [empty, &largest] {
+        client.send_message(message).await.unwrap();
+        assert_eq!(server.recv_message().await.unwrap(), message);
+    }
+    assert!(matches!(
+        client.send_message(&[0; BOLT8_MAX_MESSAGE_LEN + 1]).await,
+        Err(NoiseError::MessageTooLarge { .. })
+    ));
+}
+
+#[tokio::test]
+async fn handshake_to_wrong_node_fails() {
+    let (client_key, server_key, other) = (keypair(0x11), keypair(0x21), keypair(0x31));
+    let (_, server) = connect_pair(&client_key.0, &other.1, &server_key.0).await;
+    assert!(matches!(
+        server.map(|_| ()),
+        Err(NoiseError::Snow(snow::Error::Decrypt))
+    ));
+}
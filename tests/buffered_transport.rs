@@ -0,0 +1,35 @@
+use std::time::Duration;
+use tokio::{
+    io::{duplex, BufWriter},
+    time::timeout,
+};
+use tokio_noise::NoiseStream;
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+/// A `BufWriter` holds back the handshake messages until it is flushed.
+#[tokio::test]
+async fn handshake_over_buffered_transport() {
+    let (client, server) = duplex(64 * 1024);
+    let (client, server) = (BufWriter::new(client), BufWriter::new(server));
+
+    let handshakes = async {
+        tokio::join!(
+            NoiseStream::handshake_initiator_psk0(client, &PSK),
+            NoiseStream::handshake_responder_psk0(server, &PSK),
+        )
+    };
+    let (client, server) = timeout(Duration::from_secs(5), handshakes)
+        .await
+        .expect("handshake deadlocked");
+    let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+    let mut buf = [0u8; 64];
+    client.send(b"hello").await.unwrap();
+    let n = server.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hello");
+
+    server.send(b"world").await.unwrap();
+    let n = client.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"world");
+}
@@ -0,0 +1,142 @@
+use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio_noise::{
+    handshakes::NNpsk0, NoiseBuilder, NoiseError, NoiseStream, MAX_CLOSE_REASON_LEN,
+};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+async fn connect_pair() -> (NoiseStream<DuplexStream>, NoiseStream<DuplexStream>) {
+    let (client, server) = duplex(64 * 1024);
+    let (client, server) = tokio::join!(
+        NoiseStream::handshake_initiator_psk0(client, &PSK),
+        NoiseStream::handshake_responder_psk0(server, &PSK),
+    );
+    (client.unwrap(), server.unwrap())
+}
+
+#[tokio::test]
+async fn peer_observes_close_code_and_reason() {
+    let (mut client, mut server) = connect_pair().await;
+
+    server.send(b"goodbye").await.unwrap();
+    server.close_with_error(403, "unauthorized").await.unwrap();
+
+    // Data sent before the close is still delivered first.
+    let mut buf = [0u8; 64];
+    let n = client.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"goodbye");
+    assert_eq!(client.closed_reason(), Some((403, "unauthorized")));
+
+    for _ in 0..2 {
+        match client.recv(&mut buf).await {
+            Err(NoiseError::ClosedByPeer { code, reason }) => {
+                assert_eq!(code, 403);
+                assert_eq!(reason, "unauthorized");
+            }
+            result => panic!("expected close by peer, got {:?}", result),
+        }
+    }
+}
+
+#[tokio::test]
+async fn close_reason_is_truncated() {
+    let (mut client, mut server) = connect_pair().await;
+
+    // Multi-byte characters straddle the cap, so the reason is cut short of it.
+    let reason = "é".repeat(MAX_CLOSE_REASON_LEN);
+    server.close_with_error(1, &reason).await.unwrap();
+
+    match client.recv(&mut [0u8; 64]).await {
+        Err(NoiseError::ClosedByPeer { code: 1, reason }) => {
+            assert_eq!(reason, "é".repeat(MAX_CLOSE_REASON_LEN / 2));
+        }
+        result => panic!("expected close by peer, got {:?}", result),
+    }
+}
+
+#[tokio::test]
+async fn normal_close_reads_as_eof() {
+    let (mut client, mut server) = connect_pair().await;
+
+    server.send(b"goodbye").await.unwrap();
+    server.shutdown().await.unwrap();
+
+    let mut buf = [0u8; 64];
+    let n = client.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"goodbye");
+    assert_eq!(client.recv(&mut buf).await.unwrap(), 0);
+    assert_eq!(client.closed_reason(), None);
+}
+
+#[tokio::test]
+async fn close_delivers_buffered_data_and_waits_for_peer() {
+    let (mut client, mut server) = connect_pair().await;
+
+    // More than the pipe holds, so the close must wait for the client to read. The
+    // client also sends data which the server never reads, and which close discards.
+    let data: Vec<u8> = (0..256 * 1024).map(|i| i as u8).collect();
+    client.send(b"unread request").await.unwrap();
+
+    let (closed, received) = tokio::join!(
+        async {
+            server.write_all(&data).await?;
+            server.close().await
+        },
+        async {
+            let mut received = Vec::new();
+            client.read_to_end(&mut received).await.unwrap();
+            client.shutdown().await.unwrap();
+            received
+        }
+    );
+    closed.unwrap();
+    assert_eq!(received, data);
+}
+
+#[tokio::test]
+async fn close_reports_peer_error() {
+    let (mut client, server) = connect_pair().await;
+
+    let (closed, _) = tokio::join!(server.close(), client.close_with_error(7, "busy"));
+    match closed {
+        Err(NoiseError::ClosedByPeer { code: 7, reason }) => assert_eq!(reason, "busy"),
+        result => panic!("expected close by peer, got {:?}", result),
+    }
+}
+
+#[tokio::test]
+async fn transport_closed_without_close_notify_is_an_error() {
+    let (mut client, mut server) = connect_pair().await;
+
+    server.send(b"partial").await.unwrap();
+    server.get_mut().shutdown().await.unwrap();
+
+    // Data which arrived is still delivered, then the truncation is reported.
+    let mut buf = [0u8; 64];
+    let n = client.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"partial");
+    for _ in 0..2 {
+        match client.recv(&mut buf).await {
+            Err(NoiseError::UnexpectedClose) => {}
+            result => panic!("expected unexpected close, got {:?}", result),
+        }
+    }
+}
+
+#[tokio::test]
+async fn unexpected_close_can_read_as_eof() {
+    let (client, server) = duplex(64 * 1024);
+    let builder = NoiseBuilder::new().allow_unexpected_close(true);
+    let (client, server) = tokio::join!(
+        builder.handshake_initiator(client, NNpsk0::try_new(&PSK).unwrap()),
+        NoiseStream::handshake_responder_psk0(server, &PSK),
+    );
+    let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+    server.send(b"partial").await.unwrap();
+    server.get_mut().shutdown().await.unwrap();
+
+    let mut received = Vec::new();
+    client.read_to_end(&mut received).await.unwrap();
+    assert_eq!(received, b"partial");
+}
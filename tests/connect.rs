@@ -0,0 +1,161 @@
+//! `NoiseTcpStream::connect_with` connects and handshakes in one step, with any
+//! handshake pattern.
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use tokio::net::TcpListener;
+use tokio_noise::{
+    handshakes::{NNpsk0, SnowHandshake},
+    HandshakeConfig, NoiseConnector, NoiseError, NoiseTcpListener, NoiseTcpStream,
+};
+
+const NAME: &str = "Noise_XX_25519_ChaChaPoly_SHA256";
+const PSK: [u8; 32] = [0xFF; 32];
+
+/// A loopback address other than the default source address.
+const SOURCE: IpAddr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2));
+
+fn xx_handshake() -> SnowHandshake {
+    let key = snow::Builder::new(NAME.parse().unwrap())
+        .generate_keypair()
+        .unwrap();
+    SnowHandshake::new(NAME)
+        .unwrap()
+        .local_private_key(&key.private)
+}
+
+/// An address on which nothing is listening.
+async fn refusing_addr() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap()
+}
+
+#[tokio::test]
+async fn connects_with_any_handshake() {
+    let listener = NoiseTcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let mut stream = listener
+            .accept()
+            .await
+            .unwrap()
+            .handshake(xx_handshake())
+            .await
+            .unwrap();
+        stream.send(b"hello").await.unwrap();
+    });
+
+    let mut client = NoiseTcpStream::connect_with(addr, xx_handshake())
+        .await
+        .unwrap();
+    assert_eq!(client.peer_addr().unwrap(), addr);
+    assert!(client.remote_static_key().is_some());
+    let mut buf = [0u8; 16];
+    let n = client.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hello");
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn later_addresses_are_tried_when_earlier_ones_refuse() {
+    let listener = NoiseTcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addrs = [refusing_addr().await, listener.local_addr().unwrap()];
+    let server = tokio::spawn(async move {
+        let incoming = listener.accept().await.unwrap();
+        incoming
+            .handshake(NNpsk0::try_new(&PSK).unwrap())
+            .await
+            .unwrap();
+    });
+
+    let client = NoiseTcpStream::connect_with(&addrs[..], NNpsk0::try_new(&PSK).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(client.peer_addr().unwrap(), addrs[1]);
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn connect_errors_are_distinguished_from_handshake_errors() {
+    let e = NoiseTcpStream::connect_with(refusing_addr().await, NNpsk0::try_new(&PSK).unwrap())
+        .await
+        .err()
+        .expect("connect succeeded");
+    assert!(matches!(e, NoiseError::Connect(_)), "{}", e);
+
+    // The peer accepts the connection, but expects a different handshake.
+    let listener = NoiseTcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let incoming = listener.accept().await.unwrap();
+        let _ = incoming
+            .handshake(NNpsk0::try_new(&[0x11; 32]).unwrap())
+            .await;
+    });
+    let e = NoiseTcpStream::connect_with(addr, NNpsk0::try_new(&PSK).unwrap())
+        .await
+        .err()
+        .expect("connect succeeded");
+    assert!(!matches!(e.without_context(), NoiseError::Connect(_)));
+}
+
+#[tokio::test]
+async fn connector_binds_the_source_address() {
+    let listener = NoiseTcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let incoming = listener.accept().await.unwrap();
+        let peer_addr = incoming.peer_addr();
+        incoming
+            .handshake(NNpsk0::try_new(&PSK).unwrap())
+            .await
+            .unwrap();
+        peer_addr
+    });
+
+    let configured = Arc::new(AtomicUsize::new(0));
+    let connector = NoiseConnector::new(HandshakeConfig::new(NNpsk0::try_new(&PSK).unwrap()))
+        .local_addr(SocketAddr::new(SOURCE, 0))
+        .configure_socket({
+            let configured = configured.clone();
+            move |socket| {
+                configured.fetch_add(1, Ordering::SeqCst);
+                // Options are set on the bound, unconnected socket.
+                assert_eq!(socket.local_addr()?.ip(), SOURCE);
+                socket.set_recv_buffer_size(64 * 1024)
+            }
+        });
+    let client = connector.connect(addr).await.unwrap();
+    let local_addr = client.local_addr().unwrap();
+    assert_eq!(local_addr.ip(), SOURCE);
+    assert_eq!(server.await.unwrap(), local_addr);
+    assert_eq!(configured.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn connector_socket_setup_errors_are_connect_errors() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = HandshakeConfig::new(NNpsk0::try_new(&PSK).unwrap());
+
+    let connector = NoiseConnector::new(config.clone())
+        .configure_socket(|_| Err(io::Error::other("refused by configure_socket")));
+    let e = connector.connect(addr).await.map(drop).unwrap_err();
+    assert!(matches!(e, NoiseError::Connect(_)), "{}", e);
+
+    // An IPv6 source can't reach an IPv4 address.
+    let connector = NoiseConnector::new(config.clone()).local_addr("[::1]:0".parse().unwrap());
+    let e = connector.connect(addr).await.map(drop).unwrap_err();
+    assert!(matches!(e, NoiseError::Connect(_)), "{}", e);
+
+    let connector = NoiseConnector::new(config).bind_device("no-such-device0");
+    let e = connector.connect(addr).await.map(drop).unwrap_err();
+    assert!(matches!(e, NoiseError::Connect(_)), "{}", e);
+}
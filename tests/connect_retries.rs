@@ -0,0 +1,99 @@
+//! A `NoiseConnector` retries transient failures with a fresh connection and
+//! handshake, but never retries an authentication failure.
+
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+use tokio_noise::{
+    handshakes::{nn_psk2, NNpsk0, NNpsk2},
+    HandshakeConfig, NoiseConnector, NoiseError, NoiseErrorKind, RetryBackoff,
+};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+#[test]
+fn backoff_doubles_up_to_its_limit() {
+    let backoff = RetryBackoff::exponential(Duration::from_millis(100), Duration::from_secs(1));
+    let delays: Vec<_> = (0..6).map(|retry| backoff.delay(retry)).collect();
+    assert_eq!(
+        delays,
+        [100, 200, 400, 800, 1000, 1000].map(Duration::from_millis)
+    );
+    assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(1));
+    assert_eq!(
+        RetryBackoff::fixed(Duration::from_millis(30)).delay(5),
+        Duration::from_millis(30)
+    );
+}
+
+#[tokio::test]
+async fn retries_until_the_server_accepts() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        // Hang up on the first two attempts partway through their handshakes, as a
+        // restarting server might.
+        for _ in 0..2 {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            drop(tcp_stream);
+        }
+        let (tcp_stream, _) = listener.accept().await.unwrap();
+        let config = HandshakeConfig::new(NNpsk0::try_new(&PSK).unwrap());
+        let mut stream = config.respond(tcp_stream).await.unwrap();
+        let mut buf = [0u8; 16];
+        let n = stream.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"hello");
+    });
+
+    let connector = NoiseConnector::new(HandshakeConfig::new(NNpsk0::try_new(&PSK).unwrap()))
+        .handshake_retries(2)
+        .retry_backoff(RetryBackoff::fixed(Duration::from_millis(10)));
+    let mut stream = connector.connect(addr).await.unwrap();
+    stream.send(b"hello").await.unwrap();
+    server.await.unwrap();
+
+    // With one retry fewer, the second hang-up is reported.
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        for _ in 0..2 {
+            drop(listener.accept().await.unwrap());
+        }
+    });
+    let connector = NoiseConnector::new(HandshakeConfig::new(NNpsk0::try_new(&PSK).unwrap()))
+        .handshake_retries(1)
+        .retry_backoff(RetryBackoff::fixed(Duration::from_millis(10)));
+    let error = connector.connect(addr).await.map(drop).unwrap_err();
+    assert_eq!(error.kind(), NoiseErrorKind::Io);
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn wrong_psk_is_not_retried() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let config =
+            HandshakeConfig::new(NNpsk2::new(nn_psk2::Responder::new(|_: &[u8]| Some(PSK))));
+        let (tcp_stream, _) = listener.accept().await.unwrap();
+        // The responder sends the last message, so only the initiator sees the failure.
+        let _ = config.respond(tcp_stream).await;
+
+        // No second attempt arrives.
+        let retry = tokio::time::timeout(Duration::from_millis(200), listener.accept()).await;
+        assert!(retry.is_err());
+    });
+
+    let connector = NoiseConnector::new(HandshakeConfig::new(NNpsk2::new(
+        nn_psk2::Initiator::try_new(*b"client", &[0xAA; 32]).unwrap(),
+    )))
+    .handshake_retries(3)
+    .retry_backoff(RetryBackoff::fixed(Duration::from_millis(10)));
+    let error = connector.connect(addr).await.map(drop).unwrap_err();
+    assert!(!error.is_transient());
+    assert!(matches!(
+        error.without_context(),
+        NoiseError::Snow(snow::Error::Decrypt)
+    ));
+    server.await.unwrap();
+}
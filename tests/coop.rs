@@ -0,0 +1,65 @@
+#![cfg(feature = "coop")]
+
+use std::{
+    rc::Rc,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use tokio::{
+    io::{duplex, AsyncReadExt, AsyncWriteExt},
+    task::LocalSet,
+};
+use tokio_noise::{NoiseStream, MAX_FRAME_SIZE};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+/// The number of frames the peer has already sent when the reader starts.
+const N_FRAMES: usize = 4096;
+
+/// A reader draining a backlog of frames in a loop shares its thread with other tasks.
+#[tokio::test(flavor = "current_thread")]
+async fn bulk_reads_yield_to_other_tasks() {
+    let (client, server) = duplex(2 * N_FRAMES * MAX_FRAME_SIZE);
+    let (client, server) = tokio::join!(
+        NoiseStream::handshake_initiator_psk0(client, &PSK),
+        NoiseStream::handshake_responder_psk0(server, &PSK),
+    );
+    let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+    let payload = vec![0xAB; N_FRAMES * (MAX_FRAME_SIZE - 19)];
+    client.write_all(&payload).await.unwrap();
+    client.shutdown().await.unwrap();
+
+    // Counts how often the detector gets to run while the reader works through the
+    // backlog. A reader which never yields would let it run only once or twice.
+    let done = Rc::new(AtomicBool::new(false));
+    let ticks = Rc::new(AtomicUsize::new(0));
+    let local = LocalSet::new();
+    local.spawn_local({
+        let (done, ticks) = (done.clone(), ticks.clone());
+        async move {
+            while !done.load(Ordering::Relaxed) {
+                ticks.fetch_add(1, Ordering::Relaxed);
+                tokio::task::yield_now().await;
+            }
+        }
+    });
+    let received = local
+        .run_until(async move {
+            let mut received = Vec::new();
+            server.read_to_end(&mut received).await.unwrap();
+            done.store(true, Ordering::Relaxed);
+            received
+        })
+        .await;
+    assert_eq!(received.len(), payload.len());
+
+    // tokio's budget allows 128 units per poll of a task, so the reader yields at least
+    // once per 128 frames.
+    let ticks = ticks.load(Ordering::Relaxed);
+    assert!(
+        ticks >= N_FRAMES / 128,
+        "detector ran only {} times while {} frames were read",
+        ticks,
+        N_FRAMES
+    );
+}
@@ -0,0 +1,139 @@
+//! Runs under either cryptography backend, so CI can cover both with `cargo test` and
+//! `cargo test --no-default-features --features crypto-rust`. The golden values must not
+//! depend on the backend.
+
+use tokio::io::{duplex, DuplexStream};
+use tokio_noise::{
+    handshakes::{SnowHandshake, CRYPTO_BACKEND},
+    NoiseStream,
+};
+
+/// A deterministic generator, which is NOT secure, for reproducible tests only.
+struct CountingRng(u64);
+
+impl rand_core::RngCore for CountingRng {
+    fn next_u32(&mut self) -> u32 {
+        self.next_u64() as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1);
+        self.0
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        rand_core::impls::fill_bytes_via_next(self, dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl rand_core::CryptoRng for CountingRng {}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+async fn connect_pair(
+    initiator: SnowHandshake,
+    responder: SnowHandshake,
+) -> (NoiseStream<DuplexStream>, NoiseStream<DuplexStream>) {
+    let (client, server) = duplex(64 * 1024);
+    let (client, server) = tokio::join!(
+        NoiseStream::handshake_initiator(client, initiator),
+        NoiseStream::handshake_responder(server, responder),
+    );
+    (client.unwrap(), server.unwrap())
+}
+
+async fn exchange_data(
+    client: &mut NoiseStream<DuplexStream>,
+    server: &mut NoiseStream<DuplexStream>,
+) {
+    let mut buf = [0u8; 64];
+    client.send(b"hello").await.unwrap();
+    let n = server.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hello");
+    server.send(b"world").await.unwrap();
+    let n = client.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"world");
+}
+
+#[test]
+fn backend_follows_features() {
+    let expected = if cfg!(feature = "crypto-ring") {
+        "ring"
+    } else {
+        "rust"
+    };
+    assert_eq!(CRYPTO_BACKEND, expected);
+    // Opting in to pure Rust selects it, once ring is off.
+    if cfg!(feature = "crypto-rust") && !cfg!(feature = "crypto-ring") {
+        assert_eq!(CRYPTO_BACKEND, "rust");
+    }
+}
+
+#[tokio::test]
+async fn seeded_handshake_is_identical_on_every_backend() {
+    const NAME: &str = "Noise_XX_25519_ChaChaPoly_SHA256";
+    let seeded = |seed| SnowHandshake::new(NAME).unwrap().rng(CountingRng(seed));
+    let (client_key, server_key) = (
+        seeded(1).generate_keypair().unwrap(),
+        seeded(2).generate_keypair().unwrap(),
+    );
+
+    let (mut client, mut server) = connect_pair(
+        seeded(3).local_private_key(&client_key.private),
+        seeded(4).local_private_key(&server_key.private),
+    )
+    .await;
+    assert_eq!(client.protocol_name(), Some(NAME));
+    assert_eq!(
+        hex(client.handshake_hash().unwrap()),
+        "4275bd2f1995c131874dd2bc6321de04bd235823b98e7528e073007ad6399104",
+        "handshake hash differs on the {} backend",
+        CRYPTO_BACKEND
+    );
+    exchange_data(&mut client, &mut server).await;
+}
+
+/// Peers each using a different backend agree on every cipher and hash.
+#[cfg(feature = "crypto-ring")]
+#[tokio::test]
+async fn backends_interoperate() {
+    use snow::resolvers::{CryptoResolver, DefaultResolver, FallbackResolver, RingResolver};
+
+    let rust = |name: &str| {
+        SnowHandshake::new(name)
+            .unwrap()
+            .with_builder(|params| snow::Builder::with_resolver(params, Box::new(DefaultResolver)))
+    };
+    let ring = |name: &str| {
+        SnowHandshake::new(name).unwrap().with_builder(|params| {
+            snow::Builder::with_resolver(
+                params,
+                Box::new(FallbackResolver::new(
+                    Box::new(RingResolver),
+                    Box::new(DefaultResolver),
+                )),
+            )
+        })
+    };
+
+    for cipher in ["ChaChaPoly", "AESGCM"] {
+        for hash in ["SHA256", "SHA512", "BLAKE2s"] {
+            let name = format!("Noise_NN_25519_{}_{}", cipher, hash);
+            let (mut client, mut server) = connect_pair(rust(&name), ring(&name)).await;
+            exchange_data(&mut client, &mut server).await;
+            let (mut client, mut server) = connect_pair(ring(&name), rust(&name)).await;
+            exchange_data(&mut client, &mut server).await;
+        }
+    }
+    // The ring resolver really does provide these primitives, rather than falling back.
+    assert!(RingResolver
+        .resolve_cipher(&snow::params::CipherChoice::AESGCM)
+        .is_some());
+}
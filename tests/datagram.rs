@@ -0,0 +1,134 @@
+use tokio::io::duplex;
+use tokio_noise::{
+    handshakes::NNpsk0, NoiseDatagramCodec, NoiseError, NoncePrefixFormat, DATAGRAM_NONCE_SIZE,
+    DATAGRAM_TAG_SIZE,
+};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+async fn codec_pair() -> (NoiseDatagramCodec, NoiseDatagramCodec) {
+    let (mut client, mut server) = duplex(64 * 1024);
+    let (client, server) = tokio::join!(
+        NoiseDatagramCodec::handshake_initiator(&mut client, NNpsk0::try_new(&PSK).unwrap()),
+        NoiseDatagramCodec::handshake_responder(&mut server, NNpsk0::try_new(&PSK).unwrap()),
+    );
+    (client.unwrap(), server.unwrap())
+}
+
+#[tokio::test]
+async fn datagrams_decrypt_out_of_order_and_duplicated() {
+    let (client, server) = codec_pair().await;
+
+    let datagrams: Vec<Vec<u8>> = (0..5u64)
+        .map(|nonce| {
+            let plaintext = format!("datagram {}", nonce);
+            let mut datagram = vec![0u8; 64];
+            let n = client
+                .seal(nonce, plaintext.as_bytes(), &mut datagram)
+                .unwrap();
+            assert_eq!(n, DATAGRAM_NONCE_SIZE + plaintext.len() + DATAGRAM_TAG_SIZE);
+            datagram.truncate(n);
+            datagram
+        })
+        .collect();
+
+    // The codec doesn't detect duplicates, so each copy decrypts.
+    let mut out = [0u8; 64];
+    for index in [3, 0, 4, 4, 1, 0, 2] {
+        let (nonce, n) = server.open(&datagrams[index], &mut out).unwrap();
+        assert_eq!(nonce, index as u64);
+        assert_eq!(&out[..n], format!("datagram {}", index).as_bytes());
+    }
+}
+
+#[tokio::test]
+async fn both_directions_use_explicit_nonces() {
+    let (client, server) = codec_pair().await;
+
+    let mut ciphertext = [0u8; 64];
+    let mut plaintext = [0u8; 64];
+    let n = server.encrypt(7, b"reply", &mut ciphertext).unwrap();
+    let n = client.decrypt(7, &ciphertext[..n], &mut plaintext).unwrap();
+    assert_eq!(&plaintext[..n], b"reply");
+
+    // A datagram only decrypts with the nonce it was encrypted with.
+    let n = server.encrypt(8, b"reply", &mut ciphertext).unwrap();
+    assert!(matches!(
+        client.decrypt(9, &ciphertext[..n], &mut plaintext),
+        Err(NoiseError::Snow(snow::Error::Decrypt))
+    ));
+}
+
+#[tokio::test]
+async fn tampered_datagrams_are_rejected() {
+    let (client, server) = codec_pair().await;
+
+    let mut datagram = [0u8; 64];
+    let n = client.seal(1, b"hello", &mut datagram).unwrap();
+    let mut out = [0u8; 64];
+
+    // Changing the nonce prefix breaks authentication too.
+    let mut tampered = datagram;
+    tampered[DATAGRAM_NONCE_SIZE - 1] ^= 1;
+    assert!(server.open(&tampered[..n], &mut out).is_err());
+
+    let mut tampered = datagram;
+    tampered[n - 1] ^= 1;
+    assert!(server.open(&tampered[..n], &mut out).is_err());
+
+    assert!(server
+        .open(&datagram[..DATAGRAM_NONCE_SIZE], &mut out)
+        .is_err());
+    assert!(server.open(&datagram[..n], &mut out).is_ok());
+}
+
+#[tokio::test]
+async fn nonce_prefix_formats_round_trip() {
+    for format in [
+        NoncePrefixFormat::BigEndian64,
+        NoncePrefixFormat::LittleEndian64,
+        NoncePrefixFormat::None,
+    ] {
+        let (client, server) = codec_pair().await;
+        let (client, server) = (
+            client.nonce_prefix_format(format),
+            server.nonce_prefix_format(format),
+        );
+
+        let nonce = 0x0102_0304_0506_0708;
+        let mut datagram = [0u8; 64];
+        let n = client.seal(nonce, b"hello", &mut datagram).unwrap();
+        assert_eq!(n, format.len() + b"hello".len() + DATAGRAM_TAG_SIZE);
+        let expected_prefix = match format {
+            NoncePrefixFormat::BigEndian64 => nonce.to_be_bytes().to_vec(),
+            NoncePrefixFormat::LittleEndian64 => nonce.to_le_bytes().to_vec(),
+            NoncePrefixFormat::None => Vec::new(),
+        };
+        assert_eq!(&datagram[..format.len()], expected_prefix);
+
+        let mut out = [0u8; 64];
+        if format.is_empty() {
+            assert!(server.open(&datagram[..n], &mut out).is_err());
+            let m = server.decrypt(nonce, &datagram[..n], &mut out).unwrap();
+            assert_eq!(&out[..m], b"hello");
+        } else {
+            let (opened_nonce, m) = server.open(&datagram[..n], &mut out).unwrap();
+            assert_eq!(opened_nonce, nonce);
+            assert_eq!(&out[..m], b"hello");
+        }
+    }
+}
+
+#[tokio::test]
+async fn mismatched_nonce_prefix_formats_fail_to_decrypt() {
+    let (client, server) = codec_pair().await;
+    let server = server.nonce_prefix_format(NoncePrefixFormat::LittleEndian64);
+
+    let mut datagram = [0u8; 64];
+    let n = client.seal(1, b"hello", &mut datagram).unwrap();
+    let mut out = [0u8; 64];
+    assert!(matches!(
+        server.open(&datagram[..n], &mut out),
+        Err(NoiseError::Snow(snow::Error::Decrypt))
+    ));
+}
@@ -0,0 +1,154 @@
+use std::net::SocketAddr;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tokio_noise::{handshakes::NNpsk0, NoiseBuilder, NoiseError, NoiseErrorKind, NoiseTcpStream};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+/// The size of the initiator's first `NNpsk0` handshake message: an ephemeral
+/// public key, plus the tag of an empty encrypted payload.
+const HANDSHAKE_MSG_SIZE: usize = 48;
+
+/// The size of the client's framing preamble, which precedes its first frame.
+const PREAMBLE_SIZE: usize = 64;
+
+/// The size of each encrypted transport frame on the wire.
+const FRAME_SIZE: usize = 2048;
+
+/// The index of the frame carrying the first message sent by the client.
+const FIRST_MESSAGE_FRAME: usize = 0;
+
+/// Copies bytes from `from` to `to`, flipping a bit in the byte at offset `corrupt_at`.
+async fn relay(
+    mut from: tokio::net::tcp::OwnedReadHalf,
+    mut to: tokio::net::tcp::OwnedWriteHalf,
+    corrupt_at: Option<usize>,
+) {
+    let mut offset = 0;
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = match from.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        if let Some(i) = corrupt_at {
+            if (offset..offset + n).contains(&i) {
+                buf[i - offset] ^= 0x01;
+            }
+        }
+        offset += n;
+        if to.write_all(&buf[..n]).await.is_err() {
+            break;
+        }
+    }
+    let _ = to.shutdown().await;
+}
+
+/// Runs a man-in-the-middle proxy in front of `server_addr`, which corrupts the
+/// client-to-server byte stream at the given offset.
+async fn spawn_mitm(server_addr: SocketAddr, corrupt_at: usize) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (client, _) = listener.accept().await.unwrap();
+        let server = TcpStream::connect(server_addr).await.unwrap();
+        let (client_read, client_write) = client.into_split();
+        let (server_read, server_write) = server.into_split();
+        tokio::spawn(relay(client_read, server_write, Some(corrupt_at)));
+        tokio::spawn(relay(server_read, client_write, None));
+    });
+    addr
+}
+
+/// Connects a client to the given address and sends the given messages, one per frame.
+async fn run_client(mitm_addr: SocketAddr, messages: &'static [&'static [u8]]) {
+    let tcp_stream = TcpStream::connect(mitm_addr).await.unwrap();
+    let mut noise_stream = NoiseTcpStream::handshake_initiator_psk0(tcp_stream, &PSK)
+        .await
+        .unwrap();
+    for msg in messages {
+        noise_stream.send(msg).await.unwrap();
+    }
+    // Wait for the server to hang up.
+    let _ = noise_stream.recv(&mut [0u8; 16]).await;
+}
+
+async fn setup(
+    corrupted_frame: usize,
+    builder: NoiseBuilder,
+    messages: &'static [&'static [u8]],
+) -> NoiseTcpStream {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server_addr = listener.local_addr().unwrap();
+    let corrupt_at = HANDSHAKE_MSG_SIZE + PREAMBLE_SIZE + corrupted_frame * FRAME_SIZE + 100;
+    let mitm_addr = spawn_mitm(server_addr, corrupt_at).await;
+
+    tokio::spawn(run_client(mitm_addr, messages));
+
+    let (tcp_stream, _) = listener.accept().await.unwrap();
+    builder
+        .handshake_responder(tcp_stream, NNpsk0::try_new(&PSK).unwrap())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn stream_terminates_after_first_decrypt_failure() {
+    let mut noise_stream = setup(
+        FIRST_MESSAGE_FRAME + 1,
+        NoiseBuilder::new(),
+        &[b"hello", b"world", b"again"],
+    )
+    .await;
+
+    let mut buf = [0u8; 16];
+    let n = noise_stream.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hello");
+
+    for _ in 0..3 {
+        match noise_stream
+            .recv(&mut buf)
+            .await
+            .map_err(NoiseError::without_context)
+        {
+            Err(NoiseError::TooManyDecryptFailures) => {}
+            result => panic!("expected TooManyDecryptFailures, got {:?}", result),
+        }
+    }
+    match noise_stream
+        .send(b"reply")
+        .await
+        .map_err(NoiseError::without_context)
+    {
+        Err(NoiseError::TooManyDecryptFailures) => {}
+        result => panic!("expected TooManyDecryptFailures, got {:?}", result),
+    }
+
+    assert!(noise_stream.is_poisoned());
+    let stats = noise_stream.stats();
+    assert_eq!(stats.decrypt_failures, 1);
+    assert_eq!(stats.consecutive_decrypt_failures, 1);
+}
+
+#[tokio::test]
+async fn stream_tolerates_failures_below_threshold() {
+    let builder = NoiseBuilder::new().max_decrypt_failures(3);
+    let mut noise_stream = setup(FIRST_MESSAGE_FRAME, builder, &[b"hello", b"world"]).await;
+
+    let mut buf = [0u8; 16];
+    match noise_stream.recv(&mut buf).await {
+        Err(e) => assert_eq!(e.kind(), NoiseErrorKind::Decrypt),
+        result => panic!("expected decryption error, got {:?}", result),
+    }
+    assert!(!noise_stream.is_poisoned());
+    assert_eq!(noise_stream.stats().consecutive_decrypt_failures, 1);
+
+    // The next genuine frame decrypts, resetting the consecutive failure count.
+    let n = noise_stream.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"world");
+    let stats = noise_stream.stats();
+    assert_eq!(stats.decrypt_failures, 1);
+    assert_eq!(stats.consecutive_decrypt_failures, 0);
+}
@@ -0,0 +1,74 @@
+//! Errors on a TCP stream carry the stream's name and peer address, so that a process
+//! with many connections can tell which peer misbehaved.
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tokio_noise::{NoiseError, NoiseErrorKind, NoiseTcpStream};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+#[tokio::test]
+async fn handshake_error_names_the_peer() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let client_addr = client.local_addr().unwrap();
+
+    // A length prefix and a first handshake message of garbage.
+    let mut garbage = vec![0, 48];
+    garbage.extend_from_slice(&[0xAB; 48]);
+    client.write_all(&garbage).await.unwrap();
+
+    let (tcp_stream, _) = listener.accept().await.unwrap();
+    let e = match NoiseTcpStream::handshake_responder_psk0(tcp_stream, &PSK).await {
+        Ok(_) => panic!("handshake succeeded"),
+        Err(e) => e,
+    };
+    assert_eq!(e.peer_addr(), Some(client_addr));
+    assert_eq!(e.context().unwrap().stream_name, "responder");
+    assert_eq!(e.kind(), NoiseErrorKind::Decrypt);
+    assert!(e.to_string().contains(&client_addr.to_string()));
+    assert!(matches!(
+        e.without_context(),
+        NoiseError::Snow(snow::Error::Decrypt)
+    ));
+}
+
+#[tokio::test]
+async fn transport_error_names_the_peer() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = tokio::spawn(async move {
+        let tcp_stream = TcpStream::connect(addr).await.unwrap();
+        let client_addr = tcp_stream.local_addr().unwrap();
+        let mut noise_stream = NoiseTcpStream::handshake_initiator_psk0(tcp_stream, &PSK)
+            .await
+            .unwrap();
+        // Garbage in place of the preamble and first frame.
+        noise_stream
+            .get_mut()
+            .write_all(&[0xAB; 4096])
+            .await
+            .unwrap();
+        let _ = noise_stream.get_mut().read(&mut [0u8; 16]).await;
+        client_addr
+    });
+
+    let (tcp_stream, _) = listener.accept().await.unwrap();
+    let mut server = NoiseTcpStream::handshake_responder_psk0(tcp_stream, &PSK)
+        .await
+        .unwrap();
+    server.set_name("tenant-a");
+
+    // Through `AsyncRead`, the context survives the round trip through `io::Error`.
+    let e = server.read(&mut [0u8; 16]).await.unwrap_err();
+    let e = NoiseError::from(e);
+    drop(server);
+    let client_addr = client.await.unwrap();
+    assert_eq!(e.peer_addr(), Some(client_addr));
+    assert_eq!(e.context().unwrap().stream_name, "tenant-a");
+    assert_eq!(e.kind(), NoiseErrorKind::Decrypt);
+}
@@ -0,0 +1,133 @@
+use std::sync::{Arc, Mutex};
+use tokio::io::{duplex, AsyncWriteExt, DuplexStream};
+use tokio_noise::{
+    handshakes::NNpsk0, CloseReason, ConnectionEvents, NoiseBuilder, NoiseError, NoiseErrorKind,
+    NoiseStream,
+};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Close(String, CloseReason),
+    Error(String, NoiseErrorKind),
+}
+
+/// Records every event it receives, in order.
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Vec<Event>>>);
+
+impl Recorder {
+    fn take(&self) -> Vec<Event> {
+        std::mem::take(&mut self.0.lock().unwrap())
+    }
+}
+
+impl ConnectionEvents for Recorder {
+    fn on_close(&self, stream: &str, reason: &CloseReason) {
+        let event = Event::Close(stream.to_string(), reason.clone());
+        self.0.lock().unwrap().push(event);
+    }
+
+    fn on_error(&self, stream: &str, error: &NoiseError) {
+        let event = Event::Error(stream.to_string(), error.kind());
+        self.0.lock().unwrap().push(event);
+    }
+}
+
+async fn connect_pair(
+    recorder: &Recorder,
+) -> (NoiseStream<DuplexStream>, NoiseStream<DuplexStream>) {
+    let builder = NoiseBuilder::new().events(recorder.clone());
+    let (client, server) = duplex(64 * 1024);
+    let (client, server) = tokio::join!(
+        builder.handshake_initiator(client, NNpsk0::try_new(&PSK).unwrap()),
+        builder.handshake_responder(server, NNpsk0::try_new(&PSK).unwrap()),
+    );
+    (client.unwrap(), server.unwrap())
+}
+
+fn close(stream: &str, reason: CloseReason) -> Event {
+    Event::Close(stream.to_string(), reason)
+}
+
+#[tokio::test]
+async fn clean_close_is_reported_once_per_side() {
+    let recorder = Recorder::default();
+    let (mut client, mut server) = connect_pair(&recorder).await;
+
+    client.send(b"hello").await.unwrap();
+    client.shutdown().await.unwrap();
+    let mut buf = [0u8; 64];
+    assert_eq!(server.recv(&mut buf).await.unwrap(), 5);
+    assert_eq!(server.recv(&mut buf).await.unwrap(), 0);
+    // Shutting down after the peer closed doesn't report a second close.
+    server.shutdown().await.unwrap();
+    assert_eq!(server.recv(&mut buf).await.unwrap(), 0);
+
+    assert_eq!(
+        recorder.take(),
+        [
+            close("initiator", CloseReason::Shutdown),
+            close("responder", CloseReason::PeerShutdown),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn close_with_error_is_reported_on_both_sides() {
+    let recorder = Recorder::default();
+    let (mut client, mut server) = connect_pair(&recorder).await;
+
+    server.close_with_error(403, "unauthorized").await.unwrap();
+    let mut buf = [0u8; 64];
+    assert!(client.recv(&mut buf).await.is_err());
+
+    let (code, reason) = (403, "unauthorized".to_string());
+    assert_eq!(
+        recorder.take(),
+        [
+            close(
+                "responder",
+                CloseReason::LocalError {
+                    code,
+                    reason: reason.clone()
+                }
+            ),
+            close("initiator", CloseReason::PeerError { code, reason }),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn errors_and_transport_close_are_reported() {
+    let recorder = Recorder::default();
+    let (mut client, mut server) = connect_pair(&recorder).await;
+    server.set_name("server");
+
+    // Garbage in place of the client's preamble fails to decrypt, and poisons the
+    // stream. Further reads fail without reporting the error again.
+    client.get_mut().write_all(&[0xAB; 64]).await.unwrap();
+    let mut buf = [0u8; 64];
+    assert!(server.recv(&mut buf).await.is_err());
+    assert!(server.recv(&mut buf).await.is_err());
+    assert_eq!(
+        recorder.take(),
+        [Event::Error("server".to_string(), NoiseErrorKind::Decrypt)]
+    );
+
+    // A transport which closes without a close notify.
+    let (mut client, mut server) = connect_pair(&recorder).await;
+    client.get_mut().shutdown().await.unwrap();
+    assert!(matches!(
+        server.recv(&mut buf).await,
+        Err(NoiseError::UnexpectedClose)
+    ));
+    assert_eq!(
+        recorder.take(),
+        [
+            close("responder", CloseReason::TransportClosed),
+            Event::Error("responder".to_string(), NoiseErrorKind::Io),
+        ]
+    );
+}
@@ -0,0 +1,63 @@
+use std::time::Duration;
+
+use tokio::{io::duplex, time::Instant};
+use tokio_noise::{
+    handshakes::SnowHandshake, HandshakeConfig, NoiseBuilder, NoiseError, NoiseStream,
+};
+
+const PSK: [u8; 32] = [0xFF; 32];
+const NAME: &str = "Noise_XX_25519_ChaChaPoly_SHA256";
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn xx() -> SnowHandshake {
+    let key = snow::Builder::new(NAME.parse().unwrap())
+        .generate_keypair()
+        .unwrap();
+    SnowHandshake::new(NAME)
+        .unwrap()
+        .local_private_key(&key.private)
+}
+
+#[tokio::test(start_paused = true)]
+async fn silent_initiator_times_out() {
+    let (_client, server) = duplex(1024);
+    let start = Instant::now();
+    let result =
+        NoiseStream::handshake_responder_psk0_with_first_message_timeout(server, &PSK, TIMEOUT)
+            .await;
+    assert!(matches!(
+        result.map_err(NoiseError::without_context),
+        Err(NoiseError::HandshakeTimeout)
+    ));
+    assert_eq!(start.elapsed(), TIMEOUT);
+
+    // A prompt initiator is unaffected.
+    let (client, server) = duplex(64 * 1024);
+    let (client, server) = tokio::join!(
+        NoiseStream::handshake_initiator_psk0(client, &PSK),
+        NoiseStream::handshake_responder_psk0_with_first_message_timeout(server, &PSK, TIMEOUT),
+    );
+    let (mut client, mut server) = (client.unwrap(), server.unwrap());
+    client.send(b"hello").await.unwrap();
+    let mut buf = [0u8; 16];
+    let n = server.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hello");
+}
+
+#[tokio::test(start_paused = true)]
+async fn later_messages_are_not_timed() {
+    // The initiator's first message is prompt, but its second comes well after the
+    // first-message timeout would have fired.
+    let initiator = HandshakeConfig::new(xx())
+        .with_inter_message_hook(|| Box::pin(tokio::time::sleep(TIMEOUT * 3)));
+    let responder =
+        HandshakeConfig::new(xx()).with_builder(NoiseBuilder::new().first_message_timeout(TIMEOUT));
+
+    let (client, server) = duplex(64 * 1024);
+    let (client, server) = tokio::join!(initiator.initiate(client), responder.respond(server));
+    let (mut client, mut server) = (client.unwrap(), server.unwrap());
+    server.send(b"world").await.unwrap();
+    let mut buf = [0u8; 16];
+    let n = client.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"world");
+}
@@ -0,0 +1,114 @@
+use tokio::io::{duplex, AsyncReadExt, DuplexStream};
+use tokio_noise::{
+    handshakes::NNpsk0, FrameSizing, NoiseBuilder, NoiseStream, MAX_FRAME_SIZE, MIN_FRAME_SIZE,
+};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+/// The size of each side's framing preamble, which precedes its first frame.
+const PREAMBLE_SIZE: usize = 64;
+
+/// The bytes of each frame which carry no data: a tag and a 3-byte header.
+const FRAME_OVERHEAD: usize = 16 + 3;
+
+async fn connect_pair(
+    client_builder: NoiseBuilder,
+) -> (NoiseStream<DuplexStream>, NoiseStream<DuplexStream>) {
+    let (client, server) = duplex(64 * 1024);
+    let server_builder = NoiseBuilder::new();
+    let (client, server) = tokio::join!(
+        client_builder.handshake_initiator(client, NNpsk0::try_new(&PSK).unwrap()),
+        server_builder.handshake_responder(server, NNpsk0::try_new(&PSK).unwrap()),
+    );
+    (client.unwrap(), server.unwrap())
+}
+
+#[tokio::test]
+async fn frames_fit_within_target_mss() {
+    const MSS: usize = 1360;
+    const SIZE: usize = 10_000;
+
+    let builder = NoiseBuilder::new().frame_sizing(FrameSizing::FitMss(MSS));
+    let (mut client, mut server) = connect_pair(builder).await;
+    assert_eq!(client.frame_size(), MSS);
+
+    let data: Vec<u8> = (0..SIZE).map(|i| i as u8).collect();
+    client.send(&data).await.unwrap();
+    let mut received = vec![0u8; SIZE];
+    server.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, data);
+    assert_eq!(server.peer_frame_size(), Some(MSS));
+
+    // Every frame after the preamble is exactly the target size, so none exceeds it.
+    let n_frames = SIZE.div_ceil(MSS - FRAME_OVERHEAD);
+    let written = client.stats().socket_bytes_written as usize;
+    assert_eq!(written, PREAMBLE_SIZE + n_frames * MSS);
+    assert_eq!(server.stats().socket_bytes_read as usize, written);
+
+    // The server still sends the largest frames.
+    server.send(b"reply").await.unwrap();
+    let n = client.recv(&mut received).await.unwrap();
+    assert_eq!(&received[..n], b"reply");
+    assert_eq!(client.peer_frame_size(), Some(MAX_FRAME_SIZE));
+    assert_eq!(
+        server.stats().socket_bytes_written as usize,
+        PREAMBLE_SIZE + MAX_FRAME_SIZE
+    );
+}
+
+#[tokio::test]
+async fn frame_size_is_clamped() {
+    let builder = NoiseBuilder::new().frame_sizing(FrameSizing::FitMss(9000));
+    let (client, _) = connect_pair(builder).await;
+    assert_eq!(client.frame_size(), MAX_FRAME_SIZE);
+
+    let builder = NoiseBuilder::new().frame_sizing(FrameSizing::FitMss(10));
+    let (client, _) = connect_pair(builder).await;
+    assert_eq!(client.frame_size(), MIN_FRAME_SIZE);
+}
+
+#[tokio::test]
+async fn auto_falls_back_to_max_without_mss() {
+    let builder = NoiseBuilder::new().frame_sizing(FrameSizing::Auto);
+    let (client, _) = connect_pair(builder).await;
+    assert_eq!(client.frame_size(), MAX_FRAME_SIZE);
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn auto_fits_tcp_mss() {
+    use tokio::net::{TcpListener, TcpSocket};
+    use tokio_noise::Transport;
+
+    const MSS: u32 = 1200;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let srv = tokio::spawn(async move {
+        let (tcp_stream, _) = listener.accept().await.unwrap();
+        let mut noise_stream = NoiseStream::handshake_responder_psk0(tcp_stream, &PSK)
+            .await
+            .unwrap();
+        let mut buf = vec![0u8; 8192];
+        noise_stream.read_exact(&mut buf).await.unwrap();
+        noise_stream
+    });
+
+    // Clamp the MSS below that of the loopback interface.
+    let socket = TcpSocket::new_v4().unwrap();
+    socket2::SockRef::from(&socket).set_tcp_mss(MSS).unwrap();
+    let tcp_stream = socket.connect(addr).await.unwrap();
+    let mss = tcp_stream.max_segment_size().unwrap();
+    assert!(mss <= MSS as usize);
+
+    let mut client = NoiseBuilder::new()
+        .frame_sizing(FrameSizing::Auto)
+        .handshake_initiator(tcp_stream, NNpsk0::try_new(&PSK).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(client.frame_size(), mss);
+
+    client.send(&[0xAB; 8192]).await.unwrap();
+    let server = srv.await.unwrap();
+    assert_eq!(server.peer_frame_size(), Some(mss));
+}
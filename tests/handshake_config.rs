@@ -0,0 +1,199 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::net::TcpStream;
+use tokio_noise::{
+    handshakes::{nn_psk2, Handshake, NNpsk0, NNpsk2},
+    BoxFuture, HandshakeConfig, NoiseBuilder, NoiseError, NoiseTcpListener,
+};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+/// A server which stores its handshake configuration without any lifetime friction.
+struct Server<H> {
+    listener: NoiseTcpListener,
+    config: HandshakeConfig<H>,
+}
+
+impl<H> Server<H>
+where
+    H: Handshake + Clone + Send + Sync + 'static,
+{
+    async fn bind(config: HandshakeConfig<H>) -> Server<H> {
+        Server {
+            listener: NoiseTcpListener::bind("127.0.0.1:0").await.unwrap(),
+            config,
+        }
+    }
+
+    /// Accept `n` connections, echoing one message back on each.
+    async fn serve(self, n: usize) -> Result<(), NoiseError> {
+        let mut tasks = Vec::new();
+        for _ in 0..n {
+            let incoming = self.listener.accept().await?;
+            let config = self.config.clone();
+            tasks.push(tokio::spawn(async move {
+                let mut noise_stream = incoming.handshake_with(&config).await?;
+                let mut buf = [0u8; 64];
+                let n = noise_stream.recv(&mut buf).await?;
+                noise_stream.send(&buf[..n]).await
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap()?;
+        }
+        Ok(())
+    }
+}
+
+async fn echo(config: &HandshakeConfig<impl Handshake + Clone>, addr: std::net::SocketAddr) {
+    let tcp_stream = TcpStream::connect(addr).await.unwrap();
+    let mut noise_stream = config.initiate(tcp_stream).await.unwrap();
+    noise_stream.send(b"hello").await.unwrap();
+    let mut buf = [0u8; 64];
+    let n = noise_stream.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hello");
+}
+
+#[tokio::test]
+async fn psk0_config_is_cloned_per_connection() {
+    let server_config = {
+        // The PSK is copied into the handshake, so the config outlives it.
+        let psk = PSK.to_vec();
+        HandshakeConfig::new(NNpsk0::try_new(&psk).unwrap())
+            .with_builder(NoiseBuilder::new().max_decrypt_failures(2))
+    };
+    let server = Server::bind(server_config).await;
+    let addr = server.listener.local_addr().unwrap();
+    let srv = tokio::spawn(server.serve(3));
+
+    let client_config = HandshakeConfig::new(NNpsk0::try_new(&PSK).unwrap());
+    for _ in 0..3 {
+        echo(&client_config, addr).await;
+    }
+    srv.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn psk2_responder_config_is_cloned_per_connection() {
+    fn find_psk(identity: &[u8]) -> Option<[u8; 32]> {
+        (identity == b"client_id_123").then_some(PSK)
+    }
+    let server_config = HandshakeConfig::new(NNpsk2::new(nn_psk2::Responder::new(find_psk)));
+    let server = Server::bind(server_config).await;
+    let addr = server.listener.local_addr().unwrap();
+    let srv = tokio::spawn(server.serve(2));
+
+    let client_config = HandshakeConfig::new(NNpsk2::new(
+        nn_psk2::Initiator::try_new(*b"client_id_123", &PSK).unwrap(),
+    ));
+    for _ in 0..2 {
+        echo(&client_config, addr).await;
+    }
+    srv.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn inter_message_hook_runs_between_messages() {
+    const DELAY: Duration = Duration::from_millis(50);
+
+    fn counting_hook(
+        count: &Arc<AtomicUsize>,
+    ) -> impl FnMut() -> BoxFuture<'static, ()> + Send + 'static {
+        let count = count.clone();
+        move || {
+            count.fetch_add(1, Ordering::SeqCst);
+            Box::pin(tokio::time::sleep(DELAY))
+        }
+    }
+
+    let server_count = Arc::new(AtomicUsize::new(0));
+    let server_config = HandshakeConfig::new(NNpsk0::try_new(&PSK).unwrap())
+        .with_inter_message_hook(counting_hook(&server_count));
+    let server = Server::bind(server_config).await;
+    let addr = server.listener.local_addr().unwrap();
+    let srv = tokio::spawn(server.serve(2));
+
+    let client_count = Arc::new(AtomicUsize::new(0));
+    let client_config = HandshakeConfig::new(NNpsk0::try_new(&PSK).unwrap())
+        .with_inter_message_hook(counting_hook(&client_count));
+    let start = Instant::now();
+    for _ in 0..2 {
+        echo(&client_config, addr).await;
+    }
+    srv.await.unwrap().unwrap();
+
+    // NNpsk0 has two messages, so each side pauses once per handshake. The client's
+    // pause overlaps with the server's, as it is waiting for the server's reply.
+    assert_eq!(client_count.load(Ordering::SeqCst), 2);
+    assert_eq!(server_count.load(Ordering::SeqCst), 2);
+    assert!(start.elapsed() >= DELAY * 2);
+}
+
+#[tokio::test]
+async fn audit_hook_reports_each_attempt() {
+    type Record = (
+        Result<tokio_noise::HandshakeOutcome, String>,
+        Option<SocketAddr>,
+    );
+    let records: Arc<Mutex<Vec<Record>>> = Arc::default();
+    let server_config = {
+        let records = records.clone();
+        HandshakeConfig::new(NNpsk0::try_new(&PSK).unwrap()).on_handshake(move |result, addr| {
+            let result = result.cloned().map_err(|e| e.to_string());
+            records.lock().unwrap().push((result, addr));
+        })
+    };
+    let listener = NoiseTcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // One client with the right PSK, and one with the wrong PSK.
+    let srv = tokio::spawn(async move {
+        let mut streams = Vec::new();
+        for _ in 0..2 {
+            let incoming = listener.accept().await.unwrap();
+            streams.push(incoming.handshake_with(&server_config).await.ok());
+        }
+        streams
+    });
+    let good = TcpStream::connect(addr).await.unwrap();
+    let good_addr = good.local_addr().unwrap();
+    let client = HandshakeConfig::new(NNpsk0::try_new(&PSK).unwrap())
+        .initiate(good)
+        .await
+        .unwrap();
+    let bad = TcpStream::connect(addr).await.unwrap();
+    let bad_addr = bad.local_addr().unwrap();
+    let bad_result = HandshakeConfig::new(NNpsk0::try_new(&[0xEE; 32]).unwrap())
+        .initiate(bad)
+        .await;
+    assert!(bad_result.is_err());
+    let streams = srv.await.unwrap();
+    let server_stream = streams[0].as_ref().unwrap();
+
+    let records = records.lock().unwrap();
+    assert_eq!(records.len(), 2);
+
+    let (outcome, addr) = &records[0];
+    let outcome = outcome.as_ref().unwrap();
+    assert_eq!(*addr, Some(good_addr));
+    assert_eq!(
+        outcome.protocol_name,
+        "Noise_NNpsk0_25519_ChaChaPoly_SHA512"
+    );
+    assert_eq!(outcome.params.name, outcome.protocol_name);
+    assert_eq!(outcome.remote_static_key, None);
+    assert_eq!(outcome.peer_fingerprint, None);
+    assert_eq!(Some(&outcome.handshake_hash[..]), client.handshake_hash());
+    assert_eq!(server_stream.handshake_hash(), client.handshake_hash());
+    assert!(outcome.duration < Duration::from_secs(5));
+
+    let (error, addr) = &records[1];
+    assert_eq!(*addr, Some(bad_addr));
+    assert!(error.is_err());
+}
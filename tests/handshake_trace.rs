@@ -0,0 +1,76 @@
+#![cfg(feature = "handshake-trace")]
+
+use std::sync::Mutex;
+use tokio::io::duplex;
+use tokio_noise::NoiseStream;
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+/// Records every log line from this crate.
+struct Recorder(Mutex<Vec<String>>);
+
+impl log::Log for Recorder {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.target().starts_with("tokio_noise")
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static RECORDER: Recorder = Recorder(Mutex::new(Vec::new()));
+
+/// Returns the hex dumped for the given message, from the line which logged it.
+fn dumped(lines: &[String], prefix: &str) -> String {
+    let line = lines
+        .iter()
+        .find(|line| line.starts_with(prefix))
+        .unwrap_or_else(|| panic!("no line starting {:?} in {:#?}", prefix, lines));
+    line.rsplit(' ').next().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn handshake_messages_are_dumped_in_hex() {
+    log::set_logger(&RECORDER).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+
+    let (client, server) = duplex(64 * 1024);
+    let (client, server) = tokio::join!(
+        NoiseStream::handshake_initiator_psk0(client, &PSK),
+        NoiseStream::handshake_responder_psk0(server, &PSK),
+    );
+    let (mut client, mut server) = (client.unwrap(), server.unwrap());
+    client.send(b"hello").await.unwrap();
+    let mut buf = [0u8; 16];
+    server.recv(&mut buf).await.unwrap();
+
+    let lines = RECORDER.0.lock().unwrap().clone();
+    for (message, sender, receiver) in
+        [(1, "initiator", "responder"), (2, "responder", "initiator")]
+    {
+        let sent = dumped(
+            &lines,
+            &format!("[{}] handshake message {} of 2 sent: ", sender, message),
+        );
+        let received = dumped(
+            &lines,
+            &format!(
+                "[{}] handshake message {} of 2 received: ",
+                receiver, message
+            ),
+        );
+        assert_eq!(sent, received);
+        // Each message is an ephemeral key and a tag, behind its 2-byte length prefix.
+        assert_eq!(&sent[..4], "0030");
+        assert_eq!(sent.len(), 2 * (2 + 48));
+    }
+
+    // Transport data is never dumped.
+    let hello = "68656c6c6f";
+    assert!(!lines.iter().any(|line| line.contains(hello)));
+}
@@ -0,0 +1,124 @@
+#![cfg(feature = "hyper")]
+
+//! The http1 tests from the stream module, with hyper driving the Noise stream through
+//! its own `Read` and `Write` traits rather than through `TokioIo`.
+
+use http_body_util::BodyExt;
+use hyper::{body::Incoming, Request, Response};
+use std::future::Future;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    task::spawn,
+};
+use tokio_noise::NoiseTcpStream;
+
+const PSK: [u8; 32] = [10u8; 32];
+
+/// Serves one connection with `service`, and sends `request` to it, returning the
+/// response body.
+async fn round_trip<F, Fut>(service: F, request: Request<String>) -> bytes::Bytes
+where
+    F: Fn(Request<Incoming>) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<Response<String>, hyper::Error>> + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let srv = spawn(async move {
+        let (tcp_stream, _) = listener.accept().await.unwrap();
+        let noise_stream = NoiseTcpStream::handshake_responder_psk0(tcp_stream, &PSK)
+            .await
+            .expect("noise handshake failed on server side");
+        hyper::server::conn::http1::Builder::new()
+            .serve_connection(noise_stream, hyper::service::service_fn(service))
+            .await
+            .expect("error serving HTTP1 request");
+    });
+
+    let tcp_stream = TcpStream::connect(&addr).await.unwrap();
+    let noise_stream = NoiseTcpStream::handshake_initiator_psk0(tcp_stream, &PSK)
+        .await
+        .expect("noise handshake failed on client side");
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(noise_stream)
+        .await
+        .expect("client failed to run HTTP1 handshake");
+
+    // Spawn a task to poll the connection, driving the HTTP state
+    let driver = spawn(async move {
+        conn.await.expect("client connection driver failed");
+    });
+
+    let res = sender
+        .send_request(request)
+        .await
+        .expect("client failed to send HTTP1 request");
+    assert_eq!(res.status(), 200);
+    let response_bytes = res
+        .collect()
+        .await
+        .expect("client error reading response body")
+        .to_bytes();
+
+    // Close the connection
+    drop(sender);
+    driver.await.unwrap();
+    srv.await.unwrap();
+    response_bytes
+}
+
+#[tokio::test]
+async fn http1_get() {
+    let response = round_trip(
+        |_req| async { Ok(Response::new("Hello world!".to_string())) },
+        Request::builder().body("".to_string()).unwrap(),
+    )
+    .await;
+    assert_eq!(response, b"Hello world!".as_ref());
+}
+
+#[tokio::test]
+async fn http1_post() {
+    let response = round_trip(
+        |req: Request<Incoming>| async move {
+            let request_bytes = req
+                .collect()
+                .await
+                .expect("server error reading request body")
+                .to_bytes();
+            assert_eq!(request_bytes, b"Client says hi".as_ref());
+            Ok(Response::new("Hello client!".to_string()))
+        },
+        Request::builder()
+            .method("POST")
+            .body("Client says hi".to_string())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(response, b"Hello client!".as_ref());
+}
+
+#[tokio::test]
+async fn http1_post_large() {
+    let expected_body = "hello".repeat(3000);
+    let response = round_trip(
+        |req: Request<Incoming>| async move {
+            let expected_body = "hello".repeat(3000);
+            let request_bytes = req
+                .collect()
+                .await
+                .expect("server error reading request body")
+                .to_bytes();
+            assert_eq!(
+                String::from_utf8_lossy(&request_bytes).as_ref(),
+                expected_body
+            );
+            Ok(Response::new(expected_body))
+        },
+        Request::builder()
+            .method("POST")
+            .body(expected_body.clone())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(String::from_utf8_lossy(&response).as_ref(), expected_body);
+}
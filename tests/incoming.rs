@@ -0,0 +1,78 @@
+#![cfg(feature = "incoming")]
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use futures_util::StreamExt;
+use tokio::net::TcpStream;
+use tokio_noise::{handshakes::NNpsk0, AcceptError, HandshakeConfig, NoiseError, NoiseTcpListener};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+async fn echo_client(addr: SocketAddr, psk: [u8; 32]) -> Result<(), NoiseError> {
+    let tcp_stream = TcpStream::connect(addr).await?;
+    let mut noise_stream = HandshakeConfig::new(NNpsk0::try_new(&psk).unwrap())
+        .initiate(tcp_stream)
+        .await?;
+    noise_stream.send(b"hello").await?;
+    let mut buf = [0u8; 64];
+    let n = noise_stream.recv(&mut buf).await?;
+    assert_eq!(&buf[..n], b"hello");
+    Ok(())
+}
+
+#[tokio::test]
+async fn failed_handshakes_do_not_end_the_stream() {
+    let listener = NoiseTcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // A peer which connects but never starts its handshake takes up one of the two
+    // handshake slots for the whole test, without holding up anyone else.
+    let _silent = TcpStream::connect(addr).await.unwrap();
+
+    let clients = tokio::spawn(async move {
+        let good = [
+            tokio::spawn(echo_client(addr, PSK)),
+            tokio::spawn(echo_client(addr, PSK)),
+            tokio::spawn(echo_client(addr, PSK)),
+        ];
+        assert!(echo_client(addr, [0xAA; 32]).await.is_err());
+        for client in good {
+            client.await.unwrap().unwrap();
+        }
+    });
+
+    let served = Arc::new(Mutex::new(0));
+    let failed = Arc::new(Mutex::new(Vec::new()));
+    listener
+        .incoming(HandshakeConfig::new(NNpsk0::try_new(&PSK).unwrap()))
+        .max_concurrent_handshakes(2)
+        .take(4)
+        .for_each_concurrent(None, |result| {
+            let (served, failed) = (served.clone(), failed.clone());
+            async move {
+                match result {
+                    Ok((mut stream, _)) => {
+                        let mut buf = [0u8; 64];
+                        let n = stream.recv(&mut buf).await.unwrap();
+                        stream.send(&buf[..n]).await.unwrap();
+                        *served.lock().unwrap() += 1;
+                    }
+                    Err(AcceptError::Handshake { peer_addr, error }) => {
+                        assert_eq!(error.peer_addr(), Some(peer_addr));
+                        failed.lock().unwrap().push(error.without_context());
+                    }
+                    Err(e) => panic!("listener failed: {}", e),
+                }
+            }
+        })
+        .await;
+    clients.await.unwrap();
+
+    assert_eq!(*served.lock().unwrap(), 3);
+    let failed = failed.lock().unwrap();
+    assert_eq!(failed.len(), 1);
+    assert!(matches!(failed[0], NoiseError::Snow(snow::Error::Decrypt)));
+}
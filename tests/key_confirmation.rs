@@ -0,0 +1,94 @@
+use tokio::io::duplex;
+use tokio_noise::{
+    handshakes::{nn_psk2, NNpsk2},
+    NoiseBuilder, NoiseError, FRAMING_VERSION,
+};
+
+const PSK: [u8; 32] = [0xFF; 32];
+const WRONG_PSK: [u8; 32] = [0xEE; 32];
+
+/// A responder which uses the given PSK for any initiator.
+fn responder_psk(
+    psk: [u8; 32],
+) -> nn_psk2::Responder<impl FnMut(&[u8]) -> Option<[u8; 32]>, [u8; 32]> {
+    nn_psk2::Responder::new(move |_: &[u8]| Some(psk))
+}
+
+#[tokio::test]
+async fn wrong_psk_fails_on_both_sides() {
+    let (client, server) = duplex(64 * 1024);
+    let builder = NoiseBuilder::new().key_confirmation(true);
+    let initiator = nn_psk2::Initiator::try_new("alice", &PSK).unwrap();
+    let (client, server) = tokio::join!(
+        builder.handshake_initiator(client, NNpsk2::new(initiator)),
+        builder.handshake_responder(server, NNpsk2::new(responder_psk(WRONG_PSK))),
+    );
+
+    // The responder sends the final message, so without confirmation only the initiator
+    // would notice the mismatch.
+    assert!(matches!(
+        client,
+        Err(NoiseError::Snow(snow::Error::Decrypt))
+    ));
+    assert!(server.is_err());
+}
+
+#[tokio::test]
+async fn wrong_psk_is_missed_without_confirmation() {
+    let (client, server) = duplex(64 * 1024);
+    let builder = NoiseBuilder::new();
+    let initiator = nn_psk2::Initiator::try_new("alice", &PSK).unwrap();
+    let (client, server) = tokio::join!(
+        builder.handshake_initiator(client, NNpsk2::new(initiator)),
+        builder.handshake_responder(server, NNpsk2::new(responder_psk(WRONG_PSK))),
+    );
+    assert!(client.is_err());
+    assert!(server.is_ok());
+}
+
+#[tokio::test]
+async fn confirmed_handshake_exchanges_data() {
+    let (client, server) = duplex(64 * 1024);
+    let builder = NoiseBuilder::new().key_confirmation(true);
+    let initiator = nn_psk2::Initiator::try_new("alice", &PSK).unwrap();
+    let (client, server) = tokio::join!(
+        builder.handshake_initiator(client, NNpsk2::new(initiator)),
+        builder.handshake_responder(server, NNpsk2::new(responder_psk(PSK))),
+    );
+    let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+    // The responder waited for the initiator's preamble before returning.
+    assert_eq!(server.peer_framing_version(), Some(FRAMING_VERSION));
+
+    let mut buf = [0u8; 64];
+    server.send(b"server speaks first").await.unwrap();
+    let n = client.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"server speaks first");
+
+    client.send(b"reply").await.unwrap();
+    let n = server.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"reply");
+}
+
+#[tokio::test]
+async fn interoperates_with_peer_without_confirmation() {
+    let (client, server) = duplex(64 * 1024);
+    let client_builder = NoiseBuilder::new();
+    let server_builder = NoiseBuilder::new().key_confirmation(true);
+    let (client, server) = tokio::join!(
+        async {
+            let initiator = nn_psk2::Initiator::try_new("alice", &PSK).unwrap();
+            let mut client = client_builder
+                .handshake_initiator(client, NNpsk2::new(initiator))
+                .await?;
+            client.send(b"hello").await?;
+            Ok::<_, NoiseError>(client)
+        },
+        server_builder.handshake_responder(server, NNpsk2::new(responder_psk(PSK))),
+    );
+    let (_client, mut server) = (client.unwrap(), server.unwrap());
+
+    let mut buf = [0u8; 64];
+    let n = server.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hello");
+}
@@ -0,0 +1,190 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use snow::{params::DHChoice, resolvers::CryptoResolver, types::Dh};
+use tokio::io::duplex;
+use tokio_noise::{
+    handshakes::{SnowHandshake, StaticKeyOps},
+    BoxFuture, NoiseError, NoiseStream,
+};
+
+const NAME: &str = "Noise_XX_25519_ChaChaPoly_SHA256";
+
+/// Stands in for an HSM: the private key stays inside, and only DH results come out.
+struct MockSigner {
+    dh: Box<dyn Dh>,
+    calls: Arc<AtomicUsize>,
+    fail: bool,
+}
+
+impl MockSigner {
+    fn new(calls: Arc<AtomicUsize>) -> MockSigner {
+        let private = snow::Builder::new(NAME.parse().unwrap())
+            .generate_keypair()
+            .unwrap()
+            .private;
+        let mut dh = snow::resolvers::DefaultResolver
+            .resolve_dh(&DHChoice::Curve25519)
+            .unwrap();
+        dh.set(&private);
+        MockSigner {
+            dh,
+            calls,
+            fail: false,
+        }
+    }
+}
+
+/// Pending on its first poll, like a signer waiting on a reply.
+struct YieldOnce(bool);
+
+impl Future for YieldOnce {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+impl StaticKeyOps for MockSigner {
+    fn public_key(&self) -> [u8; 32] {
+        self.dh.pubkey().try_into().unwrap()
+    }
+
+    fn dh<'a>(
+        &'a self,
+        remote_public: &'a [u8; 32],
+    ) -> BoxFuture<'a, Result<[u8; 32], NoiseError>> {
+        Box::pin(async move {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            YieldOnce(false).await;
+            if self.fail {
+                return Err(NoiseError::PeerRejected);
+            }
+            let mut shared = [0u8; 32];
+            self.dh.dh(remote_public, &mut shared)?;
+            Ok(shared)
+        })
+    }
+}
+
+#[tokio::test]
+async fn xx_handshake_with_signer_on_both_sides() {
+    let (client_calls, server_calls) =
+        (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+    let client_signer = MockSigner::new(client_calls.clone());
+    let server_signer = MockSigner::new(server_calls.clone());
+    let (client_public, server_public) = (client_signer.public_key(), server_signer.public_key());
+
+    let initiator = SnowHandshake::new(NAME)
+        .unwrap()
+        .local_key_ops(Box::new(client_signer))
+        .unwrap()
+        .expect_remote_static(&server_public);
+    let responder = SnowHandshake::new(NAME)
+        .unwrap()
+        .local_key_ops(Box::new(server_signer))
+        .unwrap()
+        .expect_remote_static(&client_public);
+
+    let (client, server) = duplex(64 * 1024);
+    let (client, server) = tokio::join!(
+        NoiseStream::handshake_initiator(client, initiator),
+        NoiseStream::handshake_responder(server, responder),
+    );
+    let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+    // XX has one DH with each side's static key: se for the initiator, es for the
+    // responder.
+    assert_eq!(client_calls.load(Ordering::SeqCst), 1);
+    assert_eq!(server_calls.load(Ordering::SeqCst), 1);
+    assert_eq!(client.local_static_public_key(), Some(&client_public[..]));
+    assert_eq!(server.remote_static_key(), Some(&client_public[..]));
+    assert_eq!(client.remote_static_key(), Some(&server_public[..]));
+
+    client.send(b"hello").await.unwrap();
+    let mut buf = [0u8; 16];
+    let n = server.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hello");
+}
+
+#[tokio::test]
+async fn signer_interoperates_with_a_software_key() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let signer = MockSigner::new(calls.clone());
+    let signer_public = signer.public_key();
+    let server_key = snow::Builder::new(NAME.parse().unwrap())
+        .generate_keypair()
+        .unwrap();
+
+    let initiator = SnowHandshake::new(NAME)
+        .unwrap()
+        .local_key_ops(Box::new(signer))
+        .unwrap();
+    let responder = SnowHandshake::new(NAME)
+        .unwrap()
+        .local_private_key(&server_key.private)
+        .expect_remote_static(&signer_public);
+
+    let (client, server) = duplex(64 * 1024);
+    let (client, server) = tokio::join!(
+        NoiseStream::handshake_initiator(client, initiator),
+        NoiseStream::handshake_responder(server, responder),
+    );
+    let (mut client, mut server) = (client.unwrap(), server.unwrap());
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    server.send(b"world").await.unwrap();
+    let mut buf = [0u8; 16];
+    let n = client.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"world");
+}
+
+#[tokio::test]
+async fn signer_failure_aborts_the_handshake() {
+    let mut signer = MockSigner::new(Arc::default());
+    signer.fail = true;
+    let server_key = snow::Builder::new(NAME.parse().unwrap())
+        .generate_keypair()
+        .unwrap();
+
+    let responder = SnowHandshake::new(NAME)
+        .unwrap()
+        .local_key_ops(Box::new(signer))
+        .unwrap();
+    let initiator = SnowHandshake::new(NAME)
+        .unwrap()
+        .local_private_key(&server_key.private);
+
+    let (client, server) = duplex(64 * 1024);
+    let (_, server) = tokio::join!(
+        async move {
+            NoiseStream::handshake_initiator(client, initiator)
+                .await
+                .map(drop)
+        },
+        NoiseStream::handshake_responder(server, responder),
+    );
+    assert!(matches!(server, Err(NoiseError::Snow(snow::Error::Dh))));
+}
+
+#[test]
+fn signer_requires_25519() {
+    let signer = MockSigner::new(Arc::default());
+    let result = SnowHandshake::new("Noise_XX_448_ChaChaPoly_SHA256")
+        .unwrap()
+        .local_key_ops(Box::new(signer));
+    assert!(matches!(result, Err(NoiseError::Handshake(_))));
+}
@@ -0,0 +1,64 @@
+use std::sync::Mutex;
+use tokio::io::{duplex, AsyncWriteExt};
+use tokio_noise::{handshakes::NNpsk0, LogContext, NoiseBuilder, NoiseStream};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+/// Records every log line from this crate.
+struct Recorder(Mutex<Vec<String>>);
+
+impl log::Log for Recorder {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.target().starts_with("tokio_noise")
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static RECORDER: Recorder = Recorder(Mutex::new(Vec::new()));
+
+fn assert_logged(lines: &[String], line: &str) {
+    assert!(
+        lines.iter().any(|l| l == line),
+        "no line {:?} in {:#?}",
+        line,
+        lines
+    );
+}
+
+#[tokio::test]
+async fn context_tags_handshake_and_stream_logs() {
+    log::set_logger(&RECORDER).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+
+    let (client, server) = duplex(64 * 1024);
+    let initiator = NoiseBuilder::new().log_context(LogContext::new().with("conn", 7));
+    let (client, server) = tokio::join!(
+        initiator.handshake_initiator(client, NNpsk0::try_new(&PSK).unwrap()),
+        NoiseStream::handshake_responder(server, NNpsk0::try_new(&PSK).unwrap()),
+    );
+    let (mut client, mut server) = (client.unwrap(), server.unwrap());
+    assert_eq!(client.log_context().get("conn"), Some("7"));
+    assert!(server.log_context().is_empty());
+
+    // Pairs added after the handshake tag later messages, with awkward values quoted.
+    server.set_log_context(LogContext::new().with("user", "Jo Bloggs").with("conn", 9));
+    client.shutdown().await.unwrap();
+    let mut buf = [0u8; 16];
+    assert_eq!(server.recv(&mut buf).await.unwrap(), 0);
+
+    let lines = RECORDER.0.lock().unwrap().clone();
+    assert_logged(&lines, "[initiator conn=7] completed noise handshake");
+    assert_logged(&lines, "[responder] completed noise handshake");
+    assert_logged(&lines, "[initiator conn=7] sending 2048-byte frames");
+    assert_logged(
+        &lines,
+        r#"[responder user="Jo Bloggs" conn=9] peer closed stream"#,
+    );
+}
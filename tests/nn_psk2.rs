@@ -1,7 +1,7 @@
 use tokio::net::{TcpListener, TcpStream};
 use tokio_noise::{
     handshakes::{nn_psk2, NNpsk2},
-    NoiseError, NoiseTcpStream,
+    NoiseError, NoiseTcpStream, PskError,
 };
 
 const PSK: [u8; 32] = [0xFF; 32];
@@ -39,10 +39,7 @@ async fn main() -> Result<(), NoiseError> {
     });
 
     // Client
-    let initiator = nn_psk2::Initiator {
-        psk: &PSK,
-        identity: b"client_id_123",
-    };
+    let initiator = nn_psk2::Initiator::try_new(b"client_id_123".as_ref(), &PSK)?;
     let tcp_stream = TcpStream::connect(&addr).await?;
     let mut noise_stream =
         NoiseTcpStream::handshake_initiator(tcp_stream, NNpsk2::new(initiator)).await?;
@@ -53,3 +50,15 @@ async fn main() -> Result<(), NoiseError> {
 
     Ok(())
 }
+
+#[test]
+fn initiator_rejects_invalid_psk() {
+    assert!(matches!(
+        nn_psk2::Initiator::try_new("alice", &[0xFF; 16]),
+        Err(NoiseError::InvalidPsk(PskError::InvalidLength(16)))
+    ));
+    assert!(matches!(
+        nn_psk2::Initiator::try_new("alice", &[0; 32]),
+        Err(NoiseError::InvalidPsk(PskError::AllZero))
+    ));
+}
@@ -0,0 +1,120 @@
+use std::{
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream},
+};
+use tokio_noise::{handshakes::NNpsk0, NoiseBuilder, NoiseStream, Transport};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+/// A TCP transport which records the socket's `TCP_NODELAY` setting at every write.
+struct RecordingStream {
+    inner: TcpStream,
+    nodelay_at_writes: Arc<Mutex<Vec<bool>>>,
+}
+
+impl AsyncRead for RecordingStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for RecordingStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let nodelay = self.inner.nodelay().unwrap();
+        self.nodelay_at_writes.lock().unwrap().push(nodelay);
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl Transport for RecordingStream {
+    fn nodelay(&self) -> Option<bool> {
+        Transport::nodelay(&self.inner)
+    }
+    fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        self.inner.set_nodelay(nodelay)
+    }
+}
+
+/// Conducts a handshake with a client using the given builder, returning the client
+/// stream and the `TCP_NODELAY` settings observed at each of its handshake writes.
+async fn handshake(builder: NoiseBuilder) -> (NoiseStream<RecordingStream>, Vec<bool>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let srv = tokio::spawn(async move {
+        let (tcp_stream, _) = listener.accept().await.unwrap();
+        NoiseStream::handshake_responder_psk0(tcp_stream, &PSK)
+            .await
+            .unwrap()
+    });
+
+    let tcp_stream = TcpStream::connect(addr).await.unwrap();
+    assert!(!tcp_stream.nodelay().unwrap());
+
+    let nodelay_at_writes = Arc::new(Mutex::new(Vec::new()));
+    let transport = RecordingStream {
+        inner: tcp_stream,
+        nodelay_at_writes: nodelay_at_writes.clone(),
+    };
+    let client = builder
+        .handshake_initiator(transport, NNpsk0::try_new(&PSK).unwrap())
+        .await
+        .unwrap();
+    srv.await.unwrap();
+
+    let nodelay_at_writes = nodelay_at_writes.lock().unwrap().clone();
+    (client, nodelay_at_writes)
+}
+
+#[tokio::test]
+async fn nodelay_is_kept_by_default() {
+    let (client, nodelay_at_writes) = handshake(NoiseBuilder::new()).await;
+    assert_eq!(nodelay_at_writes, [true]);
+    assert!(client.get_ref().inner.nodelay().unwrap());
+}
+
+#[tokio::test]
+async fn nodelay_is_restored_after_handshake() {
+    let (client, nodelay_at_writes) = handshake(NoiseBuilder::new().nodelay(false)).await;
+    assert_eq!(nodelay_at_writes, [true]);
+    assert!(!client.get_ref().inner.nodelay().unwrap());
+}
+
+#[tokio::test]
+async fn tcp_stream_handshake_sets_nodelay() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let srv = tokio::spawn(async move {
+        let (tcp_stream, _) = listener.accept().await.unwrap();
+        NoiseStream::handshake_responder_psk0(tcp_stream, &PSK)
+            .await
+            .unwrap()
+    });
+
+    let tcp_stream = TcpStream::connect(addr).await.unwrap();
+    let client = NoiseStream::handshake_initiator_psk0(tcp_stream, &PSK)
+        .await
+        .unwrap();
+    let server = srv.await.unwrap();
+    assert!(client.nodelay().unwrap());
+    assert!(server.nodelay().unwrap());
+}
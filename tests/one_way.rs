@@ -0,0 +1,128 @@
+use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+use tokio_noise::{handshakes::SnowHandshake, NoiseBuilder, NoiseError, NoiseStream};
+
+fn server_keypair() -> snow::Keypair {
+    snow::Builder::new("Noise_N_25519_ChaChaPoly_SHA512".parse().unwrap())
+        .generate_keypair()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn n_handshake_sends_one_way() {
+    let keypair = server_keypair();
+    let (client, server) = duplex(64 * 1024);
+
+    // The initiator's handshake doesn't wait on the responder at all.
+    let mut client = NoiseStream::handshake_initiator_n(client, &keypair.public)
+        .await
+        .unwrap();
+    client.send(b"log line 1").await.unwrap();
+    client.send(b"log line 2").await.unwrap();
+    client.shutdown().await.unwrap();
+
+    let mut server = NoiseStream::handshake_responder_n(server, &keypair)
+        .await
+        .unwrap();
+    assert_eq!(
+        server.protocol_name(),
+        Some("Noise_N_25519_ChaChaPoly_SHA512")
+    );
+    let mut received = Vec::new();
+    server.read_to_end(&mut received).await.unwrap();
+    assert_eq!(received, b"log line 1log line 2");
+}
+
+#[tokio::test]
+async fn reverse_direction_is_unavailable() {
+    let keypair = server_keypair();
+    let (client, server) = duplex(64 * 1024);
+    let (client, server) = tokio::join!(
+        NoiseStream::handshake_initiator_n(client, &keypair.public),
+        NoiseStream::handshake_responder_n(server, &keypair),
+    );
+    // Unwrapped from their one-way types, the streams refuse the missing direction at
+    // runtime instead, and can't be narrowed to it.
+    let client = client.unwrap().into_inner();
+    let server = server.unwrap().into_inner();
+    let mut client = client
+        .into_recv_only()
+        .err()
+        .expect("narrowed to receive only");
+    let mut server = server
+        .into_send_only()
+        .err()
+        .expect("narrowed to send only");
+
+    let mut buf = [0u8; 64];
+    match client.recv(&mut buf).await {
+        Err(NoiseError::OneWay { send: false }) => {}
+        result => panic!("initiator received: {:?}", result),
+    }
+    match server.send(b"reply").await {
+        Err(e @ NoiseError::OneWay { send: true }) => assert_eq!(
+            e.to_string(),
+            "Noise stream is one-way, so the responder cannot send"
+        ),
+        result => panic!("responder sent: {:?}", result),
+    }
+    match server.close_with_error(1, "nope").await {
+        Err(NoiseError::OneWay { send: true }) => {}
+        result => panic!("responder closed with an error: {:?}", result),
+    }
+
+    // Both sides can still close, and the responder reads the initiator's close
+    // notify as a clean EOF.
+    client.close().await.unwrap();
+    assert_eq!(server.recv(&mut buf).await.unwrap(), 0);
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn key_confirmation_is_skipped() {
+    let keypair = server_keypair();
+    let builder = NoiseBuilder::new().key_confirmation(true);
+    let (client, server) = duplex(64 * 1024);
+    let handshake = SnowHandshake::new("Noise_N_25519_ChaChaPoly_SHA512")
+        .unwrap()
+        .remote_public_key(&keypair.public);
+    let mut client = builder
+        .handshake_initiator(client, handshake)
+        .await
+        .unwrap()
+        .into_send_only()
+        .ok()
+        .expect("initiator can send");
+    client.send(b"hello").await.unwrap();
+
+    let handshake = SnowHandshake::new("Noise_N_25519_ChaChaPoly_SHA512")
+        .unwrap()
+        .local_private_key(&keypair.private);
+    let mut server = builder
+        .handshake_responder(server, handshake)
+        .await
+        .unwrap()
+        .into_recv_only()
+        .ok()
+        .expect("responder can receive");
+    let mut buf = [0u8; 64];
+    let n = server.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hello");
+}
+
+#[tokio::test]
+async fn two_way_streams_can_be_narrowed() {
+    let (client, server) = duplex(64 * 1024);
+    let (client, server) = tokio::join!(
+        NoiseStream::handshake_initiator_psk0(client, &[0xFF; 32]),
+        NoiseStream::handshake_responder_psk0(server, &[0xFF; 32]),
+    );
+    let mut client = client.unwrap().into_send_only().ok().unwrap();
+    let mut server = server.unwrap().into_recv_only().ok().unwrap();
+
+    client.write_all(b"hello").await.unwrap();
+    client.close().await.unwrap();
+    let mut buf = [0u8; 64];
+    let n = server.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hello");
+    server.close().await.unwrap();
+}
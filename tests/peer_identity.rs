@@ -0,0 +1,135 @@
+//! The displayed and serialized forms of a `PeerIdentity` end up in logs, APIs and
+//! persisted allowlists, so changing them breaks whatever reads them back.
+
+#![cfg(feature = "router")]
+
+use std::collections::HashMap;
+use tokio_noise::{Fingerprint, FingerprintError, PeerIdentity};
+
+const FINGERPRINT: &str = "SHA256:Zmh6rfhivXdsj8GLjp+OIAiXFIVu4jOzkCpZHQ1fKSU";
+
+fn identity() -> PeerIdentity {
+    PeerIdentity {
+        remote_static_key: Some(vec![0u8; 32]),
+        remote_fingerprint: Some(Fingerprint::of(&[0u8; 32])),
+        local_static_key: Some(vec![0xAB; 4]),
+        protocol_name: Some("Noise_XX_25519_ChaChaPoly_SHA256".to_string()),
+        peer_addr: Some("127.0.0.1:9000".parse().unwrap()),
+    }
+}
+
+#[test]
+fn display_format_is_stable() {
+    assert_eq!(
+        identity().to_string(),
+        "SHA256:Zmh6rfhivXdsj8GLjp+OIAiXFIVu4jOzkCpZHQ1fKSU \
+         via Noise_XX_25519_ChaChaPoly_SHA256 from 127.0.0.1:9000"
+    );
+
+    let anonymous = PeerIdentity {
+        remote_static_key: None,
+        remote_fingerprint: None,
+        protocol_name: Some("Noise_NN_25519_ChaChaPoly_SHA256".to_string()),
+        ..identity()
+    };
+    assert_eq!(
+        anonymous.to_string(),
+        "anonymous peer via Noise_NN_25519_ChaChaPoly_SHA256 from 127.0.0.1:9000"
+    );
+
+    let short: PeerIdentity = FINGERPRINT.parse().unwrap();
+    assert_eq!(short.to_string(), FINGERPRINT);
+}
+
+#[test]
+fn short_form_parses_to_an_equal_identity() {
+    let short: PeerIdentity = FINGERPRINT.parse().unwrap();
+    assert_eq!(short.remote_fingerprint, identity().remote_fingerprint);
+    assert_eq!(short.remote_static_key, None);
+    assert_eq!(short.peer_addr, None);
+    assert_eq!(short, identity());
+
+    assert_eq!(
+        "Zmh6rfhivXdsj8GLjp+OIAiXFIVu4jOzkCpZHQ1fKSU".parse::<PeerIdentity>(),
+        Err(FingerprintError::MissingPrefix)
+    );
+}
+
+#[test]
+fn identities_key_maps_by_fingerprint() {
+    let mut connections = HashMap::new();
+    *connections.entry(identity()).or_insert(0) += 1;
+    let reconnected = PeerIdentity {
+        peer_addr: Some("127.0.0.1:9001".parse().unwrap()),
+        ..identity()
+    };
+    *connections.entry(reconnected).or_insert(0) += 1;
+    let other = PeerIdentity {
+        remote_static_key: Some(vec![1u8; 32]),
+        remote_fingerprint: Some(Fingerprint::of(&[1u8; 32])),
+        ..identity()
+    };
+    *connections.entry(other).or_insert(0) += 1;
+
+    assert_eq!(connections.len(), 2);
+    assert_eq!(connections[&FINGERPRINT.parse().unwrap()], 2);
+}
+
+#[cfg(feature = "serde")]
+mod serde {
+    use super::*;
+
+    const JSON: &str = concat!(
+        r#"{"fingerprint":"SHA256:Zmh6rfhivXdsj8GLjp+OIAiXFIVu4jOzkCpZHQ1fKSU","#,
+        r#""remote_static_key":"0000000000000000000000000000000000000000000000000000000000000000","#,
+        r#""local_static_key":"abababab","#,
+        r#""protocol_name":"Noise_XX_25519_ChaChaPoly_SHA256","#,
+        r#""peer_addr":"127.0.0.1:9000"}"#,
+    );
+
+    #[test]
+    fn serialized_format_is_stable() {
+        assert_eq!(serde_json::to_string(&identity()).unwrap(), JSON);
+        assert_eq!(
+            serde_json::to_string(&Fingerprint::of(&[0u8; 32])).unwrap(),
+            format!("\"{}\"", FINGERPRINT)
+        );
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let parsed: PeerIdentity = serde_json::from_str(JSON).unwrap();
+        assert_eq!(parsed.remote_static_key, identity().remote_static_key);
+        assert_eq!(parsed.remote_fingerprint, identity().remote_fingerprint);
+        assert_eq!(parsed.local_static_key, identity().local_static_key);
+        assert_eq!(parsed.protocol_name, identity().protocol_name);
+        assert_eq!(parsed.peer_addr, identity().peer_addr);
+
+        let fingerprint: Fingerprint =
+            serde_json::from_str(&format!("\"{}\"", FINGERPRINT)).unwrap();
+        assert!(fingerprint.matches(&[0u8; 32]));
+    }
+
+    #[test]
+    fn missing_fields_deserialize_as_none() {
+        let key_only: PeerIdentity = serde_json::from_str(
+            r#"{"remote_static_key":"0000000000000000000000000000000000000000000000000000000000000000"}"#,
+        )
+        .unwrap();
+        assert_eq!(key_only.remote_fingerprint, identity().remote_fingerprint);
+        assert_eq!(key_only.peer_addr, None);
+
+        let fingerprint_only: PeerIdentity =
+            serde_json::from_str(&format!(r#"{{"fingerprint":"{}"}}"#, FINGERPRINT)).unwrap();
+        assert_eq!(fingerprint_only.remote_static_key, None);
+        assert_eq!(fingerprint_only, identity());
+    }
+
+    #[test]
+    fn mismatched_fingerprint_is_rejected() {
+        let json = JSON.replace("\"remote_static_key\":\"00", "\"remote_static_key\":\"01");
+        let e = serde_json::from_str::<PeerIdentity>(&json).unwrap_err();
+        assert!(e.to_string().contains("does not match"), "{}", e);
+        assert!(serde_json::from_str::<PeerIdentity>(r#"{"local_static_key":"xyz"}"#).is_err());
+    }
+}
@@ -0,0 +1,39 @@
+#![cfg(feature = "profiling")]
+
+use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+use tokio_noise::{NoiseStream, MAX_FRAME_SIZE};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+#[tokio::test]
+async fn latency_histograms_count_every_frame() {
+    let (client, server) = duplex(64 * 1024);
+    let (client, server) = tokio::join!(
+        NoiseStream::handshake_initiator_psk0(client, &PSK),
+        NoiseStream::handshake_responder_psk0(server, &PSK),
+    );
+    let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+    let payload = vec![0xAB; 10 * MAX_FRAME_SIZE];
+    let send = async {
+        client.write_all(&payload).await.unwrap();
+        client.shutdown().await.unwrap();
+    };
+    let mut received = Vec::new();
+    let (_, read) = tokio::join!(send, server.read_to_end(&mut received));
+    read.unwrap();
+    assert_eq!(received, payload);
+
+    // Every frame the client encrypted, including its preamble and close notify, was
+    // decrypted by the server.
+    let (sent, recvd) = (client.stats(), server.stats());
+    let frames = sent.encrypt_latency.count();
+    assert!(frames > 10, "only {} frames encrypted", frames);
+    assert_eq!(recvd.decrypt_latency.count(), frames);
+    assert_eq!(recvd.encrypt_latency.count(), 0);
+
+    let latency = recvd.decrypt_latency;
+    assert!(latency.total() > std::time::Duration::ZERO);
+    assert!(latency.mean().unwrap() <= latency.total());
+    assert!(latency.quantile(0.5).unwrap() <= latency.quantile(0.99).unwrap());
+}
@@ -0,0 +1,59 @@
+use tokio::io::{duplex, AsyncReadExt};
+use tokio_noise::{
+    handshakes::{Handshake, NNpsk0},
+    NoiseError, NoiseStream,
+};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+/// A custom handshake whose protocol name has a typo in its pattern.
+struct Misnamed(NNpsk0);
+
+impl Handshake for Misnamed {
+    fn name(&self) -> String {
+        "Noise_NNpks0_25519_ChaChaPoly_SHA512".to_string()
+    }
+
+    fn new_builder(&self) -> snow::Builder<'_> {
+        self.0.new_builder()
+    }
+}
+
+#[tokio::test]
+async fn protocol_name_is_reported_on_both_ends() {
+    let (client, server) = duplex(64 * 1024);
+    let (client, server) = tokio::join!(
+        NoiseStream::handshake_initiator(client, NNpsk0::try_new(&PSK).unwrap()),
+        NoiseStream::handshake_responder(server, NNpsk0::try_new(&PSK).unwrap()),
+    );
+    let (client, server) = (client.unwrap(), server.unwrap());
+
+    let expected = "Noise_NNpsk0_25519_ChaChaPoly_SHA512";
+    assert_eq!(NNpsk0::try_new(&PSK).unwrap().name(), expected);
+    assert_eq!(client.protocol_name(), Some(expected));
+    assert_eq!(server.protocol_name(), Some(expected));
+    assert_eq!(client.local_static_public_key(), None);
+    assert_eq!(server.local_static_public_key(), None);
+}
+
+#[tokio::test]
+async fn malformed_protocol_name_fails_before_sending() {
+    let (client, mut server) = duplex(64 * 1024);
+    let handshake = Misnamed(NNpsk0::try_new(&PSK).unwrap());
+
+    match NoiseStream::handshake_initiator(client, handshake).await {
+        Err(NoiseError::Handshake(e)) => {
+            assert_eq!(e.handshake_pattern, "Noise_NNpks0_25519_ChaChaPoly_SHA512");
+            assert!(
+                e.description.contains("invalid Noise protocol name"),
+                "{}",
+                e.description
+            );
+        }
+        result => panic!("expected handshake error, got {:?}", result.err()),
+    }
+
+    // Nothing reached the peer.
+    let mut buf = [0u8; 64];
+    assert_eq!(server.read(&mut buf).await.unwrap(), 0);
+}
@@ -0,0 +1,203 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tokio_noise::{
+    handshakes::NNpsk0, HandshakeConfig, NoiseError, NoiseTcpListener, ProxyHeaderError,
+    ProxyProtocolConfig, SniffConfig, Sniffed, TarpitConfig,
+};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+const V1_HEADER: &[u8] = b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n";
+
+/// A version 2 header for a client at `[2001:db8::1]:4000`, which connected to
+/// `[2001:db8::2]:443` with the authority `noise.example`.
+fn v2_header() -> Vec<u8> {
+    let mut header = b"\r\n\r\n\0\r\nQUIT\n".to_vec();
+    let authority = b"noise.example";
+    header.extend_from_slice(&[0x21, 0x21]);
+    header.extend_from_slice(&(36 + 3 + authority.len() as u16).to_be_bytes());
+    header.extend_from_slice(
+        &"2001:db8::1"
+            .parse::<std::net::Ipv6Addr>()
+            .unwrap()
+            .octets(),
+    );
+    header.extend_from_slice(
+        &"2001:db8::2"
+            .parse::<std::net::Ipv6Addr>()
+            .unwrap()
+            .octets(),
+    );
+    header.extend_from_slice(&4000u16.to_be_bytes());
+    header.extend_from_slice(&443u16.to_be_bytes());
+    header.push(0x02);
+    header.extend_from_slice(&(authority.len() as u16).to_be_bytes());
+    header.extend_from_slice(authority);
+    header
+}
+
+/// Connect as if through a proxy, sending `header` before the handshake.
+async fn proxied_client(addr: SocketAddr, header: &[u8], psk: [u8; 32]) -> Result<(), NoiseError> {
+    let mut tcp_stream = TcpStream::connect(addr).await?;
+    tcp_stream.write_all(header).await?;
+    let mut noise_stream = HandshakeConfig::new(NNpsk0::try_new(&psk).unwrap())
+        .initiate(tcp_stream)
+        .await?;
+    noise_stream.send(b"hello").await
+}
+
+async fn proxied_listener(timeout: Duration) -> NoiseTcpListener {
+    let mut listener = NoiseTcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.set_proxy_protocol(ProxyProtocolConfig { timeout });
+    listener
+}
+
+#[tokio::test]
+async fn v1_header_before_handshake() {
+    let mut listener = proxied_listener(Duration::from_secs(5)).await;
+    listener.set_tarpit(TarpitConfig {
+        base_delay: Duration::from_millis(1),
+        ..TarpitConfig::default()
+    });
+    let addr = listener.local_addr().unwrap();
+    let client = tokio::spawn(async move {
+        proxied_client(addr, V1_HEADER, PSK).await.unwrap();
+        // The server can't decrypt this client's handshake.
+        let _ = proxied_client(addr, V1_HEADER, [0xAA; 32]).await;
+    });
+
+    let incoming = listener.accept().await.unwrap();
+    let mut stream = incoming
+        .handshake(NNpsk0::try_new(&PSK).unwrap())
+        .await
+        .unwrap();
+    let client_addr: SocketAddr = "192.0.2.1:56324".parse().unwrap();
+    assert_eq!(stream.proxied_peer_addr(), Some(client_addr));
+    assert_eq!(
+        stream.proxied_local_addr(),
+        Some("198.51.100.1:443".parse().unwrap())
+    );
+    assert_eq!(stream.proxy_header().unwrap().version, 1);
+    assert!(stream.peer_addr().unwrap().ip().is_loopback());
+    let mut buf = [0u8; 16];
+    let n = stream.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hello");
+
+    // A failed handshake counts against the client, not the proxy, and its error names
+    // the client.
+    let incoming = listener.accept().await.unwrap();
+    let error = incoming
+        .handshake(NNpsk0::try_new(&PSK).unwrap())
+        .await
+        .map(drop)
+        .unwrap_err();
+    assert_eq!(error.peer_addr(), Some(client_addr));
+    let tarpit = listener.tarpit().unwrap();
+    assert_eq!(tarpit.failures(client_addr.ip()), 1);
+    assert_eq!(tarpit.failures("127.0.0.1".parse().unwrap()), 0);
+    client.await.unwrap();
+}
+
+#[tokio::test]
+async fn v2_header_with_tlvs() {
+    let listener = proxied_listener(Duration::from_secs(5)).await;
+    let addr = listener.local_addr().unwrap();
+    let client = tokio::spawn(async move { proxied_client(addr, &v2_header(), PSK).await });
+
+    let audited = Arc::new(Mutex::new(Vec::new()));
+    let config = HandshakeConfig::new(NNpsk0::try_new(&PSK).unwrap()).on_handshake({
+        let audited = audited.clone();
+        move |_, peer_addr| audited.lock().unwrap().push(peer_addr)
+    });
+
+    let mut incoming = listener.accept().await.unwrap();
+    let header = incoming.read_proxy_header().await.unwrap().unwrap();
+    assert_eq!(header.version, 2);
+    assert_eq!(header.tlv(0x02), Some(&b"noise.example"[..]));
+    let client_addr = "[2001:db8::1]:4000".parse().unwrap();
+    assert_eq!(incoming.proxied_peer_addr(), Some(client_addr));
+
+    let mut stream = incoming.handshake_with(&config).await.unwrap();
+    assert_eq!(stream.proxied_peer_addr(), Some(client_addr));
+    assert_eq!(
+        stream.proxied_local_addr(),
+        Some("[2001:db8::2]:443".parse().unwrap())
+    );
+    let mut buf = [0u8; 16];
+    let n = stream.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hello");
+    client.await.unwrap().unwrap();
+    assert_eq!(*audited.lock().unwrap(), [Some(client_addr)]);
+}
+
+#[tokio::test]
+async fn sniffed_plaintext_keeps_header() {
+    let listener = proxied_listener(Duration::from_secs(5)).await;
+    let addr = listener.local_addr().unwrap();
+    let client = tokio::spawn(async move {
+        let mut tcp_stream = TcpStream::connect(addr).await.unwrap();
+        tcp_stream.write_all(V1_HEADER).await.unwrap();
+        tcp_stream.write_all(b"HELLO\n").await.unwrap();
+    });
+
+    let incoming = listener.accept().await.unwrap();
+    match incoming.sniff(&SniffConfig::new()).await.unwrap() {
+        Sniffed::Plaintext(mut tcp_stream, Some(header)) => {
+            assert_eq!(header.source, Some("192.0.2.1:56324".parse().unwrap()));
+            // Only the header was consumed.
+            let mut line = [0u8; 6];
+            tcp_stream.read_exact(&mut line).await.unwrap();
+            assert_eq!(&line, b"HELLO\n");
+        }
+        sniffed => panic!("expected plaintext with a header, got {:?}", sniffed),
+    }
+    client.await.unwrap();
+}
+
+#[tokio::test]
+async fn missing_and_malformed_headers_are_rejected() {
+    let listener = proxied_listener(Duration::from_millis(100)).await;
+    let addr = listener.local_addr().unwrap();
+
+    for (header, expected) in [
+        // A client which skipped the proxy.
+        (&[][..], ProxyHeaderError::Missing),
+        (
+            b"PROXY TCP4 192.0.2.1 not-an-address 1 2\r\n",
+            ProxyHeaderError::Malformed,
+        ),
+        (
+            b"\r\n\r\n\0\r\nQUIT\n\x11\x11\0\0",
+            ProxyHeaderError::UnsupportedVersion(1),
+        ),
+        // A header which declares itself longer than the client sends.
+        (
+            b"\r\n\r\n\0\r\nQUIT\n\x21\x11\xFF\xFF",
+            ProxyHeaderError::Timeout,
+        ),
+    ] {
+        let client = tokio::spawn(async move { proxied_client(addr, header, PSK).await });
+        let incoming = listener.accept().await.unwrap();
+        let peer_addr = incoming.peer_addr();
+        let error = incoming
+            .handshake(NNpsk0::try_new(&PSK).unwrap())
+            .await
+            .map(drop)
+            .unwrap_err();
+        assert_eq!(error.peer_addr(), Some(peer_addr));
+        match error.without_context() {
+            NoiseError::ProxyHeader(e) => assert_eq!(e, expected),
+            e => panic!("unexpected error: {}", e),
+        }
+        // The client's handshake fails once the server hangs up.
+        assert!(client.await.unwrap().is_err());
+    }
+}
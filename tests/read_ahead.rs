@@ -0,0 +1,150 @@
+use std::{future::poll_fn, pin::Pin, task::Poll};
+use tokio::io::{duplex, AsyncRead, AsyncReadExt, DuplexStream, ReadBuf};
+use tokio_noise::{handshakes::NNpsk0, NoiseBuilder, NoiseStream, MAX_FRAME_SIZE};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+/// The bytes of data carried by each full-size frame.
+const FRAME_PAYLOAD: usize = MAX_FRAME_SIZE - 16 - 3;
+
+const N_FRAMES: usize = 10;
+
+/// Connects a pair of streams, and sends `N_FRAMES` full frames of data from the client
+/// to the server, which is configured with the given builder.
+async fn send_frames(
+    server_builder: NoiseBuilder,
+) -> (
+    NoiseStream<DuplexStream>,
+    NoiseStream<DuplexStream>,
+    Vec<u8>,
+) {
+    let (client, server) = duplex(64 * 1024);
+    let client_builder = NoiseBuilder::new();
+    let (client, server) = tokio::join!(
+        client_builder.handshake_initiator(client, NNpsk0::try_new(&PSK).unwrap()),
+        server_builder.handshake_responder(server, NNpsk0::try_new(&PSK).unwrap()),
+    );
+    let (mut client, server) = (client.unwrap(), server.unwrap());
+
+    let data: Vec<u8> = (0..N_FRAMES * FRAME_PAYLOAD).map(|i| i as u8).collect();
+    client.send(&data).await.unwrap();
+    (client, server, data)
+}
+
+/// Makes a small read, and then reads the rest of the data, checking it all arrived
+/// intact. Returns the ciphertext left undecrypted after the small read.
+async fn read_all(server: &mut NoiseStream<DuplexStream>, data: &[u8]) -> usize {
+    let mut received = vec![0u8; data.len()];
+    let n = server.recv(&mut received[..10]).await.unwrap();
+    assert_eq!(n, 10);
+    let unprocessed = server.unprocessed_ciphertext_len();
+
+    server.read_exact(&mut received[10..]).await.unwrap();
+    assert_eq!(received, data);
+    unprocessed
+}
+
+#[tokio::test]
+async fn read_ahead_is_disabled_by_default() {
+    let (_client, mut server, data) = send_frames(NoiseBuilder::new()).await;
+    let unprocessed = read_all(&mut server, &data).await;
+    assert_eq!(unprocessed, (N_FRAMES - 1) * MAX_FRAME_SIZE);
+}
+
+#[tokio::test]
+async fn read_ahead_decrypts_received_frames() {
+    let builder = NoiseBuilder::new().read_ahead(64 * 1024);
+    let (_client, mut server, data) = send_frames(builder).await;
+    let unprocessed = read_all(&mut server, &data).await;
+    assert_eq!(unprocessed, 0);
+}
+
+#[tokio::test]
+async fn read_ahead_stops_at_limit() {
+    // The first frame leaves `FRAME_PAYLOAD - 10` bytes buffered, so it takes two more
+    // frames to reach the limit.
+    let builder = NoiseBuilder::new().read_ahead(2 * FRAME_PAYLOAD);
+    let (_client, mut server, data) = send_frames(builder).await;
+    let unprocessed = read_all(&mut server, &data).await;
+    assert_eq!(unprocessed, (N_FRAMES - 3) * MAX_FRAME_SIZE);
+}
+
+#[tokio::test]
+async fn read_ahead_respects_frames_per_poll() {
+    let builder = NoiseBuilder::new()
+        .read_ahead(64 * 1024)
+        .max_frames_per_poll(4);
+    let (_client, mut server, data) = send_frames(builder).await;
+    let unprocessed = read_all(&mut server, &data).await;
+    assert_eq!(unprocessed, (N_FRAMES - 4) * MAX_FRAME_SIZE);
+}
+
+#[tokio::test]
+async fn buffered_plaintext_can_be_taken_without_reading() {
+    let builder = NoiseBuilder::new().read_ahead(64 * 1024);
+    let (_client, mut server, data) = send_frames(builder).await;
+    assert!(server.take_buffered().is_empty());
+
+    let mut first = [0u8; 10];
+    server.read_exact(&mut first).await.unwrap();
+    let unprocessed = server.unprocessed_ciphertext_len();
+    let taken = server.take_buffered();
+    assert!(!taken.is_empty());
+    assert_eq!(taken.len(), data.len() - 10 - unprocessed);
+    assert_eq!(taken, data[10..][..taken.len()]);
+    assert_eq!(server.buffered_read_bytes(), 0);
+    assert_eq!(server.unprocessed_ciphertext_len(), unprocessed);
+
+    // Whatever was still encrypted follows on the next read.
+    let mut rest = vec![0u8; data.len() - 10 - taken.len()];
+    server.read_exact(&mut rest).await.unwrap();
+    assert_eq!(rest, data[10 + taken.len()..]);
+    assert_eq!(server.stats().plaintext_bytes_read, data.len() as u64);
+}
+
+#[tokio::test]
+async fn reads_either_side_of_a_whole_frame_are_intact() {
+    // Buffers with room for a whole frame's plaintext, header included, are decrypted
+    // into directly. Smaller ones go through scratch space and overflow.
+    for read_size in [
+        FRAME_PAYLOAD - 1,
+        FRAME_PAYLOAD,
+        FRAME_PAYLOAD + 3,
+        3 * FRAME_PAYLOAD,
+    ] {
+        let (_client, mut server, data) = send_frames(NoiseBuilder::new()).await;
+        let mut received = Vec::new();
+        let mut buf = vec![0u8; read_size];
+        while received.len() < data.len() {
+            let n = server.recv(&mut buf).await.unwrap();
+            received.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(received, data, "read_size={}", read_size);
+        assert_eq!(server.buffered_read_bytes(), 0);
+    }
+}
+
+#[tokio::test]
+async fn leftover_cleartext_is_ready_while_the_transport_is_pending() {
+    let (_client, mut server, data) = send_frames(NoiseBuilder::new()).await;
+    // Room for more than was sent, so the read doesn't stop once it is full.
+    let mut received = vec![0u8; data.len() + 100];
+    server.read_exact(&mut received[..10]).await.unwrap();
+    assert!(server.buffered_read_bytes() > 0);
+
+    // Everything the client sent has now arrived, so the transport has nothing more to
+    // give. The leftover cleartext, and the frames behind it, must still be returned by
+    // this poll rather than left in the buffer behind a `Pending`.
+    let mut buf = ReadBuf::new(&mut received[10..]);
+    let poll = poll_fn(|cx| Poll::Ready(Pin::new(&mut server).poll_read(cx, &mut buf))).await;
+    assert!(matches!(poll, Poll::Ready(Ok(()))), "{:?}", poll);
+    assert_eq!(buf.filled().len(), data.len() - 10);
+    assert_eq!(received[..data.len()], data);
+    assert_eq!(server.buffered_read_bytes(), 0);
+
+    // With nothing left, the next poll waits for the transport.
+    let mut byte = [0u8; 1];
+    let mut buf = ReadBuf::new(&mut byte);
+    let poll = poll_fn(|cx| Poll::Ready(Pin::new(&mut server).poll_read(cx, &mut buf))).await;
+    assert!(poll.is_pending());
+}
@@ -0,0 +1,224 @@
+//! TCP may split a frame across any number of reads, or deliver several frames in one.
+//! The receiver must buffer ciphertext until a whole frame has arrived, whatever the
+//! segmentation.
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{duplex, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream, ReadBuf},
+    time::{timeout, Instant},
+};
+use tokio_noise::{NoiseError, NoiseStream, Transport, MAX_FRAME_SIZE};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+/// A transport which returns at most one byte per read, and is not ready on every
+/// other poll, as if each byte arrived in a separate TCP segment.
+struct Trickle {
+    inner: DuplexStream,
+    ready: bool,
+}
+
+impl Trickle {
+    fn new(inner: DuplexStream) -> Trickle {
+        Trickle {
+            inner,
+            ready: false,
+        }
+    }
+}
+
+impl AsyncRead for Trickle {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        self.ready = !self.ready;
+        if !self.ready {
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+
+        let mut byte = [0u8; 1];
+        let mut one = ReadBuf::new(&mut byte[..buf.remaining().min(1)]);
+        let poll = Pin::new(&mut self.inner).poll_read(cx, &mut one);
+        if let Poll::Ready(Ok(())) = poll {
+            buf.put_slice(one.filled());
+        }
+        poll
+    }
+}
+
+impl AsyncWrite for Trickle {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl Transport for Trickle {}
+
+async fn trickle_pair() -> (NoiseStream<Trickle>, NoiseStream<Trickle>) {
+    let (client, server) = duplex(64 * 1024);
+    let handshakes = async {
+        tokio::join!(
+            NoiseStream::handshake_initiator_psk0(Trickle::new(client), &PSK),
+            NoiseStream::handshake_responder_psk0(Trickle::new(server), &PSK),
+        )
+    };
+    let (client, server) = timeout(Duration::from_secs(10), handshakes)
+        .await
+        .expect("handshake stalled");
+    (client.unwrap(), server.unwrap())
+}
+
+#[tokio::test]
+async fn frames_split_into_single_bytes_reassemble() {
+    let (mut client, mut server) = trickle_pair().await;
+
+    let mut buf = [0u8; 64];
+    client.send(b"hello").await.unwrap();
+    let n = server.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hello");
+
+    server.send(b"world").await.unwrap();
+    let n = client.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"world");
+}
+
+#[tokio::test]
+async fn multi_frame_payloads_reassemble() {
+    let (mut client, mut server) = trickle_pair().await;
+
+    // Several frames' worth, so frame boundaries fall mid-payload.
+    let payload: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+    let send = async {
+        client.write_all(&payload).await.unwrap();
+        client.shutdown().await.unwrap();
+    };
+    let recv = async {
+        let mut received = Vec::new();
+        server.read_to_end(&mut received).await.unwrap();
+        received
+    };
+    let ((), received) = timeout(Duration::from_secs(30), async { tokio::join!(send, recv) })
+        .await
+        .expect("transfer stalled");
+    assert_eq!(received, payload);
+}
+
+#[tokio::test]
+async fn coalesced_frames_are_each_delivered() {
+    let (client, server) = duplex(64 * 1024);
+    let (client, server) = tokio::join!(
+        NoiseStream::handshake_initiator_psk0(client, &PSK),
+        NoiseStream::handshake_responder_psk0(server, &PSK),
+    );
+    let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+    // Each send is flushed as its own frame, and all three sit in the pipe together by
+    // the time the server reads.
+    for message in [&b"one"[..], b"two", b"three"] {
+        client.send(message).await.unwrap();
+    }
+
+    let mut buf = [0u8; 64];
+    let mut received = Vec::new();
+    while received.len() < b"onetwothree".len() {
+        let n = server.recv(&mut buf).await.unwrap();
+        received.extend_from_slice(&buf[..n]);
+    }
+    assert_eq!(received, b"onetwothree");
+}
+
+/// Returns the responder's error after the initiator sends `bytes` and hangs up.
+async fn respond_to_truncated(bytes: &[u8]) -> NoiseError {
+    let (mut client, server) = duplex(64 * 1024);
+    client.write_all(bytes).await.unwrap();
+    client.shutdown().await.unwrap();
+    match NoiseStream::handshake_responder_psk0(server, &PSK).await {
+        Ok(_) => panic!("handshake succeeded"),
+        Err(e) => e,
+    }
+}
+
+#[tokio::test]
+async fn truncated_handshake_message_reports_bytes_received() {
+    // A length prefix announcing 48 bytes, followed by only 32 of them.
+    let mut bytes = vec![0, 48];
+    bytes.extend_from_slice(&[0xAB; 32]);
+    let e = respond_to_truncated(&bytes).await;
+    match e {
+        NoiseError::HandshakeTruncated {
+            message: 1,
+            received: 32,
+            expected: Some(48),
+        } => {}
+        e => panic!("expected a truncated handshake, got {:?}", e),
+    }
+    assert_eq!(
+        e.to_string(),
+        "Noise transport closed after receiving 32 of 48 bytes of handshake message 1"
+    );
+}
+
+#[tokio::test]
+async fn truncated_handshake_length_is_reported() {
+    for bytes in [&[][..], &[0]] {
+        match respond_to_truncated(bytes).await {
+            NoiseError::HandshakeTruncated {
+                message: 1,
+                received,
+                expected: None,
+            } => assert_eq!(received, bytes.len()),
+            e => panic!("expected a truncated handshake, got {:?}", e),
+        }
+    }
+}
+
+#[tokio::test]
+async fn partial_frame_is_held_until_complete() {
+    let (client, server) = duplex(64 * 1024);
+    let (client, server) = tokio::join!(
+        NoiseStream::handshake_initiator_psk0(client, &PSK),
+        NoiseStream::handshake_responder_psk0(server, &PSK),
+    );
+    let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+    // Take the client's preamble and data frame off the wire, and feed them back in
+    // with the end of the data frame missing.
+    client.send(b"hello").await.unwrap();
+    let mut ciphertext = vec![0u8; 4 * MAX_FRAME_SIZE];
+    let n = server.get_mut().read(&mut ciphertext).await.unwrap();
+    ciphertext.truncate(n);
+    let (head, tail) = ciphertext.split_at(n - 10);
+    client.get_mut().write_all(head).await.unwrap();
+
+    let mut buf = [0u8; 64];
+    let deadline = Instant::now() + Duration::from_millis(50);
+    assert!(matches!(
+        server.recv_deadline(&mut buf, deadline).await,
+        Err(NoiseError::DeadlineExceeded)
+    ));
+    assert_eq!(server.unprocessed_ciphertext_len(), MAX_FRAME_SIZE - 10);
+
+    client.get_mut().write_all(tail).await.unwrap();
+    let n = server.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hello");
+    assert_eq!(server.unprocessed_ciphertext_len(), 0);
+}
@@ -0,0 +1,131 @@
+//! A `NoiseConnector` looks up host names with its `Resolver`, and tries the resolved
+//! addresses in the order they are returned.
+
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::net::TcpListener;
+use tokio_noise::{
+    handshakes::NNpsk0, BoxFuture, HandshakeConfig, NoiseConnector, NoiseError, Resolver,
+};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+/// Resolves every host to the same addresses, recording each query.
+#[derive(Clone, Default)]
+struct MockResolver {
+    addrs: Vec<SocketAddr>,
+    queries: Arc<Mutex<Vec<(String, u16)>>>,
+}
+
+impl Resolver for MockResolver {
+    fn resolve<'a>(
+        &'a self,
+        host: &'a str,
+        port: u16,
+    ) -> BoxFuture<'a, io::Result<Vec<SocketAddr>>> {
+        self.queries.lock().unwrap().push((host.to_string(), port));
+        Box::pin(async move {
+            match host {
+                "missing.test" => Err(io::Error::new(io::ErrorKind::NotFound, "no such host")),
+                _ => Ok(self.addrs.clone()),
+            }
+        })
+    }
+}
+
+/// Returns an address on which nothing is listening.
+async fn dead_addr() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap()
+}
+
+#[tokio::test]
+async fn falls_back_through_resolved_addresses_in_order() {
+    let dead = dead_addr().await;
+    let first = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let second = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let resolver = MockResolver {
+        addrs: vec![
+            dead,
+            first.local_addr().unwrap(),
+            second.local_addr().unwrap(),
+        ],
+        ..MockResolver::default()
+    };
+
+    let server = tokio::spawn(async move {
+        let (tcp_stream, _) = first.accept().await.unwrap();
+        let config = HandshakeConfig::new(NNpsk0::try_new(&PSK).unwrap());
+        let mut stream = config.respond(tcp_stream).await.unwrap();
+        let mut buf = [0u8; 16];
+        let n = stream.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"hello");
+
+        // Once the first live address accepts, the one after it is never tried.
+        let later = tokio::time::timeout(Duration::from_millis(200), second.accept()).await;
+        assert!(later.is_err());
+    });
+
+    let connector = NoiseConnector::new(HandshakeConfig::new(NNpsk0::try_new(&PSK).unwrap()))
+        .resolver(resolver.clone());
+    let mut stream = connector.connect("server.test:9735").await.unwrap();
+    stream.send(b"hello").await.unwrap();
+    server.await.unwrap();
+
+    assert_eq!(
+        *resolver.queries.lock().unwrap(),
+        [("server.test".to_string(), 9735)]
+    );
+}
+
+#[tokio::test]
+async fn resolver_failures_and_literal_addresses() {
+    let resolver = MockResolver {
+        addrs: vec![dead_addr().await],
+        ..MockResolver::default()
+    };
+    let connector = NoiseConnector::new(HandshakeConfig::new(NNpsk0::try_new(&PSK).unwrap()))
+        .resolver(resolver.clone());
+
+    let error = connector
+        .connect(("missing.test", 1))
+        .await
+        .map(drop)
+        .unwrap_err();
+    match error.without_context() {
+        NoiseError::Connect(e) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+        e => panic!("unexpected error: {}", e),
+    }
+
+    // No resolved address accepts.
+    let error = connector
+        .connect("server.test:1")
+        .await
+        .map(drop)
+        .unwrap_err();
+    assert!(matches!(error.without_context(), NoiseError::Connect(_)));
+
+    // IP address literals are connected to without a lookup.
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (tcp_stream, _) = listener.accept().await.unwrap();
+        let config = HandshakeConfig::new(NNpsk0::try_new(&PSK).unwrap());
+        config.respond(tcp_stream).await.unwrap();
+    });
+    connector.connect(addr.to_string()).await.unwrap();
+    server.await.unwrap();
+
+    assert_eq!(
+        *resolver.queries.lock().unwrap(),
+        [
+            ("missing.test".to_string(), 1),
+            ("server.test".to_string(), 1)
+        ]
+    );
+}
@@ -0,0 +1,143 @@
+#![cfg(feature = "router")]
+
+use tokio::{net::TcpStream, sync::mpsc};
+use tokio_noise::{
+    handshakes::SnowHandshake, Fingerprint, KeyMatcher, NoiseError, NoiseTcpListener,
+    NoiseTcpStream, PeerIdentity, Router, UNKNOWN_PEER_CODE,
+};
+
+const NAME: &str = "Noise_XX_25519_ChaChaPoly_SHA256";
+
+fn generate_keypair() -> snow::Keypair {
+    snow::Builder::new(NAME.parse().unwrap())
+        .generate_keypair()
+        .unwrap()
+}
+
+async fn connect(addr: std::net::SocketAddr, key: &snow::Keypair) -> NoiseTcpStream {
+    let handshake = SnowHandshake::new(NAME)
+        .unwrap()
+        .local_private_key(&key.private);
+    let tcp_stream = TcpStream::connect(addr).await.unwrap();
+    NoiseTcpStream::handshake_initiator(tcp_stream, handshake)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn connections_are_routed_by_static_key() {
+    let (server_key, alice, bob, mallory) = (
+        generate_keypair(),
+        generate_keypair(),
+        generate_keypair(),
+        generate_keypair(),
+    );
+    let listener = NoiseTcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // Each tenant's handler greets the peer with the tenant's name.
+    let (routed_tx, mut routed_rx) = mpsc::unbounded_channel();
+    let greeter = |tenant: &'static str| {
+        let routed_tx = routed_tx.clone();
+        move |mut stream: NoiseTcpStream, peer: PeerIdentity| {
+            let routed_tx = routed_tx.clone();
+            async move {
+                routed_tx.send((tenant, peer.remote_static_key)).unwrap();
+                stream.send(tenant.as_bytes()).await.unwrap();
+            }
+        }
+    };
+    let router = Router::new()
+        .route(KeyMatcher::exact(alice.public.clone()), greeter("alice"))
+        .route(
+            KeyMatcher::any_of([bob.public.clone(), vec![0u8; 32]]),
+            greeter("bob"),
+        );
+
+    let server = tokio::spawn(async move {
+        let handshake = SnowHandshake::new(NAME)
+            .unwrap()
+            .local_private_key(&server_key.private);
+        let mut results = Vec::new();
+        for _ in 0..3 {
+            let connection = listener.accept().await.unwrap();
+            let result = connection
+                .handshake_and_route(handshake.clone(), &router)
+                .await;
+            results.push(result.map(drop));
+        }
+        results
+    });
+
+    let mut buf = [0u8; 16];
+    for (key, tenant) in [(&alice, "alice"), (&bob, "bob")] {
+        let mut client = connect(addr, key).await;
+        let n = client.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], tenant.as_bytes());
+        assert_eq!(
+            routed_rx.recv().await.unwrap(),
+            (tenant, Some(key.public.clone()))
+        );
+    }
+
+    let mut client = connect(addr, &mallory).await;
+    match client
+        .recv(&mut buf)
+        .await
+        .map_err(NoiseError::without_context)
+    {
+        Err(NoiseError::ClosedByPeer { code, .. }) => assert_eq!(code, UNKNOWN_PEER_CODE),
+        result => panic!("expected the unknown peer to be rejected, got {:?}", result),
+    }
+
+    let results = server.await.unwrap();
+    assert!(results[0].is_ok() && results[1].is_ok());
+    assert!(matches!(results[2], Err(NoiseError::UnknownPeer)));
+}
+
+#[test]
+fn fingerprint_matcher_accepts_listed_keys() {
+    let peer = |key: &[u8]| PeerIdentity {
+        remote_static_key: Some(key.to_vec()),
+        remote_fingerprint: Some(Fingerprint::of(key)),
+        local_static_key: None,
+        protocol_name: None,
+        peer_addr: Some("127.0.0.1:1".parse().unwrap()),
+    };
+    let allowlist = ["SHA256:Zmh6rfhivXdsj8GLjp+OIAiXFIVu4jOzkCpZHQ1fKSU"];
+    let matcher = KeyMatcher::fingerprints(allowlist.iter().map(|s| s.parse().unwrap()));
+
+    assert!(matcher.matches(&peer(&[0u8; 32])));
+    assert!(!matcher.matches(&peer(&[1u8; 32])));
+    assert!(KeyMatcher::fingerprints([Fingerprint::of(&[1u8; 32])]).matches(&peer(&[1u8; 32])));
+    assert!(!matcher.matches(&PeerIdentity {
+        remote_static_key: None,
+        ..peer(&[0u8; 32])
+    }));
+}
+
+#[tokio::test]
+async fn unmatched_connections_go_to_the_default_route() {
+    let (server_key, client_key) = (generate_keypair(), generate_keypair());
+    let listener = NoiseTcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let router = Router::new()
+        .route(KeyMatcher::predicate(|_| false), |_, _| async {})
+        .default_route(|mut stream, _| async move {
+            stream.send(b"guest").await.unwrap();
+        });
+    let server = tokio::spawn(async move {
+        let handshake = SnowHandshake::new(NAME)
+            .unwrap()
+            .local_private_key(&server_key.private);
+        let connection = listener.accept().await.unwrap();
+        connection.handshake_and_route(handshake, &router).await
+    });
+
+    let mut client = connect(addr, &client_key).await;
+    let mut buf = [0u8; 16];
+    let n = client.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"guest");
+    server.await.unwrap().unwrap().await.unwrap();
+}
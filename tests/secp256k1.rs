@@ -0,0 +1,96 @@
+#![cfg(feature = "secp256k1")]
+
+use tokio::io::{duplex, DuplexStream};
+use tokio_noise::{
+    handshakes::Secp256k1Handshake,
+    secp256k1::{PublicKey, Secp256k1, SecretKey},
+    NoiseError, NoiseStream,
+};
+
+fn keypair(byte: u8) -> (SecretKey, PublicKey) {
+    let secret = SecretKey::from_slice(&[byte; 32]).unwrap();
+    (
+        secret,
+        PublicKey::from_secret_key(&Secp256k1::new(), &secret),
+    )
+}
+
+async fn connect_pair(
+    initiator: Secp256k1Handshake,
+    responder: Secp256k1Handshake,
+) -> (
+    Result<NoiseStream<DuplexStream>, NoiseError>,
+    Result<NoiseStream<DuplexStream>, NoiseError>,
+) {
+    let (client, server) = duplex(64 * 1024);
+    tokio::join!(
+        NoiseStream::handshake_initiator(client, initiator),
+        NoiseStream::handshake_responder(server, responder),
+    )
+}
+
+async fn check_pair(initiator: Secp256k1Handshake, responder: Secp256k1Handshake, name: &str) {
+    let (client_key, server_key) = (keypair(0x11), keypair(0x21));
+    let (client, server) = connect_pair(initiator, responder).await;
+    let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+    assert_eq!(client.protocol_name(), Some(name));
+    assert_eq!(server.protocol_name(), Some(name));
+    assert_eq!(
+        client.remote_static_key(),
+        Some(&server_key.1.serialize()[..])
+    );
+    assert_eq!(
+        server.remote_static_key(),
+        Some(&client_key.1.serialize()[..])
+    );
+    assert_eq!(
+        client.local_static_public_key(),
+        Some(&client_key.1.serialize()[..])
+    );
+    assert_eq!(
+        server.local_static_public_key(),
+        Some(&server_key.1.serialize()[..])
+    );
+
+    let mut buf = [0u8; 64];
+    client.send(b"hello").await.unwrap();
+    let n = server.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hello");
+    server.send(b"world").await.unwrap();
+    let n = client.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"world");
+}
+
+#[tokio::test]
+async fn xk_handshake_authenticates_both_parties() {
+    let (client_key, server_key) = (keypair(0x11), keypair(0x21));
+    check_pair(
+        Secp256k1Handshake::xk_initiator(&client_key.0, &server_key.1),
+        Secp256k1Handshake::xk_responder(&server_key.0),
+        "Noise_XK_secp256k1_ChaChaPoly_SHA256",
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn ik_handshake_authenticates_both_parties() {
+    let (client_key, server_key) = (keypair(0x11), keypair(0x21));
+    check_pair(
+        Secp256k1Handshake::ik_initiator(&client_key.0, &server_key.1),
+        Secp256k1Handshake::ik_responder(&server_key.0),
+        "Noise_IK_secp256k1_ChaChaPoly_SHA256",
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn wrong_responder_key_fails() {
+    let (client_key, server_key, other_key) = (keypair(0x11), keypair(0x21), keypair(0x31));
+    let (_client, server) = connect_pair(
+        Secp256k1Handshake::xk_initiator(&client_key.0, &other_key.1),
+        Secp256k1Handshake::xk_responder(&server_key.0),
+    )
+    .await;
+    assert!(server.is_err());
+}
@@ -0,0 +1,147 @@
+//! With sequence numbers enabled, each data frame carries an application sequence number,
+//! which lets frames lost or repeated across reconnects be detected.
+
+use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio_noise::{handshakes::NNpsk0, NoiseBuilder, NoiseError, NoiseErrorKind, NoiseStream};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+async fn connect_pair(
+    client_builder: NoiseBuilder,
+    server_builder: NoiseBuilder,
+) -> (NoiseStream<DuplexStream>, NoiseStream<DuplexStream>) {
+    let (client, server) = duplex(256 * 1024);
+    let (client, server) = tokio::join!(
+        client_builder.handshake_initiator(client, NNpsk0::try_new(&PSK).unwrap()),
+        server_builder.handshake_responder(server, NNpsk0::try_new(&PSK).unwrap()),
+    );
+    (client.unwrap(), server.unwrap())
+}
+
+fn sequenced() -> NoiseBuilder {
+    NoiseBuilder::new().sequence_numbers(true)
+}
+
+#[tokio::test]
+async fn sequenced_frames_round_trip() {
+    let (mut client, mut server) = connect_pair(sequenced(), sequenced()).await;
+
+    let data: Vec<u8> = (0..20_000).map(|i| i as u8).collect();
+    client.send(&data).await.unwrap();
+    let mut received = vec![0u8; data.len()];
+    server.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, data);
+
+    // Each data frame took a number, and the server saw every one.
+    let frames = client.sending_sequence();
+    assert!(frames > 1, "expected several frames, got {}", frames);
+    assert_eq!(server.receiving_sequence(), frames);
+
+    server.send(b"reply").await.unwrap();
+    let mut buf = [0u8; 5];
+    client.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"reply");
+    assert_eq!(client.receiving_sequence(), 1);
+}
+
+#[tokio::test]
+async fn numbering_continues_across_reconnect() {
+    let (mut client, mut server) = connect_pair(sequenced(), sequenced()).await;
+    for message in [&b"one"[..], b"two", b"three"] {
+        client.send(message).await.unwrap();
+    }
+    let mut buf = [0u8; 11];
+    server.read_exact(&mut buf).await.unwrap();
+    let client_sequences = (client.sending_sequence(), client.receiving_sequence());
+    let server_sequences = (server.sending_sequence(), server.receiving_sequence());
+    assert_eq!(client_sequences, (3, 0));
+    drop((client, server));
+
+    // A fresh session starts its nonces from zero, but the numbering carries on.
+    let (mut client, mut server) = connect_pair(sequenced(), sequenced()).await;
+    client.resume_sequences(client_sequences.0, client_sequences.1);
+    server.resume_sequences(server_sequences.0, server_sequences.1);
+    client.send(b"four").await.unwrap();
+    let mut buf = [0u8; 4];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"four");
+    assert_eq!(server.receiving_sequence(), 4);
+}
+
+#[tokio::test]
+async fn lost_frame_across_reconnect_is_a_gap() {
+    let (mut client, mut server) = connect_pair(sequenced(), sequenced()).await;
+    // The server resumes expecting frame 3, but the client lost track and resends from 2.
+    client.resume_sequences(2, 0);
+    server.resume_sequences(0, 3);
+    client.send(b"again").await.unwrap();
+
+    let e = server.recv(&mut [0u8; 16]).await.unwrap_err();
+    assert!(matches!(
+        e,
+        NoiseError::SequenceGap {
+            expected: 3,
+            got: 2
+        }
+    ));
+    // The stream is poisoned.
+    assert!(server.is_poisoned());
+    assert!(matches!(
+        server.recv(&mut [0u8; 16]).await,
+        Err(NoiseError::SequenceGap { .. })
+    ));
+}
+
+#[tokio::test]
+async fn data_before_a_gap_is_returned_first() {
+    let (mut client, mut server) = connect_pair(sequenced(), sequenced()).await;
+    client.write_all(b"first").await.unwrap();
+    client.resume_sequences(7, 0);
+    client.write_all(b"skipped").await.unwrap();
+    client.flush().await.unwrap();
+
+    let mut buf = [0u8; 64];
+    let n = server.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"first");
+    assert!(matches!(
+        server.recv(&mut buf).await,
+        Err(NoiseError::SequenceGap {
+            expected: 1,
+            got: 7
+        })
+    ));
+}
+
+#[tokio::test]
+async fn peers_must_agree_on_sequence_numbers() {
+    let (mut client, mut server) = connect_pair(sequenced(), NoiseBuilder::new()).await;
+    client.send(b"hello").await.unwrap();
+    server.send(b"hello").await.unwrap();
+
+    // Each side finds the disagreement in the other's preamble.
+    for (stream, remote) in [(&mut client, false), (&mut server, true)] {
+        let e = stream.recv(&mut [0u8; 16]).await.unwrap_err();
+        assert_eq!(e.kind(), NoiseErrorKind::Protocol);
+        assert!(!e.is_transient());
+        assert!(matches!(
+            e.without_context(),
+            NoiseError::SequenceNumbersMismatch { remote: r } if r == remote
+        ));
+        assert!(stream.is_poisoned());
+    }
+}
+
+#[tokio::test]
+async fn sequenced_frames_read_into_a_large_buffer() {
+    let (mut client, mut server) = connect_pair(sequenced(), sequenced()).await;
+    let data: Vec<u8> = (0..20_000).map(|i| (i * 7) as u8).collect();
+    client.send(&data).await.unwrap();
+
+    // Each frame is decrypted in place, and its sequence number is not returned.
+    let mut received = vec![0u8; 64 * 1024];
+    let mut n = 0;
+    while n < data.len() {
+        n += server.recv(&mut received[n..]).await.unwrap();
+    }
+    assert_eq!(&received[..n], &data[..]);
+}
@@ -0,0 +1,161 @@
+//! An established stream can be exported and resumed over the same socket, as a process
+//! handing its connections to its replacement would.
+#![cfg(all(feature = "session-export", unix))]
+
+use std::os::fd::AsFd;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tokio_noise::{NoiseError, NoiseStream, NoiseTcpStream, SessionError, SessionSnapshot};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+async fn connect_pair() -> (NoiseTcpStream, NoiseTcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (client, server) = tokio::join!(
+        async {
+            let tcp_stream = TcpStream::connect(addr).await.unwrap();
+            NoiseTcpStream::handshake_initiator_psk0(tcp_stream, &PSK).await
+        },
+        async {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            NoiseTcpStream::handshake_responder_psk0(tcp_stream, &PSK).await
+        },
+    );
+    (client.unwrap(), server.unwrap())
+}
+
+/// Duplicate the stream's socket, as passing it to another process with `SCM_RIGHTS`
+/// would.
+fn dup_socket(stream: &NoiseTcpStream) -> TcpStream {
+    let fd = stream.get_ref().as_fd().try_clone_to_owned().unwrap();
+    let std_stream = std::net::TcpStream::from(fd);
+    std_stream.set_nonblocking(true).unwrap();
+    TcpStream::from_std(std_stream).unwrap()
+}
+
+fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed))
+        .collect()
+}
+
+#[tokio::test]
+async fn exported_stream_resumes_mid_conversation() {
+    let (mut client, mut server) = connect_pair().await;
+
+    client.write_all(b"before handoff").await.unwrap();
+    let mut buf = [0u8; 14];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"before handoff");
+
+    // Leave part of a message unread by the server, so it is exported in its buffers.
+    let in_flight = pattern(10_000, 1);
+    client.write_all(&in_flight).await.unwrap();
+    client.flush().await.unwrap();
+    let mut received = vec![0u8; 100];
+    server.read_exact(&mut received).await.unwrap();
+
+    let socket = dup_socket(&server);
+    let snapshot = server.export_session().unwrap();
+    assert!(!snapshot.is_initiator());
+    assert_eq!(snapshot.protocol_name(), server.protocol_name().unwrap());
+    let handshake_hash = server.handshake_hash().unwrap().to_vec();
+
+    // The exported stream is dead, and dropping it says nothing to the peer.
+    assert!(matches!(
+        server
+            .send(b"stale")
+            .await
+            .map_err(NoiseError::without_context),
+        Err(NoiseError::Session(SessionError::Exported))
+    ));
+    drop(server);
+
+    let snapshot = SessionSnapshot::from_bytes(&snapshot.to_bytes()).unwrap();
+    let mut server = NoiseStream::import_session(socket, snapshot).unwrap();
+    assert_eq!(server.name(), "responder");
+    assert_eq!(server.handshake_hash().unwrap(), handshake_hash);
+
+    received.resize(in_flight.len(), 0);
+    server.read_exact(&mut received[100..]).await.unwrap();
+    assert_eq!(received, in_flight);
+
+    // Traffic continues byte-exact in both directions.
+    let to_client = pattern(50_000, 2);
+    let mut from_server = vec![0u8; to_client.len()];
+    let (written, read) = tokio::join!(
+        async {
+            server.write_all(&to_client).await?;
+            server.flush().await
+        },
+        client.read_exact(&mut from_server),
+    );
+    written.unwrap();
+    read.unwrap();
+    assert_eq!(from_server, to_client);
+
+    let to_server = pattern(50_000, 3);
+    let mut from_client = vec![0u8; to_server.len()];
+    let (written, read) = tokio::join!(
+        async {
+            client.write_all(&to_server).await?;
+            client.flush().await
+        },
+        server.read_exact(&mut from_client),
+    );
+    written.unwrap();
+    read.unwrap();
+    assert_eq!(from_client, to_server);
+}
+
+#[tokio::test]
+async fn stream_without_handshake_keys_cannot_be_exported() {
+    let (client, server) = tokio::io::duplex(1024);
+    let build = |initiator| {
+        let builder = snow::Builder::new("Noise_NN_25519_ChaChaPoly_SHA512".parse().unwrap());
+        if initiator {
+            builder.build_initiator().unwrap()
+        } else {
+            builder.build_responder().unwrap()
+        }
+    };
+    let (mut initiator, mut responder) = (build(true), build(false));
+    let mut message = [0u8; 128];
+    let n = initiator.write_message(&[], &mut message).unwrap();
+    responder.read_message(&message[..n], &mut []).unwrap();
+    let n = responder.write_message(&[], &mut message).unwrap();
+    initiator.read_message(&message[..n], &mut []).unwrap();
+
+    let mut stream = NoiseStream::new(
+        "bare".to_string(),
+        client,
+        initiator.into_transport_mode().unwrap(),
+    );
+    assert!(matches!(
+        stream.export_session(),
+        Err(NoiseError::Session(SessionError::KeysUnavailable))
+    ));
+    drop(server);
+}
+
+#[tokio::test]
+async fn truncated_snapshot_is_rejected() {
+    let (_client, mut server) = connect_pair().await;
+    let bytes = server.export_session().unwrap().to_bytes();
+    for len in [0, 1, bytes.len() / 2, bytes.len() - 1] {
+        assert!(matches!(
+            SessionSnapshot::from_bytes(&bytes[..len]),
+            Err(SessionError::Malformed)
+        ));
+    }
+    let mut extended = bytes.clone();
+    extended.push(0);
+    assert!(matches!(
+        SessionSnapshot::from_bytes(&extended),
+        Err(SessionError::Malformed)
+    ));
+}
@@ -0,0 +1,109 @@
+use std::{io, net::SocketAddr, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tokio_noise::{
+    handshakes::NNpsk0, NoiseTcpListener, NoiseTcpStream, SniffConfig, SniffTimeout, Sniffed,
+};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+/// Accepts one connection, and replies to its first message over Noise or plaintext,
+/// whichever it speaks. Returns which it was.
+async fn serve_one(listener: &NoiseTcpListener, config: &SniffConfig) -> io::Result<&'static str> {
+    let incoming = listener.accept().await?;
+    match incoming.sniff(config).await? {
+        Sniffed::Noise(incoming) => {
+            let mut noise_stream = incoming
+                .handshake(NNpsk0::try_new(&PSK).unwrap())
+                .await
+                .unwrap();
+            let mut buf = [0u8; 64];
+            let n = noise_stream.recv(&mut buf).await.unwrap();
+            noise_stream.send(&buf[..n]).await.unwrap();
+            Ok("noise")
+        }
+        Sniffed::Plaintext(mut tcp_stream, proxy_header) => {
+            assert_eq!(proxy_header, None);
+            // Nothing was consumed, so the legacy protocol sees the whole request.
+            let mut line = [0u8; 6];
+            if tcp_stream.read_exact(&mut line).await.is_ok() {
+                tcp_stream.write_all(&line).await.unwrap();
+            }
+            Ok("plaintext")
+        }
+    }
+}
+
+#[tokio::test]
+async fn noise_and_plaintext_share_a_port() {
+    let listener = NoiseTcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = SniffConfig::new();
+
+    let client = tokio::spawn(async move {
+        let mut noise_stream =
+            NoiseTcpStream::handshake_initiator_psk0(TcpStream::connect(addr).await.unwrap(), &PSK)
+                .await
+                .unwrap();
+        noise_stream.send(b"secret").await.unwrap();
+        let mut buf = [0u8; 64];
+        let n = noise_stream.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"secret");
+    });
+    assert_eq!(serve_one(&listener, &config).await.unwrap(), "noise");
+    client.await.unwrap();
+
+    let client = tokio::spawn(async move {
+        let mut tcp_stream = TcpStream::connect(addr).await.unwrap();
+        tcp_stream.write_all(b"HELLO\n").await.unwrap();
+        let mut buf = [0u8; 6];
+        tcp_stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"HELLO\n");
+    });
+    assert_eq!(serve_one(&listener, &config).await.unwrap(), "plaintext");
+    client.await.unwrap();
+}
+
+/// Connects a client which sends nothing, and sniffs it.
+async fn sniff_silent(
+    listener: &NoiseTcpListener,
+    config: &SniffConfig,
+) -> (TcpStream, io::Result<Sniffed>) {
+    let addr: SocketAddr = listener.local_addr().unwrap();
+    let (client, incoming) = tokio::join!(TcpStream::connect(addr), listener.accept());
+    (client.unwrap(), incoming.unwrap().sniff(config).await)
+}
+
+#[tokio::test]
+async fn silent_clients_time_out() {
+    let listener = NoiseTcpListener::bind("127.0.0.1:0").await.unwrap();
+    let config = SniffConfig::new().timeout(Duration::from_millis(50));
+    let (_client, sniffed) = sniff_silent(&listener, &config).await;
+    assert!(matches!(sniffed.unwrap(), Sniffed::Plaintext(_, None)));
+
+    let config = config.on_timeout(SniffTimeout::Drop);
+    let (mut client, sniffed) = sniff_silent(&listener, &config).await;
+    assert_eq!(sniffed.unwrap_err().kind(), io::ErrorKind::TimedOut);
+    let mut buf = [0u8; 1];
+    assert_eq!(client.read(&mut buf).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn custom_classifier_is_used() {
+    let listener = NoiseTcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    // Legacy clients open with a JSON object, which the default classifier would also
+    // reject, but this one needs only a single byte.
+    let config = SniffConfig::new().classifier(1, |bytes| bytes != b"{");
+
+    let client = tokio::spawn(async move {
+        let mut tcp_stream = TcpStream::connect(addr).await.unwrap();
+        tcp_stream.write_all(b"{}\n\n\n\n").await.unwrap();
+        let mut buf = [0u8; 6];
+        tcp_stream.read_exact(&mut buf).await.unwrap();
+    });
+    assert_eq!(serve_one(&listener, &config).await.unwrap(), "plaintext");
+    client.await.unwrap();
+}
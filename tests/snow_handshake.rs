@@ -0,0 +1,344 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use tokio::io::{duplex, DuplexStream};
+use tokio_noise::{handshakes::SnowHandshake, Fingerprint, NoiseError, NoiseStream};
+
+/// A deterministic generator, which is NOT secure, for reproducible tests only.
+struct CountingRng(u64);
+
+impl rand_core::RngCore for CountingRng {
+    fn next_u32(&mut self) -> u32 {
+        self.next_u64() as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1);
+        self.0
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        rand_core::impls::fill_bytes_via_next(self, dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl rand_core::CryptoRng for CountingRng {}
+
+fn generate_keypair(protocol_name: &str) -> snow::Keypair {
+    snow::Builder::new(protocol_name.parse().unwrap())
+        .generate_keypair()
+        .unwrap()
+}
+
+async fn connect_pair(
+    initiator: SnowHandshake,
+    responder: SnowHandshake,
+) -> (NoiseStream<DuplexStream>, NoiseStream<DuplexStream>) {
+    let (client, server) = duplex(64 * 1024);
+    let (client, server) = tokio::join!(
+        NoiseStream::handshake_initiator(client, initiator),
+        NoiseStream::handshake_responder(server, responder),
+    );
+    (client.unwrap(), server.unwrap())
+}
+
+async fn exchange_data(
+    client: &mut NoiseStream<DuplexStream>,
+    server: &mut NoiseStream<DuplexStream>,
+) {
+    let mut buf = [0u8; 64];
+    client.send(b"hello").await.unwrap();
+    let n = server.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hello");
+    server.send(b"world").await.unwrap();
+    let n = client.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"world");
+}
+
+#[tokio::test]
+async fn nx_handshake_authenticates_responder() {
+    const NAME: &str = "Noise_NX_25519_AESGCM_SHA256";
+    let server_key = generate_keypair(NAME);
+
+    let (mut client, mut server) = connect_pair(
+        SnowHandshake::new(NAME).unwrap(),
+        SnowHandshake::new(NAME)
+            .unwrap()
+            .local_private_key(&server_key.private),
+    )
+    .await;
+
+    assert_eq!(client.protocol_name(), Some(NAME));
+    assert_eq!(server.protocol_name(), Some(NAME));
+    assert_eq!(client.remote_static_key(), Some(&server_key.public[..]));
+    assert_eq!(server.remote_static_key(), None);
+    assert_eq!(client.local_static_public_key(), None);
+    assert_eq!(
+        server.local_static_public_key(),
+        Some(&server_key.public[..])
+    );
+    exchange_data(&mut client, &mut server).await;
+}
+
+#[tokio::test]
+async fn xx_handshake_uses_three_messages() {
+    const NAME: &str = "Noise_XXpsk3_25519_ChaChaPoly_BLAKE2s";
+    let (client_key, server_key) = (generate_keypair(NAME), generate_keypair(NAME));
+    let psk = [0x42; 32];
+
+    let initiator = SnowHandshake::new(NAME)
+        .unwrap()
+        .local_private_key(&client_key.private)
+        .psk(3, &psk)
+        .unwrap()
+        .prologue(b"v1");
+    let responder = SnowHandshake::new(NAME)
+        .unwrap()
+        .local_private_key(&server_key.private)
+        .psk(3, &psk)
+        .unwrap()
+        .prologue(b"v1");
+    let (mut client, mut server) = connect_pair(initiator, responder).await;
+
+    assert_eq!(client.remote_static_key(), Some(&server_key.public[..]));
+    assert_eq!(server.remote_static_key(), Some(&client_key.public[..]));
+    assert_eq!(
+        client.local_static_public_key(),
+        Some(&client_key.public[..])
+    );
+    assert_eq!(
+        server.local_static_public_key(),
+        Some(&server_key.public[..])
+    );
+
+    // Each side's view of the other's fingerprint matches what the other computes of
+    // its own key, including in string form.
+    let client_fingerprint = Fingerprint::of(client.local_static_public_key().unwrap());
+    let server_fingerprint = Fingerprint::of(server.local_static_public_key().unwrap());
+    assert_eq!(server.peer_fingerprint(), Some(client_fingerprint));
+    assert_eq!(client.peer_fingerprint(), Some(server_fingerprint));
+    assert_eq!(
+        client.peer_fingerprint().unwrap().to_string(),
+        Fingerprint::of(&server_key.public).to_string()
+    );
+    exchange_data(&mut client, &mut server).await;
+}
+
+#[tokio::test]
+async fn pinned_remote_keys_are_accepted() {
+    const NAME: &str = "Noise_XX_25519_ChaChaPoly_SHA256";
+    let (client_key, server_key) = (generate_keypair(NAME), generate_keypair(NAME));
+
+    let initiator = SnowHandshake::new(NAME)
+        .unwrap()
+        .local_private_key(&client_key.private)
+        .expect_remote_static(&server_key.public);
+    let responder = SnowHandshake::new(NAME)
+        .unwrap()
+        .local_private_key(&server_key.private)
+        .expect_remote_fingerprint(Fingerprint::of(&client_key.public));
+    let (mut client, mut server) = connect_pair(initiator, responder).await;
+    exchange_data(&mut client, &mut server).await;
+}
+
+#[tokio::test]
+async fn pinned_remote_key_mismatch_aborts_before_final_message() {
+    const NAME: &str = "Noise_XX_25519_ChaChaPoly_SHA256";
+    let (client_key, server_key, other_key) = (
+        generate_keypair(NAME),
+        generate_keypair(NAME),
+        generate_keypair(NAME),
+    );
+
+    let (client, server) = duplex(64 * 1024);
+    let initiator = SnowHandshake::new(NAME)
+        .unwrap()
+        .local_private_key(&client_key.private)
+        .expect_remote_static(&other_key.public);
+    let responder = SnowHandshake::new(NAME)
+        .unwrap()
+        .local_private_key(&server_key.private);
+    let (client, server) = tokio::join!(
+        async move {
+            let result = NoiseStream::handshake_initiator(client, initiator).await;
+            // Dropping the transport on failure closes it, without the final message.
+            result.map(drop)
+        },
+        NoiseStream::handshake_responder(server, responder),
+    );
+
+    match client {
+        Err(NoiseError::PeerKeyMismatch { expected, got }) => {
+            assert_eq!(expected, Fingerprint::of(&other_key.public));
+            assert_eq!(got, Fingerprint::of(&server_key.public));
+        }
+        result => panic!("expected PeerKeyMismatch, got {:?}", result),
+    }
+    // The responder never received the message carrying the initiator's identity.
+    assert!(server.is_err());
+}
+
+#[tokio::test]
+async fn responder_accepts_old_and_new_static_keys_during_rotation() {
+    for name in [
+        "Noise_NK_25519_ChaChaPoly_SHA256",
+        "Noise_XK_25519_ChaChaPoly_SHA256",
+        "Noise_IK_25519_ChaChaPoly_SHA256",
+    ] {
+        let (old_key, new_key, client_key) = (
+            generate_keypair(name),
+            generate_keypair(name),
+            generate_keypair(name),
+        );
+        let responder = SnowHandshake::new(name)
+            .unwrap()
+            .local_private_key(&new_key.private)
+            .fallback_local_private_key(&old_key.private);
+        let pinned_to = |key: &snow::Keypair| {
+            SnowHandshake::new(name)
+                .unwrap()
+                .local_private_key(&client_key.private)
+                .remote_public_key(&key.public)
+        };
+
+        for server_key in [&new_key, &old_key] {
+            let (mut client, mut server) =
+                connect_pair(pinned_to(server_key), responder.clone()).await;
+            // The responder reports which of its keys the initiator reached.
+            assert_eq!(
+                server.local_static_public_key(),
+                Some(&server_key.public[..]),
+                "{}",
+                name
+            );
+            exchange_data(&mut client, &mut server).await;
+        }
+
+        let unknown_key = generate_keypair(name);
+        let (client, server) = duplex(64 * 1024);
+        let (_client, server) = tokio::join!(
+            NoiseStream::handshake_initiator(client, pinned_to(&unknown_key)),
+            NoiseStream::handshake_responder(server, responder.clone()),
+        );
+        match server {
+            Err(NoiseError::Snow(snow::Error::Decrypt)) => {}
+            result => panic!(
+                "expected {} to fail to decrypt, got {:?}",
+                name,
+                result.err()
+            ),
+        }
+    }
+}
+
+#[tokio::test]
+async fn mismatched_prologue_fails() {
+    const NAME: &str = "Noise_NN_25519_ChaChaPoly_SHA256";
+    let (client, server) = duplex(64 * 1024);
+    let initiator = SnowHandshake::new(NAME).unwrap().prologue(b"a");
+    let responder = SnowHandshake::new(NAME).unwrap().prologue(b"b");
+    let (client, _server) = tokio::join!(
+        NoiseStream::handshake_initiator(client, initiator),
+        NoiseStream::handshake_responder(server, responder),
+    );
+    // The prologue is first authenticated by the responder's reply.
+    assert!(client.is_err());
+}
+
+#[tokio::test]
+async fn custom_builder_is_used() {
+    const NAME: &str = "Noise_NN_25519_ChaChaPoly_SHA512";
+    let builds = Arc::new(AtomicUsize::new(0));
+    let counted = |builds: Arc<AtomicUsize>| {
+        SnowHandshake::new(NAME)
+            .unwrap()
+            .with_builder(move |params| {
+                builds.fetch_add(1, Ordering::Relaxed);
+                snow::Builder::new(params)
+            })
+    };
+
+    let (mut client, mut server) =
+        connect_pair(counted(builds.clone()), counted(builds.clone())).await;
+    assert_eq!(builds.load(Ordering::Relaxed), 2);
+    exchange_data(&mut client, &mut server).await;
+}
+
+#[test]
+fn keypairs_are_drawn_from_the_configured_rng() {
+    const NAME: &str = "Noise_XX_25519_ChaChaPoly_SHA256";
+    let seeded = |seed| SnowHandshake::new(NAME).unwrap().rng(CountingRng(seed));
+
+    let keypair = seeded(1).generate_keypair().unwrap();
+    assert_eq!(
+        seeded(1).generate_keypair().unwrap().private,
+        keypair.private
+    );
+    assert_ne!(
+        seeded(2).generate_keypair().unwrap().private,
+        keypair.private
+    );
+
+    // Clones share the generator's state, so they don't repeat each other's keys.
+    let handshake = seeded(1);
+    let first = handshake.clone().generate_keypair().unwrap();
+    assert_eq!(first.private, keypair.private);
+    assert_ne!(handshake.generate_keypair().unwrap().private, first.private);
+}
+
+#[tokio::test]
+async fn ephemeral_keys_are_drawn_from_the_configured_rng() {
+    const NAME: &str = "Noise_NN_25519_ChaChaPoly_SHA256";
+    let seeded = |seed| SnowHandshake::new(NAME).unwrap().rng(CountingRng(seed));
+
+    // With the same seeds, both sides pick the same ephemeral keys, and so derive the
+    // same session each time.
+    let (client, _server) = connect_pair(seeded(1), seeded(2)).await;
+    let (again, _server) = connect_pair(seeded(1), seeded(2)).await;
+    assert_eq!(client.handshake_hash(), again.handshake_hash());
+
+    let (other, _server) = connect_pair(seeded(1), seeded(3)).await;
+    assert_ne!(client.handshake_hash(), other.handshake_hash());
+}
+
+#[test]
+fn invalid_protocol_name_is_rejected() {
+    let e = SnowHandshake::new("Noise_ZZ_25519_ChaChaPoly_SHA256").unwrap_err();
+    assert!(
+        e.to_string().contains("invalid Noise protocol name"),
+        "{}",
+        e
+    );
+}
+
+#[tokio::test]
+async fn every_two_way_pattern_completes() {
+    for pattern in [
+        "NN", "NK", "NX", "KN", "KK", "KX", "XN", "XK", "XX", "IN", "IK", "IX", "NK1", "NX1",
+        "X1N", "X1K", "XK1", "X1K1", "X1X", "XX1", "X1X1", "K1N", "K1K", "KK1", "K1K1", "K1X",
+        "KX1", "K1X1", "I1N", "I1K", "IK1", "I1K1", "I1X", "IX1", "I1X1",
+    ] {
+        let name = format!("Noise_{}_25519_ChaChaPoly_SHA256", pattern);
+        let (client_key, server_key) = (generate_keypair(&name), generate_keypair(&name));
+        // Each side is given both static keys. Those which the pattern doesn't use are
+        // ignored.
+        let initiator = SnowHandshake::new(&name)
+            .unwrap()
+            .local_private_key(&client_key.private)
+            .remote_public_key(&server_key.public);
+        let responder = SnowHandshake::new(&name)
+            .unwrap()
+            .local_private_key(&server_key.private)
+            .remote_public_key(&client_key.public);
+        let (mut client, mut server) = connect_pair(initiator, responder).await;
+        assert_eq!(client.handshake_hash(), server.handshake_hash(), "{}", name);
+        exchange_data(&mut client, &mut server).await;
+    }
+}
@@ -0,0 +1,129 @@
+//! An active attacker who flips bits in transit must never get corrupted plaintext
+//! delivered: each tampered byte, whether in a handshake message, the preamble, or a
+//! data frame, makes the receiver fail with an authentication error instead.
+
+use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf};
+use tokio_noise::{NoiseError, NoiseErrorKind, NoiseStream};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+/// The size of the length prefix before each handshake message. Like the length
+/// fields of the Noise spec, it isn't authenticated: a tampered length leaves the
+/// receiver waiting for bytes which never come, so it isn't exercised here.
+const HANDSHAKE_LEN_SIZE: usize = 2;
+
+/// The size of the initiator's first `NNpsk0` handshake message.
+const HANDSHAKE_MSG_SIZE: usize = 48;
+
+/// The offset of the preamble, after the initiator's only handshake message.
+const PREAMBLE: usize = HANDSHAKE_LEN_SIZE + HANDSHAKE_MSG_SIZE;
+
+/// The size of the preamble which precedes the client's first data frame.
+const PREAMBLE_SIZE: usize = 64;
+
+/// The size of each encrypted data frame on the wire.
+const FRAME_SIZE: usize = 2048;
+
+const FIRST_FRAME: usize = PREAMBLE + PREAMBLE_SIZE;
+
+/// Copies bytes from `from` to `to`, flipping the lowest bit of the byte at offset
+/// `corrupt_at`, if any.
+async fn relay(
+    mut from: ReadHalf<DuplexStream>,
+    mut to: WriteHalf<DuplexStream>,
+    corrupt_at: Option<usize>,
+) {
+    let mut offset = 0;
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = match from.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        if let Some(i) = corrupt_at.filter(|i| (offset..offset + n).contains(i)) {
+            buf[i - offset] ^= 0x01;
+        }
+        offset += n;
+        if to.write_all(&buf[..n]).await.is_err() {
+            break;
+        }
+    }
+    let _ = to.shutdown().await;
+}
+
+/// Returns a pair of transports joined by a malicious peer, which tampers with the
+/// client-to-server bytes at the given offset.
+fn tampered_pair(corrupt_at: usize) -> (DuplexStream, DuplexStream) {
+    let (client, client_proxy) = duplex(64 * 1024);
+    let (server_proxy, server) = duplex(64 * 1024);
+    let (client_read, client_write) = tokio::io::split(client_proxy);
+    let (server_read, server_write) = tokio::io::split(server_proxy);
+    tokio::spawn(relay(client_read, server_write, Some(corrupt_at)));
+    tokio::spawn(relay(server_read, client_write, None));
+    (client, server)
+}
+
+fn assert_auth_failure<T: std::fmt::Debug>(result: Result<T, NoiseError>, corrupt_at: usize) {
+    match result {
+        Err(e) => assert_eq!(
+            e.kind(),
+            NoiseErrorKind::Decrypt,
+            "offset {}: {}",
+            corrupt_at,
+            e
+        ),
+        Ok(value) => panic!(
+            "tampering at offset {} went undetected: {:?}",
+            corrupt_at, value
+        ),
+    }
+}
+
+#[tokio::test]
+async fn tampered_handshake_fails() {
+    for corrupt_at in [
+        HANDSHAKE_LEN_SIZE,
+        HANDSHAKE_LEN_SIZE + HANDSHAKE_MSG_SIZE / 2,
+        PREAMBLE - 1,
+    ] {
+        let (client, server) = tampered_pair(corrupt_at);
+        let (_client, server) = tokio::join!(
+            NoiseStream::handshake_initiator_psk0(client, &PSK),
+            NoiseStream::handshake_responder_psk0(server, &PSK),
+        );
+        assert_auth_failure(server.map(drop), corrupt_at);
+    }
+}
+
+#[tokio::test]
+async fn tampered_frames_are_never_delivered() {
+    let offsets = [
+        // The preamble.
+        PREAMBLE,
+        FIRST_FRAME - 1,
+        // The encrypted kind and length header, the payload, the padding, and the tag.
+        FIRST_FRAME,
+        FIRST_FRAME + 2,
+        FIRST_FRAME + 4,
+        FIRST_FRAME + 500,
+        FIRST_FRAME + FRAME_SIZE - 1,
+    ];
+
+    for corrupt_at in offsets {
+        let (client, server) = tampered_pair(corrupt_at);
+        let (client, server) = tokio::join!(
+            NoiseStream::handshake_initiator_psk0(client, &PSK),
+            NoiseStream::handshake_responder_psk0(server, &PSK),
+        );
+        let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+        client.send(b"hello").await.unwrap();
+        let mut buf = [0u8; 64];
+        let result = server.recv(&mut buf).await.map(|n| buf[..n].to_vec());
+        assert_auth_failure(result, corrupt_at);
+
+        // The stream stays failed, rather than resynchronising on later frames.
+        client.send(b"world").await.unwrap();
+        assert!(server.recv(&mut buf).await.is_err());
+    }
+}
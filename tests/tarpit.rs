@@ -0,0 +1,76 @@
+use std::{net::SocketAddr, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpSocket, TcpStream},
+    time::Instant,
+};
+use tokio_noise::{handshakes::NNpsk0, NoiseTcpListener, NoiseTcpStream, TarpitConfig};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+async fn connect_from(local_ip: &str, addr: SocketAddr) -> TcpStream {
+    let socket = TcpSocket::new_v4().unwrap();
+    socket
+        .bind(format!("{}:0", local_ip).parse().unwrap())
+        .unwrap();
+    socket.connect(addr).await.unwrap()
+}
+
+/// Sends a garbage handshake message and waits for the responder to hang up.
+async fn send_garbage(mut tcp_stream: TcpStream) {
+    tcp_stream.write_all(&[0xAB; 64]).await.unwrap();
+    let mut buf = [0u8; 64];
+    while tcp_stream.read(&mut buf).await.unwrap_or(0) > 0 {}
+}
+
+/// Accepts one connection and returns how long the handshake took to fail or succeed.
+async fn time_handshake(listener: &NoiseTcpListener) -> (bool, Duration) {
+    let incoming = listener.accept().await.unwrap();
+    let start = Instant::now();
+    let result = incoming.handshake(NNpsk0::try_new(&PSK).unwrap()).await;
+    (result.is_ok(), start.elapsed())
+}
+
+#[tokio::test(start_paused = true)]
+async fn failed_handshakes_are_delayed_exponentially() {
+    let mut listener = NoiseTcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.set_tarpit(TarpitConfig {
+        base_delay: Duration::from_secs(1),
+        multiplier: 2.0,
+        max_delay: Duration::from_secs(60),
+        max_tracked_addrs: 16,
+    });
+    let addr = listener.local_addr().unwrap();
+
+    // Each consecutive failure waits out the previous penalty before the handshake
+    // starts, then the new penalty before the failure is reported.
+    for expected_secs in [1, 3, 6, 12] {
+        let client =
+            tokio::spawn(async move { send_garbage(connect_from("127.0.0.1", addr).await).await });
+        let (ok, elapsed) = time_handshake(&listener).await;
+        client.await.unwrap();
+
+        assert!(!ok);
+        assert_eq!(elapsed, Duration::from_secs(expected_secs));
+    }
+
+    // A different address is unaffected by the first address's failures.
+    let client =
+        tokio::spawn(async move { send_garbage(connect_from("127.0.0.2", addr).await).await });
+    let (ok, elapsed) = time_handshake(&listener).await;
+    client.await.unwrap();
+    assert!(!ok);
+    assert_eq!(elapsed, Duration::from_secs(1));
+
+    // Successful handshakes from an untainted address are not delayed at all.
+    let client = tokio::spawn(async move {
+        let tcp_stream = connect_from("127.0.0.3", addr).await;
+        NoiseTcpStream::handshake_initiator_psk0(tcp_stream, &PSK)
+            .await
+            .unwrap()
+    });
+    let (ok, elapsed) = time_handshake(&listener).await;
+    client.await.unwrap();
+    assert!(ok);
+    assert_eq!(elapsed, Duration::ZERO);
+}
@@ -0,0 +1,31 @@
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_noise::{NoiseTcpStream, TcpKeepalive};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+#[tokio::test]
+async fn tcp_keepalive_can_be_enabled_and_disabled() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (tcp_stream, _) = listener.accept().await.unwrap();
+        NoiseTcpStream::handshake_responder_psk0(tcp_stream, &PSK).await
+    });
+    let tcp_stream = TcpStream::connect(addr).await.unwrap();
+    let client = NoiseTcpStream::handshake_initiator_psk0(tcp_stream, &PSK)
+        .await
+        .unwrap();
+    let _server = server.await.unwrap().unwrap();
+
+    assert!(!client.tcp_keepalive().unwrap());
+
+    let keepalive = TcpKeepalive::new()
+        .with_time(Duration::from_secs(30))
+        .with_interval(Duration::from_secs(5));
+    client.set_tcp_keepalive(Some(keepalive)).unwrap();
+    assert!(client.tcp_keepalive().unwrap());
+
+    client.set_tcp_keepalive(None).unwrap();
+    assert!(!client.tcp_keepalive().unwrap());
+}
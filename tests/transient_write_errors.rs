@@ -0,0 +1,123 @@
+//! A transport may fail a write with `Interrupted` or `WouldBlock` when it could simply
+//! be retried. Those are not fatal, and other errors still are.
+
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{
+    duplex, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream, ReadBuf,
+};
+use tokio_noise::{NoiseError, NoiseStream, Transport};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+/// A transport whose writes and flushes fail with the given error on every other
+/// attempt. `WouldBlock` is returned without registering the waker, as a transport
+/// wrapping a raw non-blocking socket might.
+struct Flaky {
+    inner: DuplexStream,
+    error: Option<io::ErrorKind>,
+    fail: bool,
+}
+
+impl Flaky {
+    fn new(inner: DuplexStream) -> Flaky {
+        Flaky {
+            inner,
+            error: None,
+            fail: false,
+        }
+    }
+
+    fn take_error(&mut self) -> Option<io::Error> {
+        let kind = self.error?;
+        self.fail = !self.fail;
+        self.fail.then(|| io::Error::from(kind))
+    }
+}
+
+impl AsyncRead for Flaky {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Flaky {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if let Some(e) = self.take_error() {
+            return Poll::Ready(Err(e));
+        }
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let Some(e) = self.take_error() {
+            return Poll::Ready(Err(e));
+        }
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl Transport for Flaky {}
+
+async fn flaky_pair() -> (NoiseStream<Flaky>, NoiseStream<DuplexStream>) {
+    let (client, server) = duplex(4 * 1024);
+    let (client, server) = tokio::join!(
+        NoiseStream::handshake_initiator_psk0(Flaky::new(client), &PSK),
+        NoiseStream::handshake_responder_psk0(server, &PSK),
+    );
+    (client.unwrap(), server.unwrap())
+}
+
+async fn send_through(kind: io::ErrorKind) {
+    let (mut client, mut server) = flaky_pair().await;
+    client.get_mut().error = Some(kind);
+
+    // Larger than the duplex buffer, so the writes also have to wait for the reader.
+    let data: Vec<u8> = (0..50_000).map(|i| i as u8).collect();
+    let mut received = vec![0u8; data.len()];
+    let (written, read) = tokio::join!(
+        async {
+            client.write_all(&data).await?;
+            client.flush().await
+        },
+        server.read_exact(&mut received),
+    );
+    written.unwrap();
+    read.unwrap();
+    assert_eq!(received, data);
+    assert!(!client.is_poisoned());
+}
+
+#[tokio::test]
+async fn interrupted_writes_are_retried() {
+    send_through(io::ErrorKind::Interrupted).await;
+}
+
+#[tokio::test]
+async fn would_block_writes_are_retried() {
+    send_through(io::ErrorKind::WouldBlock).await;
+}
+
+#[tokio::test]
+async fn other_write_errors_are_fatal() {
+    let (mut client, _server) = flaky_pair().await;
+    client.get_mut().error = Some(io::ErrorKind::BrokenPipe);
+
+    let e = client.send(b"hello").await.unwrap_err();
+    assert!(matches!(e, NoiseError::Io(ref e) if e.kind() == io::ErrorKind::BrokenPipe));
+}
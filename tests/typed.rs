@@ -0,0 +1,98 @@
+#![cfg(feature = "json")]
+
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::io::{duplex, AsyncWriteExt, DuplexStream};
+use tokio_noise::{NoiseError, NoiseStream, TypedNoiseStream};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum Rpc {
+    Ping(u64),
+    Echo(String),
+}
+
+async fn connect_pair() -> (NoiseStream<DuplexStream>, NoiseStream<DuplexStream>) {
+    let (client, server) = duplex(64 * 1024);
+    let (client, server) = tokio::join!(
+        NoiseStream::handshake_initiator_psk0(client, &PSK),
+        NoiseStream::handshake_responder_psk0(server, &PSK),
+    );
+    (client.unwrap(), server.unwrap())
+}
+
+#[tokio::test]
+async fn typed_messages_round_trip() {
+    let (client, server) = connect_pair().await;
+    let mut client = client.into_typed::<Rpc>();
+    let mut server = server.into_typed::<Rpc>();
+
+    // Large enough to span several frames.
+    let long = "x".repeat(10_000);
+    client.send(&Rpc::Ping(1)).await.unwrap();
+    client.send(&Rpc::Echo(long.clone())).await.unwrap();
+    assert_eq!(server.recv().await.unwrap(), Some(Rpc::Ping(1)));
+    assert_eq!(server.recv().await.unwrap(), Some(Rpc::Echo(long)));
+
+    server.send(&Rpc::Ping(2)).await.unwrap();
+    assert_eq!(client.recv().await.unwrap(), Some(Rpc::Ping(2)));
+
+    client.close().await.unwrap();
+    assert_eq!(server.recv().await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn typed_stream_is_a_sink_and_stream() {
+    let (client, server) = connect_pair().await;
+    let mut client: TypedNoiseStream<_, Rpc, _> = client.into_typed();
+    let server = server.into_typed::<Rpc>();
+
+    for n in 0..3 {
+        client.feed(Rpc::Ping(n)).await.unwrap();
+    }
+    client.close().await.unwrap();
+
+    let received: Vec<Rpc> = server.map(Result::unwrap).collect().await;
+    assert_eq!(received, [Rpc::Ping(0), Rpc::Ping(1), Rpc::Ping(2)]);
+}
+
+#[tokio::test]
+async fn oversized_messages_are_rejected() {
+    let (client, server) = connect_pair().await;
+    let mut client = client.into_typed::<Rpc>().max_message_len(16);
+    let mut server = server.into_typed::<Rpc>().max_message_len(16);
+
+    match client.send(&Rpc::Echo("x".repeat(100))).await {
+        Err(NoiseError::MessageTooLarge { max: 16, .. }) => {}
+        result => panic!("expected the message to be too large, got {:?}", result),
+    }
+
+    // A peer with a higher limit can still send one, which the receiver refuses.
+    let mut client = client.into_inner().into_typed::<Rpc>();
+    client.send(&Rpc::Echo("x".repeat(100))).await.unwrap();
+    match server.recv().await {
+        Err(NoiseError::MessageTooLarge { max: 16, .. }) => {}
+        result => panic!("expected the message to be too large, got {:?}", result),
+    }
+}
+
+#[tokio::test]
+async fn malformed_and_truncated_messages_fail() {
+    let (mut client, server) = connect_pair().await;
+    let mut server = server.into_typed::<Rpc>();
+
+    client.write_all(&[0, 0, 0, 2, b'{', b'}']).await.unwrap();
+    match server.recv().await {
+        Err(NoiseError::MessageFormat(_)) => {}
+        result => panic!("expected a malformed message, got {:?}", result),
+    }
+
+    // A length prefix announcing more than arrives before the peer closes.
+    client.write_all(&[0, 0, 0, 10, b'[']).await.unwrap();
+    client.shutdown().await.unwrap();
+    match server.recv().await {
+        Err(NoiseError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof),
+        result => panic!("expected a truncated message, got {:?}", result),
+    }
+}
@@ -0,0 +1,121 @@
+#![cfg(unix)]
+
+use std::{os::fd::AsFd, process::Stdio};
+
+use tokio::{io::AsyncWriteExt, net::UnixStream};
+use tokio_noise::{
+    child_socketpair, handshakes::NNpsk0, inherit_unix_stream, noise_socketpair, HandshakeConfig,
+    NoiseError, NoiseStream,
+};
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+/// Set in the environment of this test binary when it is re-run as a child process.
+const CHILD_ENV: &str = "TOKIO_NOISE_TEST_SOCKETPAIR_CHILD";
+
+#[tokio::test]
+async fn peer_cred_is_that_of_this_process() {
+    let (client, server) = UnixStream::pair().unwrap();
+    let config = HandshakeConfig::new(NNpsk0::try_new(&PSK).unwrap());
+    let (client, server) = tokio::join!(config.initiate(client), config.respond(server));
+    let (client, server) = (client.unwrap(), server.unwrap());
+
+    let cred = server.peer_cred().unwrap();
+    assert_eq!(cred.pid(), Some(std::process::id() as i32));
+    assert_eq!(client.peer_cred().unwrap().uid(), cred.uid());
+}
+
+#[tokio::test]
+async fn peers_are_admitted_by_uid() {
+    let uid = UnixStream::pair().unwrap().0.peer_cred().unwrap().uid();
+
+    // A peer of our own uid is admitted, and completes the handshake.
+    let (client, server) = UnixStream::pair().unwrap();
+    let config = HandshakeConfig::new(NNpsk0::try_new(&PSK).unwrap())
+        .admit(move |peer| peer.cred.is_some_and(|cred| cred.uid() == uid));
+    let (client, server) = tokio::join!(config.initiate(client), config.respond(server));
+    assert!(client.is_ok());
+    assert!(server.is_ok());
+
+    // Any other uid is turned away before the responder reads the first handshake
+    // message, and the initiator sees the connection drop.
+    let (client, server) = UnixStream::pair().unwrap();
+    let initiator = HandshakeConfig::new(NNpsk0::try_new(&PSK).unwrap());
+    let responder = HandshakeConfig::new(NNpsk0::try_new(&PSK).unwrap())
+        .admit(move |peer| peer.cred.is_some_and(|cred| cred.uid() != uid));
+    let server = match responder.respond(server).await {
+        Ok(_) => panic!("handshake with a rejected peer succeeded"),
+        Err(e) => e,
+    };
+    assert!(matches!(server, NoiseError::PeerRejected));
+    assert!(initiator.initiate(client).await.is_err());
+}
+
+#[tokio::test]
+async fn socketpair_streams_are_connected() {
+    let (mut a, mut b) = noise_socketpair(
+        NNpsk0::try_new(&PSK).unwrap(),
+        NNpsk0::try_new(&PSK).unwrap(),
+    )
+    .await
+    .unwrap();
+    a.send(b"ping").await.unwrap();
+    let mut buf = [0u8; 16];
+    let n = b.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"ping");
+
+    // Mismatched keys fail on both sides, rather than leaving one waiting.
+    assert!(noise_socketpair(
+        NNpsk0::try_new(&PSK).unwrap(),
+        NNpsk0::try_new(&[0xAA; 32]).unwrap()
+    )
+    .await
+    .is_err());
+}
+
+/// The child's side of `child_handshakes_over_inherited_socket`, when this binary is
+/// re-run as the child. Otherwise there is nothing to do.
+#[tokio::test]
+async fn socketpair_child() {
+    if std::env::var_os(CHILD_ENV).is_none() {
+        return;
+    }
+    let socket = inherit_unix_stream(std::io::stdin().as_fd()).unwrap();
+    let mut stream = NoiseStream::handshake_responder(socket, NNpsk0::try_new(&PSK).unwrap())
+        .await
+        .unwrap();
+    let mut buf = [0u8; 16];
+    let n = stream.recv(&mut buf).await.unwrap();
+    stream.send(&buf[..n]).await.unwrap();
+    stream.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn child_handshakes_over_inherited_socket() {
+    let (socket, child_socket) = child_socketpair().unwrap();
+    let mut child = std::process::Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "socketpair_child", "--nocapture"])
+        .env(CHILD_ENV, "1")
+        .stdin(child_socket)
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let mut stream = NoiseStream::handshake_initiator(socket, NNpsk0::try_new(&PSK).unwrap())
+        .await
+        .unwrap();
+    stream.send(b"hello").await.unwrap();
+    let mut buf = [0u8; 16];
+    let n = stream.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hello");
+    assert_eq!(stream.recv(&mut buf).await.unwrap(), 0);
+
+    let status = tokio::task::spawn_blocking(move || child.wait())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(status.success());
+
+    // Anything but a socket is refused.
+    assert!(inherit_unix_stream(std::fs::File::open("/dev/null").unwrap().as_fd()).is_err());
+}
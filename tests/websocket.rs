@@ -0,0 +1,71 @@
+#![cfg(feature = "websocket")]
+
+use futures_util::SinkExt;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tokio_noise::{NoiseStream, WebSocketTransport};
+use tokio_tungstenite::tungstenite::Message;
+
+const PSK: [u8; 32] = [0xFF; 32];
+
+/// Accepts one WebSocket connection, and echoes back everything received over Noise.
+/// Pings the client before each echo, which the Noise layer must never see.
+async fn echo_server(listener: TcpListener) {
+    let (tcp_stream, _) = listener.accept().await.unwrap();
+    let ws_stream = tokio_tungstenite::accept_async(tcp_stream).await.unwrap();
+    let mut server =
+        NoiseStream::handshake_responder_psk0(WebSocketTransport::new(ws_stream), &PSK)
+            .await
+            .unwrap();
+
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = server.read(&mut buf).await.unwrap();
+        if n == 0 {
+            break;
+        }
+        let ping = Message::Ping(b"are you there".to_vec());
+        server.get_mut().get_mut().send(ping).await.unwrap();
+        server.write_all(&buf[..n]).await.unwrap();
+        server.flush().await.unwrap();
+    }
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn noise_runs_over_websocket() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(echo_server(listener));
+
+    let tcp_stream = TcpStream::connect(addr).await.unwrap();
+    let url = format!("ws://{}/noise", addr);
+    let (ws_stream, _) = tokio_tungstenite::client_async(url, tcp_stream)
+        .await
+        .unwrap();
+    let mut client =
+        NoiseStream::handshake_initiator_psk0(WebSocketTransport::new(ws_stream), &PSK)
+            .await
+            .unwrap();
+
+    let mut buf = [0u8; 64];
+    for message in [&b"hello"[..], b"over", b"websocket"] {
+        client.send(message).await.unwrap();
+        let n = client.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], message);
+    }
+
+    // Larger than a frame, so it spans several binary messages.
+    let payload: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+    client.write_all(&payload).await.unwrap();
+    client.flush().await.unwrap();
+    let mut echoed = vec![0u8; payload.len()];
+    client.read_exact(&mut echoed).await.unwrap();
+    assert_eq!(echoed, payload);
+
+    client.shutdown().await.unwrap();
+    assert_eq!(client.recv(&mut buf).await.unwrap(), 0);
+    server.await.unwrap();
+}